@@ -0,0 +1,99 @@
+//! Hooks invoked by picotest around the session cluster and every test.
+//!
+//! Hooks are registered once per test binary, typically from a `#[ctor]`
+//! function, and are invoked in the order of registration. Cluster hooks run
+//! once, when the session cluster is created by the first test using it.
+//! Test hooks run for every `#[picotest]` and `#[picotest_unit]` test.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use ctor::ctor;
+//! use picotest::hooks::{self, Hooks, TestContext};
+//!
+//! fn seed_users(cluster: &Cluster) {
+//!     cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')").unwrap();
+//! }
+//!
+//! fn report_failure(test: &TestContext) {
+//!     if test.failed {
+//!         eprintln!("{} has failed on cluster {}", test.test_name, test.cluster.uuid);
+//!     }
+//! }
+//!
+//! #[ctor]
+//! fn register_hooks() {
+//!     hooks::register(Hooks {
+//!         after_cluster_ready: Some(seed_users),
+//!         after_each: Some(report_failure),
+//!         ..Default::default()
+//!     });
+//! }
+//! ```
+
+use crate::Cluster;
+use std::sync::{PoisonError, RwLock};
+
+/// Test the hook is invoked for.
+pub struct TestContext<'a> {
+    /// Name of the test function.
+    pub test_name: &'a str,
+    pub cluster: &'a Cluster,
+    /// Test has panicked, always `false` in `before_each` hooks.
+    pub failed: bool,
+}
+
+/// Set of hooks, missing ones are skipped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hooks {
+    /// Invoked before the session cluster is created and started.
+    pub before_cluster_start: Option<fn()>,
+    /// Invoked once the session cluster is started, e.g. to insert seed data.
+    pub after_cluster_ready: Option<fn(&Cluster)>,
+    /// Invoked before every test, after the cluster is obtained.
+    pub before_each: Option<fn(&TestContext)>,
+    /// Invoked after every test, whether it has passed or not.
+    pub after_each: Option<fn(&TestContext)>,
+}
+
+static HOOKS: RwLock<Vec<Hooks>> = RwLock::new(Vec::new());
+
+/// Registers `hooks` for the rest of the test binary run.
+///
+/// Hooks registered after the session cluster is started
+/// don't get cluster hooks invoked.
+pub fn register(hooks: Hooks) {
+    HOOKS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(hooks);
+}
+
+/// Returns registered hooks. Lock isn't held while hooks
+/// are running, so hooks may register other ones.
+fn registered() -> Vec<Hooks> {
+    HOOKS.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+pub(crate) fn run_before_cluster_start() {
+    for hook in registered().iter().filter_map(|h| h.before_cluster_start) {
+        hook();
+    }
+}
+
+pub(crate) fn run_after_cluster_ready(cluster: &Cluster) {
+    for hook in registered().iter().filter_map(|h| h.after_cluster_ready) {
+        hook(cluster);
+    }
+}
+
+pub(crate) fn run_before_each(test: &TestContext) {
+    for hook in registered().iter().filter_map(|h| h.before_each) {
+        hook(test);
+    }
+}
+
+pub(crate) fn run_after_each(test: &TestContext) {
+    for hook in registered().iter().filter_map(|h| h.after_each) {
+        hook(test);
+    }
+}
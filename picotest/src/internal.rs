@@ -3,44 +3,115 @@
 //! Contains helper routines called by proc macro unfolding.
 //! This module isn't supposed to be used manually.
 
+use crate::hooks::{self, TestContext};
 use anyhow::bail;
+use picotest_helpers::cassette::{
+    self, Cassette, CassetteMode, DEFAULT_CASSETTE_PATH, PICOTEST_CASSETTE, PICOTEST_CASSETTE_PATH,
+};
+use picotest_helpers::cluster_cache::ClusterCache;
+use picotest_helpers::container::Backend;
+use picotest_helpers::coverage::{ensure_instrumented_build, CoverageConfig};
+use picotest_helpers::embedded::EmbeddedRuntime;
+use picotest_helpers::flaky::{TestHistory, TestTracker, PICOTEST_TRACK_FLAKY};
+use picotest_helpers::impact::{
+    ImpactMap, ImpactSelection, ImpactTracker, PICOTEST_CHANGED_FILES, PICOTEST_TRACK_IMPACT,
+};
+use picotest_helpers::keepalive::{KeepaliveState, KEEPALIVE_DATA_DIR};
+use picotest_helpers::log_stream::{LogFilter, LogStream, PICOTEST_STREAM_LOGS};
 use picotest_helpers::migration::{
     find_migrations_directories, make_ddl_tier_overrides, parse_migrations,
+    MigrationContextProvider, Migrations,
 };
+use picotest_helpers::panic_report::format_remote_panic;
+use picotest_helpers::plugin_build::{
+    build_plugin, ensure_fresh_build, find_plugin_library, find_plugin_root, BuildProfile,
+    StaleBuildPolicy,
+};
+use picotest_helpers::plugin_package::PluginSource;
+use picotest_helpers::remote::PICOTEST_REMOTE_CONFIG;
+use picotest_helpers::retry::RetryPolicy;
+use picotest_helpers::sanitizer::{check_build_flags, check_library, check_picodata, Sanitizer};
+use picotest_helpers::seed::{session_seed, PICOTEST_SEED};
+use picotest_helpers::state_dump::{test_artifacts_dir, PICOTEST_DUMP_STATE};
+use picotest_helpers::tags::{PicotestConfig, TagFilter, PICOTEST_TAGS};
+use picotest_helpers::tls::TlsConfig;
 use picotest_helpers::topology::{
-    parse_topology, PluginTopology, SingleNodeTopologyTransformer, TopologyTransformer,
+    self, parse_topology, PluginTopology, SingleNodeTopologyTransformer, TopologyTransformer,
     DEFAULT_TIER,
 };
-use picotest_helpers::{Cluster, DEFAULT_WAIT_VSHARD_ENABLED};
-use std::collections::HashMap;
+use picotest_helpers::unit::UnitTestContext;
+use picotest_helpers::watchdog::{Watchdog, WatchdogConfig};
+#[cfg(feature = "harness")]
+use picotest_helpers::{scheduler::ClusterProfile, tags::TopologyProfile};
+use picotest_helpers::{stop_cluster, Cluster, DEFAULT_WAIT_VSHARD_ENABLED};
+use std::collections::{BTreeMap, HashMap};
 use std::env::{var, VarError};
 use std::{
-    env,
+    any::Any,
+    env, fs,
+    io::{self, Write},
+    panic,
     path::{Path, PathBuf},
-    sync::OnceLock,
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
+    sync::{Arc, Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
 };
 
-#[cfg(target_os = "linux")]
-const LIB_EXT: &str = "so";
-
-#[cfg(target_os = "macos")]
-const LIB_EXT: &str = "dylib";
+/// Result of the method of client generated by `#[rpc_client]`.
+pub type RpcResult<T> = anyhow::Result<T>;
 
 const PLUGIN_TOPOLOGY_FILENAME: &str = "topology.toml";
 
 const ENV_WAIT_VSHARD_DISCOVERY: &str = "WAIT_VSHARD_DISCOVERY";
 const ENV_PICODATA_PATH: &str = "PICODATA_PATH";
 const ENV_TOPOLOGY_PATH: &str = "TOPOLOGY_PATH";
+const ENV_PICOTEST_ATTACH: &str = "PICOTEST_ATTACH";
+const ENV_PICOTEST_KEEPALIVE: &str = "PICOTEST_KEEPALIVE";
+const ENV_PICOTEST_STALE_BUILD: &str = "PICOTEST_STALE_BUILD";
+const ENV_PICOTEST_QUERY_TIMEOUT: &str = "PICOTEST_QUERY_TIMEOUT";
+const ENV_PICOTEST_SKIP: &str = "PICOTEST_SKIP";
+const ENV_PICOTEST_RETRY_ATTEMPTS: &str = "PICOTEST_RETRY_ATTEMPTS";
+const ENV_PICOTEST_BACKEND: &str = "PICOTEST_BACKEND";
+const ENV_PICOTEST_VERIFY_MIGRATIONS: &str = "PICOTEST_VERIFY_MIGRATIONS";
+const ENV_PICOTEST_CLUSTER_CACHE: &str = "PICOTEST_CLUSTER_CACHE";
+
+/// Returns cargo profile the plugin is built with,
+/// see [`picotest_helpers::plugin_build::PICOTEST_BUILD_PROFILE`].
+pub fn build_profile() -> BuildProfile {
+    BuildProfile::from_env().expect("invalid build profile")
+}
 
 pub fn plugin_profile_build_path(plugin_path: &Path) -> PathBuf {
-    plugin_path.join("target").join("debug")
+    build_profile().build_path(plugin_path)
 }
 
-/// Constructs a path to the shared library of the plugin
+/// Constructs a path to the shared library of the package
 /// located by passed `plugin_path`.
 pub fn plugin_dylib_path(plugin_path: &Path, package_name: &str) -> PathBuf {
-    let plugin_dylib_filename = format!("lib{}.{LIB_EXT}", package_name.replace('-', "_"));
-    plugin_profile_build_path(plugin_path).join(plugin_dylib_filename)
+    package_dylib_path(plugin_path, plugin_path, package_name)
+}
+
+/// Constructs a path to the shared library of the package located in
+/// `package_dir`, which may be a sub-crate of the plugin workspace.
+///
+/// Path is resolved once per package, see [`find_plugin_library`].
+///
+/// ### Arguments
+/// - `plugin_path` - path to the plugin root directory.
+/// - `package_dir` - directory of the package, e.g. its `CARGO_MANIFEST_DIR`.
+/// - `package_name` - name of the package.
+///
+pub fn package_dylib_path(plugin_path: &Path, package_dir: &Path, package_name: &str) -> PathBuf {
+    static PATHS: Mutex<BTreeMap<(PathBuf, String), PathBuf>> = Mutex::new(BTreeMap::new());
+
+    PATHS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry((package_dir.to_path_buf(), package_name.to_string()))
+        .or_insert_with(|| {
+            find_plugin_library(package_dir, plugin_path, build_profile(), package_name)
+        })
+        .clone()
 }
 
 /// Constructs a path to the topology file of the plugin.
@@ -63,23 +134,21 @@ pub fn plugin_topology_path(plugin_path: &Path) -> PathBuf {
 ///
 /// Panics if it was not found.
 ///
-/// Basically, it looks for topology.toml file and then
-/// returns its parent directory.
+/// Plugin is located by its topology file in the cargo workspace of the
+/// package under test, see [`find_plugin_root`], so tests may reside
+/// in the plugin crate or in any sub-crate of the plugin workspace.
 pub fn plugin_root_dir() -> PathBuf {
-    let plugin_topology_path = find_plugin_topology_path()
-        .expect("Error occurred while searching for plugin topology configuration")
-        .expect("Plugin topology configuration is not found");
-
-    let plugin_root_dir = plugin_topology_path
-        .parent()
-        .expect("Failed to obtain parent directory of plugin topology file");
+    static PLUGIN_ROOT: OnceLock<PathBuf> = OnceLock::new();
 
-    assert!(
-        plugin_root_dir.join("Cargo.toml").exists(),
-        "broken plugin directory?"
-    );
-
-    plugin_root_dir.to_path_buf()
+    PLUGIN_ROOT
+        .get_or_init(|| {
+            let manifest_dir: PathBuf = env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR is not set, tests must be run by cargo")
+                .into();
+            find_plugin_root(&manifest_dir)
+                .unwrap_or_else(|err| panic!("Failed to locate the plugin under test: {err:#}"))
+        })
+        .clone()
 }
 
 /// Finds path to the plugin topology file.
@@ -92,16 +161,20 @@ pub fn plugin_root_dir() -> PathBuf {
 pub fn find_plugin_topology_path() -> anyhow::Result<Option<PathBuf>> {
     let manifest_dir: PathBuf = env::var("CARGO_MANIFEST_DIR")?.into();
 
-    for path in manifest_dir.ancestors() {
-        let topology_path = path.join(PLUGIN_TOPOLOGY_FILENAME);
+    Ok(find_plugin_root(&manifest_dir)
+        .ok()
+        .map(|plugin_root| plugin_root.join(PLUGIN_TOPOLOGY_FILENAME)))
+}
 
-        if topology_path.exists() {
-            return Ok(Some(topology_path));
-        }
-    }
+/// Name of the Lua global table with unit-test libraries loaded on the instance.
+const UNIT_LIBRARIES_LUA_TABLE: &str = "__picotest_unit_libraries";
 
-    Ok(None)
-}
+/// Size of the buffer the unit test writes its panic message into.
+/// Longer messages are truncated.
+const UNIT_RESULT_CAPACITY: usize = 64 * 1024;
+
+/// Whether unit tests have loaded libraries on the session cluster.
+static UNIT_LIBRARIES_LOADED: AtomicBool = AtomicBool::new(false);
 
 /// Creates Lua script that does FFI call of provided target function taken
 /// from dynamic library.
@@ -109,82 +182,622 @@ pub fn find_plugin_topology_path() -> anyhow::Result<Option<PathBuf>> {
 /// This script is supposed to be executed from Picodata environment. E.g.,
 /// through admin tty.
 ///
+/// Library is loaded once and cached on the instance along with its
+/// fingerprint (modification time and size). If library has changed since
+/// it was loaded, e.g. plugin was rebuilt while cluster was kept alive,
+/// it's loaded again from a copy, because `dlopen` would return the
+/// library already loaded from the same path. Copy is removed once loaded,
+/// as loaded library stays mapped.
+///
+/// Test function receives [`UnitTestContext`] of the instance as JSON
+/// completed by the script with the working directory of the instance.
+/// It writes panic message into the buffer owned by the script and returns
+/// its length, which is zero if test has passed. Message is raised as a
+/// Lua error.
+///
 /// ### Arguments
 /// - `test_fn_name` - name of the test function to call dynamically.
 /// - `plugin_dylib_path` - path to the plugin shared library, which should
 ///   contain test function symbol.
+/// - `cluster` - cluster the test is executed by its main instance.
 ///
-pub fn lua_ffi_call_unit_test(test_fn_name: &str, plugin_dylib_path: &str) -> String {
+pub fn lua_ffi_call_unit_test(
+    test_fn_name: &str,
+    plugin_dylib_path: &str,
+    cluster: &Cluster,
+) -> String {
+    UNIT_LIBRARIES_LOADED.store(true, Ordering::Relaxed);
+    let context = UnitTestContext::for_instance(cluster.main());
+    unit_test_lua(test_fn_name, plugin_dylib_path, &context)
+}
+
+fn unit_test_lua(test_fn_name: &str, plugin_dylib_path: &str, context: &UnitTestContext) -> String {
+    let context = context.to_lua_literal();
     format!(
         r#"
-"[*] Running unit-test '{test_fn_name}'"
+local ffi = require("ffi")
+local fio = require("fio")
+local json = require("json")
+local path = "{plugin_dylib_path}"
+local libraries = rawget(_G, "{UNIT_LIBRARIES_LUA_TABLE}")
+if libraries == nil then
+    libraries = {{}}
+    rawset(_G, "{UNIT_LIBRARIES_LUA_TABLE}", libraries)
+end
+local stat = fio.stat(path)
+local fingerprint = stat ~= nil and (tostring(stat.mtime) .. "_" .. tostring(stat.size)) or ""
+local cached = libraries[path]
+if cached == nil or cached.fingerprint ~= fingerprint then
+    local load_path = path
+    if cached ~= nil then
+        load_path = path .. "." .. fingerprint
+        fio.copyfile(path, load_path)
+    end
+    local ok, library = pcall(ffi.load, load_path)
+    if load_path ~= path then
+        fio.unlink(load_path)
+    end
+    if not ok then
+        error(library, 0)
+    end
+    cached = {{ library = library, fingerprint = fingerprint }}
+    libraries[path] = cached
+end
+pcall(ffi.cdef, [[size_t {test_fn_name}(char *buffer, size_t capacity,
+    const char *context, size_t context_length);]])
+local context = json.decode({context})
+context.data_dir = fio.abspath(box.cfg.work_dir or fio.cwd())
+context = json.encode(context)
+local buffer = ffi.new("char[?]", {UNIT_RESULT_CAPACITY})
+local length = tonumber(cached.library.{test_fn_name}(
+    buffer, {UNIT_RESULT_CAPACITY}, context, #context))
+if length ~= 0 then
+    error(ffi.string(buffer, length), 0)
+end
+return true"#
+    )
+}
 
-ffi = require("ffi")
-ffi.cdef[[void {test_fn_name}();]]
-dylib = "{plugin_dylib_path}"
-ffi.load(dylib).{test_fn_name}()
+/// Runs unit test in the embedded runtime instead of the session cluster,
+/// see `runtime` attribute of `#[picotest_unit]`.
+///
+/// Plugin is built once per test binary, as there's no pike to build it.
+/// Output is checked by [`verify_unit_test_output`].
+pub fn run_embedded_unit_test(
+    test_fn_name: &str,
+    plugin_path: &Path,
+    plugin_dylib_path: &Path,
+) -> std::io::Result<String> {
+    let coverage = CoverageConfig::from_env(plugin_path);
+    let picodata_path = var(ENV_PICODATA_PATH)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("picodata"));
+    static PLUGIN_BUILT: OnceLock<()> = OnceLock::new();
+    PLUGIN_BUILT.get_or_init(|| {
+        let sanitizer = Sanitizer::from_env().expect("Invalid sanitizer");
+        check_build_flags(sanitizer).expect("Plugin can't be built for picodata");
+        build_plugin(plugin_path, build_profile()).expect("Failed to build the plugin");
+        if coverage.is_some() {
+            ensure_instrumented_build(plugin_path, &plugin_profile_build_path(plugin_path))
+                .expect("Failed to build the plugin with coverage instrumentation");
+        }
+        if let Some(sanitizer) = sanitizer {
+            check_picodata(&picodata_path, sanitizer).expect("Picodata can't run sanitized tests");
+        }
+        check_library(plugin_dylib_path, &picodata_path)
+            .expect("Unit tests can't be loaded by picodata");
+    });
+
+    let plugin_dylib_path = fs::canonicalize(plugin_dylib_path)?;
+    EmbeddedRuntime::new(plugin_path, picodata_path)
+        .coverage(coverage)
+        .run_lua(&unit_test_lua(
+            test_fn_name,
+            &plugin_dylib_path.to_string_lossy(),
+            &UnitTestContext::default(),
+        ))
+}
 
-"[*] Test '{test_fn_name}' has been finished"
-true"#
+/// Checks once per test binary that library of unit tests can be loaded
+/// by the instances of the `cluster`, so the test fails with a clear error
+/// instead of crashing the instance, see [`picotest_helpers::sanitizer`].
+pub fn check_unit_test_library(cluster: &Cluster, plugin_dylib_path: &Path) {
+    static CHECKED: OnceLock<Result<(), String>> = OnceLock::new();
+    let result = CHECKED.get_or_init(|| {
+        cluster
+            .check_sanitized_library(plugin_dylib_path)
+            .map_err(|err| format!("{err:#}"))
+    });
+    if let Err(err) = result {
+        panic!("Unit tests can't be loaded by picodata: {err}");
+    }
+}
+
+/// Returns `true` if scripts produced by [`lua_ffi_call_unit_test`]
+/// may have loaded libraries on the instances.
+pub fn unit_libraries_loaded() -> bool {
+    UNIT_LIBRARIES_LOADED.load(Ordering::Relaxed)
+}
+
+/// Creates Lua script that releases unit-test libraries
+/// cached by [`lua_ffi_call_unit_test`] on the instance.
+///
+/// Libraries are unloaded by garbage collector once they aren't referenced.
+pub fn lua_ffi_unload_unit_libraries() -> String {
+    format!(
+        r#"
+rawset(_G, "{UNIT_LIBRARIES_LUA_TABLE}", nil)
+collectgarbage()
+return true"#
     )
 }
 
-pub fn verify_unit_test_output(output: &str) -> anyhow::Result<()> {
-    if output.contains("cannot open shared object file") {
-        bail!("failed to open plugin shared library")
-    } else if output.contains("missing declaration") || output.contains("undefined symbol") {
-        bail!("failed to call unit-test routine: missing symbol in plugin shared library")
-    } else if !output.contains("true") {
-        bail!("test has finished unexpectedly")
+/// Checks result of the unit-test Lua script produced by [`lua_ffi_call_unit_test`].
+pub fn verify_unit_test_output(output: &std::io::Result<String>) -> anyhow::Result<()> {
+    match output {
+        Err(err) => {
+            let err = err.to_string();
+            if err.contains("cannot open shared object file") {
+                bail!("failed to open plugin shared library: {err}")
+            } else if err.contains("missing declaration") || err.contains("undefined symbol") {
+                bail!("failed to call unit-test routine: missing symbol in plugin shared library")
+            }
+            bail!(
+                "test has finished unexpectedly: {}",
+                format_remote_panic(&err)
+            )
+        }
+        Ok(output) if !output.contains("true") => bail!("test has finished unexpectedly"),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Options of the session cluster requested by test macros.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterOptions {
+    /// Keep cluster running after tests are finished
+    /// and reuse it on the next run if it's compatible.
+    pub keepalive: bool,
+    /// Default timeout of queries executed through admin console.
+    pub query_timeout: Option<Duration>,
+    /// Where the plugin is installed from.
+    pub install_from: PluginSource,
+    /// Retrying of cluster start, user creation and config apply.
+    /// If `None`, number of attempts is taken from `PICOTEST_RETRY_ATTEMPTS`.
+    pub retry_policy: Option<RetryPolicy>,
+    /// Where cluster instances are running.
+    /// If `None`, backend is taken from `PICOTEST_BACKEND`.
+    pub backend: Option<Backend>,
+    /// Function applied to the plugin topology before cluster is started.
+    pub topology_transform: Option<fn(&PluginTopology) -> PluginTopology>,
+    /// Instances write audit log, see [`Cluster::audit_events`].
+    pub enable_audit: bool,
+    /// pgproto connections are encrypted, see [`picotest_helpers::tls`].
+    pub pg_tls: Option<TlsConfig>,
+    /// iproto connections are encrypted, see [`picotest_helpers::tls`].
+    pub iproto_tls: Option<TlsConfig>,
+}
+
+/// Options of the session cluster, which can't be changed once it's started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SessionClusterOptions {
+    keepalive: bool,
+    install_from: PluginSource,
+}
+
+impl SessionClusterOptions {
+    fn of(options: &ClusterOptions) -> Self {
+        Self {
+            keepalive: options.keepalive || env_flag(ENV_PICOTEST_KEEPALIVE, false),
+            install_from: options.install_from,
+        }
     }
+}
+
+static SESSION_CLUSTER_OPTIONS: OnceLock<SessionClusterOptions> = OnceLock::new();
 
-    Ok(())
+/// Remembers `options` the session cluster is created with.
+pub fn record_session_cluster_options(options: &ClusterOptions) {
+    let _ = SESSION_CLUSTER_OPTIONS.set(SessionClusterOptions::of(options));
+}
+
+/// Panics if `options` requested by the test differ from the ones the
+/// session cluster is created with, as test would run against the cluster
+/// it hasn't asked for.
+fn ensure_session_cluster_options(options: &ClusterOptions) {
+    let Some(created) = SESSION_CLUSTER_OPTIONS.get() else {
+        return;
+    };
+    let requested = SessionClusterOptions::of(options);
+    if requested != *created {
+        panic!(
+            "Test requests session cluster with keepalive = {}, install_from = {:?}, \
+            but it's already started with keepalive = {}, install_from = {:?}. \
+            Move tests requesting different options into separate test binaries \
+            or enable `harness` feature of picotest, which starts cluster per group of tests",
+            requested.keepalive, requested.install_from, created.keepalive, created.install_from
+        );
+    }
+}
+
+/// Installs cassette if [`PICOTEST_CASSETTE`] is set.
+fn install_cassette(plugin_path: &Path) -> Option<&'static Cassette> {
+    let mode = var(PICOTEST_CASSETTE).ok()?;
+    let mode = mode.parse::<CassetteMode>().expect("invalid cassette mode");
+    let path = var(PICOTEST_CASSETTE_PATH)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| plugin_path.join(DEFAULT_CASSETTE_PATH));
+    let cassette = match mode {
+        CassetteMode::Record => Cassette::record(&path),
+        CassetteMode::Replay => Cassette::replay(&path),
+    }
+    .expect("Failed to open the cassette");
+    Some(cassette::install(cassette).expect("Failed to install the cassette"))
+}
+
+/// Creates cluster of the group of tests run by [`crate::scheduler`].
+#[cfg(feature = "harness")]
+///
+/// Topology is reduced to the `topology` profile of the group
+/// instead of the profile of tags selected by `PICOTEST_TAGS`.
+pub(crate) fn create_group_cluster(profile: &ClusterProfile, topology: TopologyProfile) -> Cluster {
+    let plugin_path = profile
+        .plugin_path
+        .map(PathBuf::from)
+        .unwrap_or_else(plugin_root_dir);
+    let plugin_topology = parse_topology(&plugin_topology_path(&plugin_path))
+        .ok()
+        .map(|plugin_topology| topology.apply(&plugin_topology));
+    let options = ClusterOptions {
+        keepalive: profile.keepalive,
+        install_from: profile
+            .install_from
+            .map(|source| source.parse().expect("invalid plugin source"))
+            .unwrap_or_default(),
+        topology_transform: profile.topology_transform.map(|(_, transform)| transform),
+        ..Default::default()
+    };
+
+    hooks::run_before_cluster_start();
+    let cluster = create_cluster(Some(plugin_path), plugin_topology, &options);
+    record_cassette_cluster(&cluster);
+    hooks::run_after_cluster_ready(&cluster);
+    cluster
+}
+
+/// Writes instances of the session `cluster` into the cassette being recorded.
+pub fn record_cassette_cluster(cluster: &Cluster) {
+    if let Some(cassette) = cassette::active() {
+        if cassette.mode() == CassetteMode::Record {
+            cassette.record_cluster(cluster);
+        }
+    }
 }
 
 /// Creates new instance of Picodata [`Cluster`].
 ///
+/// If `PICOTEST_ATTACH` environment variable is set, attaches to already
+/// running cluster instead. See [`Cluster::attach`].
+///
 /// ### Arguments
 /// - `plugin_path` - path to the plugin root directory.
 ///   If `None`, directory is identified automatically.
 /// - `plugin_topology` - instance of `PluginTopology`.
 ///   If `None`, topology is parsed from default path.
+/// - `options` - options requested by test macros.
 ///
 pub fn create_cluster(
     plugin_path: Option<PathBuf>,
     plugin_topology: Option<PluginTopology>,
+    options: &ClusterOptions,
 ) -> Cluster {
     // Look up plugin root directory automatically
     // unless explicitly specified.
     let plugin_path = plugin_path.unwrap_or_else(plugin_root_dir);
+    // Seed of the session is printed on startup, even if no test uses it yet.
+    session_seed();
+
+    let query_timeout = options.query_timeout.or_else(query_timeout_from_env);
+    let retry_policy = options.retry_policy.unwrap_or_else(retry_policy_from_env);
+
+    if let Some(cassette) = install_cassette(&plugin_path) {
+        if cassette.mode() == CassetteMode::Replay {
+            println!(
+                "{PICOTEST_CASSETTE} environment variable is set, replaying cassette '{}'",
+                cassette.path().display()
+            );
+            return Cluster::replay(plugin_path, cassette)
+                .expect("Failed to replay the cluster")
+                .query_timeout(query_timeout)
+                .retry_policy(retry_policy);
+        }
+    }
+
+    if let Ok(target) = var(ENV_PICOTEST_ATTACH) {
+        println!("{ENV_PICOTEST_ATTACH} environment variable is set, attaching to '{target}'");
+        // Clusters started by picotest are built by pike on start,
+        // only build of the attached one can be outdated.
+        let stale_build_policy = var(ENV_PICOTEST_STALE_BUILD)
+            .map(|v| {
+                v.parse::<StaleBuildPolicy>()
+                    .expect("invalid stale build policy")
+            })
+            .unwrap_or_default();
+        ensure_fresh_build(&plugin_path, build_profile(), stale_build_policy)
+            .expect("Plugin build is stale");
+        return Cluster::attach(plugin_path, &target)
+            .expect("Failed to attach to the cluster")
+            .query_timeout(query_timeout)
+            .retry_policy(retry_policy)
+            .pg_tls(options.pg_tls.clone())
+            .iproto_tls(options.iproto_tls.clone());
+    }
+
+    if let Ok(config_path) = var(PICOTEST_REMOTE_CONFIG) {
+        println!(
+            "{PICOTEST_REMOTE_CONFIG} environment variable is set, connecting to remote cluster"
+        );
+        return Cluster::remote(plugin_path, Path::new(&config_path))
+            .expect("Failed to connect to the remote cluster")
+            .query_timeout(query_timeout)
+            .retry_policy(retry_policy)
+            .pg_tls(options.pg_tls.clone())
+            .iproto_tls(options.iproto_tls.clone());
+    }
+
     // Use passed topology or go and parse original topology
-    // located in plugin root directory.
-    let plugin_topology = plugin_topology.map_or_else(
-        || parse_topology(&plugin_topology_path(&plugin_path)),
-        Result::Ok,
+    // located in plugin root directory. Parsed topology is reduced
+    // to the profile of tags selected by `PICOTEST_TAGS`.
+    let mut plugin_topology = plugin_topology.unwrap_or_else(|| {
+        let profile = PicotestConfig::load(&plugin_path)
+            .and_then(|config| TagFilter::from_env().session_profile(&config))
+            .expect("Failed to determine topology profile of selected tags");
+        profile.apply(&parse_topology(&plugin_topology_path(&plugin_path)).unwrap())
+    });
+    if let Some(topology_transform) = options.topology_transform {
+        plugin_topology = topology_transform.transform(&plugin_topology);
+    }
+    ensure_valid_topology(&plugin_topology);
+
+    let backend = options.backend.unwrap_or_else(backend_from_env);
+    let picodata_path = picodata_path_from_env(backend);
+
+    let wait_vshard_discovery = env_flag(ENV_WAIT_VSHARD_DISCOVERY, DEFAULT_WAIT_VSHARD_ENABLED);
+    let keepalive = options.keepalive || env_flag(ENV_PICOTEST_KEEPALIVE, false);
+
+    let sanitizer = Sanitizer::from_env().expect("Invalid sanitizer");
+    check_build_flags(sanitizer).expect("Plugin can't be built for picodata");
+    if let (Some(sanitizer), Backend::Local) = (sanitizer, backend) {
+        check_picodata(&picodata_path, sanitizer).expect("Picodata can't run sanitized plugin");
+    }
+
+    // Cluster kept alive isn't shut down by the tests, so its profiles aren't collected.
+    let coverage = CoverageConfig::from_env(&plugin_path).filter(|_| !keepalive);
+    if coverage.is_some() {
+        ensure_instrumented_build(&plugin_path, &plugin_profile_build_path(&plugin_path))
+            .expect("Failed to build the plugin with coverage instrumentation");
+    }
+
+    if !keepalive {
+        let cluster = Cluster::new(plugin_path.clone(), plugin_topology, picodata_path)
+            .expect("Failed to create the cluster")
+            .wait_vshard_discovery(wait_vshard_discovery)
+            .query_timeout(query_timeout)
+            .install_from(options.install_from)
+            .build_profile(build_profile())
+            .retry_policy(retry_policy)
+            .backend(backend)
+            .enable_audit(options.enable_audit)
+            .pg_tls(options.pg_tls.clone())
+            .iproto_tls(options.iproto_tls.clone())
+            .coverage(coverage)
+            .sanitizer(sanitizer);
+        if env_flag(ENV_PICOTEST_CLUSTER_CACHE, false) {
+            return cluster
+                .run_cached(&ClusterCache::from_env(&plugin_path))
+                .expect("Failed to start the cluster");
+        }
+        return cluster.run().expect("Failed to start the cluster");
+    }
+
+    // Plugin is built by pike on cluster start, but running
+    // cluster is reused only if it was started from the actual build.
+    build_plugin(&plugin_path, build_profile()).expect("Failed to build the plugin");
+
+    let state = KeepaliveState::new(
+        KEEPALIVE_DATA_DIR.into(),
+        &plugin_topology,
+        &plugin_profile_build_path(&plugin_path),
     );
+    if let Some(cluster) = reuse_kept_alive_cluster(&plugin_path, &state) {
+        return cluster
+            .query_timeout(query_timeout)
+            .retry_policy(retry_policy)
+            .pg_tls(options.pg_tls.clone())
+            .iproto_tls(options.iproto_tls.clone());
+    }
 
-    let picodata_path = var(ENV_PICODATA_PATH)
+    let cluster = Cluster::new(plugin_path.clone(), plugin_topology, picodata_path)
+        .expect("Failed to create the cluster")
+        .with_data_dir(state.data_dir.clone())
+        .wait_vshard_discovery(wait_vshard_discovery)
+        .daemon(true)
+        .keep_alive(true)
+        .query_timeout(query_timeout)
+        .install_from(options.install_from)
+        .build_profile(build_profile())
+        .retry_policy(retry_policy)
+        .backend(backend)
+        .enable_audit(options.enable_audit)
+        .pg_tls(options.pg_tls.clone())
+        .iproto_tls(options.iproto_tls.clone())
+        .sanitizer(sanitizer)
+        .run()
+        .expect("Failed to start the cluster");
+
+    state
+        .save(&plugin_path)
+        .expect("Failed to save keep-alive cluster state");
+
+    cluster
+}
+
+/// Options of the cluster started by [`crate::spawn_cluster`].
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    /// Path to the plugin root directory.
+    /// If `None`, directory is identified automatically.
+    pub plugin_path: Option<PathBuf>,
+    /// Topology of the cluster.
+    /// If `None`, topology of the plugin is used.
+    pub topology: Option<PluginTopology>,
+    /// Number added to the ports of the instances, so they don't intersect with
+    /// ports of other clusters. If `None`, it's chosen by the number of spawned clusters.
+    pub port_offset: Option<u16>,
+    /// Default timeout of queries executed through admin console.
+    pub query_timeout: Option<Duration>,
+    /// Where the plugin is installed from.
+    pub install_from: PluginSource,
+}
+
+/// Ports of the instances of every next spawned cluster are shifted by this number,
+/// so up to 100 instances of each cluster don't intersect with other clusters.
+pub const SPAWNED_CLUSTER_PORT_STEP: u16 = 100;
+
+/// Starts new cluster alongside the session one, see [`crate::spawn_cluster`].
+pub fn spawn_cluster(options: &SpawnOptions) -> Cluster {
+    static SPAWNED_CLUSTERS: AtomicU16 = AtomicU16::new(0);
+
+    let plugin_path = options.plugin_path.clone().unwrap_or_else(plugin_root_dir);
+    let plugin_topology = options.topology.clone().unwrap_or_else(|| {
+        parse_topology(&plugin_topology_path(&plugin_path)).expect("Failed to parse the topology")
+    });
+    ensure_valid_topology(&plugin_topology);
+
+    let port_offset = options.port_offset.unwrap_or_else(|| {
+        let number = SPAWNED_CLUSTERS.fetch_add(1, Ordering::SeqCst) + 1;
+        number
+            .checked_mul(SPAWNED_CLUSTER_PORT_STEP)
+            .expect("Too many clusters are spawned")
+    });
+    let backend = backend_from_env();
+    Cluster::new(
+        plugin_path,
+        plugin_topology,
+        picodata_path_from_env(backend),
+    )
+    .expect("Failed to create the cluster")
+    .port_offset(port_offset)
+    .wait_vshard_discovery(env_flag(
+        ENV_WAIT_VSHARD_DISCOVERY,
+        DEFAULT_WAIT_VSHARD_ENABLED,
+    ))
+    .query_timeout(options.query_timeout.or_else(query_timeout_from_env))
+    .install_from(options.install_from)
+    .retry_policy(retry_policy_from_env())
+    .backend(backend)
+    .run()
+    .expect("Failed to start the spawned cluster")
+}
+
+fn query_timeout_from_env() -> Option<Duration> {
+    var(ENV_PICOTEST_QUERY_TIMEOUT).ok().map(|value| {
+        let secs = value
+            .parse::<f64>()
+            .expect("invalid query timeout, expected number of seconds");
+        Duration::from_secs_f64(secs)
+    })
+}
+
+fn retry_policy_from_env() -> RetryPolicy {
+    var(ENV_PICOTEST_RETRY_ATTEMPTS)
+        .map(|value| {
+            let attempts = value
+                .parse::<u32>()
+                .expect("invalid number of retry attempts");
+            RetryPolicy::new(attempts)
+        })
+        .unwrap_or_default()
+}
+
+fn backend_from_env() -> Backend {
+    var(ENV_PICOTEST_BACKEND)
+        .map(|value| value.parse().expect("invalid cluster backend"))
+        .unwrap_or_default()
+}
+
+fn picodata_path_from_env(backend: Backend) -> PathBuf {
+    var(ENV_PICODATA_PATH)
         .map(PathBuf::from)
         .unwrap_or_else(|_| {
+            if backend == Backend::Container {
+                return PathBuf::from("picodata");
+            }
             println!(
                 "PICODATA_PATH environment variable is not set, \
                 using default picodata binary from PATH"
             );
             PathBuf::from("picodata")
-        });
+        })
+}
 
-    let wait_vshard_discovery = var(ENV_WAIT_VSHARD_DISCOVERY)
-        .map(|v| v.parse::<bool>().expect("invalid boolean"))
-        .unwrap_or_else(|e| match e {
-            VarError::NotPresent => DEFAULT_WAIT_VSHARD_ENABLED,
-            _ => panic!("failed to read {ENV_WAIT_VSHARD_DISCOVERY}: {e}"),
-        });
+/// Panics listing issues of the topology, so test fails before pike is involved.
+fn ensure_valid_topology(plugin_topology: &PluginTopology) {
+    let issues = topology::validate(plugin_topology);
+    if !issues.is_empty() {
+        let issues: Vec<String> = issues.iter().map(|issue| format!("  - {issue}")).collect();
+        panic!("Plugin topology is invalid:\n{}", issues.join("\n"));
+    }
+}
 
-    Cluster::new(plugin_path, plugin_topology.unwrap(), picodata_path)
-        .expect("Failed to create the cluster")
-        .wait_vshard_discovery(wait_vshard_discovery)
-        .run()
-        .expect("Failed to start the cluster")
+/// Attaches to the cluster kept alive by previous run if it's compatible
+/// with the `actual` state. Otherwise, stops the stale cluster.
+fn reuse_kept_alive_cluster(plugin_path: &Path, actual: &KeepaliveState) -> Option<Cluster> {
+    let saved = KeepaliveState::load(plugin_path)?;
+    let data_dir = plugin_path.join(&saved.data_dir);
+
+    if &saved == actual {
+        match Cluster::attach(plugin_path.to_path_buf(), &data_dir.to_string_lossy()) {
+            Ok(cluster) => {
+                let health = cluster.health();
+                if health.is_ready() {
+                    println!("Reusing kept alive cluster in '{}'", data_dir.display());
+                    return Some(cluster);
+                }
+                println!(
+                    "Kept alive cluster in '{}' is not ready, restarting:\n{health}",
+                    data_dir.display()
+                );
+            }
+            Err(err) => println!("Failed to attach to kept alive cluster: {err}"),
+        }
+    } else {
+        println!(
+            "Kept alive cluster in '{}' is stale, restarting",
+            data_dir.display()
+        );
+    }
+
+    if let Err(err) = stop_cluster(plugin_path, &saved.data_dir) {
+        println!("Failed to stop kept alive cluster: {err}");
+    }
+    let _ = fs::remove_dir_all(&data_dir);
+    KeepaliveState::remove(plugin_path);
+
+    None
+}
+
+/// Reads boolean flag from environment variable. Accepts `true`/`false` and `1`/`0`.
+fn env_flag(name: &str, default: bool) -> bool {
+    match var(name) {
+        Ok(value) => match value.as_str() {
+            "1" => true,
+            "0" => false,
+            value => value.parse::<bool>().expect("invalid boolean"),
+        },
+        Err(VarError::NotPresent) => default,
+        Err(e) => panic!("failed to read {name}: {e}"),
+    }
 }
 
 /// Provides topology specifically for running unit-tests.
@@ -206,10 +819,21 @@ pub fn get_or_create_unit_test_topology() -> &'static PluginTopology {
         let profile_path = plugin_profile_build_path(&plugin_root);
         let migrations_paths = find_migrations_directories(profile_path).unwrap();
         let mut context_vars_map = HashMap::new();
+        let mut plugins_migrations = Vec::new();
         for (plugin_name, migrations_path) in migrations_paths {
-            let plugin_migrations = parse_migrations(&migrations_path).unwrap();
+            let plugin_migrations = parse_migrations(&migrations_path).unwrap_or_else(|err| {
+                panic!("Failed to parse migrations of plugin '{plugin_name}': {err:#}")
+            });
+            for warning in plugin_migrations.warnings() {
+                println!("Migrations of plugin '{plugin_name}': {warning}");
+            }
             let ctx_vars = make_ddl_tier_overrides(&plugin_migrations, DEFAULT_TIER);
-            context_vars_map.insert(plugin_name, ctx_vars);
+            context_vars_map.insert(plugin_name.clone(), ctx_vars);
+            plugins_migrations.push((plugin_name, plugin_migrations));
+        }
+
+        if env_flag(ENV_PICOTEST_VERIFY_MIGRATIONS, false) {
+            verify_migrations(&plugin_root, &plugins_migrations, &context_vars_map);
         }
 
         let mut transformer = SingleNodeTopologyTransformer::default();
@@ -217,3 +841,298 @@ pub fn get_or_create_unit_test_topology() -> &'static PluginTopology {
         transformer.transform(&plugin_topology)
     })
 }
+
+/// Runs every migration of the plugins UP, DOWN and UP again on a scratch
+/// single-node cluster without plugins. Panics if DOWN doesn't cleanly reverse UP.
+///
+/// Scratch cluster is started before the session cluster, as both of them
+/// share directory of temporary cluster data, which is cleaned on cluster creation.
+fn verify_migrations(
+    plugin_path: &Path,
+    plugins_migrations: &[(String, Migrations)],
+    context_provider: &impl MigrationContextProvider,
+) {
+    println!("{ENV_PICOTEST_VERIFY_MIGRATIONS} environment variable is set, verifying migrations");
+
+    let topology = SingleNodeTopologyTransformer::default().transform(&PluginTopology::default());
+    let picodata_path = var(ENV_PICODATA_PATH)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("picodata"));
+    let cluster = Cluster::new(plugin_path.to_path_buf(), topology, picodata_path)
+        .expect("Failed to create the cluster")
+        .run()
+        .expect("Failed to start scratch cluster for migrations verification");
+
+    for (plugin_name, migrations) in plugins_migrations {
+        let context = context_provider.get_migration_context(plugin_name);
+        if let Err(err) = cluster.verify_migrations(migrations, &context) {
+            panic!("Migrations of plugin '{plugin_name}' are broken: {err:#}");
+        }
+    }
+}
+
+/// Lock on global cluster state shared by tests in the test binary.
+///
+/// Tests marked with `#[picotest(serial)]` hold it exclusively, while
+/// tests marked with `#[picotest(shared_lock)]` hold it shared.
+static TEST_LOCK: RwLock<()> = RwLock::new(());
+
+/// Acquires global test lock exclusively.
+///
+/// Lock isn't considered poisoned by a failed test, as test failure
+/// doesn't leave the lock itself in inconsistent state.
+pub fn lock_test_exclusive() -> RwLockWriteGuard<'static, ()> {
+    TEST_LOCK.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Acquires global test lock shared.
+pub fn lock_test_shared() -> RwLockReadGuard<'static, ()> {
+    TEST_LOCK.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns session cluster requested by `#[picotest]` test.
+///
+/// Called from the test body rather than resolved as a fixture,
+/// so cluster is started only when a test using it actually runs.
+/// Panics if test requests `keepalive` or `install_from` different
+/// from the ones the session cluster is started with.
+pub fn session_cluster(
+    plugin_path: Option<&str>,
+    keepalive: bool,
+    install_from: Option<&str>,
+    topology_transform: Option<fn(&PluginTopology) -> PluginTopology>,
+) -> &'static Cluster {
+    #[cfg(feature = "harness")]
+    if let Some(cluster) = crate::scheduler::group_cluster() {
+        return cluster;
+    }
+    let options = ClusterOptions {
+        keepalive,
+        install_from: install_from
+            .map(|source| source.parse().expect("invalid plugin source"))
+            .unwrap_or_default(),
+        topology_transform,
+        ..Default::default()
+    };
+    let cluster = crate::get_or_create_session_cluster_with(plugin_path, None, &options);
+    ensure_session_cluster_options(&options);
+    cluster
+}
+
+/// Returns `true` if tests requiring cluster are asked to be skipped
+/// through `PICOTEST_SKIP` environment variable.
+pub fn skip_requested() -> bool {
+    env_flag(ENV_PICOTEST_SKIP, false)
+}
+
+/// Prints a marker of the test skipped because of `PICOTEST_SKIP`.
+///
+/// Skip is decided at runtime, so test harness can't tell skipped test
+/// from the passed one and reports it as `ok`. Marker is printed to stderr
+/// directly, bypassing output capture, to be visible in the harness output.
+pub fn report_test_skipped(test_name: &str) {
+    let _ = writeln!(
+        io::stderr(),
+        "test {test_name} ... SKIPPED ({ENV_PICOTEST_SKIP} is set, reported as passed)"
+    );
+}
+
+/// Returns `true` if test tagged by `tag` is selected by `PICOTEST_TAGS` environment variable.
+pub fn tag_selected(tag: Option<&str>) -> bool {
+    static FILTER: OnceLock<TagFilter> = OnceLock::new();
+    FILTER.get_or_init(TagFilter::from_env).is_selected(tag)
+}
+
+/// Prints a notice about the test skipped because its tag isn't selected.
+pub fn report_test_filtered(test_name: &str) {
+    eprintln!("Test '{test_name}' is skipped, because its tag is not listed in {PICOTEST_TAGS}");
+}
+
+/// Returns `true` if the test `test_name` located in `source_file` is affected by
+/// files listed in `PICOTEST_CHANGED_FILES` environment variable, if it's set.
+pub fn impact_selected(plugin_path: Option<&str>, test_name: &str, source_file: &str) -> bool {
+    static SELECTION: OnceLock<Option<ImpactSelection>> = OnceLock::new();
+    let selection = SELECTION.get_or_init(|| {
+        let plugin_path = plugin_path
+            .map(PathBuf::from)
+            .unwrap_or_else(plugin_root_dir);
+        let selection = ImpactSelection::from_env(&plugin_path)
+            .expect("Failed to select tests affected by the changed files");
+        if let Some(file) = selection.as_ref().and_then(ImpactSelection::run_all_by) {
+            println!(
+                "Changed file '{file}' isn't attributed to plugin services, running all tests"
+            );
+        }
+        selection
+    });
+    selection
+        .as_ref()
+        .is_none_or(|selection| selection.is_selected(test_name, source_file))
+}
+
+/// Prints a notice about the test skipped because it isn't affected by the changes.
+pub fn report_test_not_impacted(test_name: &str) {
+    eprintln!(
+        "Test '{test_name}' is skipped, because it is not affected by files listed in {PICOTEST_CHANGED_FILES}"
+    );
+}
+
+/// Outcomes of tests listed in `#[depends_on]`, keyed by full names of the tests.
+static PREREQUISITES: Mutex<BTreeMap<&'static str, Arc<OnceLock<bool>>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Runs prerequisite test `test_name` unless it has already run.
+///
+/// Test runs once, either by itself or before the first of its dependents.
+/// Concurrent callers wait for it to finish.
+///
+/// ### Returns
+/// `true` if the test has passed and panic payload if it has failed in this call.
+fn run_prerequisite(test_name: &'static str, test: fn()) -> (bool, Option<Box<dyn Any + Send>>) {
+    let outcome = PREREQUISITES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(test_name)
+        .or_default()
+        .clone();
+
+    let mut payload = None;
+    let passed = *outcome.get_or_init(|| match panic::catch_unwind(test) {
+        Ok(()) => true,
+        Err(err) => {
+            payload = Some(err);
+            false
+        }
+    });
+    (passed, payload)
+}
+
+/// Runs the test, which other tests depend on.
+///
+/// Test fails if it has failed before one of its dependents as well.
+pub fn run_prerequisite_test(test_name: &'static str, test: fn()) {
+    match run_prerequisite(test_name, test) {
+        (_, Some(payload)) => panic::resume_unwind(payload),
+        (false, None) => panic!("Test '{test_name}' has failed before its dependent test"),
+        (true, None) => {}
+    }
+}
+
+/// Runs prerequisites of the test `test_name` listed in `#[depends_on]`,
+/// which haven't run yet.
+///
+/// ### Returns
+/// `false` if some prerequisite has failed, so the test must be skipped.
+pub fn prerequisites_passed(test_name: &str, prerequisites: &[(&'static str, fn())]) -> bool {
+    for &(prerequisite, test) in prerequisites {
+        if !run_prerequisite(prerequisite, test).0 {
+            eprintln!(
+                "Test '{test_name}' is skipped, because its prerequisite '{prerequisite}' has failed"
+            );
+            return false;
+        }
+    }
+    true
+}
+
+/// Starts streaming logs of the `cluster` instances for the duration
+/// of the test if `PICOTEST_STREAM_LOGS` environment variable is set.
+pub fn stream_logs(cluster: &Cluster) -> Option<LogStream> {
+    env_flag(PICOTEST_STREAM_LOGS, false).then(|| cluster.stream_logs(LogFilter::all()))
+}
+
+/// Starts tracking outcome of the test `test_name` if
+/// `PICOTEST_TRACK_FLAKY` environment variable is set.
+pub fn track_test(test_name: &str, cluster: &Cluster) -> Option<TestTracker<'static>> {
+    static HISTORY: OnceLock<TestHistory> = OnceLock::new();
+    env_flag(PICOTEST_TRACK_FLAKY, false).then(|| {
+        let history = HISTORY.get_or_init(|| TestHistory::from_env(&cluster.plugin_path));
+        TestTracker::start(history, test_name, cluster)
+    })
+}
+
+/// Starts recording footprint of the test `test_name` located in `source_file`
+/// if `PICOTEST_TRACK_IMPACT` environment variable is set.
+pub fn track_impact(
+    test_name: &str,
+    source_file: &str,
+    cluster: &Cluster,
+) -> Option<ImpactTracker<'static>> {
+    static MAP: OnceLock<ImpactMap> = OnceLock::new();
+    env_flag(PICOTEST_TRACK_IMPACT, false).then(|| {
+        let map = MAP.get_or_init(|| ImpactMap::from_env(&cluster.plugin_path));
+        ImpactTracker::start(map, test_name, source_file)
+    })
+}
+
+/// Starts watchdog of the test `test_name`, which prints fibers of the
+/// `cluster` instances if the test hangs, see [`picotest_helpers::watchdog`].
+pub fn watch_test(test_name: &str, cluster: &'static Cluster) -> Option<Watchdog> {
+    static CONFIG: OnceLock<WatchdogConfig> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| WatchdogConfig::from_env().expect("invalid test timeout"));
+    Watchdog::start(test_name, *config, || cluster.fiber_dump())
+}
+
+/// Appends outcome of the tracked test, which has `failed` or not, to the test history.
+/// Failure to write the history doesn't fail the test.
+pub fn record_test_outcome(tracker: Option<TestTracker>, failed: bool) {
+    if let Some(Err(err)) = tracker.map(|tracker| tracker.finish(failed)) {
+        eprintln!("Failed to record test outcome: {err:#}");
+    }
+}
+
+/// Appends footprint of the test, which has `failed` or not, to the impact map.
+/// Failure to write the map doesn't fail the test.
+pub fn record_test_impact(tracker: Option<ImpactTracker>, failed: bool) {
+    if let Some(Err(err)) = tracker.map(|tracker| tracker.finish(failed)) {
+        eprintln!("Failed to record test footprint: {err:#}");
+    }
+}
+
+/// Runs `before_each` hooks of the test `test_name`.
+pub fn run_before_each_hooks(test_name: &str, cluster: &Cluster) {
+    hooks::run_before_each(&TestContext {
+        test_name,
+        cluster,
+        failed: false,
+    });
+}
+
+/// Runs `after_each` hooks of the test `test_name`, which has `failed` or not.
+pub fn run_after_each_hooks(test_name: &str, cluster: &Cluster, failed: bool) {
+    hooks::run_after_each(&TestContext {
+        test_name,
+        cluster,
+        failed,
+    });
+}
+
+/// Dumps state of the `cluster` after the test `test_name` has failed,
+/// unless `PICOTEST_DUMP_STATE` environment variable disables it.
+pub fn dump_test_state(test_name: &str, cluster: &Cluster) {
+    if !env_flag(PICOTEST_DUMP_STATE, true) {
+        return;
+    }
+    let dump_dir = test_artifacts_dir(&cluster.plugin_path, test_name);
+    match cluster.dump_state(&dump_dir) {
+        Ok(()) => eprintln!(
+            "State of the cluster is dumped into '{}'",
+            dump_dir.display()
+        ),
+        Err(err) => eprintln!("Failed to dump state of the cluster: {err:#}"),
+    }
+}
+
+/// Prints commands attaching to the instances of the `cluster` after
+/// the test has failed and pauses it, see [`picotest_helpers::interactive`].
+pub fn inspect_failed_test(cluster: &Cluster) {
+    cluster.inspect_failure();
+}
+
+/// Prints details required to reproduce failed test.
+pub fn report_test_failure() {
+    eprintln!(
+        "Rerun with {PICOTEST_SEED}={} to reproduce random values of the test",
+        session_seed()
+    );
+}
@@ -1,38 +1,239 @@
 use dtor::dtor;
+pub use internal::SpawnOptions;
+#[cfg(feature = "unit_runner")]
+pub use picotest_helpers::mock;
 pub use picotest_helpers::{
-    topology::PluginTopology, Cluster, PICOTEST_USER, PICOTEST_USER_PASSWORD,
+    backup, catalog, coverage, datagen, export_failpoints, fail_point, failpoint, governor, impact,
+    interactive, lua_modules, manifest, migration::DEFAULT_MIGRATIONS_TIMEOUT, perf,
+    plugin_config::ConfigScope, plugin_config::DEFAULT_APPLY_CONFIG_TIMEOUT, progress,
+    rpc_batch::RpcRequest, sanitizer, seed::PicotestRng, tls, topology::PluginTopology,
+    transaction, unit, users, watchdog, Cluster, PicotestInstance, PICOTEST_USER,
+    PICOTEST_USER_PASSWORD,
 };
 pub use picotest_macros::*;
+#[cfg(not(feature = "legacy_reexports"))]
+use rstest::fixture;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(feature = "legacy_reexports"))]
+use std::{path::PathBuf, sync::OnceLock};
+
+// Deprecated re-exports of rstest and std items, which clash with items of
+// the tests importing `picotest::*`. Use `picotest::prelude` instead.
+// Kept by default until the next major version.
+#[cfg(feature = "legacy_reexports")]
 pub use rstest::*;
+#[cfg(feature = "legacy_reexports")]
 pub use std::{panic, path::PathBuf, sync::OnceLock, time::Duration};
 
+pub mod hooks;
 pub mod internal;
+#[cfg(feature = "harness")]
+pub mod scheduler;
+
+/// Items commonly used by tests, imported by `use picotest::prelude::*`.
+///
+/// ### Examples:
+/// ```rust,ignore
+/// use picotest::prelude::*;
+///
+/// #[picotest]
+/// fn test_users_table() {
+///     cluster.run_sql("SELECT * FROM users").unwrap();
+/// }
+/// ```
+pub mod prelude {
+    pub use crate::{cluster, rng, PluginConfigMap};
+    pub use picotest_helpers::{
+        lua_modules::LuaModule, migration::DEFAULT_MIGRATIONS_TIMEOUT, plugin_config::ConfigScope,
+        plugin_config::DEFAULT_APPLY_CONFIG_TIMEOUT, rpc_batch::RpcRequest, seed::PicotestRng,
+        topology::PluginTopology, Cluster, PicotestInstance,
+    };
+    pub use picotest_macros::{picotest, picotest_unit, rpc_client};
+}
+
+/// Paths used by code generated by picotest macros.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "harness")]
+    pub use ctor;
+    pub use rstest::rstest;
+}
 
 pub static SESSION_CLUSTER: OnceLock<Cluster> = OnceLock::new();
 
 pub type PluginConfigMap = picotest_helpers::PluginConfigMap;
 
 #[fixture]
-pub fn cluster(#[default(None)] plugin_path: Option<&str>) -> &'static Cluster {
-    get_or_create_session_cluster(plugin_path, None)
+pub fn cluster(
+    #[default(None)] plugin_path: Option<&str>,
+    #[default(false)] keepalive: bool,
+    #[default(None)] install_from: Option<&str>,
+) -> &'static Cluster {
+    internal::session_cluster(plugin_path, keepalive, install_from, None)
+}
+
+/// Random generator seeded from the session seed and the test name.
+///
+/// Seed of the session is printed on startup and can be set
+/// through `PICOTEST_SEED` environment variable to reproduce the run.
+#[fixture]
+pub fn rng() -> PicotestRng {
+    picotest_helpers::seed::test_rng()
+}
+
+/// Compiles FFI entry point of `#[picotest_unit]` test into the plugin library.
+#[cfg(feature = "unit_runner")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __unit_test_entry {
+    ($($item:tt)*) => {
+        $($item)*
+    };
+}
+
+/// Compiles FFI entry point of `#[picotest_unit]` test into the plugin library.
+#[cfg(not(feature = "unit_runner"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __unit_test_entry {
+    ($($item:tt)*) => {
+        ::std::compile_error!(
+            "#[picotest_unit] tests require `unit_runner` feature of picotest, enable it in the dependency of the crate declaring them"
+        );
+    };
 }
 
 pub fn get_or_create_session_cluster(
     plugin_path: Option<&str>,
     plugin_topology: Option<&PluginTopology>,
+) -> &'static Cluster {
+    get_or_create_session_cluster_with(plugin_path, plugin_topology, &Default::default())
+}
+
+/// Same as [`get_or_create_session_cluster`], but cluster is created with `options`.
+///
+/// Options are taken into account only on first call, when cluster is created.
+pub fn get_or_create_session_cluster_with(
+    plugin_path: Option<&str>,
+    plugin_topology: Option<&PluginTopology>,
+    options: &internal::ClusterOptions,
 ) -> &'static Cluster {
     SESSION_CLUSTER.get_or_init(|| {
-        env_logger::init();
+        // Logger is already initialized if tests are run by the custom harness.
+        let _ = env_logger::try_init();
         let plugin_path = plugin_path.map(PathBuf::from);
         let plugin_topology = plugin_topology.cloned();
 
-        internal::create_cluster(plugin_path, plugin_topology)
+        hooks::run_before_cluster_start();
+        let cluster = internal::create_cluster(plugin_path, plugin_topology, options);
+        internal::record_session_cluster_options(options);
+        internal::record_cassette_cluster(&cluster);
+        hooks::run_after_cluster_ready(&cluster);
+        cluster
     })
 }
 
+/// Starts new cluster alongside the session one, e.g. to test plugin talking
+/// to another picodata cluster: replication target, federation, backup restore.
+///
+/// Cluster has its own data directory and listens on ports shifted by
+/// [`SpawnOptions::port_offset`]. It's stopped when dropped.
+///
+/// ### Examples:
+/// ```rust,ignore
+/// #[picotest]
+/// fn test_replication_to_another_cluster() {
+///     let target = picotest::spawn_cluster(&SpawnOptions::default());
+///     let target_main = target.main();
+///     let target_address = format!("{}:{}", target_main.host(), target_main.bin_port);
+///     // Configure plugin of the session cluster to replicate into `target_address`.
+///     target.run_sql("SELECT * FROM replicated").unwrap();
+/// }
+/// ```
+pub fn spawn_cluster(options: &SpawnOptions) -> Cluster {
+    internal::spawn_cluster(options)
+}
+
+/// Runs tests of the target built with `harness = false` by the custom
+/// harness of picotest, see [`scheduler`].
+///
+/// Harness owns clusters of the tests: they're started lazily by the first
+/// test using them and stopped before the process exits, so it doesn't rely
+/// on destructors run at exit. It accepts filters and common flags of libtest.
+///
+/// Requires `harness` feature, which registers tests in the harness.
+///
+/// ### Examples:
+/// ```rust,ignore
+/// // tests/integration.rs with `harness = false` in Cargo.toml
+/// mod replication;
+/// mod smoke;
+///
+/// picotest::main!();
+/// ```
+#[cfg(feature = "harness")]
+#[macro_export]
+macro_rules! main {
+    () => {
+        fn main() {
+            $crate::scheduler::main();
+        }
+    };
+}
+
+/// Runs tests of the target built with `harness = false` by the custom
+/// harness of picotest. Requires `harness` feature, which registers tests
+/// in the harness.
+#[cfg(not(feature = "harness"))]
+#[macro_export]
+macro_rules! main {
+    () => {
+        ::std::compile_error!(
+            "picotest::main! requires `harness` feature of picotest, without it tests aren't registered in the harness"
+        );
+
+        fn main() {}
+    };
+}
+
+/// Compiles registration of `#[picotest]` test in the custom harness.
+#[cfg(feature = "harness")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __harness_registration {
+    ($($item:tt)*) => {
+        $($item)*
+    };
+}
+
+/// Compiles registration of `#[picotest]` test in the custom harness.
+#[cfg(not(feature = "harness"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __harness_registration {
+    ($($item:tt)*) => {};
+}
+
 #[dtor]
 unsafe fn tear_down() {
+    stop_session_cluster();
+}
+
+/// Stops the session cluster once, either by the harness or on exit.
+pub(crate) fn stop_session_cluster() {
+    static STOPPED: AtomicBool = AtomicBool::new(false);
+    if STOPPED.swap(true, Ordering::SeqCst) {
+        return;
+    }
     if let Some(cluster) = SESSION_CLUSTER.get() {
-        cluster.stop().expect("Failed to stop the cluster");
+        // Attached or kept alive cluster outlives the tests,
+        // so only libraries loaded by unit tests are released.
+        if cluster.is_kept_alive() {
+            if internal::unit_libraries_loaded() {
+                let _ = cluster.run_lua(internal::lua_ffi_unload_unit_libraries());
+            }
+            return;
+        }
+        cluster.shutdown().expect("Failed to stop the cluster");
     }
 }
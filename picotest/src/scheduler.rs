@@ -0,0 +1,218 @@
+//! Custom test harness running `#[picotest]` tests grouped by their cluster.
+//!
+//! With the standard harness all tests of the binary share the session cluster
+//! started for the first of them and stopped by destructor at exit. Test target
+//! built with `harness = false` runs tests by [`main`] declared by
+//! [`picotest::main!`](crate::main!) instead: tests are grouped by the cluster
+//! they require (tag topology profile, topology transform, plugin path and
+//! source), groups are run one after another, each on its own cluster stopped
+//! before the next group starts, and tests of the group are run in parallel.
+//!
+//! ```toml
+//! [[test]]
+//! name = "integration"
+//! harness = false
+//! ```
+//!
+//! Tests are registered in the harness only with `harness` feature of picotest.
+//!
+//! **Note:** only `#[picotest]` tests without arguments are run by the
+//! harness, i.e. tests with rstest cases, values, fixtures or `engines`,
+//! tests wrapped by `#[tokio::test]` and `#[picotest_unit]` tests require the
+//! standard harness. Harness warns about `#[picotest]` tests it skips.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! // tests/integration.rs
+//! mod smoke;
+//! mod replication;
+//!
+//! picotest::main!();
+//! ```
+
+use crate::internal;
+use picotest_helpers::scheduler::{plan, run_group, HarnessArgs, Outcome, USAGE};
+pub use picotest_helpers::scheduler::{ClusterProfile, ScheduledTest, TopologyTransformFn};
+use picotest_helpers::tags::{PicotestConfig, TopologyProfile};
+use picotest_helpers::Cluster;
+use std::io::{self, Write};
+use std::process;
+use std::sync::{Mutex, OnceLock, PoisonError, RwLock};
+use std::time::Instant;
+
+/// Tests registered by `#[picotest]` on startup of the test binary.
+static TESTS: Mutex<Vec<ScheduledTest>> = Mutex::new(Vec::new());
+
+/// Names of `#[picotest]` tests, which the harness can't run.
+static UNSCHEDULED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Group of tests being run.
+static GROUP: RwLock<Option<&'static GroupCluster>> = RwLock::new(None);
+
+/// Cluster of the group, which is started by the first test using it.
+struct GroupCluster {
+    profile: ClusterProfile,
+    topology: TopologyProfile,
+    cluster: OnceLock<Cluster>,
+}
+
+/// Registers test in the harness, called by code generated by `#[picotest]`.
+#[doc(hidden)]
+pub fn register(test: ScheduledTest) {
+    TESTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(test);
+}
+
+/// Registers test the harness can't run, called by code generated by `#[picotest]`.
+#[doc(hidden)]
+pub fn register_unscheduled(name: &'static str) {
+    UNSCHEDULED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(name);
+}
+
+/// Returns cluster of the group being run, if tests are run by [`main`].
+pub(crate) fn group_cluster() -> Option<&'static Cluster> {
+    let group = (*GROUP.read().unwrap_or_else(PoisonError::into_inner))?;
+    Some(
+        group
+            .cluster
+            .get_or_init(|| internal::create_group_cluster(&group.profile, group.topology)),
+    )
+}
+
+fn set_group(group: Option<&'static GroupCluster>) {
+    *GROUP.write().unwrap_or_else(PoisonError::into_inner) = group;
+}
+
+/// Stops cluster of the finished group. Cluster kept alive outlives the
+/// tests only if its group is the last one, as the next cluster would
+/// conflict with it otherwise.
+fn stop_group_cluster(cluster: &Cluster, is_last: bool) {
+    if cluster.is_attached() || (cluster.is_kept_alive() && is_last) {
+        return;
+    }
+    if let Err(err) = cluster.shutdown() {
+        eprintln!("Failed to stop the cluster: {err:#}");
+    }
+}
+
+/// Runs registered tests, see [module docs](self), and exits
+/// the process with failure if some test has failed.
+///
+/// All clusters started by the tests, except the kept alive one,
+/// are stopped before the function returns or exits the process.
+pub fn main() {
+    let _ = env_logger::try_init();
+    let args = HarnessArgs::from_env().unwrap_or_else(|err| {
+        eprintln!("error: {err:#}\n\n{USAGE}");
+        process::exit(1);
+    });
+    if args.help {
+        print!("{USAGE}");
+        return;
+    }
+    let tests = TESTS.lock().unwrap_or_else(PoisonError::into_inner).clone();
+    let config = PicotestConfig::load(&internal::plugin_root_dir())
+        .expect("Failed to load picotest configuration");
+
+    let groups = plan(&tests, &args, &config);
+    let selected: usize = groups.iter().map(|group| group.tests.len()).sum();
+    if args.list {
+        let listed: Vec<_> = groups
+            .iter()
+            .flat_map(|group| &group.tests)
+            .filter(|test| !args.ignored || test.ignored)
+            .collect();
+        for test in &listed {
+            println!("{}: test", test.name);
+        }
+        println!("\n{} tests, 0 benchmarks", listed.len());
+        return;
+    }
+
+    let mut unscheduled = UNSCHEDULED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    unscheduled.sort_unstable();
+    for name in unscheduled {
+        eprintln!(
+            "warning: test {name} is skipped: tests with arguments, rstest cases, \
+            `engines` or #[tokio::test] require the standard harness"
+        );
+    }
+
+    let started = Instant::now();
+    println!(
+        "\nrunning {selected} tests in {} cluster group(s)",
+        groups.len()
+    );
+    let (mut passed, mut ignored, mut failures) = (0, 0, Vec::new());
+    for (index, group) in groups.iter().enumerate() {
+        if !args.quiet {
+            println!(
+                "\ngroup {}/{} of {} tests, {}",
+                index + 1,
+                groups.len(),
+                group.tests.len(),
+                group.key
+            );
+        }
+        // Group is leaked like the session cluster, as tests take it for `'static`.
+        let group_cluster: &'static GroupCluster = Box::leak(Box::new(GroupCluster {
+            profile: group.tests[0].profile,
+            topology: group.key.topology,
+            cluster: OnceLock::new(),
+        }));
+        set_group(Some(group_cluster));
+        let outcomes = run_group(&group.tests, &args, |test, outcome| {
+            if args.quiet {
+                print!("{}", outcome.as_char());
+                let _ = io::stdout().flush();
+            } else {
+                println!("test {} ... {outcome}", test.name);
+            }
+        });
+        set_group(None);
+        if let Some(cluster) = group_cluster.cluster.get() {
+            stop_group_cluster(cluster, index + 1 == groups.len());
+        }
+
+        for (test, outcome) in group.tests.iter().zip(outcomes) {
+            match outcome {
+                Outcome::Passed => passed += 1,
+                Outcome::Ignored => ignored += 1,
+                Outcome::Failed => failures.push(test.name),
+            }
+        }
+    }
+
+    // Clusters are stopped before exit explicitly, as `process::exit`
+    // doesn't guarantee that destructors of the session are run.
+    crate::stop_session_cluster();
+    if args.quiet {
+        println!();
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for name in &failures {
+            println!("    {name}");
+        }
+    }
+    println!(
+        "\ntest result: {}. {passed} passed; {} failed; {ignored} ignored; \
+        0 measured; {} filtered out; finished in {:.2}s\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        failures.len(),
+        tests.len() - selected,
+        started.elapsed().as_secs_f64()
+    );
+    if !failures.is_empty() {
+        process::exit(101);
+    }
+}
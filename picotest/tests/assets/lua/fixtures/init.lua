@@ -0,0 +1,11 @@
+local M = {}
+
+function M.sum(...)
+    local result = 0
+    for _, value in ipairs({ ... }) do
+        result = result + value
+    end
+    return result
+end
+
+return M
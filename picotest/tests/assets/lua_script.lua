@@ -0,0 +1,3 @@
+local fixtures = require("fixtures")
+local greetings = require("fixtures.greetings")
+return fixtures.sum(1, 2, 3), greetings.greet("Dodo")
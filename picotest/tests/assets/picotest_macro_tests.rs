@@ -5,7 +5,7 @@
 //!
 
 use rstest::rstest;
-use picotest::*;
+use picotest::prelude::*;
 
 #[picotest]
 fn test_integration_test_inside_plugin() {
@@ -19,6 +19,26 @@ pub mod should_success {
         println!("Hello from test_should_success");
     }
 
+    #[picotest::picotest_unit]
+    #[serial]
+    fn b_test_should_success_after_nested_panic() {
+        let nested = std::panic::catch_unwind(|| panic!("nested panic"));
+        assert!(nested.is_err());
+        println!("Hello from test_should_success");
+    }
+
+    #[picotest::picotest_unit]
+    #[serial]
+    fn c_test_should_success_with_context() {
+        let context = picotest::unit::context();
+        assert_eq!(context.tier, "default");
+        assert!(!context.instance_name.is_empty());
+        assert_ne!(context.bin_port, 0);
+        assert!(context.data_dir.is_dir());
+        assert_eq!(context.plugin_version, env!("CARGO_PKG_VERSION"));
+        println!("Hello from test_should_success");
+    }
+
     #[should_panic]
     #[picotest::picotest_unit]
     #[serial]
@@ -32,4 +52,20 @@ pub mod should_fail {
     fn test_should_fail() {
         panic!("Hello from test_should_fail");
     }
+
+    #[picotest::picotest_unit]
+    fn test_should_fail_after_nested_panic() {
+        let _ = std::panic::catch_unwind(|| panic!("nested panic"));
+        panic!("Hello from test_should_fail");
+    }
+}
+
+pub mod embedded_runtime {
+    #[picotest::picotest_unit(runtime = "embedded")]
+    fn test_should_success_in_embedded_runtime() {
+        let context = picotest::unit::context();
+        assert!(context.instance_name.is_empty());
+        assert!(context.data_dir.is_dir());
+        println!("Hello from test_embedded_runtime");
+    }
 }
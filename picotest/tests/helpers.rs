@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 use constcat::concat;
-use picotest_helpers::run_pike;
+use picotest_helpers::pike_command::run_pike_captured;
 use rstest::fixture;
 use std::fs;
 use std::io::{BufRead, BufReader, Error, Read, Write};
@@ -54,14 +54,15 @@ pub fn create_test_plugin(remove_if_exists: bool) -> TestPlugin {
 
     fs::create_dir_all(TMP_DIR).expect("Failed to create directory for pike plugin");
 
-    let pike_process = run_pike(
+    // Output is ignored as plugin may already exist
+    // and the files below are checked anyway.
+    let _ = run_pike_captured(
         vec!["plugin", "new", PLUGIN_NAME, "--workspace", "--without-git"],
         TMP_DIR,
+        Some(PROCESS_WAIT_TIMEOUT),
     )
     .expect("Failed to generate plugin boilerplate code");
 
-    let _ = wait_for_process_termination(pike_process, PROCESS_WAIT_TIMEOUT);
-
     assert!(fs::metadata(concat!(PLUGIN_DIR, "/Cargo.toml")).is_ok());
     assert!(fs::metadata(concat!(PLUGIN_DIR, "/topology.toml")).is_ok());
 
@@ -76,8 +77,9 @@ pub fn create_test_plugin(remove_if_exists: bool) -> TestPlugin {
 
     // Add necessary crates to the test plugin dependencies.
     // This is mandatory for running tests of macros inside plugin workspace.
+    // Plugin declares unit tests, so it needs `unit_runner` feature.
     let crates_to_add = [
-        CargoCrate::Path(env!("CARGO_MANIFEST_DIR")),
+        CargoCrate::Path(env!("CARGO_MANIFEST_DIR"), &["unit_runner"]),
         CargoCrate::Name("rstest"),
         CargoCrate::Name("serial_test"),
     ];
@@ -298,8 +300,8 @@ fn wait_for_process_termination(mut child: Child, timeout: Duration) -> ExitStat
 
 #[derive(Debug)]
 enum CargoCrate {
-    /// Path on the filesystem
-    Path(&'static str),
+    /// Path on the filesystem and features to enable
+    Path(&'static str, &'static [&'static str]),
     /// Name of the package on crates.io
     Name(&'static str),
 }
@@ -315,7 +317,13 @@ fn add_crate_to_test_plugin(cc: &CargoCrate, plugin_path: &str) -> Result<Child,
     let cmd = cmd.arg("add").arg("--quiet");
 
     let cmd = match cc {
-        CargoCrate::Path(path) => cmd.arg("--path").arg(path),
+        CargoCrate::Path(path, features) => {
+            let cmd = cmd.arg("--path").arg(path);
+            if !features.is_empty() {
+                cmd.arg("--features").arg(features.join(","));
+            }
+            cmd
+        }
         CargoCrate::Name(name) => cmd.arg(name),
     };
 
@@ -327,7 +335,7 @@ fn delete_crate_from_dev(cc: &CargoCrate, plugin_path: &str) -> Result<Child, Er
     let cmd = cmd.arg("remove").arg("--quiet").arg("--dev");
 
     let cmd = match cc {
-        CargoCrate::Path(path) => cmd.arg("--path").arg(path),
+        CargoCrate::Path(path, _) => cmd.arg("--path").arg(path),
         CargoCrate::Name(name) => cmd.arg(name),
     };
 
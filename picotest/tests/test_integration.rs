@@ -2,17 +2,34 @@ mod helpers;
 
 use ctor::ctor;
 use helpers::{plugin, TestPlugin};
-use picotest::*;
-use picotest_helpers::{LUA_OUTPUT_HEADER, OUTPUT_FOOTER};
+use picotest::prelude::*;
+use picotest::SpawnOptions;
+use picotest_helpers::failure::{ErrorClass, ErrorMatcher};
+use picotest_helpers::watch::TableEvent;
+use picotest_helpers::{LUA_OUTPUT_HEADER, OUTPUT_FOOTER, PICOTEST_USER_IPROTO};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use std::{collections::HashMap, panic, sync::OnceLock, thread};
 use uuid::Uuid;
 
 static GLOBAL_CLUSTER_UUID: OnceLock<Uuid> = OnceLock::new();
 
+static READY_CLUSTER_UUID: OnceLock<Uuid> = OnceLock::new();
+static STARTED_TESTS: AtomicUsize = AtomicUsize::new(0);
+
 #[ctor]
 unsafe fn init_plugin() {
     plugin();
+    picotest::hooks::register(picotest::hooks::Hooks {
+        after_cluster_ready: Some(|cluster| {
+            READY_CLUSTER_UUID.set(cluster.uuid).unwrap();
+        }),
+        before_each: Some(|_| {
+            STARTED_TESTS.fetch_add(1, Ordering::SeqCst);
+        }),
+        ..Default::default()
+    });
 }
 
 #[picotest(path = "../tmp/test_plugin")]
@@ -56,6 +73,74 @@ fn test_apply_config(plugin: &TestPlugin) {
     assert!(service_properties.contains(must_be_overriden));
 }
 
+#[picotest(path = "../tmp/test_plugin")]
+fn test_apply_config_and_wait(plugin: &TestPlugin) {
+    let expected_value = serde_norway::to_value("applied_and_acknowledged").unwrap();
+    let service_config = HashMap::from([("value".to_string(), expected_value.clone())]);
+    let plugin_config = HashMap::from([(plugin.service_name.clone(), service_config)]);
+
+    let effective_config = cluster
+        .apply_config_and_wait(&plugin.name, plugin_config, DEFAULT_APPLY_CONFIG_TIMEOUT)
+        .expect("Failed to apply test plugin configuration");
+
+    assert_eq!(
+        effective_config[&plugin.service_name].get("value"),
+        Some(&expected_value)
+    );
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_apply_config_on_tier(plugin: &TestPlugin) {
+    let service_config = HashMap::from([(
+        "value".to_string(),
+        serde_norway::to_value("applied_on_tier").unwrap(),
+    )]);
+    let plugin_config = HashMap::from([
+        (plugin.service_name.clone(), service_config.clone()),
+        ("not_deployed_service".to_string(), service_config),
+    ]);
+
+    let applied = cluster
+        .apply_config_on_tier("default", plugin_config.clone())
+        .expect("Failed to apply config on tier");
+    assert_eq!(applied.keys().collect::<Vec<_>>(), [&plugin.service_name]);
+
+    let scope = ConfigScope::Services(vec!["not_deployed_service".to_string()]);
+    assert!(cluster.apply_config_scoped(plugin_config, &scope).is_err());
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_plugin_manifest(plugin: &TestPlugin) {
+    let manifest = cluster.manifest().expect("Failed to load plugin manifest");
+    assert_eq!(manifest.name, plugin.name);
+    assert!(manifest.service(&plugin.service_name).is_some());
+
+    let default_config = manifest.default_config().unwrap();
+    assert!(default_config.contains_key(&plugin.service_name));
+    assert!(manifest.migration_paths().iter().all(|path| path.is_file()));
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TestServiceConfig {
+    value: String,
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_typed_service_config(plugin: &TestPlugin) {
+    let config = TestServiceConfig {
+        value: "typed_service_config".to_string(),
+    };
+
+    cluster
+        .apply_service_config(&plugin.service_name, &config)
+        .expect("Failed to apply test service configuration");
+
+    let actual: TestServiceConfig = cluster
+        .get_service_config(&plugin.service_name)
+        .expect("Failed to read test service configuration");
+    assert_eq!(actual, config);
+}
+
 #[picotest(path = "../tmp/test_plugin")]
 fn test_get_instances() {
     let cluster_uuid = GLOBAL_CLUSTER_UUID.get_or_init(|| cluster.uuid);
@@ -65,6 +150,260 @@ fn test_get_instances() {
     assert_eq!(cluster.main().pg_port, 5433)
 }
 
+#[picotest(path = "../tmp/test_plugin")]
+fn test_instance_selectors() {
+    let main = cluster.main();
+    assert_eq!(
+        cluster.instance(&main.instance_name).bin_port,
+        main.bin_port
+    );
+    assert_eq!(cluster.instances_in_tier("default").len(), 4);
+
+    let replicaset = cluster
+        .catalog()
+        .instance(&main.instance_name)
+        .expect("Failed to query replicaset of the main instance")
+        .expect("Main instance should be in the catalog")
+        .replicaset_name;
+
+    let master = cluster
+        .master_of(&replicaset)
+        .expect("Failed to get replicaset master");
+    let replica = cluster
+        .any_replica_of(&replicaset)
+        .expect("Failed to get replicaset replica");
+
+    assert_ne!(master.instance_name, replica.instance_name);
+    assert!(!master.box_info().unwrap().ro);
+    assert!(replica.box_info().unwrap().ro);
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_instance_lookup_maps() {
+    let instances = cluster.instances();
+    assert!(instances
+        .windows(2)
+        .all(|pair| (&pair[0].tier, pair[0].instance_id) < (&pair[1].tier, pair[1].instance_id)));
+
+    let by_name = cluster.by_name();
+    assert_eq!(by_name.len(), instances.len());
+    assert_eq!(
+        by_name[cluster.main().instance_name.as_str()].bin_port,
+        cluster.main().bin_port
+    );
+
+    let by_raft_id = cluster.by_raft_id().unwrap();
+    assert_eq!(by_raft_id.len(), instances.len());
+    for instance in instances {
+        assert_eq!(
+            by_raft_id[&u64::from(instance.instance_id)].instance_name,
+            instance.instance_name
+        );
+    }
+
+    let by_replicaset = cluster.by_replicaset().unwrap();
+    assert_eq!(
+        by_replicaset.values().map(Vec::len).sum::<usize>(),
+        instances.len()
+    );
+    for (replicaset, members) in &by_replicaset {
+        let master = cluster.master_of(replicaset).unwrap();
+        assert!(members
+            .iter()
+            .any(|member| member.instance_name == master.instance_name));
+    }
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_catalog(plugin: &TestPlugin) {
+    let catalog = cluster.catalog();
+
+    let instances = catalog.instances().unwrap();
+    assert_eq!(instances.len(), cluster.instances().len());
+    assert!(catalog.instance("missing_instance").unwrap().is_none());
+
+    let tiers = catalog.tiers().unwrap();
+    assert!(tiers.iter().any(|tier| tier.name == "default"));
+    let replicasets = catalog.replicasets().unwrap();
+    assert!(replicasets.iter().all(|replicaset| instances
+        .iter()
+        .any(|instance| instance.name == replicaset.current_master_name)));
+
+    let installed = catalog
+        .plugin(&plugin.name)
+        .unwrap()
+        .expect("Test plugin should be installed");
+    assert!(installed.enabled);
+    assert!(catalog
+        .services()
+        .unwrap()
+        .iter()
+        .any(|service| service.plugin_name == plugin.name && service.name == plugin.service_name));
+    assert!(catalog
+        .plugin_config(&plugin.name)
+        .unwrap()
+        .iter()
+        .all(|entry| entry.plugin == plugin.name));
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_assert_consistent() {
+    cluster.assert_consistent(
+        "SELECT name, enabled FROM _pico_plugin",
+        Duration::from_secs(10),
+    );
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_wait_governor_idle() {
+    cluster
+        .run_sql(
+            "CREATE TABLE governor_table (id INTEGER NOT NULL, PRIMARY KEY (id)) \
+                DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table");
+    cluster
+        .wait_governor_idle(Duration::from_secs(30))
+        .expect("Governor should become idle");
+
+    let queue = cluster
+        .governor_queue()
+        .expect("Failed to read governor queue");
+    assert!(queue.is_idle(), "{queue}");
+    cluster.run_sql("DROP TABLE governor_table;").unwrap();
+    cluster.assert_governor_idle(Duration::from_secs(30));
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_dump_state() {
+    let dump_dir = std::env::temp_dir().join(format!("picotest_{}", Uuid::new_v4()));
+    cluster
+        .dump_state(&dump_dir)
+        .expect("Failed to dump cluster state");
+
+    let health = std::fs::read_to_string(dump_dir.join("health.txt")).unwrap();
+    let instances = std::fs::read_to_string(dump_dir.join("system_tables/_pico_instance.yaml"));
+    let main_dir = dump_dir
+        .join("instances")
+        .join(&cluster.main().instance_name);
+    let log_exists = main_dir.join("picodata.log").is_file();
+    let fibers = std::fs::read_to_string(main_dir.join("fibers.yaml")).unwrap();
+    std::fs::remove_dir_all(&dump_dir).unwrap();
+
+    assert!(health.contains(&cluster.main().instance_name));
+    assert!(instances.unwrap().contains(&cluster.main().instance_name));
+    assert!(log_exists);
+    assert!(!fibers.starts_with("# failed"), "{fibers}");
+}
+
+#[picotest(path = "../tmp/test_plugin", cluster_var = "test_cluster")]
+fn test_custom_cluster_var() {
+    let cluster_uuid = GLOBAL_CLUSTER_UUID.get_or_init(|| test_cluster.uuid);
+    assert_eq!(cluster_uuid, &test_cluster.uuid);
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_explicit_cluster_argument(cluster: &Cluster) {
+    let cluster_uuid = GLOBAL_CLUSTER_UUID.get_or_init(|| cluster.uuid);
+    assert_eq!(cluster_uuid, &cluster.uuid);
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_seeded_rng(mut rng: PicotestRng) {
+    use rand::RngExt;
+
+    let mut same_rng = picotest_helpers::seed::test_rng();
+    assert_eq!(rng.random::<u64>(), same_rng.random::<u64>());
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_live_topology() {
+    let topology = cluster.topology().expect("Failed to get cluster topology");
+    let tiers = cluster.tiers().expect("Failed to get tiers");
+    let replicasets = cluster.replicasets().expect("Failed to get replicasets");
+
+    assert_eq!(topology.tiers.len(), tiers.len());
+    for tier in &tiers {
+        let tier_topology = &topology.tiers[&tier.name];
+        let instances = cluster.instances_in_tier(&tier.name).len();
+        assert_eq!(
+            instances,
+            tier_topology.replicasets as usize * tier_topology.replication_factor as usize
+        );
+    }
+    for replicaset in &replicasets {
+        let master = cluster
+            .master_of(&replicaset.name)
+            .expect("Failed to get replicaset master");
+        assert_eq!(master.instance_name, replicaset.current_master_name);
+    }
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_run_query_with_timeout() {
+    let err = cluster
+        .run_query_with_timeout(
+            "\\lua\nrequire('fiber').sleep(10)\n",
+            Duration::from_secs(1),
+        )
+        .expect_err("Query should time out");
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    let output = cluster
+        .run_query_with_timeout("\\lua\nreturn 1\n", Duration::from_secs(10))
+        .expect("Failed to run query");
+    assert!(output.contains('1'));
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_advance_clock() {
+    let clock_offset = |instance: &picotest_helpers::PicotestInstance| -> f64 {
+        instance
+            .run_lua_typed(
+                "return require('fiber').time() - __picotest_clock.originals.fiber_time()",
+            )
+            .expect("Failed to get clock offset")
+    };
+
+    {
+        let _clock = cluster
+            .advance_clock(Duration::from_secs(3600))
+            .expect("Failed to advance clock");
+        for instance in cluster.instances() {
+            assert!((3600.0..3601.0).contains(&clock_offset(instance)));
+        }
+    }
+
+    for instance in cluster.instances() {
+        assert!(clock_offset(instance) < 1.0);
+    }
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_fibers() {
+    let baseline = cluster.fibers().expect("Failed to get fibers");
+    assert!(baseline
+        .iter()
+        .all(|fiber| fiber.name != "picotest_sleeper"));
+
+    cluster
+        .run_lua(
+            "local fiber = require('fiber'); \
+            rawset(_G, 'picotest_sleeper', fiber.new(fiber.sleep, 1000)); \
+            picotest_sleeper:name('picotest_sleeper')",
+        )
+        .expect("Failed to spawn fiber");
+    let fibers = cluster.fibers().expect("Failed to get fibers");
+    assert!(fibers
+        .iter()
+        .any(|fiber| fiber.name == "picotest_sleeper" && fiber.status == "suspended"));
+
+    cluster
+        .run_lua("picotest_sleeper:cancel()")
+        .expect("Failed to cancel fiber");
+    cluster.assert_no_fiber_leak(&baseline);
+}
+
 #[picotest(path = "../tmp/test_plugin")]
 #[case(1, 1)]
 #[case(2, 2)]
@@ -75,6 +414,200 @@ fn test_function_with_case(#[case] input: i32, #[case] expected: i32) {
     assert_eq!(input, expected);
 }
 
+#[picotest(path = "../tmp/test_plugin", engines = ["memtx", "vinyl"])]
+fn test_function_with_engines() {
+    let table_name = format!("engine_table_{engine}");
+    cluster
+        .run_sql(format!(
+            "CREATE TABLE {table_name} (id INTEGER NOT NULL, PRIMARY KEY (id)) \
+                USING {engine} DISTRIBUTED BY (id);"
+        ))
+        .expect("Failed to create table");
+
+    let table_engine = cluster
+        .run_sql(format!(
+            "SELECT engine FROM _pico_table WHERE name = '{table_name}';"
+        ))
+        .expect("Failed to run query");
+    assert!(table_engine.contains(engine));
+}
+
+#[picotest(path = "../tmp/test_plugin", engines(memtx, vinyl))]
+fn test_function_with_engines_list() {
+    assert!(["memtx", "vinyl"].contains(&engine));
+}
+
+#[derive(Serialize)]
+struct AssertedRow {
+    id: i64,
+    name: String,
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_table_assertions() {
+    cluster
+        .run_sql(
+            "CREATE TABLE asserted_table (id INTEGER NOT NULL, name TEXT NOT NULL, \
+                PRIMARY KEY (id)) DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table");
+    cluster
+        .run_sql("INSERT INTO asserted_table VALUES (1, 'Alice'), (2, 'Bob');")
+        .expect("Failed to insert rows");
+
+    cluster.assert_row_exists("asserted_table", &HashMap::from([("name", "Bob")]));
+    cluster.assert_table_eq(
+        "asserted_table",
+        &[
+            AssertedRow {
+                id: 2,
+                name: "Bob".to_string(),
+            },
+            AssertedRow {
+                id: 1,
+                name: "Alice".to_string(),
+            },
+        ],
+    );
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        cluster.assert_table_eq::<AssertedRow>("asserted_table", &[]);
+    }));
+    assert!(result.is_err());
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_query_snapshot() {
+    cluster
+        .run_sql(
+            "CREATE TABLE snapshot_table (id INTEGER NOT NULL, name TEXT NOT NULL, \
+                PRIMARY KEY (id)) DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table");
+    cluster
+        .run_sql("INSERT INTO snapshot_table VALUES (2, 'Bob'), (1, 'Alice');")
+        .expect("Failed to insert rows");
+
+    cluster.assert_query_snapshot("snapshot_table", "SELECT id, name FROM snapshot_table");
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_populate() {
+    cluster
+        .run_sql(
+            "CREATE TABLE populated_table (id UNSIGNED NOT NULL, name TEXT NOT NULL, \
+                score DOUBLE, token UUID NOT NULL, PRIMARY KEY (id)) DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table");
+
+    let schema = cluster
+        .table_schema("populated_table")
+        .expect("Failed to get table schema");
+    assert_eq!(schema.len(), 4);
+    assert!(schema[0].is_primary && !schema[0].is_nullable);
+    assert!(schema[2].is_nullable);
+
+    cluster
+        .populate("populated_table", 1200, 7)
+        .expect("Failed to populate table");
+    let count = cluster
+        .sql_query("SELECT COUNT(*) FROM populated_table", &[])
+        .expect("Failed to count rows");
+    assert_eq!(
+        count.rows,
+        vec![vec![serde_norway::to_value(1200).unwrap()]]
+    );
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_explain() {
+    cluster
+        .run_sql(
+            "CREATE TABLE explained_table (id INTEGER NOT NULL, name TEXT NOT NULL, \
+                PRIMARY KEY (id)) DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table");
+
+    let plan = cluster
+        .explain("SELECT name FROM explained_table WHERE id = 1")
+        .expect("Failed to explain query");
+    assert!(!plan.nodes_of("scan").is_empty());
+    plan.assert_single_storage();
+    plan.assert_no_motion();
+
+    let plan = cluster
+        .explain("SELECT COUNT(*) FROM explained_table")
+        .expect("Failed to explain query");
+    assert!(!plan.is_single_storage());
+}
+
+static SERIAL_TEST_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[picotest(path = "../tmp/test_plugin", serial)]
+#[case(1)]
+#[case(2)]
+fn test_serial(#[case] _case: u32) {
+    assert!(!SERIAL_TEST_RUNNING.swap(true, Ordering::SeqCst));
+    thread::sleep(Duration::from_millis(100));
+    SERIAL_TEST_RUNNING.store(false, Ordering::SeqCst);
+}
+
+#[picotest(path = "../tmp/test_plugin", shared_lock)]
+fn test_shared_lock() {
+    assert!(!SERIAL_TEST_RUNNING.load(Ordering::SeqCst));
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct WatchedRow {
+    id: i64,
+    state: String,
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_watch_table() {
+    cluster
+        .run_sql(
+            "CREATE TABLE watched_table (id INTEGER NOT NULL, state TEXT NOT NULL, \
+                PRIMARY KEY (id)) DISTRIBUTED GLOBALLY;",
+        )
+        .expect("Failed to create table");
+
+    let mut watcher = cluster
+        .watch_table::<WatchedRow>("watched_table")
+        .expect("Failed to watch table");
+
+    cluster
+        .run_sql("INSERT INTO watched_table VALUES (1, 'offline');")
+        .expect("Failed to insert row");
+    cluster
+        .run_sql("UPDATE watched_table SET state = 'online' WHERE id = 1;")
+        .expect("Failed to update row");
+
+    let event = watcher
+        .wait_for(
+            Duration::from_secs(10),
+            |event| matches!(event, TableEvent::Inserted(row) if row.state == "online"),
+        )
+        .expect("Row should become online");
+    assert_eq!(event.row().id, 1);
+}
+
+#[picotest(path = "../tmp/test_plugin", prefix = "it_")]
+mod custom_prefix_mod {
+    use crate::GLOBAL_CLUSTER_UUID;
+
+    fn it_is_wired_to_cluster() {
+        let cluster_uuid = GLOBAL_CLUSTER_UUID.get_or_init(|| cluster.uuid);
+        assert_eq!(cluster_uuid, &cluster.uuid);
+    }
+
+    #[picotest::test]
+    fn marked_function_is_wired_to_cluster() {
+        let cluster_uuid = GLOBAL_CLUSTER_UUID.get_or_init(|| cluster.uuid);
+        assert_eq!(cluster_uuid, &cluster.uuid);
+    }
+}
+
 #[picotest(path = "../tmp/test_plugin")]
 mod test_mod {
     use crate::{plugin, TestPlugin};
@@ -108,12 +641,119 @@ mod test_mod {
     }
 }
 
+#[picotest(path = "../tmp/test_plugin")]
+mod dependent_tests {
+    use std::collections::HashMap;
+
+    fn test_create_dependent_table() {
+        cluster
+            .run_sql(
+                "CREATE TABLE dependent_table (id INTEGER NOT NULL, name TEXT NOT NULL, \
+                    PRIMARY KEY (id)) DISTRIBUTED BY (id);",
+            )
+            .expect("Failed to create table");
+    }
+
+    #[depends_on(test_create_dependent_table)]
+    fn test_insert_into_dependent_table() {
+        cluster
+            .run_sql("INSERT INTO dependent_table VALUES (1, 'Alice');")
+            .expect("Failed to insert row");
+    }
+
+    #[depends_on(test_insert_into_dependent_table)]
+    fn test_select_from_dependent_table() {
+        cluster.assert_row_exists("dependent_table", &HashMap::from([("name", "Alice")]));
+    }
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_spawn_cluster() {
+    let spawned = picotest::spawn_cluster(&SpawnOptions {
+        plugin_path: Some("../tmp/test_plugin".into()),
+        ..Default::default()
+    });
+    assert_ne!(spawned.uuid, cluster.uuid);
+    assert_ne!(spawned.data_dir_path(), cluster.data_dir_path());
+    for instance in spawned.instances() {
+        assert!(cluster.instances().iter().all(|session_instance| {
+            session_instance.bin_port != instance.bin_port
+                && session_instance.pg_port != instance.pg_port
+                && session_instance.http_port != instance.http_port
+        }));
+    }
+
+    spawned
+        .run_sql(
+            "CREATE TABLE spawned_cluster_table (id INTEGER NOT NULL, PRIMARY KEY (id)) \
+                DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table in spawned cluster");
+    assert!(spawned
+        .run_query("SELECT * FROM spawned_cluster_table")
+        .is_ok());
+    assert!(cluster
+        .run_query("SELECT * FROM spawned_cluster_table")
+        .is_err());
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_backup_and_restore() {
+    let spawned = picotest::spawn_cluster(&SpawnOptions {
+        plugin_path: Some("../tmp/test_plugin".into()),
+        ..Default::default()
+    });
+    spawned
+        .run_sql(
+            "CREATE TABLE backup_table (id INTEGER NOT NULL, name TEXT NOT NULL, PRIMARY KEY (id)) \
+                DISTRIBUTED BY (id);",
+        )
+        .expect("Failed to create table");
+    spawned
+        .run_sql("INSERT INTO backup_table VALUES (1, 'Alice'), (2, 'Bob');")
+        .expect("Failed to insert rows");
+    let expected = spawned
+        .capture_tables(&["backup_table"])
+        .expect("Failed to capture table");
+    assert_eq!(expected.rows("backup_table").map(<[_]>::len), Some(2));
+
+    let backup_dir = std::env::temp_dir().join(format!("picotest_{}", Uuid::new_v4()));
+    spawned
+        .backup(&backup_dir)
+        .expect("Failed to back up cluster");
+    assert!(spawned.backup(&backup_dir).is_err());
+    spawned
+        .run_sql("DELETE FROM backup_table WHERE id = 2;")
+        .expect("Failed to delete row");
+
+    let restored = spawned
+        .restore(&backup_dir)
+        .expect("Failed to restore cluster");
+    std::fs::remove_dir_all(&backup_dir).unwrap();
+    restored.assert_tables_restored(&expected);
+}
+
 #[picotest(path = "../tmp/test_plugin")]
 fn test_select_from_missing_table() {
     let result = cluster.run_query("SELECT * FROM table1");
     assert!(result.is_err(), "result : {:?}", result);
 }
 
+#[picotest(path = "../tmp/test_plugin")]
+fn test_assert_query_fails() {
+    cluster.assert_query_fails("SELEKT 1", ErrorClass::Syntax);
+    let error = cluster.assert_query_fails(
+        "SELECT * FROM missing_table",
+        ErrorMatcher::class(ErrorClass::NotFound).message_contains("missing_table"),
+    );
+    assert!(!error.message.is_empty());
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        cluster.assert_query_fails("SELECT 1", ErrorMatcher::any());
+    }));
+    assert!(result.is_err());
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
     name: String,
@@ -149,6 +789,122 @@ async fn test_rpc_handle(plugin: &TestPlugin) {
     );
 }
 
+#[rpc_client(plugin = "test_plugin", service = "example_service", version = "0.1.0")]
+trait TestPluginClient {
+    #[rpc(path = "/greetings_rpc")]
+    fn greetings(user: &User) -> ExampleResponse;
+}
+
+#[tokio::test]
+#[picotest(path = "../tmp/test_plugin")]
+async fn test_rpc_client() {
+    let user = User {
+        name: "Dodo".to_string(),
+    };
+
+    let response = TestPluginClient::from(cluster)
+        .greetings(&user)
+        .await
+        .unwrap();
+
+    assert_eq!(response.rpc_hello_response, "Hello Dodo, long time no see.");
+}
+
+#[tokio::test]
+#[picotest(path = "../tmp/test_plugin")]
+async fn test_assert_rpc_fails(plugin: &TestPlugin) {
+    cluster
+        .main()
+        .assert_rpc_fails(
+            &plugin.name,
+            "/missing_rpc",
+            &plugin.service_name,
+            "0.1.0",
+            &User {
+                name: "Dodo".to_string(),
+            },
+            ErrorMatcher::any(),
+        )
+        .await;
+}
+
+#[tokio::test]
+#[picotest(path = "../tmp/test_plugin")]
+async fn test_rpc_batch(plugin: &TestPlugin) {
+    let requests = (0..100).map(|i| RpcRequest {
+        plugin_name: &plugin.name,
+        path: "/greetings_rpc",
+        service_name: &plugin.service_name,
+        plugin_version: "0.1.0",
+        input: User {
+            name: format!("user_{i}"),
+        },
+    });
+
+    let report = cluster
+        .rpc_batch::<_, ExampleResponse, _>(requests, 8)
+        .await;
+
+    assert_eq!(report.failures(), 0);
+    assert_eq!(report.latencies.len(), 100);
+    assert_eq!(
+        report.results[42].as_ref().unwrap().rpc_hello_response,
+        "Hello user_42, long time no see."
+    );
+    assert!(report.stats.p50 <= report.stats.p95);
+    assert!(report.stats.p95 <= report.stats.max);
+}
+
+#[tokio::test]
+#[picotest(path = "../tmp/test_plugin")]
+async fn test_call_proc(_plugin: &TestPlugin) {
+    let instance = cluster.main();
+    instance
+        .run_lua(format!(
+            r#"
+            function picotest_sum(a, b) return a + b, "sum" end
+            box.schema.func.create("picotest_sum", {{ if_not_exists = true }})
+            box.schema.user.grant("{PICOTEST_USER_IPROTO}", "execute", "function", "picotest_sum", {{ if_not_exists = true }})
+            "#
+        ))
+        .unwrap();
+
+    let (sum, name): (i64, String) = instance.call_proc("picotest_sum", &(1, 2)).await.unwrap();
+    assert_eq!((sum, name.as_str()), (3, "sum"));
+
+    let err = instance
+        .call_proc::<_, (i64,)>("picotest_missing_proc", &())
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("picotest_missing_proc"));
+}
+
+#[tokio::test]
+#[picotest(path = "../tmp/test_plugin")]
+async fn test_instance_refresh(_plugin: &TestPlugin) {
+    cluster
+        .main()
+        .run_lua(format!(
+            r#"
+            function picotest_echo(value) return value end
+            box.schema.func.create("picotest_echo", {{ if_not_exists = true }})
+            box.schema.user.grant("{PICOTEST_USER_IPROTO}", "execute", "function", "picotest_echo", {{ if_not_exists = true }})
+            "#
+        ))
+        .unwrap();
+
+    // Instance with outdated properties, as if it has been restarted on other ports.
+    let mut instance = PicotestInstance::attach(cluster.main().socket_path.clone()).unwrap();
+    instance.bin_port = 1;
+
+    let (value,): (i64,) = instance.call_proc("picotest_echo", &(42,)).await.unwrap();
+    assert_eq!(value, 42);
+
+    instance.refresh().unwrap();
+    assert_eq!(instance.bin_port, cluster.main().bin_port);
+    assert_eq!(instance.instance_name, cluster.main().instance_name);
+}
+
 #[picotest(path = "../tmp/test_plugin")]
 fn test_run_lua_query(_plugin: &TestPlugin) {
     let res = cluster.instances()[1].run_lua("return 1 + 1").unwrap();
@@ -157,6 +913,35 @@ fn test_run_lua_query(_plugin: &TestPlugin) {
     assert!(!res.contains(OUTPUT_FOOTER));
 }
 
+#[picotest(path = "../tmp/test_plugin")]
+fn test_run_lua_raised_error() {
+    let res = cluster.run_lua("error('boom')");
+    assert!(res.is_err_and(|err| err.to_string().contains("boom")));
+
+    let res = cluster
+        .run_lua_with_status("local a = 1\nlocal b = 2\nreturn a + b, 'multiline'")
+        .expect("Failed to run Lua query");
+    assert!(res.is_ok());
+    assert_eq!(res.payload, "---\n- 3\n- multiline\n...\n");
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_run_lua_file_with_modules() {
+    let modules = LuaModule::from_dir("tests/assets/lua").unwrap();
+    cluster.install_lua_modules(&modules).unwrap();
+
+    for instance in cluster.instances() {
+        let res = instance
+            .run_lua_file("tests/assets/lua_script.lua")
+            .unwrap();
+        assert!(res.contains("- 6\n"));
+        assert!(res.contains("Hello, Dodo"));
+    }
+
+    let res = cluster.run_lua_file("tests/assets/missing.lua");
+    assert!(res.is_err_and(|err| err.kind() == std::io::ErrorKind::NotFound));
+}
+
 #[picotest(path = "../tmp/test_plugin")]
 fn test_run_lua_select_and_serialize_output_from_yaml() {
     // This test creates box and insert some values into it.
@@ -180,11 +965,6 @@ fn test_run_lua_select_and_serialize_output_from_yaml() {
         )
         .expect("Failed to run Lua query");
 
-    let output = cluster
-        .main()
-        .run_lua("box.space.test_space.index.i:select()")
-        .expect("Failed to run Lua query");
-
     #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
     struct SomeStruct(String, i32, Vec<String>, SomeStatus);
 
@@ -194,12 +974,14 @@ fn test_run_lua_select_and_serialize_output_from_yaml() {
         Completed(HashMap<String, Vec<String>>),
     }
 
-    let actual: Vec<Vec<SomeStruct>> =
-        serde_norway::from_str(&output).expect("Failed to deserialize struct from YAML string");
+    let actual: Vec<SomeStruct> = cluster
+        .main()
+        .run_lua_typed("box.space.test_space.index.i:select()")
+        .expect("Failed to deserialize struct from Lua output");
 
     assert_eq!(
         actual,
-        vec![vec![
+        vec![
             SomeStruct(
                 "ccc".into(),
                 -3,
@@ -218,6 +1000,81 @@ fn test_run_lua_select_and_serialize_output_from_yaml() {
                 vec!["list_item1".into(), "list_item2".into()],
                 SomeStatus::Completed([("Completed".into(), vec!["filepath1.dsv".into()])].into())
             ),
-        ]]
+        ]
     )
 }
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_box_introspection() {
+    let info = cluster.box_info().expect("Failed to get box.info");
+    assert_eq!(info.status, "running");
+    assert!(!info.replication.is_empty());
+
+    let slab_info = cluster.instances()[1]
+        .box_slab_info()
+        .expect("Failed to get box.slab.info");
+    assert!(slab_info.quota_used <= slab_info.quota_size);
+
+    let _ = cluster.run_lua("box.space._space:select()");
+    let stat = cluster.box_stat().expect("Failed to get box.stat");
+    assert!(stat.get("select").is_some_and(|select| select.total > 0));
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_cluster_health() {
+    let health = cluster.health();
+    assert!(health.is_ready(), "cluster isn't ready:\n{health}");
+    assert_eq!(health.instances.len(), cluster.instances().len());
+
+    let instance = &cluster.instances()[1];
+    assert!(instance.is_alive());
+    assert!(instance.is_ready());
+    cluster.wait_ready(Duration::from_secs(5)).unwrap();
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_hooks() {
+    assert_eq!(READY_CLUSTER_UUID.get(), Some(&cluster.uuid));
+    assert!(STARTED_TESTS.load(Ordering::SeqCst) > 0);
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_picodata_version() {
+    let version = cluster.picodata_version().unwrap();
+    assert!(version >= picotest_helpers::compat::min_picodata_version());
+    assert!(cluster.supports(picotest_helpers::compat::Capability::RpcDispatchContext));
+}
+
+#[picotest(path = "../tmp/test_plugin", tag = "smoke")]
+fn test_tagged() {
+    assert!(!cluster.instances().is_empty());
+}
+
+struct ClusterSuite {
+    cluster_uuid: Uuid,
+    instance_names: Vec<String>,
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+impl ClusterSuite {
+    fn new(cluster: &Cluster) -> Self {
+        Self {
+            cluster_uuid: cluster.uuid,
+            instance_names: cluster
+                .instances()
+                .iter()
+                .map(|instance| instance.instance_name.clone())
+                .collect(),
+        }
+    }
+
+    fn test_suite_shares_cluster(&self, cluster: &Cluster) {
+        assert_eq!(self.cluster_uuid, cluster.uuid);
+    }
+
+    #[case(0)]
+    #[case(1)]
+    fn test_suite_instances(&self, #[case] index: usize) {
+        assert!(self.instance_names.len() > index);
+    }
+}
@@ -2,7 +2,8 @@ mod helpers;
 
 use ctor::ctor;
 use helpers::plugin;
-use picotest::*;
+use picotest::prelude::*;
+use picotest_helpers::users::{AuthMethod, TestUser};
 use picotest_helpers::{PICOTEST_USER, PICOTEST_USER_PASSWORD};
 use postgres::{Client, NoTls};
 
@@ -36,20 +37,25 @@ fn test_get_instances_by_tier() {
 #[picotest(path = "../tmp/test_plugin")]
 fn test_pg_connection() {
     let conn_string = format!(
-        "host=localhost port={} user={} password={}",
+        "host={} port={} user={} password={}",
+        cluster.main().host(),
         cluster.main().pg_port,
         PICOTEST_USER,
         PICOTEST_USER_PASSWORD
     );
     let mut client = Client::connect(conn_string.as_str(), NoTls).unwrap();
+    let schema = cluster.temp_schema();
+    let users_table = schema.table("users");
     client
         .execute(
-            "
-            CREATE TABLE IF NOT EXISTS users (
+            &format!(
+                "
+            CREATE TABLE {users_table} (
             Id INT PRIMARY KEY,
             Name VARCHAR(50) NOT NULL,
             LastName VARCHAR(50) NOT NULL
-        )",
+        )"
+            ),
             &[],
         )
         .unwrap();
@@ -63,7 +69,7 @@ fn test_pg_connection() {
     client
         .execute(
             &format!(
-                "INSERT INTO users (Id, Name, LastName) VALUES ({}, '{}', '{}')",
+                "INSERT INTO {users_table} (Id, Name, LastName) VALUES ({}, '{}', '{}')",
                 user.id, user.name, user.last_name
             ),
             &[],
@@ -71,7 +77,10 @@ fn test_pg_connection() {
         .unwrap();
 
     let users = client
-        .query("SELECT Id, Name, LastName FROM users", &[])
+        .query(
+            &format!("SELECT Id, Name, LastName FROM {users_table}"),
+            &[],
+        )
         .unwrap()
         .iter()
         .map(|row| User {
@@ -86,3 +95,81 @@ fn test_pg_connection() {
     let pg_user = users.first().unwrap();
     assert_eq!(&user, pg_user);
 }
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_temp_schema_drops_tables() {
+    let schema = cluster.temp_schema();
+    let table = schema.table("users");
+    cluster
+        .run_sql(format!(
+            "CREATE TABLE {table} (id INT PRIMARY KEY, name TEXT) DISTRIBUTED BY (id)"
+        ))
+        .unwrap();
+    assert_eq!(schema.tables().unwrap(), std::slice::from_ref(&table));
+
+    let other_schema = cluster.temp_schema();
+    assert_ne!(schema.prefix(), other_schema.prefix());
+    assert!(other_schema.tables().unwrap().is_empty());
+
+    drop(schema);
+    assert!(other_schema.tables().unwrap().is_empty());
+    let output = cluster
+        .run_lua(format!("return box.space.{table} == nil"))
+        .unwrap();
+    assert!(output.contains("true"));
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_with_rollback() {
+    let schema = cluster.temp_schema();
+    let prices = schema.table("prices");
+    cluster
+        .run_sql(format!(
+            "CREATE TABLE {prices} (id INT PRIMARY KEY, value INT) DISTRIBUTED BY (id)"
+        ))
+        .unwrap();
+    let discounts = schema.table("discounts");
+
+    let inserted = cluster
+        .with_rollback(|tx| {
+            tx.execute(&format!("INSERT INTO {prices} VALUES (1, 100)"), &[])?;
+            tx.execute(
+                &format!("CREATE TABLE {discounts} (id INT PRIMARY KEY) DISTRIBUTED BY (id)"),
+                &[],
+            )?;
+            tx.cleanup(format!("DROP TABLE {discounts}"));
+            Ok(tx.query(&format!("SELECT * FROM {prices}"), &[])?.len())
+        })
+        .unwrap();
+    assert_eq!(inserted, 1);
+
+    let rows = cluster
+        .pg_client()
+        .unwrap()
+        .query(&format!("SELECT * FROM {prices}"), &[])
+        .unwrap();
+    assert!(rows.is_empty());
+    assert_eq!(schema.tables().unwrap(), std::slice::from_ref(&prices));
+}
+
+#[picotest(path = "../tmp/test_plugin")]
+fn test_pg_client_as_created_user() {
+    let user = TestUser::new("picotest_md5_reader", AuthMethod::Md5)
+        .password("Reader-Pic0test")
+        .grant("READ TABLE");
+    cluster.drop_user(&user.name).unwrap();
+    cluster.create_user(&user).unwrap();
+
+    let rows = cluster
+        .pg_client_as(&user)
+        .unwrap()
+        .query("SELECT 1", &[])
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+
+    let wrong_password = user.clone().password("Wrong-Pic0test");
+    assert!(cluster.pg_client_as(&wrong_password).is_err());
+    assert!(cluster.create_user(&user).is_err());
+
+    cluster.drop_user(&user.name).unwrap();
+}
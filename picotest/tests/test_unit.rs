@@ -39,10 +39,25 @@ fn assert_failed_tests(plugin_path: &PathBuf) {
     assert!(line_matcher.has_matched());
 }
 
+// Run tests executed by the embedded runtime instead of the cluster.
+fn assert_embedded_runtime_tests(plugin_path: &PathBuf) {
+    let module_name = concat!(TEST_SOURCE_MODULE_NAME, "::embedded_runtime");
+    let mut line_matcher = LineMatcher::new("Hello from test_embedded_runtime");
+    let exit_status =
+        run_cargo_test_in_plugin_workspace(plugin_path, module_name, &mut line_matcher);
+
+    assert!(
+        exit_status.success(),
+        "tests are supposed to finish successfully"
+    );
+    assert!(line_matcher.has_matched());
+}
+
 #[rstest]
 fn run_unit_tests_inside_plugin_workspace(fresh_plugin: &TestPlugin) {
     add_source_file_to_plugin(fresh_plugin, asset!(TEST_SOURCE_FILE_PATH).into());
 
     assert_success_tests(&fresh_plugin.path);
     assert_failed_tests(&fresh_plugin.path);
+    assert_embedded_runtime_tests(&fresh_plugin.path);
 }
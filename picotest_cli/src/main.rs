@@ -0,0 +1,360 @@
+//! Picotest command line interface.
+//!
+//! Starts long-lived development cluster of the plugin, so that it can be
+//! inspected or reused between test runs without paying startup cost.
+//! Reports flaky tests from the history of test outcomes and tests
+//! affected by the changed files.
+
+use anyhow::{bail, Context};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use picotest_helpers::cluster_cache::{
+    ClusterCache, DEFAULT_CLUSTER_CACHE_DIR, PICOTEST_CLUSTER_CACHE_DIR,
+};
+use picotest_helpers::flaky::{flaky_tests, TestHistory, PICOTEST_TEST_HISTORY};
+use picotest_helpers::impact::{
+    parse_changed_files, ImpactSelection, PICOTEST_CHANGED_FILES, PICOTEST_TRACK_IMPACT,
+};
+use picotest_helpers::topology::parse_topology;
+use picotest_helpers::{
+    instance_dirs, stop_cluster, Cluster, ADMIN_SOCKET_NAME, DEFAULT_WAIT_VSHARD_ENABLED,
+    INSTANCE_LOG_NAME,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const PLUGIN_TOPOLOGY_FILENAME: &str = "topology.toml";
+const DEFAULT_DATA_DIR: &str = "tmp/dev_cluster";
+const LOGS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[command(
+    name = "picotest",
+    version,
+    about = "Manage development cluster of Picodata plugin"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start cluster in background.
+    Up(UpArgs),
+    /// Stop running cluster.
+    Down(ClusterArgs),
+    /// Show state of cluster instances.
+    Status(ClusterArgs),
+    /// Print logs of cluster instances.
+    Logs(LogsArgs),
+    /// Remove clusters cached by tests.
+    ClearCache(CacheArgs),
+    /// Summarize history of test outcomes.
+    #[command(subcommand)]
+    Report(Report),
+}
+
+#[derive(Subcommand)]
+enum Report {
+    /// Show tests with intermittent failures.
+    Flaky(FlakyArgs),
+    /// Show tests affected by the changed files.
+    Impact(ImpactArgs),
+}
+
+#[derive(Args)]
+struct ClusterArgs {
+    /// Path to the plugin root directory.
+    #[arg(long, default_value = ".")]
+    plugin_path: PathBuf,
+    /// Cluster data directory, relative to the plugin path.
+    #[arg(long, default_value = DEFAULT_DATA_DIR)]
+    data_dir: PathBuf,
+}
+
+impl ClusterArgs {
+    fn data_dir_path(&self) -> PathBuf {
+        self.plugin_path.join(&self.data_dir)
+    }
+}
+
+#[derive(Args)]
+struct UpArgs {
+    #[command(flatten)]
+    cluster: ClusterArgs,
+    /// Path to the plugin topology file.
+    /// Defaults to topology.toml in the plugin root directory.
+    #[arg(long, env = "TOPOLOGY_PATH")]
+    topology: Option<PathBuf>,
+    /// Path to the picodata executable.
+    #[arg(long, env = "PICODATA_PATH", default_value = "picodata")]
+    picodata_path: PathBuf,
+    /// Wait until vshard is initialized and initial resharding is done.
+    #[arg(
+        long,
+        env = "WAIT_VSHARD_DISCOVERY",
+        default_value_t = DEFAULT_WAIT_VSHARD_ENABLED,
+        action = ArgAction::Set
+    )]
+    wait_vshard_discovery: bool,
+}
+
+#[derive(Args)]
+struct LogsArgs {
+    #[command(flatten)]
+    cluster: ClusterArgs,
+    /// Keep printing logs as they are appended.
+    #[arg(short, long)]
+    follow: bool,
+    /// Print logs of the instance only.
+    instance_name: Option<String>,
+}
+
+#[derive(Args)]
+struct CacheArgs {
+    /// Path to the plugin root directory.
+    #[arg(long, default_value = ".")]
+    plugin_path: PathBuf,
+    /// Directory of cached clusters.
+    /// Defaults to tmp/cluster_cache in the plugin root directory.
+    #[arg(long, env = PICOTEST_CLUSTER_CACHE_DIR)]
+    cache_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct FlakyArgs {
+    /// Path to the plugin root directory.
+    #[arg(long, default_value = ".")]
+    plugin_path: PathBuf,
+    /// Path to the history of test outcomes.
+    /// Defaults to tmp/test_history.jsonl in the plugin root directory.
+    #[arg(long, env = PICOTEST_TEST_HISTORY)]
+    history: Option<PathBuf>,
+    /// Minimum number of runs of the test to be reported.
+    #[arg(long, default_value_t = 2)]
+    min_runs: usize,
+}
+
+#[derive(Args)]
+struct ImpactArgs {
+    /// Path to the plugin root directory.
+    #[arg(long, default_value = ".")]
+    plugin_path: PathBuf,
+    /// Changed files separated by newlines or commas,
+    /// relative to the plugin root directory.
+    #[arg(long, env = PICOTEST_CHANGED_FILES)]
+    changed_files: String,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    match Cli::parse().command {
+        Command::Up(args) => up(args),
+        Command::Down(args) => down(&args),
+        Command::Status(args) => status(&args),
+        Command::Logs(args) => logs(&args),
+        Command::ClearCache(args) => clear_cache(args),
+        Command::Report(Report::Flaky(args)) => report_flaky(args),
+        Command::Report(Report::Impact(args)) => report_impact(args),
+    }
+}
+
+fn up(args: UpArgs) -> anyhow::Result<()> {
+    if is_running(&args.cluster.data_dir_path()) {
+        bail!(
+            "cluster in '{}' is already running",
+            args.cluster.data_dir_path().display()
+        );
+    }
+
+    let topology_path = args
+        .topology
+        .unwrap_or_else(|| args.cluster.plugin_path.join(PLUGIN_TOPOLOGY_FILENAME));
+    let topology = parse_topology(&topology_path)?;
+
+    let cluster = Cluster::new(args.cluster.plugin_path, topology, args.picodata_path)?
+        .with_data_dir(args.cluster.data_dir)
+        .wait_vshard_discovery(args.wait_vshard_discovery)
+        .daemon(true)
+        .run()
+        .context("failed to start the cluster")?;
+
+    println!(
+        "Cluster is running in '{}'",
+        cluster.data_dir_path().display()
+    );
+    for instance in cluster.instances() {
+        println!(
+            "{:<24} tier: {:<16} bin: {:<6} pg: {:<6} http: {}",
+            instance.instance_name,
+            instance.tier,
+            instance.bin_port,
+            instance.pg_port,
+            instance.http_port
+        );
+    }
+
+    cluster.detach();
+    Ok(())
+}
+
+fn down(args: &ClusterArgs) -> anyhow::Result<()> {
+    stop_cluster(&args.plugin_path, &args.data_dir)?;
+    println!("Cluster in '{}' is stopped", args.data_dir_path().display());
+    Ok(())
+}
+
+fn status(args: &ClusterArgs) -> anyhow::Result<()> {
+    let data_dir = args.data_dir_path();
+    let dirs = instance_dirs(&data_dir)
+        .with_context(|| format!("no cluster found in '{}'", data_dir.display()))?;
+
+    for dir in dirs {
+        let state = if is_instance_running(&dir) {
+            "running"
+        } else {
+            "stopped"
+        };
+        println!("{:<24} {state}", instance_name(&dir));
+    }
+    Ok(())
+}
+
+fn clear_cache(args: CacheArgs) -> anyhow::Result<()> {
+    let cache_dir = args
+        .cache_dir
+        .unwrap_or_else(|| args.plugin_path.join(DEFAULT_CLUSTER_CACHE_DIR));
+    let cache = ClusterCache::new(cache_dir);
+    let removed = cache.clear()?;
+    println!(
+        "Removed {removed} cached cluster(s) from '{}'",
+        cache.dir().display()
+    );
+    Ok(())
+}
+
+fn report_flaky(args: FlakyArgs) -> anyhow::Result<()> {
+    let history = match args.history {
+        Some(path) => TestHistory::new(path),
+        None => TestHistory::from_env(&args.plugin_path),
+    };
+    let runs = history.load()?;
+    if runs.is_empty() {
+        bail!(
+            "no test outcomes found in '{}', run tests with PICOTEST_TRACK_FLAKY=1",
+            history.path().display()
+        );
+    }
+
+    let flaky = flaky_tests(&runs, args.min_runs);
+    println!(
+        "Found {} flaky test(s) in {} recorded run(s)",
+        flaky.len(),
+        runs.len()
+    );
+    for test in flaky {
+        print!("{test}");
+    }
+    Ok(())
+}
+
+fn report_impact(args: ImpactArgs) -> anyhow::Result<()> {
+    let selection =
+        ImpactSelection::load(&args.plugin_path, parse_changed_files(&args.changed_files))?;
+    if let Some(file) = selection.run_all_by() {
+        println!(
+            "Changed file '{file}' isn't attributed to plugin services, all tests are affected"
+        );
+        return Ok(());
+    }
+
+    let changes = selection.changes();
+    println!(
+        "Changed services: {:?}, tables: {:?}",
+        changes.services, changes.tables
+    );
+    let affected = selection.affected_tests();
+    println!(
+        "Found {} affected test(s) with recorded footprints",
+        affected.len()
+    );
+    for test in affected {
+        println!("  {test}");
+    }
+    println!(
+        "Tests without footprints are affected as well, record them with {PICOTEST_TRACK_IMPACT}=1"
+    );
+    Ok(())
+}
+
+fn logs(args: &LogsArgs) -> anyhow::Result<()> {
+    let data_dir = args.cluster.data_dir_path();
+    let log_paths: Vec<(String, PathBuf)> = instance_dirs(&data_dir)
+        .with_context(|| format!("no cluster found in '{}'", data_dir.display()))?
+        .into_iter()
+        .map(|dir| (instance_name(&dir), dir.join(INSTANCE_LOG_NAME)))
+        .filter(|(name, _)| args.instance_name.as_ref().is_none_or(|n| n == name))
+        .collect();
+
+    if log_paths.is_empty() {
+        bail!("no instances found in '{}'", data_dir.display());
+    }
+
+    let mut offsets = HashMap::new();
+    loop {
+        for (name, path) in &log_paths {
+            let offset = offsets.entry(name.clone()).or_insert(0);
+            *offset = print_log_tail(name, path, *offset)?;
+        }
+
+        if !args.follow {
+            return Ok(());
+        }
+        thread::sleep(LOGS_POLL_INTERVAL);
+    }
+}
+
+/// Prints log lines appended after `offset` prefixed by instance name.
+/// Returns new offset.
+fn print_log_tail(instance_name: &str, path: &Path, offset: u64) -> anyhow::Result<u64> {
+    let Ok(mut file) = File::open(path) else {
+        return Ok(offset);
+    };
+
+    // Log has been rotated or truncated.
+    let offset = if file.metadata()?.len() < offset {
+        0
+    } else {
+        offset
+    };
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = String::new();
+    let read = file.read_to_string(&mut buf)?;
+
+    for line in buf.lines() {
+        println!("[{instance_name}] {line}");
+    }
+    Ok(offset + read as u64)
+}
+
+fn instance_name(instance_dir: &Path) -> String {
+    instance_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_instance_running(instance_dir: &Path) -> bool {
+    UnixStream::connect(instance_dir.join(ADMIN_SOCKET_NAME)).is_ok()
+}
+
+fn is_running(data_dir: &Path) -> bool {
+    instance_dirs(data_dir).is_ok_and(|dirs| dirs.iter().any(|dir| is_instance_running(dir)))
+}
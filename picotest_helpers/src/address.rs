@@ -0,0 +1,316 @@
+//! Addresses cluster instances listen on and clients connect to.
+//!
+//! Pike starts instances listening on `127.0.0.1`. If another bind host is
+//! set by [`Cluster::address`] or [`PICOTEST_BIND_HOST`], pike is pointed to
+//! a generated launcher script, which rewrites listen addresses passed to
+//! picodata with the bind host and peer addresses with the host reachable
+//! by other instances.
+//!
+//! Clients ([`PicotestInstance::execute_rpc`], pgproto connections built from
+//! [`PicotestInstance::host`]) connect to the connect host, which may differ
+//! from the bind host, e.g. if instances listen on all interfaces or are
+//! reachable by the container-internal address.
+//!
+//! Ports assigned by pike are the same for every cluster, so the launcher
+//! also shifts them by [`AddressConfig::port_offset`], if several clusters
+//! are running side by side.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::address::AddressConfig;
+//!
+//! let cluster = Cluster::new(plugin_path, topology, picodata_path)?
+//!     .address(AddressConfig::new("::1"))
+//!     .run()?;
+//! assert_eq!(cluster.main().host(), "::1");
+//! ```
+//!
+//! [`Cluster::address`]: crate::Cluster::address
+//! [`PicotestInstance::execute_rpc`]: crate::PicotestInstance::execute_rpc
+//! [`PicotestInstance::host`]: crate::PicotestInstance::host
+
+use crate::launcher::{self, shell_quote};
+use log::debug;
+use std::env;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Environment variable with host cluster instances listen on.
+pub const PICOTEST_BIND_HOST: &str = "PICOTEST_BIND_HOST";
+
+/// Environment variable with host clients connect to instances by.
+pub const PICOTEST_CONNECT_HOST: &str = "PICOTEST_CONNECT_HOST";
+
+/// Host pike starts instances listening on.
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+
+/// Directory (relative to the plugin path) of generated launcher scripts.
+pub const ADDRESS_LAUNCHERS_DIR: &str = "tmp/address";
+
+/// Hosts cluster instances listen on and are reachable at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressConfig {
+    /// Host instances listen on, e.g. `::1`, `0.0.0.0` or address
+    /// of the container network. IPv6 address is set without brackets.
+    pub bind_host: String,
+    /// Host clients connect to instances by.
+    /// If `None`, instances are reachable at the bind host.
+    pub connect_host: Option<String>,
+    /// Number added to every port assigned by pike.
+    pub port_offset: u16,
+}
+
+impl Default for AddressConfig {
+    /// Reads configuration from `PICOTEST_BIND_HOST` and
+    /// `PICOTEST_CONNECT_HOST` environment variables.
+    fn default() -> Self {
+        let mut config =
+            env::var(PICOTEST_BIND_HOST).map_or_else(|_| Self::new(DEFAULT_HOST), Self::new);
+        if let Ok(connect_host) = env::var(PICOTEST_CONNECT_HOST) {
+            config = config.connect_host(connect_host);
+        }
+        config
+    }
+}
+
+impl AddressConfig {
+    pub fn new(bind_host: impl Into<String>) -> Self {
+        Self {
+            bind_host: strip_brackets(bind_host.into()),
+            connect_host: None,
+            port_offset: 0,
+        }
+    }
+
+    /// Sets host clients connect to instances by.
+    pub fn connect_host(mut self, host: impl Into<String>) -> Self {
+        self.connect_host = Some(strip_brackets(host.into()));
+        self
+    }
+
+    /// Shifts ports instances listen on by `offset`, so they don't
+    /// intersect with ports of another cluster started by pike.
+    pub fn port_offset(mut self, offset: u16) -> Self {
+        self.port_offset = offset;
+        self
+    }
+
+    /// Returns `true` if instances listen on the host and ports set by pike.
+    pub fn is_default(&self) -> bool {
+        self.bind_host == DEFAULT_HOST && self.port_offset == 0
+    }
+
+    /// Returns host instances are reachable at by each other.
+    ///
+    /// It's the bind host, unless instances listen on all interfaces.
+    pub fn peer_host(&self) -> &str {
+        match self.bind_host.as_str() {
+            "0.0.0.0" => self.connect_host.as_deref().unwrap_or(DEFAULT_HOST),
+            "::" => self.connect_host.as_deref().unwrap_or("::1"),
+            bind_host => bind_host,
+        }
+    }
+
+    /// Returns host clients connect to instances by.
+    pub fn client_host(&self) -> &str {
+        self.connect_host
+            .as_deref()
+            .unwrap_or_else(|| self.peer_host())
+    }
+}
+
+fn strip_brackets(host: String) -> String {
+    match host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+    {
+        Some(host) => host.to_string(),
+        None => host,
+    }
+}
+
+/// Returns `host:port` address, IPv6 host is enclosed in brackets.
+pub fn socket_address(host: &str, port: u16) -> String {
+    format!("{}:{port}", socket_host(host))
+}
+
+/// Returns host as it's written in `host:port` address.
+fn socket_host(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    }
+}
+
+/// Writes launcher script running `picodata_path` listening on the hosts
+/// of `config`.
+///
+/// ### Returns
+/// Path to the script to be used as picodata binary.
+pub(crate) fn write_launcher(
+    plugin_path: &Path,
+    cluster_uuid: &Uuid,
+    picodata_path: &Path,
+    config: &AddressConfig,
+) -> anyhow::Result<PathBuf> {
+    let launcher_path = launcher::write_launcher(
+        &plugin_path
+            .join(ADDRESS_LAUNCHERS_DIR)
+            .join(cluster_uuid.to_string()),
+        "picodata",
+        &launcher_script(picodata_path, config),
+    )?;
+
+    debug!(
+        "Instances will be started listening on '{}' by '{}'",
+        config.bind_host,
+        launcher_path.display()
+    );
+    Ok(launcher_path)
+}
+
+fn launcher_script(picodata_path: &Path, config: &AddressConfig) -> String {
+    let exec = launcher::exec_line(picodata_path);
+    let bind_host = shell_quote(&socket_host(&config.bind_host));
+    let peer_host = shell_quote(&socket_host(config.peer_host()));
+    let port_offset = config.port_offset;
+    format!(
+        r#"#!/bin/sh
+# Generated by picotest: runs picodata listening on {bind_host}, ports shifted by {port_offset}.
+set -e
+shift_ports() {{
+    awk -v offset={port_offset} '{{
+        out = ""
+        while (match($0, /:[0-9]+(,|$)/)) {{
+            port = substr($0, RSTART + 1, RLENGTH - 1)
+            separator = ""
+            if (port ~ /,$/) {{
+                separator = ","
+                port = substr(port, 1, length(port) - 1)
+            }}
+            out = out substr($0, 1, RSTART) (port + offset) separator
+            $0 = substr($0, RSTART + RLENGTH)
+        }}
+        print out $0
+    }}'
+}}
+rewrite() {{
+    printf '%s\n' "$1" | shift_ports | sed -e "s/127\\.0\\.0\\.1:/$2:/g" -e "s/localhost:/$2:/g"
+}}
+previous=
+listen=
+advertise=
+for arg do
+    shift
+    option=$previous
+    case "$arg" in
+        --*=*) option=${{arg%%=*}} ;;
+    esac
+    case "$option" in
+        --listen|--iproto-listen|--pg-listen|--http-listen) arg=$(rewrite "$arg" {bind_host}) ;;
+        --peer) arg=$(rewrite "$arg" {peer_host}) ;;
+        --advertise|--iproto-advertise) advertise=1; arg=$(rewrite "$arg" {peer_host}) ;;
+    esac
+    case "$option" in
+        --listen|--iproto-listen) listen=$arg ;;
+    esac
+    previous=$arg
+    set -- "$@" "$arg"
+done
+if [ -z "$advertise" ] && [ -n "$listen" ] && [ {bind_host} != {peer_host} ]; then
+    PICODATA_IPROTO_ADVERTISE={peer_host}":${{listen##*:}}"
+    PICODATA_ADVERTISE=$PICODATA_IPROTO_ADVERTISE
+    export PICODATA_IPROTO_ADVERTISE PICODATA_ADVERTISE
+fi
+{exec}"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{launcher_script, socket_address, AddressConfig};
+    use rstest::rstest;
+    use std::path::Path;
+    use std::process::Command;
+
+    #[rstest]
+    #[case("127.0.0.1", 3301, "127.0.0.1:3301")]
+    #[case("::1", 3301, "[::1]:3301")]
+    #[case("picodata-1", 5432, "picodata-1:5432")]
+    fn test_socket_address(#[case] host: &str, #[case] port: u16, #[case] expected: &str) {
+        assert_eq!(socket_address(host, port), expected);
+    }
+
+    #[rstest]
+    #[case(AddressConfig::new("127.0.0.1"), "127.0.0.1", "127.0.0.1")]
+    #[case(AddressConfig::new("[::1]"), "::1", "::1")]
+    #[case(AddressConfig::new("0.0.0.0"), "127.0.0.1", "127.0.0.1")]
+    #[case(AddressConfig::new("::"), "::1", "::1")]
+    #[case(AddressConfig::new("0.0.0.0").connect_host("10.0.0.2"), "10.0.0.2", "10.0.0.2")]
+    #[case(AddressConfig::new("172.17.0.2").connect_host("localhost"), "172.17.0.2", "localhost")]
+    fn test_address_hosts(
+        #[case] config: AddressConfig,
+        #[case] peer_host: &str,
+        #[case] client_host: &str,
+    ) {
+        assert_eq!(config.peer_host(), peer_host);
+        assert_eq!(config.client_host(), client_host);
+    }
+
+    #[rstest]
+    fn test_launcher_script() {
+        let script = launcher_script(Path::new("echo"), &AddressConfig::new("::"));
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.ends_with("exec 'echo' \"$@\"\n"));
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&script)
+            .arg("picodata")
+            .args(["run", "--iproto-listen", "127.0.0.1:3301"])
+            .args([
+                "--pg-listen=127.0.0.1:5432",
+                "--http-listen",
+                "localhost:8000",
+            ])
+            .args(["--peer", "127.0.0.1:3301,127.0.0.1:3302"])
+            .args(["--instance-name", "default_1_1"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "run --iproto-listen [::]:3301 --pg-listen=[::]:5432 --http-listen [::]:8000 \
+             --peer [::1]:3301,[::1]:3302 --instance-name default_1_1\n"
+        );
+    }
+
+    #[rstest]
+    fn test_launcher_script_port_offset() {
+        let config = AddressConfig::new("127.0.0.1").port_offset(100);
+        assert!(!config.is_default());
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(launcher_script(Path::new("echo"), &config))
+            .arg("picodata")
+            .args(["run", "--iproto-listen", "127.0.0.1:3301"])
+            .args([
+                "--pg-listen=127.0.0.1:5432",
+                "--http-listen",
+                "localhost:8000",
+            ])
+            .args(["--peer", "127.0.0.1:3301,127.0.0.1:3302"])
+            .args(["--instance-name", "default_1_1"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "run --iproto-listen 127.0.0.1:3401 --pg-listen=127.0.0.1:5532 \
+             --http-listen 127.0.0.1:8100 --peer 127.0.0.1:3401,127.0.0.1:3402 \
+             --instance-name default_1_1\n"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Resolution of admin socket paths of cluster instances.
+//!
+//! Pike creates admin socket of the instance at
+//! `<data_dir>/cluster/<instance_name>/admin.sock`. Clusters started by other
+//! versions of picodata and pike or manually may use different layout, which
+//! is described by custom [`SocketResolver`] or by template of the socket path
+//! set through [`PICOTEST_ADMIN_SOCKET`] environment variable.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::admin_socket::SocketTemplate;
+//!
+//! let cluster = Cluster::new(plugin_path, topology, picodata_path)?
+//!     .socket_resolver(SocketTemplate::new("{data_dir}/{instance_name}/console.sock"))
+//!     .run()?;
+//!
+//! // Or explicit path of every instance.
+//! let cluster = Cluster::new(plugin_path, topology, picodata_path)?
+//!     .socket_resolver(|_: &Path, instance_name: &str| {
+//!         PathBuf::from(format!("/var/run/picodata/{instance_name}.sock"))
+//!     })
+//!     .run()?;
+//! ```
+
+use crate::ADMIN_SOCKET_NAME;
+use anyhow::bail;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Environment variable with template of the admin socket path,
+/// see [`SocketTemplate`].
+pub const PICOTEST_ADMIN_SOCKET: &str = "PICOTEST_ADMIN_SOCKET";
+
+/// Resolves where admin socket of the instance is located.
+pub trait SocketResolver: Send + Sync {
+    /// Returns paths, where admin socket of the instance `instance_name`
+    /// of the cluster located in `data_dir` may be found, in order of preference.
+    fn candidates(&self, data_dir: &Path, instance_name: &str) -> Vec<PathBuf>;
+}
+
+/// Explicit path of the socket returned by the function.
+impl<F> SocketResolver for F
+where
+    F: Fn(&Path, &str) -> PathBuf + Send + Sync,
+{
+    fn candidates(&self, data_dir: &Path, instance_name: &str) -> Vec<PathBuf> {
+        vec![self(data_dir, instance_name)]
+    }
+}
+
+/// Layout of the cluster started by pike.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PikeLayout;
+
+impl SocketResolver for PikeLayout {
+    fn candidates(&self, data_dir: &Path, instance_name: &str) -> Vec<PathBuf> {
+        vec![data_dir
+            .join("cluster")
+            .join(instance_name)
+            .join(ADMIN_SOCKET_NAME)]
+    }
+}
+
+/// Socket path built from the template with `{data_dir}` and
+/// `{instance_name}` placeholders, e.g. `/var/run/picodata/{instance_name}.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketTemplate(String);
+
+impl SocketTemplate {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self(template.into())
+    }
+}
+
+impl SocketResolver for SocketTemplate {
+    fn candidates(&self, data_dir: &Path, instance_name: &str) -> Vec<PathBuf> {
+        let path = self
+            .0
+            .replace("{data_dir}", &data_dir.to_string_lossy())
+            .replace("{instance_name}", instance_name);
+        vec![PathBuf::from(path)]
+    }
+}
+
+/// Returns resolver of the template set by [`PICOTEST_ADMIN_SOCKET`]
+/// or [`PikeLayout`] if it isn't set.
+pub fn resolver_from_env() -> Arc<dyn SocketResolver> {
+    match env::var(PICOTEST_ADMIN_SOCKET) {
+        Ok(template) if !template.is_empty() => Arc::new(SocketTemplate::new(template)),
+        _ => Arc::new(PikeLayout),
+    }
+}
+
+/// Returns the first existing candidate of the admin socket path.
+///
+/// ### Returns
+/// Error listing searched paths if none of them exists.
+pub fn resolve_socket(
+    resolver: &dyn SocketResolver,
+    data_dir: &Path,
+    instance_name: &str,
+) -> anyhow::Result<PathBuf> {
+    let candidates = resolver.candidates(data_dir, instance_name);
+    if let Some(path) = candidates.iter().find(|path| path.exists()) {
+        return Ok(path.clone());
+    }
+
+    let searched: Vec<String> = candidates
+        .iter()
+        .map(|path| format!("  {}", path.display()))
+        .collect();
+    bail!(
+        "admin socket of instance '{instance_name}' is not found, searched:\n{}\n\
+         Set {PICOTEST_ADMIN_SOCKET} or `Cluster::socket_resolver` if the cluster uses another layout",
+        searched.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_socket, PikeLayout, SocketResolver, SocketTemplate};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[rstest]
+    fn test_socket_candidates() {
+        let data_dir = Path::new("tmp/cluster");
+        assert_eq!(
+            PikeLayout.candidates(data_dir, "default_1_1"),
+            [PathBuf::from("tmp/cluster/cluster/default_1_1/admin.sock")]
+        );
+        assert_eq!(
+            SocketTemplate::new("{data_dir}/{instance_name}.sock").candidates(data_dir, "i1"),
+            [PathBuf::from("tmp/cluster/i1.sock")]
+        );
+
+        let explicit =
+            |_: &Path, instance_name: &str| PathBuf::from(format!("/run/{instance_name}"));
+        assert_eq!(
+            explicit.candidates(data_dir, "i1"),
+            [PathBuf::from("/run/i1")]
+        );
+    }
+
+    #[rstest]
+    fn test_resolve_socket() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let socket_dir = dir.join("cluster").join("default_1_1");
+        fs::create_dir_all(&socket_dir).unwrap();
+        fs::write(socket_dir.join("admin.sock"), "").unwrap();
+
+        assert_eq!(
+            resolve_socket(&PikeLayout, &dir, "default_1_1").unwrap(),
+            socket_dir.join("admin.sock")
+        );
+
+        let err = resolve_socket(&PikeLayout, &dir, "default_2_1").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("admin socket of instance 'default_2_1' is not found"));
+        assert!(message.contains(
+            &dir.join("cluster/default_2_1/admin.sock")
+                .display()
+                .to_string()
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,413 @@
+//! Assertions on table data.
+//!
+//! Rows are fetched through [`Cluster::sql_query`] and compared as
+//! mappings of column names to values, so expected rows can be
+//! represented by any serializable struct or map.
+
+use crate::lua::lua_string_literal;
+use crate::sql::{quote_identifier, to_row_map, SqlRows};
+use crate::{Cluster, PicotestInstance};
+use serde::Serialize;
+use serde_norway::{Mapping, Value};
+use std::fmt::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONSISTENCY_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+impl Cluster {
+    /// Asserts that table has at least one row, which columns
+    /// are equal to corresponding fields of `filter`.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use std::collections::BTreeMap;
+    ///
+    /// #[picotest]
+    /// fn test_user_is_inserted() {
+    ///     cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')").unwrap();
+    ///     cluster.assert_row_exists("users", &BTreeMap::from([("name", "Alice")]));
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn assert_row_exists<F: Serialize + ?Sized>(&self, table: &str, filter: &F) {
+        let filter = to_row_map(filter).expect("Filter should serialize into a mapping");
+
+        let mut conditions = Vec::with_capacity(filter.len());
+        let mut params = Vec::with_capacity(filter.len());
+        for (column, value) in &filter {
+            let Value::String(column) = column else {
+                panic!("Filter has non-string column name {column:?}");
+            };
+            if value.is_null() {
+                conditions.push(format!("{} IS NULL", quote_identifier(column)));
+            } else {
+                conditions.push(format!("{} = ?", quote_identifier(column)));
+                params.push(value.clone());
+            }
+        }
+
+        let mut query = format!("SELECT * FROM {}", quote_identifier(table));
+        if !conditions.is_empty() {
+            write!(query, " WHERE {}", conditions.join(" AND ")).unwrap();
+        }
+
+        let result = self
+            .sql_query(&query, &params)
+            .unwrap_or_else(|err| panic!("Failed to select from table '{table}': {err:#}"));
+        assert!(
+            !result.is_empty(),
+            "No row matching {} in table '{table}'",
+            format_row(&filter)
+        );
+    }
+
+    /// Asserts that table contains exactly `expected_rows` in any order.
+    ///
+    /// On failure, panics with a diff of missing (`-`) and unexpected (`+`) rows.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct User {
+    ///     id: u64,
+    ///     name: String,
+    /// }
+    ///
+    /// #[picotest]
+    /// fn test_users() {
+    ///     cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')").unwrap();
+    ///     cluster.assert_table_eq("users", &[User { id: 1, name: "Alice".into() }]);
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn assert_table_eq<T: Serialize>(&self, table: &str, expected_rows: &[T]) {
+        let expected: Vec<Mapping> = expected_rows
+            .iter()
+            .map(|row| to_row_map(row).expect("Expected row should serialize into a mapping"))
+            .collect();
+
+        let actual = self
+            .sql_query(&format!("SELECT * FROM {}", quote_identifier(table)), &[])
+            .unwrap_or_else(|err| panic!("Failed to select from table '{table}': {err:#}"));
+
+        if let Some(diff) = rows_diff(&expected, &actual) {
+            panic!("Table '{table}' content differs from expected:\n{diff}");
+        }
+    }
+
+    /// Asserts that `query` returns the same rows (in any order) on every
+    /// instance of the cluster within `timeout`.
+    ///
+    /// Results are compared with result of the main instance. On failure,
+    /// panics with a diff for every diverged instance.
+    ///
+    /// **Note:** query is executed by `pico.sql` on each instance. Queries
+    /// to global tables read local data of the instance, while queries to
+    /// sharded tables are routed to storages and can't reveal replication lag.
+    /// Use [`Cluster::assert_replicaset_consistent`] to compare local data
+    /// of sharded tables.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[picotest]
+    /// fn test_config_is_replicated() {
+    ///     cluster.assert_consistent(
+    ///         "SELECT * FROM _pico_plugin_config",
+    ///         Duration::from_secs(10),
+    ///     );
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn assert_consistent(&self, query: &str, timeout: Duration) {
+        let instances: Vec<&PicotestInstance> = self.instances().iter().collect();
+        assert_converges(&instances, &format!("'{query}'"), timeout, || {
+            find_divergences(&instances, query)
+        });
+    }
+
+    /// Asserts that local data of `table` is the same (in any order)
+    /// on every replica of the replicaset within `timeout`.
+    ///
+    /// Tuples are read by `box.space[table]:select()` on each replica, so
+    /// unlike [`Cluster::assert_consistent`] it reveals replication lag and
+    /// divergence of sharded tables. On failure, panics with a diff of tuples
+    /// for every diverged replica.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[picotest]
+    /// fn test_orders_are_replicated() {
+    ///     cluster.run_sql("INSERT INTO orders VALUES (1, 'new')").unwrap();
+    ///     cluster.assert_replicaset_consistent("default_1", "orders", Duration::from_secs(10));
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn assert_replicaset_consistent(
+        &self,
+        replicaset_name: &str,
+        table: &str,
+        timeout: Duration,
+    ) {
+        let instances = self
+            .replicaset_instances(replicaset_name)
+            .unwrap_or_else(|err| {
+                panic!("Failed to get instances of '{replicaset_name}': {err:#}")
+            });
+        assert_converges(
+            &instances,
+            &format!("local data of '{table}'"),
+            timeout,
+            || find_local_divergences(&instances, table),
+        );
+    }
+}
+
+/// Polls `find_divergences` until it returns no divergences or `timeout` expires.
+#[track_caller]
+fn assert_converges(
+    instances: &[&PicotestInstance],
+    what: &str,
+    timeout: Duration,
+    find_divergences: impl Fn() -> anyhow::Result<Vec<(String, String)>>,
+) {
+    let start_time = Instant::now();
+    loop {
+        let report = match find_divergences() {
+            Ok(divergences) if divergences.is_empty() => return,
+            Ok(divergences) => divergences
+                .into_iter()
+                .map(|(instance_name, diff)| format!("[{instance_name}]\n{diff}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(err) => format!("{err:#}"),
+        };
+
+        if start_time.elapsed() > timeout {
+            panic!(
+                "Results of {what} didn't converge within {}s, \
+                compared with '{}':\n{report}",
+                timeout.as_secs_f64(),
+                instances[0].instance_name
+            );
+        }
+        thread::sleep(CONSISTENCY_POLL_INTERVAL);
+    }
+}
+
+/// Runs `query` on every instance and returns diffs
+/// of results, which differ from result of the first one.
+fn find_divergences(
+    instances: &[&PicotestInstance],
+    query: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let Some((reference, others)) = instances.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    let expected = reference.sql_query(query, &[])?.to_maps();
+    let mut divergences = Vec::new();
+    for instance in others {
+        let actual = instance.sql_query(query, &[])?;
+        if let Some(diff) = rows_diff(&expected, &actual) {
+            divergences.push((instance.instance_name.clone(), diff));
+        }
+    }
+    Ok(divergences)
+}
+
+/// Reads local tuples of `table` on every instance and returns diffs
+/// of tuples, which differ from tuples of the first one.
+fn find_local_divergences(
+    instances: &[&PicotestInstance],
+    table: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let Some((reference, others)) = instances.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    let script = local_tuples_lua(table);
+    let expected: Vec<Value> = reference.run_lua_typed(&script)?;
+    let mut divergences = Vec::new();
+    for instance in others {
+        let actual: Vec<Value> = instance.run_lua_typed(&script)?;
+        if let Some(diff) = tuples_diff(&expected, &actual) {
+            divergences.push((instance.instance_name.clone(), diff));
+        }
+    }
+    Ok(divergences)
+}
+
+/// Lua script returning all tuples of the local space of `table`.
+fn local_tuples_lua(table: &str) -> String {
+    let table = lua_string_literal(table);
+    format!(
+        "local space = box.space[{table}]\n\
+        if space == nil then error('space ' .. {table} .. ' is not found') end\n\
+        return space:select()"
+    )
+}
+
+/// Compares tuples regardless of their order. Returns diff of missing
+/// and unexpected tuples or `None` if tuples are equal.
+fn tuples_diff(expected: &[Value], actual: &[Value]) -> Option<String> {
+    let mut unexpected: Vec<&Value> = actual.iter().collect();
+    let mut missing = Vec::new();
+    for tuple in expected {
+        match unexpected.iter().position(|actual| *actual == tuple) {
+            Some(index) => {
+                unexpected.swap_remove(index);
+            }
+            None => missing.push(tuple),
+        }
+    }
+
+    if missing.is_empty() && unexpected.is_empty() {
+        return None;
+    }
+
+    let mut diff = String::new();
+    for tuple in missing {
+        writeln!(diff, "- {}", format_tuple(tuple)).unwrap();
+    }
+    for tuple in unexpected {
+        writeln!(diff, "+ {}", format_tuple(tuple)).unwrap();
+    }
+    Some(diff)
+}
+
+/// Formats tuple as a single line YAML flow sequence.
+fn format_tuple(tuple: &Value) -> String {
+    match tuple {
+        Value::Sequence(fields) => {
+            let fields: Vec<String> = fields.iter().map(format_value).collect();
+            format!("[{}]", fields.join(", "))
+        }
+        value => format_value(value),
+    }
+}
+
+/// Compares rows regardless of their order. Returns diff of missing
+/// and unexpected rows or `None` if rows are equal.
+pub(crate) fn rows_diff(expected: &[Mapping], actual: &SqlRows) -> Option<String> {
+    let mut unexpected = actual.to_maps();
+    let mut missing = Vec::new();
+    for row in expected {
+        match unexpected.iter().position(|actual_row| actual_row == row) {
+            Some(index) => {
+                unexpected.swap_remove(index);
+            }
+            None => missing.push(row),
+        }
+    }
+
+    if missing.is_empty() && unexpected.is_empty() {
+        return None;
+    }
+
+    let mut diff = String::new();
+    for row in missing {
+        writeln!(diff, "- {}", format_row(row)).unwrap();
+    }
+    for row in &unexpected {
+        writeln!(diff, "+ {}", format_row(row)).unwrap();
+    }
+    Some(diff)
+}
+
+/// Formats row as a single line YAML flow mapping.
+fn format_row(row: &Mapping) -> String {
+    let columns: Vec<String> = row
+        .iter()
+        .map(|(column, value)| format!("{}: {}", format_value(column), format_value(value)))
+        .collect();
+    format!("{{{}}}", columns.join(", "))
+}
+
+fn format_value(value: &Value) -> String {
+    serde_norway::to_string(value)
+        .map(|yaml| yaml.trim_end().to_string())
+        .unwrap_or_else(|_| format!("{value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_row, local_tuples_lua, rows_diff, tuples_diff};
+    use crate::sql::SqlRows;
+    use rstest::rstest;
+    use serde_norway::{Mapping, Value};
+
+    fn rows(yaml: &str) -> SqlRows {
+        serde_norway::from_str(yaml).unwrap()
+    }
+
+    fn maps(yaml: &str) -> Vec<Mapping> {
+        serde_norway::from_str(yaml).unwrap()
+    }
+
+    #[rstest]
+    fn test_rows_diff_ignores_order() {
+        let actual = rows("{metadata: [{name: id, type: integer}], rows: [[1], [2]]}");
+        assert_eq!(rows_diff(&maps("[{id: 2}, {id: 1}]"), &actual), None);
+    }
+
+    #[rstest]
+    fn test_rows_diff() {
+        let actual = rows(
+            "{metadata: [{name: id, type: integer}, {name: name, type: string}], \
+            rows: [[1, Alice], [2, Bob]]}",
+        );
+        let diff = rows_diff(
+            &maps("[{id: 1, name: Alice}, {id: 2, name: Robert}]"),
+            &actual,
+        )
+        .unwrap();
+
+        assert_eq!(diff, "- {id: 2, name: Robert}\n+ {id: 2, name: Bob}\n");
+    }
+
+    #[rstest]
+    fn test_format_row() {
+        let row = &maps("[{id: 1, name: 'a b', tags: null}]")[0];
+        assert_eq!(format_row(row), "{id: 1, name: a b, tags: null}");
+    }
+
+    #[rstest]
+    fn test_tuples_diff() {
+        let expected: Vec<Value> =
+            serde_norway::from_str("[[1, Alice, 100], [2, Bob, 200]]").unwrap();
+        let reordered: Vec<Value> =
+            serde_norway::from_str("[[2, Bob, 200], [1, Alice, 100]]").unwrap();
+        let lagging: Vec<Value> = serde_norway::from_str("[[1, Alice, 100]]").unwrap();
+
+        assert_eq!(tuples_diff(&expected, &reordered), None);
+        assert_eq!(
+            tuples_diff(&expected, &lagging).unwrap(),
+            "- [2, Bob, 200]\n"
+        );
+    }
+
+    #[rstest]
+    fn test_local_tuples_lua() {
+        assert_eq!(
+            local_tuples_lua("orders"),
+            "local space = box.space[\"orders\"]\n\
+            if space == nil then error('space ' .. \"orders\" .. ' is not found') end\n\
+            return space:select()"
+        );
+    }
+}
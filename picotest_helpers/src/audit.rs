@@ -0,0 +1,264 @@
+//! Audit log of cluster instances.
+//!
+//! Audit is enabled by [`Cluster::enable_audit`]. Pike starts instances by
+//! executing picodata binary, so it's pointed to a generated launcher script,
+//! which sets `PICODATA_AUDIT_LOG` to the [`AUDIT_LOG_NAME`] file in the
+//! instance directory. Every line of the log is a JSON record.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let mark = cluster.audit_mark()?;
+//! cluster.run_sql("CREATE USER \"alice\" WITH PASSWORD 'P@ssw0rd'")?;
+//! let event = cluster.wait_audit_event(&mark, "create_user", Duration::from_secs(5))?;
+//! assert_eq!(event.fields["user"], "alice");
+//! ```
+
+use crate::compat::Capability;
+use crate::launcher::{self, INSTANCE_DIR_SCRIPT};
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use log::debug;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Name of the audit log file in the instance directory.
+pub const AUDIT_LOG_NAME: &str = "audit.log";
+
+/// Directory (relative to the plugin path) of generated launcher scripts.
+pub const AUDIT_LAUNCHERS_DIR: &str = "tmp/audit";
+
+/// Interval between checks whether awaited audit event is written.
+const AUDIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Record of the instance audit log.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AuditEvent {
+    /// Name of the instance, which has written the record.
+    #[serde(skip)]
+    pub instance_name: String,
+    pub id: String,
+    pub time: String,
+    pub severity: String,
+    /// Type of the event, e.g. `create_user` or `auth_ok`.
+    pub title: String,
+    pub message: String,
+    /// Event specific fields, e.g. `user` or `initiator`.
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Position in audit logs of cluster instances, see [`Cluster::audit_mark`].
+///
+/// Default mark points to the beginning of the logs.
+#[derive(Debug, Clone, Default)]
+pub struct AuditMark {
+    offsets: BTreeMap<String, u64>,
+}
+
+/// Writes launcher script running `picodata_path` with audit enabled.
+///
+/// ### Returns
+/// Path to the script to be used as picodata binary.
+pub(crate) fn write_launcher(
+    plugin_path: &Path,
+    cluster_uuid: &Uuid,
+    picodata_path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let launcher_path = launcher::write_launcher(
+        &plugin_path
+            .join(AUDIT_LAUNCHERS_DIR)
+            .join(cluster_uuid.to_string()),
+        "picodata",
+        &launcher_script(picodata_path),
+    )?;
+
+    debug!(
+        "Instances will be started with audit log by '{}'",
+        launcher_path.display()
+    );
+    Ok(launcher_path)
+}
+
+fn launcher_script(picodata_path: &Path) -> String {
+    let exec = launcher::exec_line(picodata_path);
+    format!(
+        r#"#!/bin/sh
+# Generated by picotest: runs picodata with audit log in the instance directory.
+set -e
+{INSTANCE_DIR_SCRIPT}
+if [ -n "$instance_dir" ]; then
+    PICODATA_AUDIT_LOG="$instance_dir/{AUDIT_LOG_NAME}"
+    export PICODATA_AUDIT_LOG
+fi
+{exec}"#
+    )
+}
+
+/// Parses complete lines of the audit log.
+///
+/// ### Returns
+/// Events and length of the parsed part. Trailing line, which is
+/// still being written, is left for the next read.
+fn parse_events(log: &str, instance_name: &str) -> anyhow::Result<(Vec<AuditEvent>, usize)> {
+    let parsed_len = log.rfind('\n').map_or(0, |pos| pos + 1);
+    let events = log[..parsed_len]
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut event: AuditEvent = serde_json::from_str(line)
+                .with_context(|| format!("invalid audit record: {line}"))?;
+            event.instance_name = instance_name.to_string();
+            Ok(event)
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok((events, parsed_len))
+}
+
+impl Cluster {
+    /// Enables audit log of cluster instances.
+    ///
+    /// Records are read by [`Cluster::audit_events`].
+    pub fn enable_audit(mut self, is_enabled: bool) -> Self {
+        self.audit = is_enabled;
+        self
+    }
+
+    fn audit_log_path(&self, instance: &PicotestInstance) -> PathBuf {
+        self.data_dir_path()
+            .join("cluster")
+            .join(&instance.instance_name)
+            .join(AUDIT_LOG_NAME)
+    }
+
+    /// Reads audit log of the `instance` starting from `offset`.
+    ///
+    /// ### Returns
+    /// Events and offset of the first unread byte.
+    fn read_audit_log(
+        &self,
+        instance: &PicotestInstance,
+        offset: u64,
+    ) -> anyhow::Result<(Vec<AuditEvent>, u64)> {
+        let path = self.audit_log_path(instance);
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => bail!(
+                "audit log '{}' is not found, is audit enabled by `Cluster::enable_audit`?",
+                path.display()
+            ),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to open '{}'", path.display()))
+            }
+        };
+
+        let mut log = String::new();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_to_string(&mut log)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        let (events, parsed_len) = parse_events(&log, &instance.instance_name)?;
+        Ok((events, offset + parsed_len as u64))
+    }
+
+    /// Returns current position in audit logs of all instances.
+    ///
+    /// Events written after this call are returned by [`Cluster::audit_events`].
+    pub fn audit_mark(&self) -> anyhow::Result<AuditMark> {
+        self.main().ensure_supports(Capability::AuditLogEnv)?;
+        let mut offsets = BTreeMap::new();
+        for instance in self.instances() {
+            let (_, offset) = self.read_audit_log(instance, 0)?;
+            offsets.insert(instance.instance_name.clone(), offset);
+        }
+        Ok(AuditMark { offsets })
+    }
+
+    /// Returns audit events of all instances written after `since`.
+    ///
+    /// Events are grouped by instance in the order they were written.
+    pub fn audit_events(&self, since: &AuditMark) -> anyhow::Result<Vec<AuditEvent>> {
+        let mut events = Vec::new();
+        for instance in self.instances() {
+            let offset = since
+                .offsets
+                .get(&instance.instance_name)
+                .copied()
+                .unwrap_or_default();
+            events.extend(self.read_audit_log(instance, offset)?.0);
+        }
+        Ok(events)
+    }
+
+    /// Waits until event with the `title` is written after `since`
+    /// on any instance.
+    ///
+    /// ### Returns
+    /// The first of such events.
+    pub fn wait_audit_event(
+        &self,
+        since: &AuditMark,
+        title: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<AuditEvent> {
+        let start_time = Instant::now();
+        loop {
+            let events = self.audit_events(since)?;
+            if let Some(event) = events.into_iter().find(|event| event.title == title) {
+                return Ok(event);
+            }
+            if start_time.elapsed() > timeout {
+                bail!(
+                    "audit event '{title}' is not written within {}s",
+                    timeout.as_secs_f64()
+                );
+            }
+            thread::sleep(AUDIT_POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{launcher_script, parse_events};
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    fn test_launcher_script() {
+        let script = launcher_script(Path::new("/usr/bin/picodata"));
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(r#"--instance-dir=*|--data-dir=*) instance_dir=${arg#*=} ;;"#));
+        assert!(script.contains(r#"PICODATA_AUDIT_LOG="$instance_dir/audit.log""#));
+        assert!(script.ends_with("exec '/usr/bin/picodata' \"$@\"\n"));
+    }
+
+    #[rstest]
+    fn test_parse_events() {
+        let log = concat!(
+            r#"{"id":"1.0.1","time":"2025-01-01T00:00:00.000+0000","severity":"low","title":"local_startup","message":"instance is starting"}"#,
+            "\n",
+            r#"{"id":"1.0.2","time":"2025-01-01T00:00:01.000+0000","severity":"high","title":"create_user","message":"user `alice` was created","user":"alice","initiator":"admin"}"#,
+            "\n",
+            r#"{"id":"1.0.3","time":"2025-01-01T00:00:02"#,
+        );
+
+        let (events, parsed_len) = parse_events(log, "default_1_1").unwrap();
+        assert_eq!(parsed_len, log.rfind('\n').unwrap() + 1);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].title, "local_startup");
+        assert!(events[0].fields.is_empty());
+        assert_eq!(events[1].instance_name, "default_1_1");
+        assert_eq!(events[1].severity, "high");
+        assert_eq!(events[1].fields["user"], "alice");
+        assert_eq!(events[1].fields["initiator"], "admin");
+
+        assert!(parse_events("{\"id\":\n", "default_1_1").is_err());
+        assert_eq!(parse_events("", "default_1_1").unwrap().1, 0);
+    }
+}
@@ -0,0 +1,253 @@
+//! Backup and restore of the running cluster.
+//!
+//! Unlike [`Cluster::freeze`], backup is taken without stopping the cluster:
+//! every instance makes a checkpoint (`box.snapshot()`) and its files are
+//! copied while garbage collection is paused by `box.backup.start()`. So
+//! backup contains data written before the checkpoint of each instance.
+//!
+//! Backup directory contains a directory per instance with files of its
+//! checkpoint. Cluster restored by [`Cluster::restore`] must have the same
+//! topology, so instances are started from their own files.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')")?;
+//! let expected = cluster.capture_tables(&["users"])?;
+//! cluster.backup(&backup_dir)?;
+//!
+//! let restored = Cluster::new(plugin_path, topology, picodata_path)?.restore(&backup_dir)?;
+//! restored.assert_tables_restored(&expected);
+//! ```
+
+use crate::assertions::rows_diff;
+use crate::sql::quote_identifier;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use serde_norway::Mapping;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Makes a checkpoint and returns paths to its files, which
+/// aren't removed until `box.backup.stop()` is called.
+const BACKUP_START_LUA: &str = r#"
+box.snapshot()
+local files = {}
+for _, path in ipairs(box.backup.start()) do
+    table.insert(files, require("fio").abspath(path))
+end
+return files"#;
+
+const BACKUP_STOP_LUA: &str = "box.backup.stop()";
+
+impl Cluster {
+    /// Copies checkpoint of every instance into `dest_dir`, which must
+    /// be empty or not exist, see [module docs](self).
+    ///
+    /// ### Returns
+    /// - On failure, instance of [`anyhow::Error`], e.g. if cluster is
+    ///   remote, so files of the instances aren't accessible.
+    pub fn backup(&self, dest_dir: &Path) -> anyhow::Result<()> {
+        if self.is_remote() {
+            bail!("remote cluster can't be backed up");
+        }
+        let is_empty = fs::read_dir(dest_dir).map_or(true, |mut entries| entries.next().is_none());
+        if !is_empty {
+            bail!("backup directory '{}' isn't empty", dest_dir.display());
+        }
+
+        let data_dir = self.data_dir_path();
+        for instance in self.instances() {
+            let instance_dir = data_dir.join("cluster").join(&instance.instance_name);
+            let backup_dir = dest_dir.join(&instance.instance_name);
+            backup_instance(instance, &instance_dir, &backup_dir).with_context(|| {
+                format!("failed to back up instance '{}'", instance.instance_name)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Starts the cluster from the backup made by [`Cluster::backup`].
+    ///
+    /// Cluster is stopped first, if it's running, and its data directory
+    /// is replaced with the files of the backup.
+    pub fn restore(self, backup_dir: &Path) -> anyhow::Result<Self> {
+        if self.is_remote() {
+            bail!("remote cluster can't be restored from backup");
+        }
+        let instance_dirs = fs::read_dir(backup_dir)
+            .with_context(|| format!("failed to read backup '{}'", backup_dir.display()))?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .collect::<Vec<_>>();
+        if instance_dirs.is_empty() {
+            bail!("backup '{}' contains no instances", backup_dir.display());
+        }
+
+        self.restart_with_data(|data_dir| {
+            debug!(
+                "Restoring data directory '{}' from backup '{}'",
+                data_dir.display(),
+                backup_dir.display()
+            );
+            for entry in &instance_dirs {
+                copy_dir(
+                    &entry.path(),
+                    &data_dir.join("cluster").join(entry.file_name()),
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Selects all rows of the `tables` to be compared with the restored
+    /// cluster by [`Cluster::assert_tables_restored`].
+    pub fn capture_tables(&self, tables: &[&str]) -> anyhow::Result<TablesContent> {
+        let mut content = BTreeMap::new();
+        for table in tables {
+            let rows = self
+                .sql_query(&format!("SELECT * FROM {}", quote_identifier(table)), &[])
+                .with_context(|| format!("failed to select from table '{table}'"))?;
+            content.insert(table.to_string(), rows.to_maps());
+        }
+        Ok(TablesContent(content))
+    }
+
+    /// Asserts that tables of the cluster contain the same rows
+    /// (in any order) as the captured ones.
+    ///
+    /// Panics with a diff of every mismatched table.
+    #[track_caller]
+    pub fn assert_tables_restored(&self, expected: &TablesContent) {
+        let mut mismatches = Vec::new();
+        for (table, expected_rows) in &expected.0 {
+            let actual = self
+                .sql_query(&format!("SELECT * FROM {}", quote_identifier(table)), &[])
+                .unwrap_or_else(|err| panic!("Failed to select from table '{table}': {err:#}"));
+            if let Some(diff) = rows_diff(expected_rows, &actual) {
+                mismatches.push(format!("Table '{table}':\n{diff}"));
+            }
+        }
+        if !mismatches.is_empty() {
+            panic!(
+                "Restored tables differ from captured ones:\n{}",
+                mismatches.join("\n")
+            );
+        }
+    }
+}
+
+/// Rows of the tables captured by [`Cluster::capture_tables`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TablesContent(BTreeMap<String, Vec<Mapping>>);
+
+impl TablesContent {
+    /// Returns captured rows of the `table`.
+    pub fn rows(&self, table: &str) -> Option<&[Mapping]> {
+        self.0.get(table).map(Vec::as_slice)
+    }
+}
+
+fn backup_instance(
+    instance: &PicotestInstance,
+    instance_dir: &Path,
+    backup_dir: &Path,
+) -> anyhow::Result<()> {
+    let files: Vec<String> = instance.run_lua_typed(BACKUP_START_LUA)?;
+    let result = files.iter().try_for_each(|file| {
+        let relative = relative_to_instance_dir(Path::new(file), &instance.instance_name)
+            .with_context(|| {
+                format!("backup file '{file}' is outside of the instance directory")
+            })?;
+        let dest = backup_dir.join(&relative);
+        debug!("Backing up '{file}' into '{}'", dest.display());
+        fs::create_dir_all(dest.parent().unwrap_or(backup_dir))?;
+        fs::copy(instance_dir.join(&relative), &dest)
+            .with_context(|| format!("failed to copy '{file}'"))?;
+        Ok(())
+    });
+
+    // Garbage collection is resumed even if copying has failed.
+    if let Err(err) = instance.run_lua(BACKUP_STOP_LUA) {
+        warn!(
+            "Failed to stop backup of '{}': {err}",
+            instance.instance_name
+        );
+    }
+    result
+}
+
+/// Returns path of the file of instance relative to its directory
+/// `<data dir>/cluster/<instance name>`.
+///
+/// Path is reported by the instance, so it's matched by the directory
+/// layout instead of the prefix, which differs in the container.
+fn relative_to_instance_dir(path: &Path, instance_name: &str) -> Option<PathBuf> {
+    let components: Vec<Component> = path.components().collect();
+    let position = components.windows(2).rposition(|pair| {
+        pair[0].as_os_str() == "cluster" && pair[1].as_os_str() == instance_name
+    })?;
+    let relative: PathBuf = components[position + 2..].iter().collect();
+    let is_nested = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (is_nested && relative.components().next().is_some()).then_some(relative)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("failed to create '{}'", to.display()))?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)
+                .with_context(|| format!("failed to copy '{}'", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_dir, relative_to_instance_dir};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[rstest]
+    #[case::snapshot(
+        "/plugin/tmp/tests/abc/cluster/default_1_1/00000000000000000042.snap",
+        Some("00000000000000000042.snap")
+    )]
+    #[case::nested(
+        "/data/cluster/default_1_1/vinyl/512/0/00000000000000000001.index",
+        Some("vinyl/512/0/00000000000000000001.index")
+    )]
+    #[case::other_instance("/data/cluster/default_1_2/00000000000000000042.snap", None)]
+    #[case::instance_dir("/data/cluster/default_1_1", None)]
+    fn test_relative_to_instance_dir(#[case] path: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            relative_to_instance_dir(Path::new(path), "default_1_1"),
+            expected.map(PathBuf::from)
+        );
+    }
+
+    #[rstest]
+    fn test_copy_dir() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let from = dir.join("backup/default_1_1");
+        fs::create_dir_all(from.join("vinyl/512")).unwrap();
+        fs::write(from.join("1.snap"), "snap").unwrap();
+        fs::write(from.join("vinyl/512/1.run"), "run").unwrap();
+
+        let to = dir.join("data/cluster/default_1_1");
+        copy_dir(&from, &to).unwrap();
+        let snap = fs::read_to_string(to.join("1.snap")).unwrap();
+        let run = fs::read_to_string(to.join("vinyl/512/1.run")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((snap.as_str(), run.as_str()), ("snap", "run"));
+    }
+}
@@ -0,0 +1,440 @@
+//! Recording and replaying interactions with the cluster.
+//!
+//! In record mode, requests to the cluster (admin console queries, Lua
+//! scripts and RPC calls) are executed as usual and their responses are
+//! appended to the cassette file along with instances of the cluster.
+//! In replay mode, cluster isn't started at all: [`Cluster::replay`] builds
+//! the cluster of recorded instances, and responses are served from the
+//! cassette. It makes a fast inner loop for tests, whose logic doesn't
+//! depend on fresh cluster state.
+//!
+//! Requests are matched by their kind, instance and text. Responses to the
+//! same request are replayed in the recorded order, the last one is repeated.
+//! Connections over pgproto aren't recorded.
+
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Environment variable, which enables the cassette: `record` or `replay`.
+pub const PICOTEST_CASSETTE: &str = "PICOTEST_CASSETTE";
+
+/// Environment variable, which overrides path to the cassette file.
+pub const PICOTEST_CASSETTE_PATH: &str = "PICOTEST_CASSETTE_PATH";
+
+/// Default path to the cassette file, relative to the plugin path.
+pub const DEFAULT_CASSETTE_PATH: &str = "tmp/cassette.jsonl";
+
+static CASSETTE: OnceLock<Cassette> = OnceLock::new();
+
+/// What the cassette does with interactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Interactions are executed and written to the cassette.
+    Record,
+    /// Interactions are served from the cassette.
+    Replay,
+}
+
+impl FromStr for CassetteMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            _ => bail!("unknown cassette mode '{s}', expected 'record' or 'replay'"),
+        }
+    }
+}
+
+/// Kind of the request to the cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionKind {
+    /// Query executed by admin console.
+    Query,
+    /// Lua script, see [`PicotestInstance::run_lua`].
+    Lua,
+    /// RPC call, see [`PicotestInstance::execute_rpc`].
+    Rpc,
+}
+
+/// Instance of the recorded cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedInstance {
+    pub instance_name: String,
+    pub tier: String,
+    pub instance_id: u16,
+    pub bin_port: u16,
+    pub pg_port: u16,
+    pub http_port: u16,
+}
+
+/// Request to the instance and its response: value or error message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub kind: InteractionKind,
+    pub instance_name: String,
+    pub request: String,
+    pub response: Result<serde_json::Value, String>,
+}
+
+/// Line of the cassette file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CassetteEntry {
+    Instance(RecordedInstance),
+    Interaction(Interaction),
+}
+
+type InteractionKey = (InteractionKind, String, String);
+
+/// Error returned by the replayed interaction.
+pub(crate) trait ReplayedError: Display {
+    fn replayed(message: String) -> Self;
+}
+
+impl ReplayedError for std::io::Error {
+    fn replayed(message: String) -> Self {
+        std::io::Error::other(message)
+    }
+}
+
+impl ReplayedError for anyhow::Error {
+    fn replayed(message: String) -> Self {
+        anyhow::Error::msg(message)
+    }
+}
+
+/// Recording of interactions with the cluster.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    /// File interactions are appended to in record mode.
+    file: Mutex<Option<File>>,
+    /// Instances of the recorded cluster.
+    instances: Vec<RecordedInstance>,
+    /// Responses left to be replayed.
+    responses: Mutex<HashMap<InteractionKey, VecDeque<Result<serde_json::Value, String>>>>,
+}
+
+impl Cassette {
+    /// Creates empty cassette file located by `path` to record interactions into.
+    pub fn record(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory '{}'", parent.display()))?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("failed to create cassette '{}'", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode: CassetteMode::Record,
+            file: Mutex::new(Some(file)),
+            instances: Vec::new(),
+            responses: Mutex::default(),
+        })
+    }
+
+    /// Loads cassette file located by `path` to replay interactions from.
+    pub fn replay(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| {
+            format!(
+                "failed to open cassette '{}', record it with {PICOTEST_CASSETTE}=record",
+                path.display()
+            )
+        })?;
+
+        let mut instances = Vec::new();
+        let mut responses: HashMap<InteractionKey, VecDeque<_>> = HashMap::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("failed to read '{}'", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .with_context(|| format!("invalid entry at {}:{}", path.display(), number + 1))?;
+            match entry {
+                CassetteEntry::Instance(instance) => instances.push(instance),
+                CassetteEntry::Interaction(interaction) => responses
+                    .entry((
+                        interaction.kind,
+                        interaction.instance_name,
+                        interaction.request,
+                    ))
+                    .or_default()
+                    .push_back(interaction.response),
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode: CassetteMode::Replay,
+            file: Mutex::new(None),
+            instances,
+            responses: Mutex::new(responses),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns instances of the recorded cluster.
+    pub fn instances(&self) -> &[RecordedInstance] {
+        &self.instances
+    }
+
+    fn append(&self, entry: &CassetteEntry) {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(file) = file.as_mut() else {
+            return;
+        };
+        let mut line = serde_json::to_string(entry).expect("cassette entry must be serializable");
+        line.push('\n');
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            debug!(
+                "Failed to write into cassette '{}': {err}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// Writes instances of the `cluster` in record mode.
+    pub fn record_cluster(&self, cluster: &Cluster) {
+        for instance in cluster.instances() {
+            self.append(&CassetteEntry::Instance(RecordedInstance {
+                instance_name: instance.instance_name.clone(),
+                tier: instance.tier.clone(),
+                instance_id: instance.instance_id,
+                bin_port: instance.bin_port,
+                pg_port: instance.pg_port,
+                http_port: instance.http_port,
+            }));
+        }
+    }
+
+    /// Writes the interaction in record mode.
+    pub(crate) fn record_interaction<T: Serialize, E: Display>(
+        &self,
+        kind: InteractionKind,
+        instance_name: &str,
+        request: &str,
+        response: &Result<T, E>,
+    ) {
+        let response = match response {
+            Ok(value) => {
+                Ok(serde_json::to_value(value).expect("response must be serializable to JSON"))
+            }
+            Err(err) => Err(format!("{err:#}")),
+        };
+        self.append(&CassetteEntry::Interaction(Interaction {
+            kind,
+            instance_name: instance_name.to_string(),
+            request: request.to_string(),
+            response,
+        }));
+    }
+
+    /// Returns recorded response to the request.
+    pub(crate) fn replay_interaction<T: DeserializeOwned, E: ReplayedError>(
+        &self,
+        kind: InteractionKind,
+        instance_name: &str,
+        request: &str,
+    ) -> Result<T, E> {
+        let key = (kind, instance_name.to_string(), request.to_string());
+        let response = {
+            let mut responses = self
+                .responses
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(queue) = responses.get_mut(&key) else {
+                return Err(E::replayed(format!(
+                    "{kind:?} request to instance '{instance_name}' is not recorded in \
+                    cassette '{}', record it again with {PICOTEST_CASSETTE}=record: {request}",
+                    self.path.display()
+                )));
+            };
+            // The last response is kept to serve repeated requests.
+            if queue.len() > 1 {
+                queue.pop_front()
+            } else {
+                queue.front().cloned()
+            }
+            .expect("recorded requests have at least one response")
+        };
+
+        let value = response.map_err(E::replayed)?;
+        serde_json::from_value(value)
+            .map_err(|err| E::replayed(format!("invalid recorded response to {request}: {err}")))
+    }
+
+    /// Executes the interaction by `call` and records it, or replays it.
+    pub(crate) fn interact<T, E>(
+        &self,
+        kind: InteractionKind,
+        instance_name: &str,
+        request: &str,
+        call: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E>
+    where
+        T: Serialize + DeserializeOwned,
+        E: ReplayedError,
+    {
+        match self.mode {
+            CassetteMode::Record => {
+                let response = call();
+                self.record_interaction(kind, instance_name, request, &response);
+                response
+            }
+            CassetteMode::Replay => self.replay_interaction(kind, instance_name, request),
+        }
+    }
+}
+
+/// Makes `cassette` used by all clusters for the rest of the run.
+pub fn install(cassette: Cassette) -> anyhow::Result<&'static Cassette> {
+    let path = cassette.path.clone();
+    CASSETTE
+        .set(cassette)
+        .map_err(|_| anyhow::anyhow!("cassette is already installed"))?;
+    debug!("Cassette '{}' is installed", path.display());
+    Ok(CASSETTE.get().expect("cassette was just installed"))
+}
+
+/// Returns installed cassette, if any.
+pub fn active() -> Option<&'static Cassette> {
+    CASSETTE.get()
+}
+
+impl PicotestInstance {
+    /// Creates instance, which serves requests from the cassette.
+    fn replayed(recorded: &RecordedInstance) -> Self {
+        let mut instance = Self::detached(PathBuf::new(), None);
+        instance.instance_name = recorded.instance_name.clone();
+        instance.tier = recorded.tier.clone();
+        instance.instance_id = recorded.instance_id;
+        instance.bin_port = recorded.bin_port;
+        instance.pg_port = recorded.pg_port;
+        instance.http_port = recorded.http_port;
+        instance
+    }
+}
+
+impl Cluster {
+    /// Creates cluster of instances recorded in the `cassette`,
+    /// which serve requests from it. Cluster isn't started.
+    pub fn replay(plugin_path: PathBuf, cassette: &Cassette) -> anyhow::Result<Self> {
+        if cassette.mode() != CassetteMode::Replay {
+            bail!("cassette '{}' is not replayed", cassette.path().display());
+        }
+        let instances = cassette
+            .instances()
+            .iter()
+            .map(PicotestInstance::replayed)
+            .collect();
+        Self::attached(plugin_path, PathBuf::new(), instances).with_context(|| {
+            format!(
+                "failed to replay cluster from '{}'",
+                cassette.path().display()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cassette, CassetteMode, InteractionKind};
+    use rstest::rstest;
+    use std::fs;
+    use std::io::Error;
+
+    #[rstest]
+    fn test_record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let path = dir.join("cassette.jsonl");
+
+        let cassette = Cassette::record(&path).unwrap();
+        let response: Result<String, Error> =
+            cassette.interact(InteractionKind::Query, "default_1_1", "SELECT 1", || {
+                Ok("1".to_string())
+            });
+        assert_eq!(response.unwrap(), "1");
+        for count in [1, 2] {
+            let _: Result<u64, Error> =
+                cassette.interact(InteractionKind::Lua, "default_1_1", "return #users", || {
+                    Ok(count)
+                });
+        }
+        let _: Result<String, Error> =
+            cassette.interact(InteractionKind::Query, "default_2_1", "SELEKT", || {
+                Err(Error::other("rule parsing error"))
+            });
+        drop(cassette);
+
+        let cassette = Cassette::replay(&path).unwrap();
+        assert_eq!(cassette.mode(), CassetteMode::Replay);
+        let replay = |kind, instance_name, request| -> Result<serde_json::Value, Error> {
+            cassette.interact(kind, instance_name, request, || {
+                panic!("replayed interaction must not be executed")
+            })
+        };
+
+        assert_eq!(
+            replay(InteractionKind::Query, "default_1_1", "SELECT 1").unwrap(),
+            "1"
+        );
+        assert_eq!(
+            replay(InteractionKind::Lua, "default_1_1", "return #users").unwrap(),
+            1
+        );
+        assert_eq!(
+            replay(InteractionKind::Lua, "default_1_1", "return #users").unwrap(),
+            2
+        );
+        assert_eq!(
+            replay(InteractionKind::Lua, "default_1_1", "return #users").unwrap(),
+            2
+        );
+        assert_eq!(
+            replay(InteractionKind::Query, "default_2_1", "SELEKT")
+                .unwrap_err()
+                .to_string(),
+            "rule parsing error"
+        );
+        let err = replay(InteractionKind::Query, "default_2_1", "SELECT 1").unwrap_err();
+        assert!(err.to_string().contains("is not recorded"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest]
+    fn test_cassette_mode() {
+        assert_eq!(
+            "Record".parse::<CassetteMode>().unwrap(),
+            CassetteMode::Record
+        );
+        assert_eq!(
+            "replay".parse::<CassetteMode>().unwrap(),
+            CassetteMode::Replay
+        );
+        assert!("rewind".parse::<CassetteMode>().is_err());
+        assert!(Cassette::replay(std::path::Path::new("/nonexistent/cassette.jsonl")).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Typed access to picodata system tables.
+//!
+//! Rows are selected by [`PicotestInstance::sql_query`] and deserialized
+//! into structs by column names, so readers work the same way in
+//! synchronous and asynchronous tests. Only columns picotest relies on
+//! are declared, others are ignored.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_plugin_is_enabled() {
+//!     let plugin = cluster.catalog().plugin("my_plugin").unwrap();
+//!     assert!(plugin.is_some_and(|plugin| plugin.enabled));
+//! }
+//! ```
+
+use crate::{Cluster, PicotestInstance};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_norway::Value;
+
+/// Instance of the running cluster, row of `_pico_instance` system table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InstanceInfo {
+    pub name: String,
+    pub uuid: String,
+    pub raft_id: u64,
+    pub replicaset_name: String,
+    pub tier: String,
+}
+
+/// Replicaset of the running cluster, row of `_pico_replicaset` system table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ReplicasetInfo {
+    pub name: String,
+    pub uuid: String,
+    pub tier: String,
+    pub current_master_name: String,
+}
+
+/// Tier of the running cluster, row of `_pico_tier` system table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TierInfo {
+    pub name: String,
+    pub replication_factor: u8,
+    pub can_vote: bool,
+}
+
+/// Plugin installed into the cluster, row of `_pico_plugin` system table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub version: String,
+    pub enabled: bool,
+}
+
+/// Service of the installed plugin, row of `_pico_service` system table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ServiceInfo {
+    pub plugin_name: String,
+    pub name: String,
+    pub version: String,
+    pub tiers: Vec<String>,
+}
+
+/// Configuration value of the plugin service, row of `_pico_plugin_config` system table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PluginConfigEntry {
+    pub plugin: String,
+    pub version: String,
+    /// Name of the service the value belongs to.
+    pub entity: String,
+    pub key: String,
+    pub value: Value,
+}
+
+/// Reader of system tables as seen by the instance,
+/// see [`PicotestInstance::catalog`].
+pub struct Catalog<'a> {
+    instance: &'a PicotestInstance,
+}
+
+impl Catalog<'_> {
+    /// Returns all instances of the cluster.
+    pub fn instances(&self) -> anyhow::Result<Vec<InstanceInfo>> {
+        self.select("_pico_instance", None)
+    }
+
+    /// Returns instances of the replicaset.
+    pub fn replicaset_instances(&self, replicaset_name: &str) -> anyhow::Result<Vec<InstanceInfo>> {
+        self.select("_pico_instance", Some(("replicaset_name", replicaset_name)))
+    }
+
+    /// Returns instance by its name or `None` if there's no such instance.
+    pub fn instance(&self, instance_name: &str) -> anyhow::Result<Option<InstanceInfo>> {
+        self.select_one("_pico_instance", ("name", instance_name))
+    }
+
+    /// Returns all replicasets of the cluster.
+    pub fn replicasets(&self) -> anyhow::Result<Vec<ReplicasetInfo>> {
+        self.select("_pico_replicaset", None)
+    }
+
+    /// Returns replicaset by its name or `None` if there's no such replicaset.
+    pub fn replicaset(&self, replicaset_name: &str) -> anyhow::Result<Option<ReplicasetInfo>> {
+        self.select_one("_pico_replicaset", ("name", replicaset_name))
+    }
+
+    /// Returns all tiers of the cluster.
+    pub fn tiers(&self) -> anyhow::Result<Vec<TierInfo>> {
+        self.select("_pico_tier", None)
+    }
+
+    /// Returns all plugins installed into the cluster.
+    pub fn plugins(&self) -> anyhow::Result<Vec<PluginInfo>> {
+        self.select("_pico_plugin", None)
+    }
+
+    /// Returns plugin by its name or `None` if it's not installed.
+    pub fn plugin(&self, plugin_name: &str) -> anyhow::Result<Option<PluginInfo>> {
+        self.select_one("_pico_plugin", ("name", plugin_name))
+    }
+
+    /// Returns services of all installed plugins.
+    pub fn services(&self) -> anyhow::Result<Vec<ServiceInfo>> {
+        self.select("_pico_service", None)
+    }
+
+    /// Returns configuration of all services of the plugin.
+    pub fn plugin_config(&self, plugin_name: &str) -> anyhow::Result<Vec<PluginConfigEntry>> {
+        self.select("_pico_plugin_config", Some(("plugin", plugin_name)))
+    }
+
+    /// Returns configuration of the service.
+    ///
+    /// **Note:** service is looked up by its name among all installed plugins.
+    pub fn service_config(&self, service_name: &str) -> anyhow::Result<Vec<PluginConfigEntry>> {
+        self.select("_pico_plugin_config", Some(("entity", service_name)))
+    }
+
+    /// Selects rows of the system `table`, optionally filtered by `column = value`.
+    fn select<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        filter: Option<(&str, &str)>,
+    ) -> anyhow::Result<Vec<T>> {
+        let (query, params) = select_query(table, filter);
+        self.instance
+            .sql_query(&query, &params)?
+            .deserialize()
+            .with_context(|| format!("failed to read {table}"))
+    }
+
+    fn select_one<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        filter: (&str, &str),
+    ) -> anyhow::Result<Option<T>> {
+        Ok(self.select(table, Some(filter))?.into_iter().next())
+    }
+}
+
+fn select_query(table: &str, filter: Option<(&str, &str)>) -> (String, Vec<Value>) {
+    match filter {
+        Some((column, value)) => (
+            format!("SELECT * FROM {table} WHERE \"{column}\" = ?"),
+            vec![value.into()],
+        ),
+        None => (format!("SELECT * FROM {table}"), Vec::new()),
+    }
+}
+
+impl PicotestInstance {
+    /// Returns reader of system tables as seen by the instance.
+    pub fn catalog(&self) -> Catalog<'_> {
+        Catalog { instance: self }
+    }
+}
+
+impl Cluster {
+    /// Returns reader of system tables of the main instance.
+    pub fn catalog(&self) -> Catalog<'_> {
+        self.main().catalog()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select_query, PluginConfigEntry, ServiceInfo};
+    use crate::sql::SqlRows;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_select_query() {
+        assert_eq!(
+            select_query("_pico_tier", None),
+            ("SELECT * FROM _pico_tier".to_string(), Vec::new())
+        );
+        assert_eq!(
+            select_query("_pico_plugin_config", Some(("entity", "router"))),
+            (
+                "SELECT * FROM _pico_plugin_config WHERE \"entity\" = ?".to_string(),
+                vec!["router".into()]
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_deserialize_catalog_rows() {
+        let rows: SqlRows = serde_norway::from_str(
+            r#"
+            metadata:
+            - {name: plugin_name, type: string}
+            - {name: name, type: string}
+            - {name: version, type: string}
+            - {name: tiers, type: array}
+            - {name: description, type: string}
+            rows:
+            - [my_plugin, router, 0.1.0, [default, storage], Router service]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rows.deserialize::<ServiceInfo>().unwrap(),
+            [ServiceInfo {
+                plugin_name: "my_plugin".into(),
+                name: "router".into(),
+                version: "0.1.0".into(),
+                tiers: vec!["default".into(), "storage".into()],
+            }]
+        );
+
+        let rows: SqlRows = serde_norway::from_str(
+            r#"
+            metadata:
+            - {name: plugin, type: string}
+            - {name: version, type: string}
+            - {name: entity, type: string}
+            - {name: key, type: string}
+            - {name: value, type: any}
+            rows:
+            - [my_plugin, 0.1.0, router, limits, {rps: 100}]
+            "#,
+        )
+        .unwrap();
+        let entries = rows.deserialize::<PluginConfigEntry>().unwrap();
+        assert_eq!(entries[0].entity, "router");
+        assert_eq!(entries[0].value["rps"], 100);
+    }
+}
@@ -0,0 +1,124 @@
+//! Manipulation of instance clocks for TTL and expiry testing.
+//!
+//! Clock is shifted by overriding Lua time functions (`fiber.time`,
+//! `fiber.clock`, `clock.*` and `os.time`) on every instance. Original
+//! functions are kept, so the shift can be reverted at any moment.
+//!
+//! **Note:** only code, which reads time through Lua API, observes the shift.
+//! Time obtained through C API (e.g. `fiber::clock()` of `tarantool` crate)
+//! is not affected.
+
+use crate::Cluster;
+use anyhow::Context;
+use std::time::Duration;
+
+/// Installs overrides of time functions once and shifts them by `{offset}` seconds.
+const ADVANCE_CLOCK_LUA: &str = r#"
+local fiber = require('fiber')
+local clock = require('clock')
+local state = rawget(_G, '__picotest_clock')
+if state == nil then
+    local originals = {
+        fiber_time = fiber.time, fiber_time64 = fiber.time64,
+        fiber_clock = fiber.clock, fiber_clock64 = fiber.clock64,
+        clock_time = clock.time, clock_time64 = clock.time64,
+        clock_realtime = clock.realtime, clock_realtime64 = clock.realtime64,
+        clock_monotonic = clock.monotonic, clock_monotonic64 = clock.monotonic64,
+        os_time = os.time,
+    }
+    state = { offset = 0, originals = originals }
+    rawset(_G, '__picotest_clock', state)
+
+    local function shift(original)
+        return function() return original() + state.offset end
+    end
+    local function shift64(original, scale)
+        return function() return original() + math.floor(state.offset * scale) end
+    end
+
+    fiber.time = shift(originals.fiber_time)
+    fiber.time64 = shift64(originals.fiber_time64, 1e6)
+    fiber.clock = shift(originals.fiber_clock)
+    fiber.clock64 = shift64(originals.fiber_clock64, 1e6)
+    clock.time = shift(originals.clock_time)
+    clock.time64 = shift64(originals.clock_time64, 1e9)
+    clock.realtime = shift(originals.clock_realtime)
+    clock.realtime64 = shift64(originals.clock_realtime64, 1e9)
+    clock.monotonic = shift(originals.clock_monotonic)
+    clock.monotonic64 = shift64(originals.clock_monotonic64, 1e9)
+    os.time = function(...)
+        if select('#', ...) > 0 then
+            return originals.os_time(...)
+        end
+        return originals.os_time() + math.floor(state.offset)
+    end
+end
+state.offset = state.offset + {offset}
+return state.offset
+"#;
+
+/// Resets shift of the clock. Overrides are kept installed.
+const RESET_CLOCK_LUA: &str = r#"
+local state = rawget(_G, '__picotest_clock')
+if state ~= nil then
+    state.offset = 0
+end
+return true
+"#;
+
+/// Guard returned by [`Cluster::advance_clock`].
+/// Resets the clock of all instances when dropped.
+#[must_use = "clock is reset as soon as the guard is dropped"]
+pub struct ClockGuard<'a> {
+    cluster: &'a Cluster,
+}
+
+impl Drop for ClockGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.cluster.reset_clock() {
+            log::warn!("Failed to reset clock of the cluster: {err:#}");
+        }
+    }
+}
+
+impl Cluster {
+    /// Shifts clocks of all cluster instances forward by `duration`.
+    ///
+    /// Consecutive calls accumulate. Clocks are reset when returned
+    /// guard is dropped, e.g. at the end of the test.
+    ///
+    /// See [module documentation](crate::clock) for limitations.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[picotest]
+    /// fn test_cache_entry_expires() {
+    ///     let _clock = cluster.advance_clock(Duration::from_secs(3600)).unwrap();
+    ///     let output = cluster.run_lua("return box.space.cache:count()").unwrap();
+    ///     assert!(output.contains("0"));
+    /// }
+    /// ```
+    pub fn advance_clock(&self, duration: Duration) -> anyhow::Result<ClockGuard<'_>> {
+        let script = ADVANCE_CLOCK_LUA.replace("{offset}", &duration.as_secs_f64().to_string());
+        for instance in self.instances() {
+            instance.run_lua(&script).with_context(|| {
+                format!("failed to advance clock of '{}'", instance.instance_name)
+            })?;
+        }
+        Ok(ClockGuard { cluster: self })
+    }
+
+    /// Resets clocks of all cluster instances shifted by [`Cluster::advance_clock`].
+    pub fn reset_clock(&self) -> anyhow::Result<()> {
+        for instance in self.instances() {
+            instance.run_lua(RESET_CLOCK_LUA).with_context(|| {
+                format!("failed to reset clock of '{}'", instance.instance_name)
+            })?;
+        }
+        Ok(())
+    }
+}
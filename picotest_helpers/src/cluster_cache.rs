@@ -0,0 +1,217 @@
+//! Cache of bootstrapped clusters.
+//!
+//! Most of cluster startup time is spent on bootstrap: instances join the
+//! cluster, vshard is discovered, plugin is installed and its migrations are
+//! applied. After the first successful bootstrap, data directory of the
+//! cluster is archived into the cache (see [`Cluster::freeze`]). Later runs
+//! with the same picodata version, plugin build and topology restart the
+//! cluster from the archive, so instances only catch up with their own state.
+//!
+//! Cache directory can be shared between CI jobs: plugin build is fingerprinted
+//! by contents, so cluster cached by one job is reused by another one.
+
+use crate::compat::picodata_version_output;
+use crate::container::Backend;
+use crate::freeze::FROZEN_CLUSTER_EXT;
+use crate::keepalive::{plugin_content_fingerprint, topology_hash};
+use crate::plugin_build::build_plugin;
+use crate::plugin_package::PluginSource;
+use crate::Cluster;
+use anyhow::Context;
+use log::{debug, info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// Environment variable, which overrides directory of the cache.
+pub const PICOTEST_CLUSTER_CACHE_DIR: &str = "PICOTEST_CLUSTER_CACHE_DIR";
+
+/// Default directory of the cache, relative to the plugin path.
+pub const DEFAULT_CLUSTER_CACHE_DIR: &str = "tmp/cluster_cache";
+
+/// Identity of the bootstrapped cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClusterCacheKey {
+    /// Output of `picodata --version` or image of container backend.
+    pub picodata_version: String,
+    pub plugin_fingerprint: String,
+    pub topology_hash: String,
+}
+
+impl ClusterCacheKey {
+    /// Name of the archive of the cluster in the cache.
+    pub fn archive_name(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        format!("{:016x}.{FROZEN_CLUSTER_EXT}", hasher.finish())
+    }
+}
+
+/// Directory of archived clusters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterCache {
+    dir: PathBuf,
+}
+
+impl ClusterCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Cache located in [`PICOTEST_CLUSTER_CACHE_DIR`] or
+    /// in [`DEFAULT_CLUSTER_CACHE_DIR`] of the plugin.
+    pub fn from_env(plugin_path: &Path) -> Self {
+        let dir = env::var(PICOTEST_CLUSTER_CACHE_DIR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| plugin_path.join(DEFAULT_CLUSTER_CACHE_DIR));
+        Self::new(dir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns archive of the cluster identified by `key` if it's cached.
+    pub fn lookup(&self, key: &ClusterCacheKey) -> Option<PathBuf> {
+        let archive = self.dir.join(key.archive_name());
+        archive.is_file().then_some(archive)
+    }
+
+    /// Moves `archive` of the cluster identified by `key` into the cache.
+    ///
+    /// ### Returns
+    /// Path to the cached archive.
+    pub fn store(&self, key: &ClusterCacheKey, archive: &Path) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create directory '{}'", self.dir.display()))?;
+
+        // Archive is copied next to its final location first,
+        // so concurrent runs never see partially written archive.
+        let cached = self.dir.join(key.archive_name());
+        let partial = cached.with_extension("partial");
+        fs::copy(archive, &partial)
+            .with_context(|| format!("failed to copy '{}' into cache", archive.display()))?;
+        fs::rename(&partial, &cached)
+            .with_context(|| format!("failed to write '{}'", cached.display()))?;
+        let _ = fs::remove_file(archive);
+        Ok(cached)
+    }
+
+    /// Removes all cached clusters.
+    ///
+    /// ### Returns
+    /// Number of removed archives.
+    pub fn clear(&self) -> anyhow::Result<usize> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(0);
+        };
+        let mut removed = 0;
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_file() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove '{}'", path.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl Cluster {
+    /// Returns key identifying bootstrapped state of the cluster in [`ClusterCache`].
+    pub fn cache_key(&self) -> anyhow::Result<ClusterCacheKey> {
+        let picodata_version = match self.backend {
+            Backend::Container => format!("image {}", self.container_config.image),
+            Backend::Local => picodata_version_output(&self.picodata_path)?,
+        };
+
+        Ok(ClusterCacheKey {
+            picodata_version,
+            plugin_fingerprint: plugin_content_fingerprint(
+                &self.build_profile.build_path(&self.plugin_path),
+            ),
+            topology_hash: topology_hash(&self.topology),
+        })
+    }
+
+    /// Starts the cluster from the archive in the `cache` if there is one.
+    /// Otherwise, starts the cluster as [`Cluster::run`] does and stores
+    /// its bootstrapped state in the `cache`.
+    ///
+    /// Clusters installing the plugin from package aren't cached.
+    pub fn run_cached(self, cache: &ClusterCache) -> anyhow::Result<Self> {
+        if self.install_from == PluginSource::Package {
+            debug!("Cluster installing plugin from package isn't cached");
+            return self.run();
+        }
+
+        // Cached cluster is valid only for the actual build of the plugin.
+        build_plugin(&self.plugin_path, self.build_profile)?;
+        let key = self.cache_key()?;
+        if let Some(archive) = cache.lookup(&key) {
+            info!("Starting the cluster from cache '{}'", archive.display());
+            return self.thaw(&archive);
+        }
+
+        let cluster = self.run()?;
+        let archive = cluster.freeze()?;
+        match cache.store(&key, &archive) {
+            Ok(cached) => {
+                info!("Cluster is cached into '{}'", cached.display());
+                cluster.thaw(&cached)
+            }
+            Err(err) => {
+                warn!("Failed to cache the cluster: {err:#}");
+                cluster.thaw(&archive)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClusterCache, ClusterCacheKey};
+    use rstest::rstest;
+    use std::fs;
+
+    fn key(picodata_version: &str) -> ClusterCacheKey {
+        ClusterCacheKey {
+            picodata_version: picodata_version.into(),
+            plugin_fingerprint: "0123456789abcdef".into(),
+            topology_hash: "fedcba9876543210".into(),
+        }
+    }
+
+    #[rstest]
+    fn test_archive_name() {
+        assert_eq!(key("25.1.1").archive_name(), key("25.1.1").archive_name());
+        assert_ne!(key("25.1.1").archive_name(), key("25.2.1").archive_name());
+        assert!(key("25.1.1").archive_name().ends_with(".tar.gz"));
+    }
+
+    #[rstest]
+    fn test_cluster_cache() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let cache = ClusterCache::new(dir.join("cache"));
+        assert!(cache.lookup(&key("25.1.1")).is_none());
+        assert_eq!(cache.clear().unwrap(), 0);
+
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("frozen.tar.gz");
+        fs::write(&archive, "archive").unwrap();
+
+        let cached = cache.store(&key("25.1.1"), &archive).unwrap();
+        assert!(!archive.exists());
+        assert_eq!(cache.lookup(&key("25.1.1")), Some(cached.clone()));
+        assert_eq!(fs::read_to_string(&cached).unwrap(), "archive");
+        assert!(cache.lookup(&key("25.2.1")).is_none());
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.lookup(&key("25.1.1")).is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
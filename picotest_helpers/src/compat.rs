@@ -0,0 +1,311 @@
+//! Compatibility of picotest with picodata releases.
+//!
+//! Picodata binary is checked before the cluster is started, so missing
+//! or outdated binary is reported right away instead of failing later
+//! on connection to the admin console.
+//!
+//! Features, which differ between releases, are described by [`Capability`].
+//! Helpers check capabilities of the running cluster, so they fail with
+//! a clear error on unsupported picodata rather than on unexpected output.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::compat::{Capability, PicodataVersion};
+//!
+//! if cluster.picodata_version()? >= PicodataVersion::new(26, 1, 0) {
+//!     // ...
+//! }
+//! assert!(cluster.supports(Capability::RpcDispatchContext));
+//! ```
+
+use crate::introspection::eval_lua_yaml;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use log::warn;
+use std::fmt::{self, Display};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Minimum picodata version required by major versions of picotest.
+/// Keep in sync with compatibility table in README.
+const PICODATA_COMPATIBILITY: &[(u32, PicodataVersion)] = &[
+    (1, PicodataVersion::new(25, 1, 1)),
+    (2, PicodataVersion::new(26, 1, 2)),
+    (3, PicodataVersion::new(26, 1, 2)),
+];
+
+/// Feature of picodata, which isn't available in all releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// `.proc_rpc_dispatch` accepts request context as a map of request id,
+    /// plugin, service and its version, see [`PicotestInstance::execute_rpc`].
+    RpcDispatchContext,
+    /// Audit log location is set by `PICODATA_AUDIT_LOG` environment variable,
+    /// see [`Cluster::enable_audit`].
+    AuditLogEnv,
+}
+
+/// Picodata releases capabilities are available since.
+const CAPABILITIES: &[(Capability, PicodataVersion)] = &[
+    (
+        Capability::RpcDispatchContext,
+        PicodataVersion::new(25, 1, 1),
+    ),
+    (Capability::AuditLogEnv, PicodataVersion::new(25, 1, 1)),
+];
+
+impl Capability {
+    /// Returns the first picodata release with the capability.
+    pub fn since(self) -> PicodataVersion {
+        CAPABILITIES
+            .iter()
+            .find(|(capability, _)| *capability == self)
+            .map(|(_, version)| *version)
+            .expect("capability must be listed in the table")
+    }
+
+    pub fn is_supported_by(self, version: PicodataVersion) -> bool {
+        version >= self.since()
+    }
+}
+
+/// Release version of picodata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PicodataVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl PicodataVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl Display for PicodataVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for PicodataVersion {
+    type Err = anyhow::Error;
+
+    /// Parses version like `25.1.1`, ignoring build suffix, e.g. `-0-g4b1ac8ba`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let release = s.split(['-', '+']).next().unwrap_or_default();
+        let parts = release
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<Vec<u32>, _>>()
+            .with_context(|| format!("invalid picodata version '{s}'"))?;
+        match parts[..] {
+            [major, minor, patch] => Ok(Self::new(major, minor, patch)),
+            [major, minor] => Ok(Self::new(major, minor, 0)),
+            _ => bail!("invalid picodata version '{s}'"),
+        }
+    }
+}
+
+/// Parses version from `picodata --version` output,
+/// e.g. `picodata 25.1.1-0-g4b1ac8ba`.
+pub fn parse_version_output(output: &str) -> anyhow::Result<PicodataVersion> {
+    let first_line = output.lines().next().unwrap_or_default();
+    let Some(version) = first_line
+        .split_whitespace()
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+    else {
+        bail!("picodata version is not found in '{first_line}'");
+    };
+    version.parse()
+}
+
+/// Returns minimum picodata version required by this release of picotest.
+pub fn min_picodata_version() -> PicodataVersion {
+    let picotest_major = env!("CARGO_PKG_VERSION_MAJOR")
+        .parse::<u32>()
+        .expect("crate major version must be a number");
+    PICODATA_COMPATIBILITY
+        .iter()
+        .rev()
+        .find(|(major, _)| *major <= picotest_major)
+        .map(|(_, version)| *version)
+        .expect("compatibility table must cover crate version")
+}
+
+/// Explains how to make picodata binary located by `picodata_path` available.
+pub fn missing_picodata_message(picodata_path: &Path) -> String {
+    format!(
+        "picodata binary '{}' is not found. Install picodata {} or newer \
+        (see https://picodata.io/download/) and either add it to PATH or point \
+        PICODATA_PATH environment variable to the binary",
+        picodata_path.display(),
+        min_picodata_version()
+    )
+}
+
+/// Returns output of `picodata --version`.
+pub fn picodata_version_output(picodata_path: &Path) -> anyhow::Result<String> {
+    let output = match Command::new(picodata_path).arg("--version").output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            bail!(missing_picodata_message(picodata_path))
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to run '{} --version'", picodata_path.display()))
+        }
+    };
+    if !output.status.success() {
+        bail!(
+            "'{} --version' has finished with failure: {}",
+            picodata_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Checks that picodata binary located by `picodata_path` exists
+/// and isn't older than [`min_picodata_version`].
+///
+/// Versions, which can't be parsed (e.g. of development builds),
+/// are accepted with a warning.
+pub fn ensure_compatible_picodata(picodata_path: &Path) -> anyhow::Result<()> {
+    let output = picodata_version_output(picodata_path)?;
+    let version = match parse_version_output(&output) {
+        Ok(version) => version,
+        Err(err) => {
+            warn!("Failed to check version of picodata binary: {err:#}");
+            return Ok(());
+        }
+    };
+
+    let min_version = min_picodata_version();
+    if version < min_version {
+        bail!(
+            "picodata {version} located by '{}' is not supported, picotest {} requires \
+            picodata {min_version} or newer. Install newer picodata or point \
+            PICODATA_PATH environment variable to it",
+            picodata_path.display(),
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    Ok(())
+}
+
+impl PicotestInstance {
+    /// Returns version of picodata the instance is running.
+    ///
+    /// Version is requested once and cached for the lifetime of the instance.
+    pub fn picodata_version(&self) -> anyhow::Result<PicodataVersion> {
+        if let Some(version) = self.picodata_version.get() {
+            return Ok(*version);
+        }
+        let version: String = eval_lua_yaml(self, "pico.PICODATA_VERSION")
+            .context("failed to get picodata version")?;
+        let version = version.parse()?;
+        Ok(*self.picodata_version.get_or_init(|| version))
+    }
+
+    /// Returns `true` if picodata running the instance has the `capability`.
+    ///
+    /// Capability is considered available if version can't be determined,
+    /// e.g. for development builds.
+    pub fn supports(&self, capability: Capability) -> bool {
+        match self.picodata_version() {
+            Ok(version) => capability.is_supported_by(version),
+            Err(err) => {
+                warn!("Failed to check {capability:?} capability: {err:#}");
+                true
+            }
+        }
+    }
+
+    /// Returns an error if picodata running the instance lacks the `capability`.
+    pub(crate) fn ensure_supports(&self, capability: Capability) -> anyhow::Result<()> {
+        if self.supports(capability) {
+            return Ok(());
+        }
+        bail!(
+            "{capability:?} requires picodata {} or newer, but instance '{}' runs picodata {}",
+            capability.since(),
+            self.instance_name,
+            self.picodata_version()?
+        );
+    }
+}
+
+impl Cluster {
+    /// Returns version of picodata the main instance is running.
+    /// See [`PicotestInstance::picodata_version`].
+    pub fn picodata_version(&self) -> anyhow::Result<PicodataVersion> {
+        self.main().picodata_version()
+    }
+
+    /// Returns `true` if picodata running the main instance has the `capability`.
+    /// See [`PicotestInstance::supports`].
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.main().supports(capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ensure_compatible_picodata, min_picodata_version, parse_version_output, Capability,
+        PicodataVersion,
+    };
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    #[case("picodata 25.1.1-0-g4b1ac8ba", PicodataVersion::new(25, 1, 1))]
+    #[case(
+        "picodata 26.1.2\ntarantool (fork) version: 2.11.5\ntarget: Linux-x86_64-RelWithDebInfo",
+        PicodataVersion::new(26, 1, 2)
+    )]
+    #[case("picodata 25.2", PicodataVersion::new(25, 2, 0))]
+    fn test_parse_version_output(#[case] output: &str, #[case] expected: PicodataVersion) {
+        assert_eq!(parse_version_output(output).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("picodata")]
+    #[case("picodata 25.x.1")]
+    #[case("picodata 25")]
+    fn test_parse_invalid_version_output(#[case] output: &str) {
+        assert!(parse_version_output(output).is_err());
+    }
+
+    #[rstest]
+    fn test_version_order() {
+        assert!(PicodataVersion::new(25, 2, 1) > PicodataVersion::new(25, 1, 2));
+        assert!(PicodataVersion::new(26, 1, 0) > PicodataVersion::new(25, 9, 9));
+        assert_eq!(min_picodata_version().to_string(), "26.1.2");
+    }
+
+    #[rstest]
+    fn test_capabilities() {
+        let capability = Capability::RpcDispatchContext;
+        assert_eq!(capability.since(), PicodataVersion::new(25, 1, 1));
+        assert!(capability.is_supported_by(PicodataVersion::new(26, 1, 2)));
+        assert!(!capability.is_supported_by(PicodataVersion::new(24, 7, 1)));
+    }
+
+    #[rstest]
+    fn test_missing_picodata() {
+        let err = ensure_compatible_picodata(Path::new("/nonexistent/picodata")).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("'/nonexistent/picodata' is not found"));
+        assert!(message.contains("PICODATA_PATH"));
+        assert!(message.contains("26.1.2 or newer"));
+    }
+}
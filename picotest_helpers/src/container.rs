@@ -0,0 +1,441 @@
+//! Running cluster instances in Docker or Podman containers.
+//!
+//! Pike starts instances by executing picodata binary, so with container
+//! backend it's pointed to a generated launcher script, which runs picodata
+//! from the container image instead. Plugin directory (with build artifacts
+//! and data directory of the cluster) is mounted into the container by the
+//! same path, so admin sockets and logs stay accessible from the host.
+//! Admin console is run by the launcher inside the container of the
+//! instance, so picodata isn't required on the host at all.
+//!
+//! Every cluster gets its own container network, created on start and
+//! removed on shutdown. Instances listen on all interfaces of their
+//! containers, reach each other by network aliases named after their
+//! iproto ports (`port-3301`) and publish ports assigned by pike on the
+//! host, so clients connect to them as to local instances.
+
+use crate::instance_dirs;
+use crate::launcher::{self, shell_quote, INSTANCE_DIR_SCRIPT};
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Environment variable, which overrides container runtime.
+pub const PICOTEST_CONTAINER_RUNTIME: &str = "PICOTEST_CONTAINER_RUNTIME";
+
+/// Environment variable, which overrides picodata image.
+pub const PICOTEST_CONTAINER_IMAGE: &str = "PICOTEST_CONTAINER_IMAGE";
+
+pub const DEFAULT_CONTAINER_RUNTIME: &str = "docker";
+pub const DEFAULT_CONTAINER_IMAGE: &str = "docker.binary.picodata.io/picodata:latest";
+
+/// Directory (relative to the plugin path) of generated launcher scripts.
+pub const CONTAINER_LAUNCHERS_DIR: &str = "tmp/container";
+
+/// Label marking containers of the cluster with its UUID.
+const CLUSTER_LABEL: &str = "io.picodata.picotest.cluster";
+
+/// Where cluster instances are running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Processes of picodata binary on the local host.
+    #[default]
+    Local,
+    /// Containers of picodata image.
+    Container,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "container" => Ok(Self::Container),
+            _ => bail!("unknown cluster backend '{s}', expected one of: local, container"),
+        }
+    }
+}
+
+/// Container runtime and image used by [`Backend::Container`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerConfig {
+    /// Command of the container runtime, e.g. `docker` or `podman`.
+    pub runtime: String,
+    pub image: String,
+}
+
+impl Default for ContainerConfig {
+    /// Reads configuration from `PICOTEST_CONTAINER_RUNTIME` and
+    /// `PICOTEST_CONTAINER_IMAGE` environment variables.
+    fn default() -> Self {
+        Self {
+            runtime: env::var(PICOTEST_CONTAINER_RUNTIME)
+                .unwrap_or_else(|_| DEFAULT_CONTAINER_RUNTIME.to_string()),
+            image: env::var(PICOTEST_CONTAINER_IMAGE)
+                .unwrap_or_else(|_| DEFAULT_CONTAINER_IMAGE.to_string()),
+        }
+    }
+}
+
+/// Writes launcher script running picodata in a container
+/// for the cluster identified by `cluster_uuid`.
+///
+/// ### Returns
+///
+/// - On success, path to the launcher to be used as picodata binary.
+/// - On failure, instance of [`anyhow::Error`].
+pub fn write_launcher(
+    plugin_path: &Path,
+    cluster_uuid: &Uuid,
+    config: &ContainerConfig,
+) -> anyhow::Result<PathBuf> {
+    let plugin_path = plugin_path
+        .canonicalize()
+        .with_context(|| format!("failed to resolve '{}'", plugin_path.display()))?;
+    let launcher_path = launcher::write_launcher(
+        &plugin_path
+            .join(CONTAINER_LAUNCHERS_DIR)
+            .join(cluster_uuid.to_string()),
+        "picodata",
+        &launcher_script(&plugin_path, cluster_uuid, config),
+    )?;
+
+    debug!(
+        "Instances will be started in '{}' containers by '{}'",
+        config.image,
+        launcher_path.display()
+    );
+    Ok(launcher_path)
+}
+
+/// Returns name of the container network of the cluster.
+fn network_name(cluster_uuid: &Uuid) -> String {
+    format!("picotest-{cluster_uuid}")
+}
+
+fn launcher_script(plugin_path: &Path, cluster_uuid: &Uuid, config: &ContainerConfig) -> String {
+    let plugin_path = plugin_path.to_string_lossy();
+    let runtime = shell_quote(&config.runtime);
+    let image = shell_quote(&config.image);
+    let volume = shell_quote(&format!("{plugin_path}:{plugin_path}"));
+    let network = shell_quote(&network_name(cluster_uuid));
+    let container_prefix = shell_quote(&format!("{}-", network_name(cluster_uuid)));
+    let plugin_path = shell_quote(&plugin_path);
+    format!(
+        r#"#!/bin/sh
+# Generated by picotest: runs picodata in a container.
+set -e
+if [ "$1" = admin ]; then
+    # Console is run in the container of the instance owning the socket.
+    instance_dir=$(cd "$(dirname "$2")" && pwd -P)
+    exec {runtime} exec --interactive {container_prefix}"${{instance_dir##*/}}" picodata "$@"
+fi
+case "$PWD" in
+    {plugin_path}|{plugin_path}/*) workdir_volume= ;;
+    *) workdir_volume="--volume=$PWD:$PWD" ;;
+esac
+if [ "$1" != run ]; then
+    exec {runtime} run --rm --interactive --network=none --user="$(id -u):$(id -g)" \
+        --volume={volume} $workdir_volume --workdir="$PWD" \
+        --entrypoint=picodata {image} "$@"
+fi
+{INSTANCE_DIR_SCRIPT}
+rewrite() {{
+    printf '%s\n' "$1" | sed -E "s/[^=,]*:([0-9]+)(,|$)/$2\1\2/g"
+}}
+publish=
+advertise=
+listen_port=
+previous=
+for arg do
+    shift
+    option=$previous
+    case "$arg" in
+        --*=*) option=${{arg%%=*}} ;;
+    esac
+    case "$option" in
+        --listen|--iproto-listen|--pg-listen|--http-listen)
+            address=${{arg#--*=}}
+            port=${{address##*:}}
+            publish="$publish --publish=$address:$port"
+            arg=$(rewrite "$arg" '0.0.0.0:')
+            ;;
+        --peer) arg=$(rewrite "$arg" 'port-\1:') ;;
+        --advertise|--iproto-advertise) advertise=1; arg=$(rewrite "$arg" 'port-\1:') ;;
+    esac
+    case "$option" in
+        --listen|--iproto-listen) listen_port=$port ;;
+    esac
+    previous=$arg
+    set -- "$@" "$arg"
+done
+network_options=--network={network}
+if [ -n "$listen_port" ]; then
+    network_options="$network_options --network-alias=port-$listen_port"
+    if [ -z "$advertise" ]; then
+        network_options="$network_options --env=PICODATA_IPROTO_ADVERTISE=port-$listen_port:$listen_port"
+        network_options="$network_options --env=PICODATA_ADVERTISE=port-$listen_port:$listen_port"
+    fi
+fi
+# Advertised addresses of the host aren't reachable from the container network.
+env_options=$(env | sed -n -e '/^PICODATA_[A-Z_]*ADVERTISE=/d' -e 's/^\(PICODATA_[A-Z0-9_]*\)=.*/--env=\1/p')
+exec {runtime} run --rm --interactive --name={container_prefix}"${{instance_dir##*/}}" \
+    $network_options $publish --user="$(id -u):$(id -g)" \
+    --label={CLUSTER_LABEL}={cluster_uuid} \
+    --volume={volume} $workdir_volume --workdir="$PWD" \
+    $env_options --entrypoint=picodata {image} "$@"
+"#
+    )
+}
+
+/// Creates container network of the cluster identified by `cluster_uuid`,
+/// unless it's left by the previous start of the cluster.
+pub fn create_network(cluster_uuid: &Uuid, config: &ContainerConfig) -> anyhow::Result<()> {
+    let network = network_name(cluster_uuid);
+    if network_exists(&network, config)? {
+        return Ok(());
+    }
+    debug!("Creating container network '{network}'");
+    run_runtime(
+        config,
+        Command::new(&config.runtime)
+            .args(["network", "create"])
+            .arg(format!("--label={CLUSTER_LABEL}={cluster_uuid}"))
+            .arg(&network),
+    )?;
+    Ok(())
+}
+
+/// Removes container network of the cluster identified by `cluster_uuid`.
+/// Containers of the cluster must be removed first.
+pub fn remove_network(cluster_uuid: &Uuid, config: &ContainerConfig) -> anyhow::Result<()> {
+    let network = network_name(cluster_uuid);
+    if !network_exists(&network, config)? {
+        return Ok(());
+    }
+    debug!("Removing container network '{network}'");
+    run_runtime(
+        config,
+        Command::new(&config.runtime)
+            .args(["network", "rm"])
+            .arg(&network),
+    )?;
+    Ok(())
+}
+
+fn network_exists(network: &str, config: &ContainerConfig) -> anyhow::Result<bool> {
+    let status = Command::new(&config.runtime)
+        .args(["network", "inspect"])
+        .arg(network)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("failed to run \"{} network inspect\"", config.runtime))?;
+    Ok(status.success())
+}
+
+/// Removes containers left by the cluster identified by `cluster_uuid`,
+/// e.g. after their runtime client processes were killed.
+pub fn remove_containers(cluster_uuid: &Uuid, config: &ContainerConfig) -> anyhow::Result<()> {
+    let containers = cluster_containers(cluster_uuid, config)?;
+    if containers.is_empty() {
+        return Ok(());
+    }
+
+    warn!("Removing leftover containers of the cluster: {containers:?}");
+    run_runtime(
+        config,
+        Command::new(&config.runtime)
+            .args(["rm", "--force"])
+            .args(&containers),
+    )?;
+    Ok(())
+}
+
+/// Kills containers of the cluster identified by `cluster_uuid` with `SIGKILL`.
+pub fn kill_containers(cluster_uuid: &Uuid, config: &ContainerConfig) -> anyhow::Result<()> {
+    let containers = cluster_containers(cluster_uuid, config)?;
+    if containers.is_empty() {
+        return Ok(());
+    }
+    run_runtime(
+        config,
+        Command::new(&config.runtime)
+            .args(["kill", "--signal=KILL"])
+            .args(&containers),
+    )?;
+    Ok(())
+}
+
+/// Returns PIDs of container runtime clients running instances of the
+/// cluster, which are written by pike into the instance directories.
+///
+/// PIDs reported by instances themselves belong to PID namespaces of
+/// containers, while clients run on the host and exit with containers.
+pub fn client_pids(data_dir: &Path) -> Vec<u32> {
+    instance_dirs(data_dir)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|dir| fs::read_to_string(dir.join("pid")).ok())
+        .filter_map(|pid| pid.trim().parse().ok())
+        .collect()
+}
+
+/// Returns IDs of all containers of the cluster, including stopped ones.
+fn cluster_containers(
+    cluster_uuid: &Uuid,
+    config: &ContainerConfig,
+) -> anyhow::Result<Vec<String>> {
+    let output = run_runtime(
+        config,
+        Command::new(&config.runtime)
+            .args(["ps", "--all", "--quiet"])
+            .arg(format!("--filter=label={CLUSTER_LABEL}={cluster_uuid}")),
+    )?;
+    Ok(output.split_whitespace().map(String::from).collect())
+}
+
+/// Runs `command` of the container runtime and returns its stdout.
+fn run_runtime(config: &ContainerConfig, command: &mut Command) -> anyhow::Result<String> {
+    let subcommand = command
+        .get_args()
+        .next()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let output = command
+        .output()
+        .with_context(|| format!("failed to run \"{} {subcommand}\"", config.runtime))?;
+    if !output.status.success() {
+        bail!(
+            "\"{} {subcommand}\" has finished with failure: {}",
+            config.runtime,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{launcher_script, Backend, ContainerConfig};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use uuid::Uuid;
+
+    #[rstest]
+    fn test_backend_from_str() {
+        assert_eq!("local".parse::<Backend>().unwrap(), Backend::Local);
+        assert_eq!("Container".parse::<Backend>().unwrap(), Backend::Container);
+        assert!("ssh".parse::<Backend>().is_err());
+    }
+
+    #[rstest]
+    fn test_launcher_script() {
+        let config = ContainerConfig {
+            runtime: "podman".into(),
+            image: "picodata:25.1".into(),
+        };
+        let script = launcher_script(Path::new("/plugin"), &Uuid::nil(), &config);
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("exec 'podman' run --rm --interactive"));
+        assert!(script.contains("--volume='/plugin:/plugin'"));
+        assert!(script.contains(&format!("cluster={}", Uuid::nil())));
+        assert!(script.contains("--entrypoint=picodata 'picodata:25.1' \"$@\""));
+        assert!(!script.contains("--network=host"));
+        assert!(!script.contains("--pid=host"));
+    }
+
+    fn run_launcher(args: &[&str]) -> String {
+        let config = ContainerConfig {
+            runtime: "echo".into(),
+            image: "picodata".into(),
+        };
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(launcher_script(Path::new("/plugin"), &Uuid::nil(), &config))
+            .arg("picodata")
+            .args(args)
+            .current_dir("/")
+            .env_remove("PICODATA_IPROTO_ADVERTISE")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let user = Command::new("sh")
+            .args(["-c", "echo \"$(id -u):$(id -g)\""])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout)
+            .replace(&Uuid::nil().to_string(), "{uuid}")
+            .replace(String::from_utf8_lossy(&user.stdout).trim(), "{user}")
+    }
+
+    #[rstest]
+    fn test_launcher_script_run() {
+        let output = run_launcher(&[
+            "run",
+            "--instance-dir",
+            "/plugin/tmp/cluster/i1",
+            "--iproto-listen",
+            "127.0.0.1:3301",
+            "--peer",
+            "127.0.0.1:3301,127.0.0.1:3302",
+            "--http-listen=0.0.0.0:8001",
+            "--pg-listen",
+            "127.0.0.1:5433",
+        ]);
+        assert_eq!(
+            output.trim_end(),
+            "run --rm --interactive --name=picotest-{uuid}-i1 \
+             --network=picotest-{uuid} --network-alias=port-3301 \
+             --env=PICODATA_IPROTO_ADVERTISE=port-3301:3301 \
+             --env=PICODATA_ADVERTISE=port-3301:3301 \
+             --publish=127.0.0.1:3301:3301 --publish=0.0.0.0:8001:8001 \
+             --publish=127.0.0.1:5433:5433 --user={user} \
+             --label=io.picodata.picotest.cluster={uuid} \
+             --volume=/plugin:/plugin --volume=/:/ --workdir=/ \
+             --entrypoint=picodata picodata run --instance-dir /plugin/tmp/cluster/i1 \
+             --iproto-listen 0.0.0.0:3301 --peer port-3301:3301,port-3302:3302 \
+             --http-listen=0.0.0.0:8001 --pg-listen 0.0.0.0:5433"
+        );
+    }
+
+    #[rstest]
+    fn test_launcher_script_admin() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("i1")).unwrap();
+        std::os::unix::fs::symlink("i1", dir.join("default_1_1")).unwrap();
+        let socket = dir.join("default_1_1").join("admin.sock");
+
+        let output = run_launcher(&["admin", &socket.to_string_lossy()]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            output.trim_end(),
+            format!(
+                "exec --interactive picotest-{{uuid}}-i1 picodata admin {}",
+                socket.display()
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_launcher_script_version() {
+        let output = run_launcher(&["--version"]);
+        assert!(
+            output.contains("run --rm --interactive --network=none"),
+            "{output}"
+        );
+        assert!(output
+            .trim_end()
+            .ends_with("--entrypoint=picodata picodata --version"));
+    }
+}
@@ -0,0 +1,378 @@
+//! Code coverage of the plugin code executed by picodata instances.
+//!
+//! Plugin library is loaded by the instances, so its coverage profiles are
+//! written by picodata processes rather than by the test process. Coverage
+//! mode is enabled by [`Cluster::coverage`] (or [`PICOTEST_COVERAGE`] for
+//! the session cluster):
+//! - plugin library must be built with `-C instrument-coverage`, see
+//!   [`ensure_instrumented_build`],
+//! - instances are started by a generated launcher script, which sets
+//!   `LLVM_PROFILE_FILE` to the [`PROFILES_DIR`] of the instance directory,
+//! - when the cluster is shut down, profiles are moved from the instance
+//!   directories into [`CoverageConfig::output_dir`] and merged.
+//!
+//! If tests are run by `cargo llvm-cov`, profiles are put next to the
+//! profiles of the test process, so they're merged into its report.
+//!
+//! **Note:** pike builds the plugin on cluster start with the environment
+//! of the test process, so `RUSTFLAGS` of the test process must include
+//! `-C instrument-coverage` too. Otherwise instrumented build is replaced.
+
+use crate::launcher::{self, INSTANCE_DIR_SCRIPT};
+use crate::plugin_build::libraries;
+use crate::Cluster;
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+/// Environment variable, which enables coverage mode of the session cluster.
+pub const PICOTEST_COVERAGE: &str = "PICOTEST_COVERAGE";
+
+/// Environment variable with path to `llvm-profdata` tool.
+pub const LLVM_PROFDATA: &str = "LLVM_PROFDATA";
+
+/// Directory of coverage profiles in the instance directory.
+pub const PROFILES_DIR: &str = "coverage";
+
+/// Default directory of collected profiles, relative to the plugin path.
+pub const DEFAULT_COVERAGE_DIR: &str = "target/picotest-coverage";
+
+/// Directory (relative to the plugin path) of generated launcher scripts.
+pub const COVERAGE_LAUNCHERS_DIR: &str = "tmp/coverage";
+
+/// File the collected profiles are merged into.
+pub const MERGED_PROFILE_NAME: &str = "picotest.profdata";
+
+/// Rustc flag enabling coverage instrumentation.
+const INSTRUMENT_COVERAGE_FLAG: &str = "-C instrument-coverage";
+
+/// Name of the section with coverage counters of instrumented binary.
+const COUNTERS_SECTION: &[u8] = b"__llvm_prf_cnts";
+
+const PROFILE_EXTENSION: &str = "profraw";
+
+/// Where and how coverage profiles of the instances are collected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageConfig {
+    /// Directory collected profiles are moved into.
+    pub output_dir: PathBuf,
+    /// Merge profiles into [`MERGED_PROFILE_NAME`] by `llvm-profdata`.
+    pub merge: bool,
+}
+
+impl CoverageConfig {
+    /// Creates config collecting profiles into `output_dir`
+    /// and merging them.
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            merge: true,
+        }
+    }
+
+    /// Creates config from [`PICOTEST_COVERAGE`] environment variable.
+    ///
+    /// If the test process writes its own profiles (`LLVM_PROFILE_FILE` is
+    /// set, e.g. by `cargo llvm-cov`), profiles are collected into the same
+    /// directory and merged by the tool, which has set it. Otherwise they're
+    /// collected into [`DEFAULT_COVERAGE_DIR`] of the plugin.
+    ///
+    /// ### Returns
+    /// `None` if coverage isn't enabled.
+    pub fn from_env(plugin_path: &Path) -> Option<Self> {
+        let enabled = env::var(PICOTEST_COVERAGE)
+            .is_ok_and(|value| !value.is_empty() && value != "0" && value != "false");
+        if !enabled {
+            return None;
+        }
+        let process_profiles_dir = env::var_os("LLVM_PROFILE_FILE")
+            .map(PathBuf::from)
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .filter(|dir| !dir.as_os_str().is_empty());
+        Some(match process_profiles_dir {
+            Some(dir) => Self {
+                output_dir: dir,
+                merge: false,
+            },
+            None => Self::new(plugin_path.join(DEFAULT_COVERAGE_DIR)),
+        })
+    }
+
+    /// Moves profiles written by instances of the cluster located in
+    /// `data_dir` into [`CoverageConfig::output_dir`] and merges them.
+    ///
+    /// ### Returns
+    /// Paths to the collected profiles.
+    pub fn collect(&self, data_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        fs::create_dir_all(&self.output_dir)
+            .with_context(|| format!("failed to create '{}'", self.output_dir.display()))?;
+        // Data directory is unique, so profiles of several clusters don't clash.
+        let cluster_id = data_dir
+            .file_name()
+            .map(OsStr::to_string_lossy)
+            .unwrap_or_default();
+
+        let mut collected = Vec::new();
+        let Ok(instance_dirs) = fs::read_dir(data_dir.join("cluster")) else {
+            return Ok(collected);
+        };
+        for instance_dir in instance_dirs.filter_map(Result::ok) {
+            let instance_name = instance_dir.file_name().to_string_lossy().into_owned();
+            for profile in profiles(&instance_dir.path().join(PROFILES_DIR)) {
+                let file_name = profile.file_name().unwrap_or_default().to_string_lossy();
+                let dest = self
+                    .output_dir
+                    .join(format!("{cluster_id}-{instance_name}-{file_name}"));
+                move_file(&profile, &dest)?;
+                collected.push(dest);
+            }
+        }
+        debug!(
+            "Collected {} coverage profiles into '{}'",
+            collected.len(),
+            self.output_dir.display()
+        );
+
+        if self.merge && !collected.is_empty() {
+            self.merge_profiles()?;
+        }
+        Ok(collected)
+    }
+
+    /// Merges all profiles of [`CoverageConfig::output_dir`]
+    /// into [`MERGED_PROFILE_NAME`] by `llvm-profdata`.
+    fn merge_profiles(&self) -> anyhow::Result<()> {
+        let llvm_profdata = env::var_os(LLVM_PROFDATA).unwrap_or_else(|| "llvm-profdata".into());
+        let merged = self.output_dir.join(MERGED_PROFILE_NAME);
+        let status = Command::new(&llvm_profdata)
+            .args(["merge", "-sparse", "-o"])
+            .arg(&merged)
+            .args(profiles(&self.output_dir))
+            .status()
+            .with_context(|| {
+                format!(
+                    "failed to run '{}', install llvm-tools or set {LLVM_PROFDATA}",
+                    llvm_profdata.to_string_lossy()
+                )
+            })?;
+        if !status.success() {
+            bail!("\"llvm-profdata merge\" has finished with failure: {status}");
+        }
+        println!("Coverage profiles are merged into '{}'", merged.display());
+        Ok(())
+    }
+}
+
+impl Cluster {
+    /// Enables coverage mode, see [module docs](self).
+    ///
+    /// Profiles are collected when the cluster is shut down.
+    pub fn coverage(mut self, config: Option<CoverageConfig>) -> Self {
+        self.coverage = config;
+        self
+    }
+}
+
+/// Returns `RUSTFLAGS` of the test process with coverage instrumentation enabled.
+pub fn instrumented_rustflags() -> String {
+    let rustflags = env::var("RUSTFLAGS").unwrap_or_default();
+    if rustflags.contains("instrument-coverage") {
+        return rustflags;
+    }
+    format!("{rustflags} {INSTRUMENT_COVERAGE_FLAG}")
+        .trim()
+        .to_string()
+}
+
+/// Returns `true` if shared library is instrumented for coverage.
+pub fn is_instrumented(library_path: &Path) -> anyhow::Result<bool> {
+    let content = fs::read(library_path)
+        .with_context(|| format!("failed to read '{}'", library_path.display()))?;
+    Ok(content
+        .windows(COUNTERS_SECTION.len())
+        .any(|window| window == COUNTERS_SECTION))
+}
+
+/// Checks that plugin libraries located in `profile_path` are instrumented
+/// for coverage and builds them with [`instrumented_rustflags`] otherwise.
+pub fn ensure_instrumented_build(plugin_path: &Path, profile_path: &Path) -> anyhow::Result<()> {
+    if !env::var("RUSTFLAGS").is_ok_and(|flags| flags.contains("instrument-coverage")) {
+        warn!(
+            "RUSTFLAGS of the test process don't enable coverage instrumentation, \
+            plugin may be rebuilt without it on cluster start"
+        );
+    }
+    if is_build_instrumented(profile_path)? {
+        return Ok(());
+    }
+
+    let rustflags = instrumented_rustflags();
+    println!("Building the plugin with coverage instrumentation (RUSTFLAGS=\"{rustflags}\")");
+    let status = Command::new("cargo")
+        .arg("build")
+        .env("RUSTFLAGS", &rustflags)
+        .current_dir(plugin_path)
+        .status()?;
+    if !status.success() {
+        bail!("\"cargo build\" has finished with failure: {status}");
+    }
+    if !is_build_instrumented(profile_path)? {
+        bail!(
+            "plugin libraries in '{}' aren't instrumented for coverage",
+            profile_path.display()
+        );
+    }
+    Ok(())
+}
+
+fn is_build_instrumented(profile_path: &Path) -> anyhow::Result<bool> {
+    let libraries = libraries(profile_path);
+    if libraries.is_empty() {
+        return Ok(false);
+    }
+    for library in libraries {
+        if !is_instrumented(&library)? {
+            debug!("'{}' isn't instrumented for coverage", library.display());
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Writes launcher script running `picodata_path` with coverage
+/// profiles written into the instance directory.
+///
+/// ### Returns
+/// Path to the script to be used as picodata binary.
+pub(crate) fn write_launcher(
+    plugin_path: &Path,
+    cluster_uuid: &Uuid,
+    picodata_path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let launcher_path = launcher::write_launcher(
+        &plugin_path
+            .join(COVERAGE_LAUNCHERS_DIR)
+            .join(cluster_uuid.to_string()),
+        "picodata",
+        &launcher_script(picodata_path),
+    )?;
+
+    debug!(
+        "Instances will be started with coverage profiling by '{}'",
+        launcher_path.display()
+    );
+    Ok(launcher_path)
+}
+
+fn launcher_script(picodata_path: &Path) -> String {
+    let exec = launcher::exec_line(picodata_path);
+    format!(
+        r#"#!/bin/sh
+# Generated by picotest: runs picodata writing coverage profiles into the instance directory.
+set -e
+{INSTANCE_DIR_SCRIPT}
+if [ -n "$instance_dir" ]; then
+    LLVM_PROFILE_FILE="$instance_dir/{PROFILES_DIR}/picodata-%p-%m.{PROFILE_EXTENSION}"
+    export LLVM_PROFILE_FILE
+fi
+{exec}"#
+    )
+}
+
+/// Returns profiles located in `dir`.
+fn profiles(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut profiles: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == PROFILE_EXTENSION))
+        .collect();
+    profiles.sort();
+    profiles
+}
+
+/// Moves file, copying it if `dest` is on another file system.
+fn move_file(from: &Path, dest: &Path) -> anyhow::Result<()> {
+    if fs::rename(from, dest).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, dest).with_context(|| format!("failed to copy '{}'", from.display()))?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_instrumented, launcher_script, CoverageConfig, PROFILES_DIR};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::Path;
+
+    #[rstest]
+    fn test_launcher_script() {
+        let script = launcher_script(Path::new("/usr/bin/picodata"));
+        assert!(
+            script.contains(r#"LLVM_PROFILE_FILE="$instance_dir/coverage/picodata-%p-%m.profraw""#)
+        );
+        assert!(script.contains(r#"exec '/usr/bin/picodata' "$@""#));
+    }
+
+    #[rstest]
+    fn test_is_instrumented() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("instrumented.so"), b"\x7fELF...__llvm_prf_cnts...").unwrap();
+        fs::write(dir.join("plain.so"), b"\x7fELF...").unwrap();
+        let instrumented = is_instrumented(&dir.join("instrumented.so")).unwrap();
+        let plain = is_instrumented(&dir.join("plain.so")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(instrumented);
+        assert!(!plain);
+    }
+
+    #[rstest]
+    fn test_collect_profiles() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let data_dir = dir.join("tmp/tests/abc");
+        for instance in ["default_1_1", "default_2_1"] {
+            let profiles_dir = data_dir.join("cluster").join(instance).join(PROFILES_DIR);
+            fs::create_dir_all(&profiles_dir).unwrap();
+            fs::write(profiles_dir.join("picodata-42-1.profraw"), instance).unwrap();
+            fs::write(profiles_dir.join("notes.txt"), "not a profile").unwrap();
+        }
+
+        let config = CoverageConfig {
+            output_dir: dir.join("coverage"),
+            merge: false,
+        };
+        let mut collected: Vec<String> = config
+            .collect(&data_dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        collected.sort();
+        let content =
+            fs::read_to_string(dir.join("coverage/abc-default_2_1-picodata-42-1.profraw")).unwrap();
+        let left = data_dir.join("cluster/default_1_1/coverage/picodata-42-1.profraw");
+        let is_moved = !left.exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            collected,
+            [
+                "abc-default_1_1-picodata-42-1.profraw",
+                "abc-default_2_1-picodata-42-1.profraw"
+            ]
+        );
+        assert_eq!(content, "default_2_1");
+        assert!(is_moved);
+    }
+}
@@ -0,0 +1,290 @@
+//! Generation of random table rows honoring table schema.
+//!
+//! Schema is taken from the format of the table space: values are generated
+//! according to column types, non-nullable columns never get `NULL`, and
+//! primary key columns get unique values. Rows are reproducible by the seed.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! cluster.run_sql(
+//!     "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL, email TEXT) \
+//!         DISTRIBUTED BY (id)",
+//! )?;
+//! cluster.populate("users", 10_000, 42)?;
+//! ```
+
+use crate::introspection::eval_lua_yaml;
+use crate::lua::lua_string_literal;
+use crate::seed::PicotestRng;
+use crate::sql::quote_identifier;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use rand::distr::Alphanumeric;
+use rand::{RngExt, SeedableRng};
+use serde::Deserialize;
+use serde_norway::Value;
+use uuid::Builder;
+
+/// Number of rows inserted by a single query.
+pub const POPULATE_BATCH_SIZE: usize = 500;
+
+/// Probability of `NULL` in nullable columns.
+const NULL_PROBABILITY: f64 = 0.1;
+
+/// Sharding column filled by picodata itself.
+const BUCKET_ID_COLUMN: &str = "bucket_id";
+
+/// Returns columns of the space `{table}` along with primary key flags.
+const TABLE_SCHEMA_LUA: &str = r#"(function()
+    local space = box.space[{table}]
+    if space == nil then error('table ' .. {table} .. ' does not exist') end
+    local primary = {}
+    for _, part in ipairs(space.index[0].parts) do primary[part.fieldno] = true end
+    local columns = {}
+    for fieldno, field in ipairs(space:format()) do
+        table.insert(columns, {
+            name = field.name,
+            type = field.type,
+            is_nullable = field.is_nullable == true,
+            is_primary = primary[fieldno] == true,
+        })
+    end
+    return columns
+end)()"#;
+
+/// Column of the table schema.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ColumnSchema {
+    pub name: String,
+    /// Type of the space field, e.g. `integer` or `string`.
+    #[serde(rename = "type")]
+    pub column_type: String,
+    #[serde(default)]
+    pub is_nullable: bool,
+    /// Column is a part of the primary key.
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+/// Generates random value of the `column`.
+///
+/// ### Arguments:
+/// - `column` - column the value is generated for
+/// - `key` - number unique for the row, primary key values are derived from it
+/// - `rng` - random generator
+pub fn generate_value(
+    column: &ColumnSchema,
+    key: u64,
+    rng: &mut PicotestRng,
+) -> anyhow::Result<Value> {
+    if column.is_nullable && !column.is_primary && rng.random_bool(NULL_PROBABILITY) {
+        return Ok(Value::Null);
+    }
+
+    let value = match (column.column_type.as_str(), column.is_primary) {
+        ("integer" | "unsigned" | "number", true) => Value::from(key),
+        ("double", true) => Value::from(key as f64),
+        ("decimal", true) => Value::from(key.to_string()),
+        ("string", true) => Value::from(format!("{}_{key}", column.name)),
+        ("integer", false) => Value::from(rng.random_range(-1_000_000i64..=1_000_000)),
+        ("unsigned", false) => Value::from(rng.random_range(0u64..=1_000_000)),
+        ("number" | "double", false) => Value::from(rng.random_range(-1e6..1e6)),
+        ("decimal", false) => Value::from(format!(
+            "{}.{:02}",
+            rng.random_range(-1_000_000i64..=1_000_000),
+            rng.random_range(0..100)
+        )),
+        ("string", false) => {
+            let len = rng.random_range(8..=16);
+            Value::from(
+                (0..len)
+                    .map(|_| char::from(rng.sample(Alphanumeric)))
+                    .collect::<String>(),
+            )
+        }
+        ("boolean", _) => Value::from(rng.random_bool(0.5)),
+        ("uuid", _) => Value::from(
+            Builder::from_random_bytes(rng.random())
+                .into_uuid()
+                .to_string(),
+        ),
+        ("datetime", _) => Value::from(format!(
+            "{}-{:02}-{:02}",
+            rng.random_range(2000..=2030),
+            rng.random_range(1..=12),
+            rng.random_range(1..=28)
+        )),
+        (column_type, _) => bail!(
+            "type '{column_type}' of column '{}' is not supported",
+            column.name
+        ),
+    };
+    Ok(value)
+}
+
+/// Generates `count` random rows of the table with `columns`.
+///
+/// Primary key values are sequential starting from a random offset.
+pub fn generate_rows(
+    columns: &[ColumnSchema],
+    count: usize,
+    rng: &mut PicotestRng,
+) -> anyhow::Result<Vec<Vec<Value>>> {
+    let first_key = rng.random_range(0..1u64 << 31);
+    (first_key..first_key + count as u64)
+        .map(|key| {
+            columns
+                .iter()
+                .map(|column| generate_value(column, key, rng))
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns SQL expression of the parameter of the `column`.
+///
+/// Values of types without literal representation are passed as strings.
+fn column_placeholder(column: &ColumnSchema) -> &'static str {
+    match column.column_type.as_str() {
+        "decimal" => "CAST(? AS DECIMAL)",
+        "uuid" => "CAST(? AS UUID)",
+        "datetime" => "TO_DATE(?, '%Y-%m-%d')",
+        _ => "?",
+    }
+}
+
+/// Builds query inserting `rows` rows of `columns` into the `table`.
+fn insert_query(table: &str, columns: &[ColumnSchema], rows: usize) -> String {
+    let names: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
+    let placeholders: Vec<&str> = columns.iter().map(column_placeholder).collect();
+    let row = format!("({})", placeholders.join(", "));
+    format!(
+        "INSERT INTO {} ({}) VALUES {}",
+        quote_identifier(table),
+        names.join(", "),
+        vec![row; rows].join(", ")
+    )
+}
+
+impl PicotestInstance {
+    /// Returns columns of the `table` except for sharding column `bucket_id`.
+    pub fn table_schema(&self, table: &str) -> anyhow::Result<Vec<ColumnSchema>> {
+        let columns: Vec<ColumnSchema> = eval_lua_yaml(
+            self,
+            &TABLE_SCHEMA_LUA.replace("{table}", &lua_string_literal(table)),
+        )
+        .with_context(|| format!("failed to get schema of table '{table}'"))?;
+        Ok(columns
+            .into_iter()
+            .filter(|column| column.name != BUCKET_ID_COLUMN)
+            .collect())
+    }
+}
+
+impl Cluster {
+    /// Returns columns of the `table`. See [`PicotestInstance::table_schema`].
+    pub fn table_schema(&self, table: &str) -> anyhow::Result<Vec<ColumnSchema>> {
+        self.main().table_schema(table)
+    }
+
+    /// Inserts `count` random rows into the `table`.
+    ///
+    /// ### Arguments:
+    /// - `table` - name of the table
+    /// - `count` - number of rows
+    /// - `seed` - seed of random values, the same seed produces the same rows
+    pub fn populate(&self, table: &str, count: usize, seed: u64) -> anyhow::Result<()> {
+        let columns = self.table_schema(table)?;
+        let mut rng = PicotestRng::seed_from_u64(seed);
+        let rows = generate_rows(&columns, count, &mut rng)
+            .with_context(|| format!("failed to generate rows of table '{table}'"))?;
+
+        for batch in rows.chunks(POPULATE_BATCH_SIZE) {
+            let params: Vec<Value> = batch.iter().flatten().cloned().collect();
+            self.sql_query(&insert_query(table, &columns, batch.len()), &params)
+                .with_context(|| format!("failed to populate table '{table}'"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_rows, generate_value, insert_query, ColumnSchema};
+    use crate::seed::PicotestRng;
+    use rand::SeedableRng;
+    use rstest::rstest;
+    use serde_norway::Value;
+    use std::collections::HashSet;
+
+    fn column(name: &str, column_type: &str, is_nullable: bool, is_primary: bool) -> ColumnSchema {
+        ColumnSchema {
+            name: name.into(),
+            column_type: column_type.into(),
+            is_nullable,
+            is_primary,
+        }
+    }
+
+    #[rstest]
+    fn test_generate_rows() {
+        let columns = [
+            column("id", "integer", false, true),
+            column("name", "string", false, false),
+            column("email", "string", true, false),
+            column("token", "uuid", false, false),
+        ];
+        let rows = generate_rows(&columns, 1000, &mut PicotestRng::seed_from_u64(1)).unwrap();
+        assert_eq!(rows.len(), 1000);
+
+        let ids: HashSet<&Value> = rows.iter().map(|row| &row[0]).collect();
+        assert_eq!(ids.len(), rows.len());
+        assert!(rows.iter().all(|row| row[1].is_string()));
+        assert!(rows.iter().any(|row| row[2].is_null()));
+        assert!(rows.iter().all(|row| row[3].as_str().unwrap().len() == 36));
+
+        let same_rows = generate_rows(&columns, 1000, &mut PicotestRng::seed_from_u64(1)).unwrap();
+        assert_eq!(rows, same_rows);
+    }
+
+    #[rstest]
+    #[case("unsigned", |value: &Value| value.as_u64().is_some())]
+    #[case("double", |value: &Value| value.as_f64().is_some())]
+    #[case("boolean", |value: &Value| value.is_bool())]
+    #[case("decimal", |value: &Value| value.as_str().unwrap().contains('.'))]
+    #[case("datetime", |value: &Value| value.as_str().unwrap().len() == 10)]
+    fn test_generate_value(#[case] column_type: &str, #[case] is_valid: fn(&Value) -> bool) {
+        let column = column("value", column_type, false, false);
+        let mut rng = PicotestRng::seed_from_u64(1);
+        for key in 0..100 {
+            assert!(is_valid(&generate_value(&column, key, &mut rng).unwrap()));
+        }
+    }
+
+    #[rstest]
+    fn test_generate_unsupported_value() {
+        let err = generate_value(
+            &column("tags", "array", false, false),
+            0,
+            &mut PicotestRng::seed_from_u64(1),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "type 'array' of column 'tags' is not supported"
+        );
+    }
+
+    #[rstest]
+    fn test_insert_query() {
+        let columns = [
+            column("id", "unsigned", false, true),
+            column("token", "uuid", true, false),
+        ];
+        assert_eq!(
+            insert_query("users", &columns, 2),
+            "INSERT INTO \"users\" (\"id\", \"token\") \
+                VALUES (?, CAST(? AS UUID)), (?, CAST(? AS UUID))"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Standalone runtime executing Lua without a cluster.
+//!
+//! Script is run by `picodata tarantool`, i.e. by Tarantool runtime built
+//! into picodata binary: box is configured in a temporary directory,
+//! but there's no raft, no instance bootstrap and no plugins installed.
+//! It's enough for code, which needs only fibers and box API, and starts
+//! in a fraction of time required by the cluster.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let runtime = EmbeddedRuntime::new(&plugin_path, PathBuf::from("picodata"));
+//! let output = runtime.run_lua("return box.info.status")?;
+//! assert_eq!(output, "running");
+//! ```
+
+use crate::coverage::CoverageConfig;
+use crate::lua::lua_string_literal;
+use log::debug;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+/// Directory of embedded runtimes data, relative to the plugin path.
+pub const EMBEDDED_DATA_DIR: &str = "tmp/embedded";
+
+/// File the script writes its result into.
+const RESULT_FILENAME: &str = "result";
+
+/// Log file of the runtime, which is kept if script has failed.
+const LOG_FILENAME: &str = "embedded.log";
+
+/// Coverage profiles of the runtime, relative to the coverage output directory.
+const EMBEDDED_PROFILE_PATTERN: &str = "embedded-%p-%m.profraw";
+
+/// Runs Lua scripts by `picodata tarantool`, see [module docs](self).
+#[derive(Debug, Clone)]
+pub struct EmbeddedRuntime {
+    picodata_path: PathBuf,
+    data_dir: PathBuf,
+    coverage: Option<CoverageConfig>,
+}
+
+impl EmbeddedRuntime {
+    /// Creates runtime keeping its data in [`EMBEDDED_DATA_DIR`] of the plugin.
+    ///
+    /// ### Arguments:
+    /// - `plugin_path` - path to the plugin root directory.
+    /// - `picodata_path` - path to picodata binary.
+    pub fn new(plugin_path: &Path, picodata_path: PathBuf) -> Self {
+        Self {
+            picodata_path,
+            data_dir: plugin_path.join(EMBEDDED_DATA_DIR),
+            coverage: None,
+        }
+    }
+
+    /// Writes coverage profiles of the runtime straight into
+    /// [`CoverageConfig::output_dir`], see [`crate::coverage`].
+    pub fn coverage(mut self, config: Option<CoverageConfig>) -> Self {
+        self.coverage = config;
+        self
+    }
+
+    /// Runs `script` in a new runtime process.
+    ///
+    /// Every call configures box in its own directory, which is removed
+    /// if script has succeeded, so scripts don't share any state.
+    ///
+    /// ### Returns
+    /// - On success, value returned by the script converted to string.
+    /// - On failure, error raised by the script or description of the
+    ///   runtime failure, e.g. if runtime has crashed.
+    pub fn run_lua(&self, script: &str) -> Result<String, Error> {
+        let work_dir = self.data_dir.join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&work_dir)?;
+        let work_dir = fs::canonicalize(work_dir)?;
+        let script_path = work_dir.join("script.lua");
+        fs::write(&script_path, embedded_script(&work_dir, script))?;
+
+        debug!(
+            "Running embedded runtime in '{}' with script:\n{script}",
+            work_dir.display()
+        );
+        let mut command = Command::new(&self.picodata_path);
+        command
+            .arg("tarantool")
+            .arg("--")
+            .arg(&script_path)
+            .current_dir(&work_dir);
+        if let Some(coverage) = &self.coverage {
+            fs::create_dir_all(&coverage.output_dir)?;
+            command.env(
+                "LLVM_PROFILE_FILE",
+                coverage.output_dir.join(EMBEDDED_PROFILE_PATTERN),
+            );
+        }
+        let output = command.output().map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!(
+                    "failed to run '{} tarantool': {err}",
+                    self.picodata_path.display()
+                ),
+            )
+        })?;
+        // Output of the script is forwarded, so it's captured by the test harness.
+        print!("{}", String::from_utf8_lossy(&output.stdout));
+        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+
+        let result = match fs::read_to_string(work_dir.join(RESULT_FILENAME)) {
+            Ok(result) if output.status.success() => Ok(result),
+            Ok(error) => Err(Error::other(error)),
+            Err(_) => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "embedded runtime has exited with {} without result, see '{}'",
+                    output.status,
+                    work_dir.join(LOG_FILENAME).display()
+                ),
+            )),
+        };
+        if result.is_ok() {
+            let _ = fs::remove_dir_all(&work_dir);
+        }
+        result
+    }
+}
+
+/// Wraps `script` into the one configuring box in `work_dir`, writing
+/// result of the script into [`RESULT_FILENAME`] and exiting the runtime.
+fn embedded_script(work_dir: &Path, script: &str) -> String {
+    let work_dir = lua_string_literal(&work_dir.to_string_lossy());
+    format!(
+        r#"
+box.cfg({{ work_dir = {work_dir}, log = "{LOG_FILENAME}" }})
+local ok, result = pcall(function()
+{script}
+end)
+local file = io.open({work_dir} .. "/{RESULT_FILENAME}", "w")
+file:write(tostring(result))
+file:close()
+os.exit(ok and 0 or 1)
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::embedded_script;
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    fn test_embedded_script() {
+        let script = embedded_script(Path::new("/plugin/tmp/embedded/1"), "return true");
+        assert!(script
+            .contains(r#"box.cfg({ work_dir = "/plugin/tmp/embedded/1", log = "embedded.log" })"#));
+        assert!(script.contains("pcall(function()\nreturn true\nend)"));
+        assert!(script.contains(r#"io.open("/plugin/tmp/embedded/1" .. "/result", "w")"#));
+    }
+}
@@ -0,0 +1,327 @@
+//! Typed plans of distributed SQL queries.
+//!
+//! Plan is obtained by `EXPLAIN` and parsed from its textual form:
+//! tree of operators (nested by indentation), execution options and
+//! buckets the query is executed on. Assertions allow to pin the plan
+//! of a query and catch regressions after picodata upgrades.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let plan = cluster.explain("SELECT * FROM users WHERE id = 1")?;
+//! plan.assert_single_storage();
+//! plan.assert_no_motion();
+//! ```
+
+use crate::introspection::eval_lua_yaml;
+use crate::lua::lua_string_literal;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use serde_norway::Value;
+use std::collections::BTreeMap;
+
+/// Width of the indentation of nested plan nodes.
+const PLAN_INDENT: usize = 4;
+
+/// Operator of the plan tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanNode {
+    /// Nesting level, root operators have zero depth.
+    pub depth: usize,
+    /// Name of the operator, e.g. `projection`, `scan` or `motion`.
+    pub operator: String,
+    /// Rest of the operator line: columns, conditions, policies.
+    pub details: String,
+}
+
+/// Buckets the query is executed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Buckets {
+    /// Query is executed on any single instance, e.g. for global tables.
+    Any,
+    /// Query is executed on the exact buckets.
+    Exact(Vec<u64>),
+    /// Query is executed on at most these buckets.
+    Estimated(Vec<u64>),
+    /// Buckets are not determined by the planner (e.g. all buckets).
+    Unknown(String),
+}
+
+/// Plan of the distributed SQL query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplainPlan {
+    /// Plan operators in the order of `EXPLAIN` output.
+    pub nodes: Vec<PlanNode>,
+    /// Execution options, e.g. `sql_motion_row_max`.
+    pub options: BTreeMap<String, String>,
+    pub buckets: Buckets,
+    /// Original `EXPLAIN` output.
+    pub text: String,
+}
+
+fn parse_bucket_list(list: &str) -> anyhow::Result<Vec<u64>> {
+    let Some(list) = list.strip_prefix('[').and_then(|l| l.strip_suffix(']')) else {
+        bail!("invalid list of buckets: {list}");
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| {
+            // Ranges of buckets are printed as `1..3`.
+            match bucket.split_once("..") {
+                Some((start, end)) => Ok((start.parse()?..=end.parse()?).collect()),
+                None => Ok(vec![bucket.parse()?]),
+            }
+        })
+        .collect::<anyhow::Result<Vec<Vec<u64>>>>()
+        .map(|ranges| ranges.concat())
+        .with_context(|| format!("invalid list of buckets: [{list}]"))
+}
+
+fn parse_buckets(line: &str) -> anyhow::Result<Buckets> {
+    let rest = line.trim_start_matches("buckets").trim();
+    if let Some(list) = rest.strip_prefix("<=") {
+        return Ok(Buckets::Estimated(parse_bucket_list(list.trim())?));
+    }
+    let value = rest.strip_prefix('=').unwrap_or(rest).trim();
+    match value {
+        "any" => Ok(Buckets::Any),
+        list if list.starts_with('[') => Ok(Buckets::Exact(parse_bucket_list(list)?)),
+        other => Ok(Buckets::Unknown(other.to_string())),
+    }
+}
+
+impl ExplainPlan {
+    /// Parses textual `EXPLAIN` output.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut nodes = Vec::new();
+        let mut options = BTreeMap::new();
+        let mut buckets = Buckets::Unknown(String::new());
+        let mut in_options = false;
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let indent = line.len() - line.trim_start().len();
+            let line = line.trim();
+            if indent == 0 {
+                in_options = false;
+            }
+
+            if indent == 0 && line == "execution options:" {
+                in_options = true;
+            } else if indent == 0 && line.starts_with("buckets") {
+                buckets = parse_buckets(line)?;
+            } else if in_options {
+                let Some((name, value)) = line.split_once('=') else {
+                    bail!("invalid execution option: {line}");
+                };
+                options.insert(name.trim().to_string(), value.trim().to_string());
+            } else {
+                let (operator, details) = line.split_once(' ').unwrap_or((line, ""));
+                nodes.push(PlanNode {
+                    depth: indent / PLAN_INDENT,
+                    operator: operator.to_string(),
+                    details: details.to_string(),
+                });
+            }
+        }
+
+        if nodes.is_empty() {
+            bail!("plan has no operators: {text}");
+        }
+        Ok(Self {
+            nodes,
+            options,
+            buckets,
+            text: text.to_string(),
+        })
+    }
+
+    /// Returns nodes of the `operator`.
+    pub fn nodes_of(&self, operator: &str) -> Vec<&PlanNode> {
+        self.nodes
+            .iter()
+            .filter(|node| node.operator == operator)
+            .collect()
+    }
+
+    /// Returns `true` if data is moved between instances during execution.
+    pub fn has_motion(&self) -> bool {
+        !self.nodes_of("motion").is_empty()
+    }
+
+    /// Returns `true` if query is executed on a single storage.
+    pub fn is_single_storage(&self) -> bool {
+        match &self.buckets {
+            Buckets::Any => true,
+            Buckets::Exact(buckets) | Buckets::Estimated(buckets) => buckets.len() == 1,
+            Buckets::Unknown(_) => false,
+        }
+    }
+
+    /// Returns `true` if some operator refers to the index `index_name`.
+    pub fn uses_index(&self, index_name: &str) -> bool {
+        let quoted = format!("\"{index_name}\"");
+        self.nodes
+            .iter()
+            .any(|node| node.details.contains(&quoted) && node.details.contains("index"))
+    }
+
+    /// Asserts that query is executed on a single storage.
+    #[track_caller]
+    pub fn assert_single_storage(&self) {
+        assert!(
+            self.is_single_storage(),
+            "Query is not executed on a single storage, buckets: {:?}\n{}",
+            self.buckets,
+            self.text
+        );
+    }
+
+    /// Asserts that data isn't moved between instances.
+    #[track_caller]
+    pub fn assert_no_motion(&self) {
+        assert!(!self.has_motion(), "Query plan has motion:\n{}", self.text);
+    }
+
+    /// Asserts that query uses the index `index_name`.
+    #[track_caller]
+    pub fn assert_uses_index(&self, index_name: &str) {
+        assert!(
+            self.uses_index(index_name),
+            "Query plan doesn't use index '{index_name}':\n{}",
+            self.text
+        );
+    }
+}
+
+/// Collects lines of `EXPLAIN` result, which is returned either
+/// as a list of lines or as rows of a single column.
+fn explain_lines(value: &Value, lines: &mut Vec<String>) {
+    match value {
+        Value::String(text) => lines.extend(text.lines().map(String::from)),
+        Value::Sequence(values) => values.iter().for_each(|v| explain_lines(v, lines)),
+        Value::Mapping(mapping) => {
+            if let Some(rows) = mapping.get("rows") {
+                explain_lines(rows, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl PicotestInstance {
+    /// Returns plan of the `query`.
+    pub fn explain(&self, query: &str) -> anyhow::Result<ExplainPlan> {
+        let explain = format!("EXPLAIN {query}");
+        let output: Value = eval_lua_yaml(
+            self,
+            &format!(
+                "(function() \
+                    local res, err = pico.sql({}); \
+                    if err ~= nil then error(err) end; \
+                    return res; \
+                end)()",
+                lua_string_literal(&explain)
+            ),
+        )
+        .with_context(|| format!("failed to explain query '{query}'"))?;
+
+        let mut lines = Vec::new();
+        explain_lines(&output, &mut lines);
+        ExplainPlan::parse(&lines.join("\n"))
+    }
+}
+
+impl Cluster {
+    /// Returns plan of the `query` built on the main instance.
+    /// See [`PicotestInstance::explain`].
+    pub fn explain(&self, query: &str) -> anyhow::Result<ExplainPlan> {
+        self.main().explain(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain_lines, Buckets, ExplainPlan, PlanNode};
+    use rstest::rstest;
+
+    const FILTERED_PLAN: &str = r#"projection ("users"."name"::string -> "name")
+    selection ("users"."id"::integer) = (1::unsigned)
+        scan "users"
+execution options:
+    sql_vdbe_opcode_max = 45000
+    sql_motion_row_max = 5000
+buckets = [1410]"#;
+
+    const JOIN_PLAN: &str = r#"projection ("o"."id"::integer -> "id")
+    join on ("o"."user_id"::integer) = ("u"."id"::integer)
+        scan "o"
+            projection ("orders"."id"::integer -> "id", "orders"."user_id"::integer -> "user_id")
+                scan "orders"
+        motion [policy: full]
+            scan "u"
+                projection ("users"."id"::integer -> "id")
+                    scan "users"
+execution options:
+    sql_vdbe_opcode_max = 45000
+    sql_motion_row_max = 5000
+buckets = unknown"#;
+
+    #[rstest]
+    fn test_parse_plan() {
+        let plan = ExplainPlan::parse(FILTERED_PLAN).unwrap();
+        assert_eq!(plan.nodes.len(), 3);
+        assert_eq!(
+            plan.nodes[2],
+            PlanNode {
+                depth: 2,
+                operator: "scan".into(),
+                details: "\"users\"".into(),
+            }
+        );
+        assert_eq!(plan.options["sql_motion_row_max"], "5000");
+        assert_eq!(plan.buckets, Buckets::Exact(vec![1410]));
+        assert!(plan.is_single_storage());
+        assert!(!plan.has_motion());
+
+        let plan = ExplainPlan::parse(JOIN_PLAN).unwrap();
+        assert_eq!(plan.nodes_of("scan").len(), 4);
+        assert_eq!(plan.nodes_of("motion")[0].depth, 2);
+        assert!(plan.has_motion());
+        assert!(!plan.is_single_storage());
+    }
+
+    #[rstest]
+    #[case("buckets = any", Buckets::Any)]
+    #[case("buckets = [1, 5..7]", Buckets::Exact(vec![1, 5, 6, 7]))]
+    #[case("buckets <= [3000]", Buckets::Estimated(vec![3000]))]
+    #[case("buckets = unknown", Buckets::Unknown("unknown".into()))]
+    fn test_parse_buckets(#[case] line: &str, #[case] expected: Buckets) {
+        let plan = ExplainPlan::parse(&format!("scan \"t\"\n{line}")).unwrap();
+        assert_eq!(plan.buckets, expected);
+    }
+
+    #[rstest]
+    fn test_plan_assertions() {
+        let plan = ExplainPlan::parse(FILTERED_PLAN).unwrap();
+        plan.assert_single_storage();
+        plan.assert_no_motion();
+
+        let plan = ExplainPlan::parse(JOIN_PLAN).unwrap();
+        assert!(std::panic::catch_unwind(|| plan.assert_no_motion()).is_err());
+        assert!(std::panic::catch_unwind(|| plan.assert_uses_index("users_name")).is_err());
+
+        let plan = ExplainPlan::parse("scan \"users\" using index \"users_name\"").unwrap();
+        plan.assert_uses_index("users_name");
+    }
+
+    #[rstest]
+    fn test_explain_lines() {
+        let output: serde_norway::Value =
+            serde_norway::from_str("[\"scan \\\"t\\\"\", \"buckets = any\"]").unwrap();
+        let mut lines = Vec::new();
+        explain_lines(&output, &mut lines);
+        assert_eq!(lines, ["scan \"t\"", "buckets = any"]);
+
+        assert!(ExplainPlan::parse("").is_err());
+    }
+}
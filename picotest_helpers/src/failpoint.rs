@@ -0,0 +1,365 @@
+//! Failpoints of the plugin code under test.
+//!
+//! Plugin marks rare error branches with [`fail_point!`](crate::fail_point)
+//! (see [`fail`](https://docs.rs/fail) crate) and exports the function
+//! toggling them with [`export_failpoints!`](crate::export_failpoints).
+//! Failpoints are compiled in only if `failpoints` feature of picotest is
+//! enabled, otherwise `fail_point!` expands to nothing.
+//!
+//! Tests toggle failpoints of running instances by [`Cluster::enable_failpoint`]:
+//! Lua script looks the exported function up in libraries loaded by the instance
+//! and calls it through FFI, so the failpoint registry of the plugin is
+//! configured in place.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! // Cargo.toml of the plugin:
+//! // [features]
+//! // failpoints = ["picotest/failpoints"]
+//!
+//! // Plugin code.
+//! picotest::export_failpoints!();
+//!
+//! fn flush(&self) -> anyhow::Result<()> {
+//!     picotest::fail_point!("my_plugin::flush_error", |_| bail!("injected flush error"));
+//!     self.storage.flush()
+//! }
+//!
+//! // Test, run by `cargo test --features failpoints`.
+//! #[picotest]
+//! fn test_flush_error() {
+//!     let _failpoint = cluster
+//!         .enable_failpoint("my_plugin::flush_error", "return")
+//!         .unwrap();
+//!     // ...
+//! }
+//! ```
+
+use crate::lua::lua_string_literal;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::ffi::{c_char, CStr};
+
+#[cfg(feature = "failpoints")]
+#[doc(hidden)]
+pub use fail;
+
+/// Name of the function exported by [`export_failpoints!`](crate::export_failpoints).
+pub const FAILPOINT_CFG_SYMBOL: &str = "picotest_failpoint_cfg";
+
+/// Size of the buffer the exported function writes its error into.
+const FAILPOINT_ERROR_CAPACITY: usize = 4096;
+
+/// Declares failpoint like `fail::fail_point!`. Expands to nothing unless
+/// `failpoints` feature is enabled.
+#[cfg(feature = "failpoints")]
+#[macro_export]
+macro_rules! fail_point {
+    ($($args:tt)*) => {
+        $crate::failpoint::fail::fail_point!($($args)*)
+    };
+}
+
+/// Declares failpoint like `fail::fail_point!`. Expands to nothing unless
+/// `failpoints` feature is enabled.
+#[cfg(not(feature = "failpoints"))]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {{}};
+    ($name:expr, $e:expr) => {{}};
+    ($name:expr, $cond:expr, $e:expr) => {{}};
+}
+
+/// Exports function, which picotest toggles failpoints of the library with.
+///
+/// Must be called once in the crate building plugin shared library.
+#[macro_export]
+macro_rules! export_failpoints {
+    () => {
+        /// Configures failpoint of the library, called by picotest through FFI.
+        ///
+        /// # Safety
+        /// See `picotest::failpoint::configure_ffi`.
+        #[allow(dead_code)]
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn picotest_failpoint_cfg(
+            name: *const ::std::ffi::c_char,
+            actions: *const ::std::ffi::c_char,
+            buffer: *mut u8,
+            capacity: usize,
+        ) -> usize {
+            // SAFETY: caller passes valid strings and the buffer of `capacity` bytes.
+            unsafe { $crate::failpoint::configure_ffi(name, actions, buffer, capacity) }
+        }
+    };
+}
+
+/// Configures failpoint `name` of the library with `actions`,
+/// or removes it if `actions` is null.
+///
+/// ### Returns
+/// Zero on success, otherwise length of the error message written into the `buffer`.
+///
+/// # Safety
+/// `name` must be a valid C string, `actions` must be either null or a valid
+/// C string, `buffer` must be valid for writes of `capacity` bytes.
+#[doc(hidden)]
+pub unsafe fn configure_ffi(
+    name: *const c_char,
+    actions: *const c_char,
+    buffer: *mut u8,
+    capacity: usize,
+) -> usize {
+    // SAFETY: guaranteed by the caller.
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    let actions =
+        (!actions.is_null()).then(|| unsafe { CStr::from_ptr(actions) }.to_string_lossy());
+    let Err(message) = configure(&name, actions.as_deref()) else {
+        return 0;
+    };
+
+    // Message is truncated on char boundary to fit into the buffer.
+    // Zero length is reserved for successful result.
+    let mut length = message.len().min(capacity);
+    while !message.is_char_boundary(length) {
+        length -= 1;
+    }
+    // SAFETY: caller guarantees that buffer has `capacity` bytes.
+    unsafe {
+        std::ptr::copy_nonoverlapping(message.as_ptr(), buffer, length);
+    }
+    length.max(1)
+}
+
+#[cfg(feature = "failpoints")]
+fn configure(name: &str, actions: Option<&str>) -> Result<(), String> {
+    match actions {
+        Some(actions) => fail::cfg(name, actions),
+        None => {
+            fail::remove(name);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "failpoints"))]
+fn configure(_name: &str, _actions: Option<&str>) -> Result<(), String> {
+    Err("failpoints are disabled in the plugin, \
+        build it with \"failpoints\" feature of picotest"
+        .to_string())
+}
+
+/// Creates Lua script calling [`FAILPOINT_CFG_SYMBOL`] of every library
+/// loaded by the instance, which exports it.
+///
+/// Libraries are listed from `/proc/self/maps`, so failpoints are
+/// supported by instances running on Linux only.
+fn failpoint_cfg_lua(name: &str, actions: Option<&str>) -> String {
+    let name = lua_string_literal(name);
+    let actions = actions.map_or_else(|| "nil".to_string(), lua_string_literal);
+    format!(
+        r#"
+local ffi = require("ffi")
+pcall(ffi.cdef, "size_t {FAILPOINT_CFG_SYMBOL}(const char *name, const char *actions, char *buffer, size_t capacity);")
+local maps = io.open("/proc/self/maps")
+if maps == nil then
+    error("failpoints are supported on Linux only", 0)
+end
+local paths, seen = {{}}, {{}}
+for line in maps:lines() do
+    local path = line:match("%s(/%S+%.so)$")
+    if path ~= nil and not seen[path] then
+        seen[path] = true
+        table.insert(paths, path)
+    end
+end
+maps:close()
+local buffer = ffi.new("char[?]", {FAILPOINT_ERROR_CAPACITY})
+local configured = 0
+for _, path in ipairs(paths) do
+    local loaded, library = pcall(ffi.load, path)
+    local found, cfg = false, nil
+    if loaded then
+        found, cfg = pcall(function() return library.{FAILPOINT_CFG_SYMBOL} end)
+    end
+    if found then
+        local length = tonumber(cfg({name}, {actions}, buffer, {FAILPOINT_ERROR_CAPACITY}))
+        if length ~= 0 then
+            error(ffi.string(buffer, length), 0)
+        end
+        configured = configured + 1
+    end
+end
+if configured == 0 then
+    error("no loaded library exports {FAILPOINT_CFG_SYMBOL}, call picotest::export_failpoints!() in the plugin", 0)
+end
+return true"#
+    )
+}
+
+/// Enabled failpoint. Failpoint is disabled on drop.
+#[must_use = "failpoint is disabled when the guard is dropped"]
+pub struct FailpointGuard<'a> {
+    name: String,
+    instances: Vec<&'a PicotestInstance>,
+}
+
+impl FailpointGuard<'_> {
+    /// Returns name of the failpoint.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for FailpointGuard<'_> {
+    fn drop(&mut self) {
+        for instance in &self.instances {
+            if let Err(err) = instance.disable_failpoint(&self.name) {
+                warn!("Failed to disable failpoint '{}': {err:#}", self.name);
+            }
+        }
+    }
+}
+
+impl PicotestInstance {
+    /// Enables failpoint of the plugin loaded by the instance.
+    ///
+    /// ### Arguments:
+    /// - `name` - name of the failpoint, e.g. `my_plugin::flush_error`.
+    /// - `actions` - actions of the failpoint in terms of `fail` crate,
+    ///   e.g. `return`, `panic`, `1*return->off` or `50%return(error)`.
+    ///
+    /// ### Returns
+    /// - On success, guard disabling the failpoint when dropped.
+    /// - On failure, instance of [`anyhow::Error`], e.g. if plugin
+    ///   doesn't export failpoints or actions are malformed.
+    pub fn enable_failpoint(
+        &self,
+        name: &str,
+        actions: &str,
+    ) -> anyhow::Result<FailpointGuard<'_>> {
+        self.configure_failpoint(name, Some(actions))?;
+        Ok(FailpointGuard {
+            name: name.to_string(),
+            instances: vec![self],
+        })
+    }
+
+    /// Disables failpoint of the plugin loaded by the instance.
+    pub fn disable_failpoint(&self, name: &str) -> anyhow::Result<()> {
+        self.configure_failpoint(name, None)
+    }
+
+    fn configure_failpoint(&self, name: &str, actions: Option<&str>) -> anyhow::Result<()> {
+        debug!(
+            "Configuring failpoint '{name}' of '{}' with {actions:?}",
+            self.instance_name
+        );
+        let output = self
+            .run_lua(failpoint_cfg_lua(name, actions))
+            .with_context(|| {
+                format!(
+                    "failed to configure failpoint '{name}' on '{}'",
+                    self.instance_name
+                )
+            })?;
+        if !output.contains("true") {
+            bail!(
+                "failed to configure failpoint '{name}' on '{}': {output}",
+                self.instance_name
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Cluster {
+    /// Enables failpoint of the plugin on all instances of the cluster.
+    /// See [`PicotestInstance::enable_failpoint`].
+    ///
+    /// ### Examples:
+    /// ```rust,ignore
+    /// #[picotest]
+    /// fn test_flush_error() {
+    ///     let _failpoint = cluster
+    ///         .enable_failpoint("my_plugin::flush_error", "return")
+    ///         .unwrap();
+    ///     assert!(cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')").is_err());
+    /// }
+    /// ```
+    pub fn enable_failpoint(
+        &self,
+        name: &str,
+        actions: &str,
+    ) -> anyhow::Result<FailpointGuard<'_>> {
+        let mut guard = FailpointGuard {
+            name: name.to_string(),
+            instances: Vec::with_capacity(self.instances().len()),
+        };
+        for instance in self.instances() {
+            // Failpoint is disabled on already configured instances
+            // by the guard, if one of the instances fails.
+            instance.configure_failpoint(name, Some(actions))?;
+            guard.instances.push(instance);
+        }
+        Ok(guard)
+    }
+
+    /// Disables failpoint of the plugin on all instances of the cluster.
+    pub fn disable_failpoint(&self, name: &str) -> anyhow::Result<()> {
+        for instance in self.instances() {
+            instance.disable_failpoint(name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{configure_ffi, failpoint_cfg_lua};
+    use rstest::rstest;
+    use std::ffi::CString;
+
+    #[rstest]
+    fn test_failpoint_cfg_lua() {
+        let script = failpoint_cfg_lua("my_plugin::flush_error", Some("1*return(\"io\")"));
+        assert!(script.contains(r#"cfg("my_plugin::flush_error", "1*return(\"io\")", buffer"#));
+
+        let script = failpoint_cfg_lua("my_plugin::flush_error", None);
+        assert!(script.contains(r#"cfg("my_plugin::flush_error", nil, buffer"#));
+    }
+
+    #[rstest]
+    fn test_configure_ffi() {
+        let name = CString::new("picotest::test_configure_ffi").unwrap();
+        let actions = CString::new("return").unwrap();
+        let mut buffer = [0u8; 8];
+
+        let length = unsafe {
+            configure_ffi(
+                name.as_ptr(),
+                actions.as_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+        if cfg!(feature = "failpoints") {
+            assert_eq!(length, 0);
+        } else {
+            // Error is truncated to fit into the buffer.
+            assert_eq!(length, buffer.len());
+            assert_eq!(&buffer, b"failpoin");
+        }
+
+        let length = unsafe {
+            configure_ffi(
+                name.as_ptr(),
+                std::ptr::null(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+        assert_eq!(length == 0, cfg!(feature = "failpoints"));
+    }
+}
@@ -0,0 +1,330 @@
+//! Assertions on failing queries and RPC calls.
+//!
+//! Error message is classified into [`ErrorClass`], so tests can tell
+//! a syntax error apart from denied access or an error of the plugin
+//! without matching exact messages, which differ between picodata versions.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::failure::{ErrorClass, ErrorMatcher};
+//!
+//! cluster.assert_query_fails("SELEKT 1", ErrorClass::Syntax);
+//! cluster.assert_query_fails(
+//!     "SELECT * FROM missing_table",
+//!     ErrorMatcher::class(ErrorClass::NotFound).message_contains("missing_table"),
+//! );
+//! ```
+
+use crate::iproto_pool::is_connection_error;
+use crate::lua::{lua_string_literal, LuaStatus};
+use crate::{Cluster, PicotestInstance};
+use serde::de::IgnoredAny;
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Class of the error determined by its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Query can't be parsed.
+    Syntax,
+    /// User lacks privileges.
+    AccessDenied,
+    /// Table, index, user or procedure doesn't exist.
+    NotFound,
+    /// Object being created already exists.
+    AlreadyExists,
+    /// Data violates unique key, nullability or types of table columns.
+    Constraint,
+    /// Operation hasn't finished in time.
+    Timeout,
+    /// Instance can't be reached.
+    Connection,
+    /// Error returned by RPC handler of the plugin.
+    Plugin,
+    Other,
+}
+
+/// Message patterns of error classes (in lowercase), checked in order.
+const ERROR_PATTERNS: &[(ErrorClass, &[&str])] = &[
+    (
+        ErrorClass::AccessDenied,
+        &["access denied", "accessdenied", "not permitted"],
+    ),
+    (
+        ErrorClass::Syntax,
+        &[
+            "rule parsing error",
+            "syntax error",
+            "parsing error",
+            "unexpected token",
+        ],
+    ),
+    (
+        ErrorClass::NotFound,
+        &["not found", "does not exist", "doesn't exist", "no such"],
+    ),
+    (ErrorClass::AlreadyExists, &["already exists"]),
+    (
+        ErrorClass::Constraint,
+        &[
+            "duplicate key",
+            "constraint",
+            "required by space format",
+            "does not match one required",
+            "can't be null",
+            "cannot be null",
+        ],
+    ),
+    (ErrorClass::Timeout, &["timeout", "timed out"]),
+    (
+        ErrorClass::Connection,
+        &["connection refused", "connection reset", "broken pipe"],
+    ),
+];
+
+impl ErrorClass {
+    /// Determines class of the error by its `message`.
+    pub fn of(message: &str) -> Self {
+        let message = message.to_lowercase();
+        ERROR_PATTERNS
+            .iter()
+            .find(|(_, patterns)| patterns.iter().any(|p| message.contains(p)))
+            .map_or(ErrorClass::Other, |(class, _)| *class)
+    }
+}
+
+/// Error of the failed query or RPC call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassifiedError {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl ClassifiedError {
+    pub fn new(message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self {
+            class: ErrorClass::of(&message),
+            message,
+        }
+    }
+}
+
+impl Display for ClassifiedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} error: {}", self.class, self.message)
+    }
+}
+
+/// Expected error of the failed operation.
+///
+/// Class and substring of the message can be set from [`ErrorClass`]
+/// and `&str` respectively, e.g. `assert_query_fails(query, ErrorClass::Syntax)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorMatcher {
+    class: Option<ErrorClass>,
+    message: Option<String>,
+}
+
+impl ErrorMatcher {
+    /// Matches any error.
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    /// Matches errors of the `class`.
+    pub fn class(class: ErrorClass) -> Self {
+        Self {
+            class: Some(class),
+            message: None,
+        }
+    }
+
+    /// Requires error message to contain `text`.
+    pub fn message_contains(mut self, text: impl Into<String>) -> Self {
+        self.message = Some(text.into());
+        self
+    }
+
+    pub fn matches(&self, error: &ClassifiedError) -> bool {
+        self.class.is_none_or(|class| class == error.class)
+            && self
+                .message
+                .as_ref()
+                .is_none_or(|text| error.message.contains(text.as_str()))
+    }
+}
+
+impl From<ErrorClass> for ErrorMatcher {
+    fn from(class: ErrorClass) -> Self {
+        Self::class(class)
+    }
+}
+
+impl From<&str> for ErrorMatcher {
+    fn from(text: &str) -> Self {
+        Self::any().message_contains(text)
+    }
+}
+
+impl Display for ErrorMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.class {
+            Some(class) => write!(f, "{class:?} error")?,
+            None => write!(f, "any error")?,
+        }
+        if let Some(text) = &self.message {
+            write!(f, " containing '{text}'")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that operation has failed with the error matching `matcher`.
+#[track_caller]
+fn expect_failure(
+    operation: &str,
+    error: Option<ClassifiedError>,
+    matcher: &ErrorMatcher,
+) -> ClassifiedError {
+    let Some(error) = error else {
+        panic!("{operation} has succeeded, but expected to fail with {matcher}");
+    };
+    assert!(
+        matcher.matches(&error),
+        "{operation} has failed with {error}, but expected {matcher}"
+    );
+    error
+}
+
+impl PicotestInstance {
+    /// Asserts that SQL `query` fails with the error matching `matcher`.
+    ///
+    /// ### Returns
+    /// Error of the query for further checks.
+    #[track_caller]
+    pub fn assert_query_fails(
+        &self,
+        query: &str,
+        matcher: impl Into<ErrorMatcher>,
+    ) -> ClassifiedError {
+        let response = self
+            .run_lua_with_status(format!(
+                "local res, err = pico.sql({}); if err ~= nil then error(err) end; return res",
+                lua_string_literal(query)
+            ))
+            .unwrap_or_else(|err| panic!("Failed to execute query '{query}': {err}"));
+        let error = match response.status {
+            LuaStatus::Ok => None,
+            LuaStatus::Error(message) => Some(ClassifiedError::new(message)),
+        };
+        expect_failure(&format!("Query '{query}'"), error, &matcher.into())
+    }
+
+    /// Asserts that RPC call fails with the error matching `matcher`.
+    /// See [`PicotestInstance::execute_rpc`] for the meaning of arguments.
+    ///
+    /// Errors, which don't fall into any other class, are classified
+    /// as [`ErrorClass::Plugin`], i.e. returned by the handler.
+    pub async fn assert_rpc_fails<S: Serialize>(
+        &self,
+        plugin_name: &str,
+        path: &str,
+        service_name: &str,
+        plugin_version: &str,
+        input: &S,
+        matcher: impl Into<ErrorMatcher>,
+    ) -> ClassifiedError {
+        let result = self
+            .execute_rpc::<S, IgnoredAny>(plugin_name, path, service_name, plugin_version, input)
+            .await;
+        let error = result.err().map(|err| {
+            let mut error = ClassifiedError::new(format!("{err:#}"));
+            if is_connection_error(&err) {
+                error.class = ErrorClass::Connection;
+            } else if error.class == ErrorClass::Other {
+                error.class = ErrorClass::Plugin;
+            }
+            error
+        });
+        expect_failure(&format!("RPC '{path}'"), error, &matcher.into())
+    }
+}
+
+impl Cluster {
+    /// Asserts that SQL `query` fails on the main instance.
+    /// See [`PicotestInstance::assert_query_fails`].
+    #[track_caller]
+    pub fn assert_query_fails(
+        &self,
+        query: &str,
+        matcher: impl Into<ErrorMatcher>,
+    ) -> ClassifiedError {
+        self.main().assert_query_fails(query, matcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expect_failure, ClassifiedError, ErrorClass, ErrorMatcher};
+    use rstest::rstest;
+    use std::panic::catch_unwind;
+
+    #[rstest]
+    #[case("sbroad: rule parsing error: expected Query", ErrorClass::Syntax)]
+    #[case(
+        "Access denied: Read access to space 'secrets' is denied for user 'guest'",
+        ErrorClass::AccessDenied
+    )]
+    #[case("sbroad: table with name \"missing\" not found", ErrorClass::NotFound)]
+    #[case("table users already exists", ErrorClass::AlreadyExists)]
+    #[case(
+        "Duplicate key exists in unique index \"users_pkey\" in space \"users\"",
+        ErrorClass::Constraint
+    )]
+    #[case("timed out waiting for DDL", ErrorClass::Timeout)]
+    #[case("Connection refused (os error 111)", ErrorClass::Connection)]
+    #[case("invalid discount code", ErrorClass::Other)]
+    fn test_error_class(#[case] message: &str, #[case] expected: ErrorClass) {
+        assert_eq!(ErrorClass::of(message), expected);
+    }
+
+    #[rstest]
+    fn test_error_matcher() {
+        let error = ClassifiedError::new("sbroad: table with name \"missing\" not found");
+
+        assert!(ErrorMatcher::any().matches(&error));
+        assert!(ErrorMatcher::from(ErrorClass::NotFound).matches(&error));
+        assert!(ErrorMatcher::from("\"missing\"").matches(&error));
+        assert!(ErrorMatcher::class(ErrorClass::NotFound)
+            .message_contains("missing")
+            .matches(&error));
+        assert!(!ErrorMatcher::class(ErrorClass::Syntax).matches(&error));
+        assert!(!ErrorMatcher::class(ErrorClass::NotFound)
+            .message_contains("users")
+            .matches(&error));
+
+        assert_eq!(
+            ErrorMatcher::class(ErrorClass::Syntax)
+                .message_contains("SELEKT")
+                .to_string(),
+            "Syntax error containing 'SELEKT'"
+        );
+    }
+
+    #[rstest]
+    fn test_expect_failure() {
+        let error = ClassifiedError::new("Access denied for user 'guest'");
+        let matcher = ErrorMatcher::class(ErrorClass::AccessDenied);
+        assert_eq!(
+            expect_failure("Query", Some(error.clone()), &matcher),
+            error
+        );
+
+        assert!(catch_unwind(|| expect_failure("Query", None, &matcher)).is_err());
+        assert!(catch_unwind(|| {
+            expect_failure("Query", Some(error), &ErrorClass::Syntax.into())
+        })
+        .is_err());
+    }
+}
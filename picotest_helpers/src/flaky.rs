@@ -0,0 +1,436 @@
+//! History of test outcomes to find flaky tests.
+//!
+//! When tracking is enabled, outcome and duration of every test are
+//! appended to the history file (one JSON object per line) together with
+//! warnings and errors written to instance logs while the test was running.
+//! [`flaky_tests`] summarizes history of many runs into tests, which
+//! both passed and failed, and warnings seen in their failed runs.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::flaky::{flaky_tests, TestHistory};
+//!
+//! let runs = TestHistory::from_env(Path::new(".")).load()?;
+//! for test in flaky_tests(&runs, 5) {
+//!     println!("{test}");
+//! }
+//! ```
+
+use crate::log_stream::{instance_log_path, TailedLog};
+use crate::Cluster;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, io};
+
+/// Environment variable, which enables tracking of test outcomes.
+pub const PICOTEST_TRACK_FLAKY: &str = "PICOTEST_TRACK_FLAKY";
+
+/// Environment variable with path to the history of test outcomes.
+pub const PICOTEST_TEST_HISTORY: &str = "PICOTEST_TEST_HISTORY";
+
+/// Default path to the history of test outcomes, relative to the plugin root.
+pub const DEFAULT_TEST_HISTORY_PATH: &str = "tmp/test_history.jsonl";
+
+/// Maximum number of log warnings kept in the outcome of a single test.
+const MAX_RUN_WARNINGS: usize = 20;
+
+/// Maximum number of warnings reported for a flaky test.
+const MAX_REPORTED_WARNINGS: usize = 3;
+
+/// Log levels of tarantool log lines, which are treated as cluster warnings.
+const WARNING_LEVELS: [&str; 4] = [" W> ", " E> ", " C> ", " F> "];
+
+/// Outcome of a single test execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestRun {
+    /// Test name including module path.
+    pub test: String,
+    /// Identifier of the test process, shared by tests run together.
+    pub session: String,
+    /// Unix time of the test start in seconds.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub passed: bool,
+    /// Warnings and errors written to instance logs while the test was running.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// File with history of test outcomes.
+#[derive(Debug)]
+pub struct TestHistory {
+    path: PathBuf,
+    // Serializes appends of tests running in parallel threads.
+    lock: Mutex<()>,
+}
+
+impl TestHistory {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// History located in [`PICOTEST_TEST_HISTORY`] or
+    /// in [`DEFAULT_TEST_HISTORY_PATH`] of the plugin.
+    pub fn from_env(plugin_path: &Path) -> Self {
+        let path = env::var(PICOTEST_TEST_HISTORY)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| plugin_path.join(DEFAULT_TEST_HISTORY_PATH));
+        Self::new(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends outcome of the test to the history.
+    pub fn append(&self, run: &TestRun) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(run)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("failed to write test history '{}'", self.path.display()))
+    }
+
+    /// Reads all outcomes from the history. Missing history is empty,
+    /// malformed lines (e.g. written by interrupted process) are skipped.
+    pub fn load(&self) -> anyhow::Result<Vec<TestRun>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to open test history '{}'", self.path.display())
+                })
+            }
+        };
+        let lines = BufReader::new(file)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()
+            .with_context(|| format!("failed to read test history '{}'", self.path.display()))?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+/// Returns identifier of the current test process.
+fn session_id() -> &'static str {
+    static SESSION: OnceLock<String> = OnceLock::new();
+    SESSION.get_or_init(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Returns message of the log `line` if it is a warning or an error.
+fn warning_message(line: &str) -> Option<&str> {
+    WARNING_LEVELS
+        .iter()
+        .filter_map(|level| line.find(level).map(|pos| pos + 1))
+        .min()
+        .map(|pos| line[pos..].trim_end())
+}
+
+/// Tracks outcome of the running test, which is appended
+/// to the history by [`TestTracker::finish`].
+pub struct TestTracker<'a> {
+    history: &'a TestHistory,
+    test: String,
+    started_at: SystemTime,
+    started: Instant,
+    logs: Vec<TailedLog>,
+}
+
+impl<'a> TestTracker<'a> {
+    /// Starts tracking of the test `test_name` running on the `cluster`.
+    pub fn start(history: &'a TestHistory, test_name: &str, cluster: &Cluster) -> Self {
+        let data_dir = cluster.data_dir_path();
+        let logs = cluster
+            .instances()
+            .iter()
+            .map(|instance| {
+                TailedLog::new(
+                    &instance.instance_name,
+                    instance_log_path(&data_dir, &instance.instance_name),
+                    None,
+                )
+            })
+            .collect();
+        Self {
+            history,
+            test: test_name.to_string(),
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            logs,
+        }
+    }
+
+    /// Appends outcome of the test, which has `failed` or not, to the history.
+    pub fn finish(mut self, failed: bool) -> anyhow::Result<()> {
+        let duration = self.started.elapsed();
+        let warnings = self
+            .logs
+            .iter_mut()
+            .flat_map(|log| {
+                let prefix = log.prefix.clone();
+                log.read_lines().into_iter().filter_map(move |line| {
+                    warning_message(&line).map(|message| format!("{prefix} {message}"))
+                })
+            })
+            .take(MAX_RUN_WARNINGS)
+            .collect();
+
+        self.history.append(&TestRun {
+            test: self.test,
+            session: session_id().to_string(),
+            started_at: self
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration_ms: duration.as_millis() as u64,
+            passed: !failed,
+            warnings,
+        })
+    }
+}
+
+/// Warning written to instance logs in runs of a flaky test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorrelatedWarning {
+    pub message: String,
+    /// Number of failed runs the warning was seen in.
+    pub failed_runs: usize,
+    /// Number of passed runs the warning was seen in.
+    pub passed_runs: usize,
+}
+
+/// Test, which has both passed and failed according to the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakyTest {
+    pub test: String,
+    pub runs: usize,
+    pub failures: usize,
+    pub mean_passed_duration: Duration,
+    pub mean_failed_duration: Duration,
+    /// Warnings seen in failed runs, most frequent first.
+    pub warnings: Vec<CorrelatedWarning>,
+}
+
+impl FlakyTest {
+    /// Returns share of failed runs in range `0.0..=1.0`.
+    pub fn failure_rate(&self) -> f64 {
+        self.failures as f64 / self.runs as f64
+    }
+}
+
+impl Display for FlakyTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: failed {} of {} runs ({:.0}%), mean duration passed {:?} / failed {:?}",
+            self.test,
+            self.failures,
+            self.runs,
+            self.failure_rate() * 100.0,
+            self.mean_passed_duration,
+            self.mean_failed_duration
+        )?;
+        for warning in &self.warnings {
+            writeln!(
+                f,
+                "    in {} failed / {} passed runs: {}",
+                warning.failed_runs, warning.passed_runs, warning.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns tests of the history, which have both passed and failed
+/// in at least `min_runs` runs, ordered by failure rate descending.
+pub fn flaky_tests(runs: &[TestRun], min_runs: usize) -> Vec<FlakyTest> {
+    let mut by_test: BTreeMap<&str, Vec<&TestRun>> = BTreeMap::new();
+    for run in runs {
+        by_test.entry(&run.test).or_default().push(run);
+    }
+
+    let mut flaky: Vec<FlakyTest> = by_test
+        .into_iter()
+        .filter_map(|(test, runs)| {
+            let (passed, failed): (Vec<&TestRun>, Vec<&TestRun>) =
+                runs.iter().partition(|run| run.passed);
+            if runs.len() < min_runs || passed.is_empty() || failed.is_empty() {
+                return None;
+            }
+            Some(FlakyTest {
+                test: test.to_string(),
+                runs: runs.len(),
+                failures: failed.len(),
+                mean_passed_duration: mean_duration(&passed),
+                mean_failed_duration: mean_duration(&failed),
+                warnings: correlated_warnings(&passed, &failed),
+            })
+        })
+        .collect();
+    flaky.sort_by(|a, b| {
+        b.failure_rate()
+            .total_cmp(&a.failure_rate())
+            .then_with(|| a.test.cmp(&b.test))
+    });
+    flaky
+}
+
+fn mean_duration(runs: &[&TestRun]) -> Duration {
+    let total: u64 = runs.iter().map(|run| run.duration_ms).sum();
+    Duration::from_millis(total / runs.len().max(1) as u64)
+}
+
+/// Counts runs every warning of `failed` runs was seen in.
+fn correlated_warnings(passed: &[&TestRun], failed: &[&TestRun]) -> Vec<CorrelatedWarning> {
+    fn seen_in(runs: &[&TestRun], message: &str) -> usize {
+        runs.iter()
+            .filter(|run| run.warnings.iter().any(|warning| warning == message))
+            .count()
+    }
+
+    let mut messages: Vec<&str> = failed
+        .iter()
+        .flat_map(|run| run.warnings.iter().map(String::as_str))
+        .collect();
+    messages.sort_unstable();
+    messages.dedup();
+
+    let mut warnings: Vec<CorrelatedWarning> = messages
+        .into_iter()
+        .map(|message| CorrelatedWarning {
+            message: message.to_string(),
+            failed_runs: seen_in(failed, message),
+            passed_runs: seen_in(passed, message),
+        })
+        .collect();
+    // Warnings specific to failed runs go first.
+    warnings.sort_by(|a, b| {
+        b.failed_runs
+            .cmp(&a.failed_runs)
+            .then_with(|| a.passed_runs.cmp(&b.passed_runs))
+            .then_with(|| a.message.cmp(&b.message))
+    });
+    warnings.truncate(MAX_REPORTED_WARNINGS);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flaky_tests, warning_message, TestHistory, TestRun};
+    use rstest::rstest;
+    use std::time::Duration;
+
+    fn run(test: &str, passed: bool, duration_ms: u64, warnings: &[&str]) -> TestRun {
+        TestRun {
+            test: test.to_string(),
+            session: "session".to_string(),
+            started_at: 0,
+            duration_ms,
+            passed,
+            warnings: warnings.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    #[rstest]
+    #[case(
+        "2025-01-01 12:00:00.000 [42] main/104/main W> slow fiber",
+        Some("W> slow fiber")
+    )]
+    #[case(
+        "2025-01-01 12:00:00.000 [42] main/104/main E> ER_TIMEOUT: boom",
+        Some("E> ER_TIMEOUT: boom")
+    )]
+    #[case("2025-01-01 12:00:00.000 [42] main/104/main I> started", None)]
+    fn test_warning_message(#[case] line: &str, #[case] expected: Option<&str>) {
+        assert_eq!(warning_message(line), expected);
+    }
+
+    #[rstest]
+    fn test_flaky_tests() {
+        let runs = [
+            run("stable", true, 10, &[]),
+            run("stable", true, 10, &[]),
+            run("broken", false, 10, &[]),
+            run("broken", false, 10, &[]),
+            run("flaky", true, 100, &["[i1] W> slow fiber"]),
+            run(
+                "flaky",
+                false,
+                300,
+                &["[i1] E> timeout", "[i1] W> slow fiber"],
+            ),
+            run("flaky", false, 500, &["[i1] E> timeout"]),
+            run("rare", true, 10, &[]),
+            run("rare", true, 10, &[]),
+            run("rare", false, 10, &[]),
+        ];
+
+        let flaky = flaky_tests(&runs, 2);
+        let names: Vec<_> = flaky.iter().map(|test| test.test.as_str()).collect();
+        assert_eq!(names, ["flaky", "rare"]);
+
+        let test = &flaky[0];
+        assert_eq!((test.runs, test.failures), (3, 2));
+        assert_eq!(test.mean_passed_duration, Duration::from_millis(100));
+        assert_eq!(test.mean_failed_duration, Duration::from_millis(400));
+        let warnings: Vec<_> = test
+            .warnings
+            .iter()
+            .map(|w| (w.message.as_str(), w.failed_runs, w.passed_runs))
+            .collect();
+        assert_eq!(
+            warnings,
+            [("[i1] E> timeout", 2, 0), ("[i1] W> slow fiber", 1, 1)]
+        );
+        assert!(test
+            .to_string()
+            .starts_with("flaky: failed 2 of 3 runs (67%)"));
+
+        assert!(flaky_tests(&runs, 4).is_empty());
+    }
+
+    #[rstest]
+    fn test_history() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let history = TestHistory::new(dir.join("history.jsonl"));
+        assert!(history.load().unwrap().is_empty());
+
+        let runs = [
+            run("a", true, 1, &[]),
+            run("b", false, 2, &["[i1] E> boom"]),
+        ];
+        for run in &runs {
+            history.append(run).unwrap();
+        }
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(history.path())
+            .and_then(|mut file| std::io::Write::write_all(&mut file, b"{\"test\":"))
+            .unwrap();
+        assert_eq!(history.load().unwrap(), runs);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
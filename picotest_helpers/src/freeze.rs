@@ -0,0 +1,130 @@
+//! Archiving and restoring on-disk state of the cluster.
+//!
+//! Frozen cluster is a `tar.gz` archive of its data directory, so cluster
+//! can be restarted from known state, e.g. pre-seeded with test data or
+//! captured right before a crash.
+
+use crate::Cluster;
+use anyhow::{bail, Context};
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory of archives produced by [`Cluster::freeze`], relative to the plugin path.
+///
+/// It's kept apart from data directories of test clusters, which are
+/// removed on creation of a new cluster.
+pub const FROZEN_CLUSTERS_DIR: &str = "tmp/frozen";
+
+/// Extension of archives produced by [`Cluster::freeze`].
+pub const FROZEN_CLUSTER_EXT: &str = "tar.gz";
+
+impl Cluster {
+    /// Stops the cluster gracefully and archives its data directory.
+    ///
+    /// ### Returns
+    ///
+    /// - On success, path to the archive located in [`FROZEN_CLUSTERS_DIR`].
+    /// - On failure, instance of [`anyhow::Error`].
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// let cluster = Cluster::new(plugin_path, topology, picodata_path)?.run()?;
+    /// cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')")?;
+    /// let archive = cluster.freeze()?;
+    ///
+    /// // Later, cluster is restarted with the inserted data.
+    /// let cluster = Cluster::new(plugin_path, topology, picodata_path)?.thaw(&archive)?;
+    /// ```
+    pub fn freeze(&self) -> anyhow::Result<PathBuf> {
+        if self.is_remote() {
+            bail!("data directory of remote cluster can't be frozen");
+        }
+        self.stop_graceful(self.stop_timeout)?;
+
+        let data_dir = self.data_dir_path();
+        let archive_dir = self.plugin_path.join(FROZEN_CLUSTERS_DIR);
+        fs::create_dir_all(&archive_dir)
+            .with_context(|| format!("failed to create directory '{}'", archive_dir.display()))?;
+        let archive_name = format!("{}.{FROZEN_CLUSTER_EXT}", self.uuid);
+        let archive = archive_dir.join(archive_name);
+        debug!(
+            "Archiving data directory '{}' into '{}'",
+            data_dir.display(),
+            archive.display()
+        );
+        run_tar(
+            Command::new("tar")
+                .arg("-czf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(&data_dir)
+                .arg("."),
+        )?;
+
+        Ok(archive)
+    }
+
+    /// Restores data directory of the cluster from `archive` produced
+    /// by [`Cluster::freeze`] and starts the cluster.
+    ///
+    /// Cluster is stopped first, if it's running.
+    pub fn thaw(self, archive: &Path) -> anyhow::Result<Self> {
+        if self.is_remote() {
+            bail!("remote cluster can't be restarted from archive");
+        }
+        self.restart_with_data(|data_dir| {
+            debug!(
+                "Restoring data directory '{}' from '{}'",
+                data_dir.display(),
+                archive.display()
+            );
+            run_tar(
+                Command::new("tar")
+                    .arg("-xzf")
+                    .arg(archive)
+                    .arg("-C")
+                    .arg(data_dir),
+            )
+        })
+    }
+
+    /// Stops the cluster if it's running, replaces its data directory
+    /// with the one filled by `restore` and starts the cluster.
+    ///
+    /// Data directory is restored anew before every retry of the start.
+    pub(crate) fn restart_with_data(
+        mut self,
+        restore: impl Fn(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Self> {
+        if !self.instances.is_empty() {
+            self.shutdown()?;
+            self.instances.clear();
+        }
+
+        let data_dir = self.data_dir_path();
+        if data_dir.exists() {
+            fs::remove_dir_all(&data_dir).with_context(|| {
+                format!("failed to remove data directory '{}'", data_dir.display())
+            })?;
+        }
+        fs::create_dir_all(&data_dir)
+            .with_context(|| format!("failed to create data directory '{}'", data_dir.display()))?;
+        restore(&data_dir)?;
+
+        self.start(&restore)
+    }
+}
+
+pub(crate) fn run_tar(command: &mut Command) -> anyhow::Result<()> {
+    let output = command.output().context("failed to run tar")?;
+    if !output.status.success() {
+        bail!(
+            "tar has failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+//! State of the governor, which applies DDL, plugin operations and
+//! topology changes to the cluster.
+//!
+//! Governor is busy while any of these is in progress:
+//! - operation of `_pico_governor_queue` system table isn't done,
+//! - `_pico_property` has pending schema change or plugin operation,
+//! - current state of an instance differs from its target state.
+//!
+//! Tests changing schema or plugins may wait for the governor to become
+//! idle instead of sleeping, and check that nothing is stuck at the end.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_create_table() {
+//!     cluster.run_sql("CREATE TABLE users (...)").unwrap();
+//!     cluster.wait_governor_idle(Duration::from_secs(30)).unwrap();
+//!     // ...
+//!     cluster.assert_governor_idle(Duration::from_secs(10));
+//! }
+//! ```
+
+use crate::Cluster;
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Status of the governor queue operation, which has been applied.
+pub const OPERATION_DONE_STATUS: &str = "done";
+
+/// Keys of `_pico_property` present while the governor applies the change.
+pub const PENDING_PROPERTIES: [&str; 2] = ["pending_schema_change", "pending_plugin_operation"];
+
+const GOVERNOR_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Operation of the governor, row of `_pico_governor_queue` system table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GovernorOperation {
+    pub id: u64,
+    pub name: String,
+    /// Kind of the operation, e.g. `sql` or `proc`.
+    pub kind: String,
+    /// Status of the operation, e.g. `pending`, `done` or `failed`.
+    pub status: String,
+    #[serde(default)]
+    pub status_description: Option<String>,
+}
+
+impl GovernorOperation {
+    pub fn is_done(&self) -> bool {
+        self.status == OPERATION_DONE_STATUS
+    }
+}
+
+/// Work the governor hasn't finished, reported by [`Cluster::governor_queue`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GovernorQueue {
+    /// Operations of the governor queue, which aren't done.
+    pub operations: Vec<GovernorOperation>,
+    /// Pending changes of `_pico_property`, see [`PENDING_PROPERTIES`].
+    pub pending_changes: Vec<String>,
+    /// Instances moving to their target state, e.g. `default_1_1 (Offline -> Online)`.
+    pub transitioning_instances: Vec<String>,
+}
+
+impl GovernorQueue {
+    /// Returns `true` if governor has nothing to do.
+    pub fn is_idle(&self) -> bool {
+        self.operations.is_empty()
+            && self.pending_changes.is_empty()
+            && self.transitioning_instances.is_empty()
+    }
+
+    /// Describes unfinished work for progress reporting.
+    fn findings(&self) -> Vec<String> {
+        let operations = self.operations.iter().map(|operation| {
+            format!(
+                "operation {} '{}' ({})",
+                operation.id, operation.name, operation.status
+            )
+        });
+        operations
+            .chain(self.pending_changes.iter().cloned())
+            .chain(self.transitioning_instances.iter().cloned())
+            .collect()
+    }
+}
+
+impl Display for GovernorQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_idle() {
+            return write!(f, "governor is idle");
+        }
+        for operation in &self.operations {
+            write!(
+                f,
+                "operation {} '{}' ({}): {}",
+                operation.id, operation.name, operation.kind, operation.status
+            )?;
+            if let Some(description) = &operation.status_description {
+                write!(f, ", {description}")?;
+            }
+            writeln!(f)?;
+        }
+        for change in &self.pending_changes {
+            writeln!(f, "{change}")?;
+        }
+        for instance in &self.transitioning_instances {
+            writeln!(f, "instance {instance}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct PropertyKey {
+    key: String,
+}
+
+impl Cluster {
+    /// Reads unfinished work of the governor as seen by the main instance.
+    pub fn governor_queue(&self) -> anyhow::Result<GovernorQueue> {
+        let main = self.main();
+        let operations = main
+            .sql_query("SELECT * FROM _pico_governor_queue", &[])?
+            .deserialize::<GovernorOperation>()
+            .context("failed to read _pico_governor_queue")?
+            .into_iter()
+            .filter(|operation| !operation.is_done())
+            .collect();
+
+        let placeholders = vec!["?"; PENDING_PROPERTIES.len()].join(", ");
+        let pending_changes = main
+            .sql_query(
+                &format!("SELECT \"key\" FROM _pico_property WHERE \"key\" IN ({placeholders})"),
+                &PENDING_PROPERTIES.map(Into::into),
+            )?
+            .deserialize::<PropertyKey>()
+            .context("failed to read _pico_property")?
+            .into_iter()
+            .map(|property| property.key)
+            .collect();
+
+        let transitioning_instances = self
+            .health()
+            .instances
+            .into_iter()
+            .filter_map(
+                |instance| match (instance.current_state, instance.target_state) {
+                    (Some(current), Some(target)) if current != target => Some(format!(
+                        "{} ({current} -> {target})",
+                        instance.instance_name
+                    )),
+                    _ => None,
+                },
+            )
+            .collect();
+
+        Ok(GovernorQueue {
+            operations,
+            pending_changes,
+            transitioning_instances,
+        })
+    }
+
+    /// Waits until governor has nothing to do, e.g. after DDL or
+    /// enabling the plugin. Unfinished work is reported by every poll,
+    /// see [`Cluster::on_progress`].
+    ///
+    /// ### Returns
+    /// Error with unfinished work if governor isn't idle within `timeout`.
+    pub fn wait_governor_idle(&self, timeout: Duration) -> anyhow::Result<()> {
+        let mut progress = self.progress("governor");
+        let start_time = Instant::now();
+        loop {
+            let queue = self.governor_queue()?;
+            progress.report(queue.findings());
+            if queue.is_idle() {
+                return Ok(());
+            }
+            if start_time.elapsed() > timeout {
+                bail!(
+                    "governor isn't idle within {}s:\n{queue}",
+                    timeout.as_secs_f64()
+                );
+            }
+            thread::sleep(GOVERNOR_POLL_INTERVAL);
+        }
+    }
+
+    /// Asserts that governor becomes idle within `timeout`, i.e. no
+    /// operation is stuck. See [`Cluster::wait_governor_idle`].
+    #[track_caller]
+    pub fn assert_governor_idle(&self, timeout: Duration) {
+        if let Err(err) = self.wait_governor_idle(timeout) {
+            panic!("Governor has unfinished operations: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GovernorOperation, GovernorQueue};
+    use crate::sql::SqlRows;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_deserialize_governor_operations() {
+        let rows: SqlRows = serde_norway::from_str(
+            r#"
+            metadata:
+            - {name: id, type: unsigned}
+            - {name: name, type: string}
+            - {name: kind, type: string}
+            - {name: status, type: string}
+            - {name: status_description, type: string}
+            - {name: description, type: string}
+            rows:
+            - [1, upgrade_26.1.2, proc, done, null, Upgrade catalog]
+            - [2, upgrade_26.1.3, sql, failed, timeout, Upgrade catalog]
+            "#,
+        )
+        .unwrap();
+        let operations = rows.deserialize::<GovernorOperation>().unwrap();
+        assert!(operations[0].is_done());
+        assert_eq!(
+            operations[1],
+            GovernorOperation {
+                id: 2,
+                name: "upgrade_26.1.3".into(),
+                kind: "sql".into(),
+                status: "failed".into(),
+                status_description: Some("timeout".into()),
+            }
+        );
+    }
+
+    #[rstest]
+    fn test_governor_queue() {
+        assert!(GovernorQueue::default().is_idle());
+        assert_eq!(GovernorQueue::default().to_string(), "governor is idle");
+
+        let queue = GovernorQueue {
+            operations: vec![GovernorOperation {
+                id: 2,
+                name: "upgrade".into(),
+                kind: "sql".into(),
+                status: "pending".into(),
+                status_description: None,
+            }],
+            pending_changes: vec!["pending_schema_change".into()],
+            transitioning_instances: vec!["default_2_1 (Offline -> Online)".into()],
+        };
+        assert!(!queue.is_idle());
+        assert_eq!(
+            queue.findings(),
+            [
+                "operation 2 'upgrade' (pending)",
+                "pending_schema_change",
+                "default_2_1 (Offline -> Online)",
+            ]
+        );
+        assert_eq!(
+            queue.to_string(),
+            "operation 2 'upgrade' (sql): pending\n\
+             pending_schema_change\n\
+             instance default_2_1 (Offline -> Online)\n"
+        );
+    }
+}
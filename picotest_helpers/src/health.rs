@@ -0,0 +1,321 @@
+//! Liveness and readiness probes of cluster instances.
+//!
+//! Instance is alive if its process accepts connections on the admin socket,
+//! and ready if, in addition, its admin console answers and its current
+//! state is `Online`.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let health = cluster.health();
+//! assert!(health.is_ready(), "instances aren't ready: {:?}", health.not_ready());
+//!
+//! restart_storage(&cluster);
+//! cluster.wait_ready(Duration::from_secs(30))?;
+//! assert!(cluster.instance("default_2_1").is_ready());
+//! ```
+
+use crate::introspection::eval_lua_yaml;
+use crate::{Cluster, PicotestInstance};
+use anyhow::bail;
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Current state of the instance, which serves requests.
+pub const ONLINE_STATE: &str = "Online";
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+const INSTANCE_STATES_EXPRESSION: &str = "(function() \
+    local info = pico.instance_info(); \
+    return { current = info.current_state.variant, target = info.target_state.variant }; \
+end)()";
+
+#[derive(Deserialize)]
+struct InstanceStates {
+    current: String,
+    target: String,
+}
+
+/// Health of the instance reported by [`PicotestInstance::health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceHealth {
+    pub instance_name: String,
+    /// Process of the instance is running.
+    pub alive: bool,
+    /// Admin console of the instance answers.
+    pub responsive: bool,
+    /// Current state of the instance, e.g. `Online` or `Offline`.
+    pub current_state: Option<String>,
+    /// State the instance is moving to.
+    pub target_state: Option<String>,
+    /// Error of the failed probe.
+    pub error: Option<String>,
+}
+
+impl InstanceHealth {
+    /// Returns `true` if instance is alive, responsive and online.
+    pub fn is_ready(&self) -> bool {
+        self.alive && self.responsive && self.current_state.as_deref() == Some(ONLINE_STATE)
+    }
+}
+
+impl Display for InstanceHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.is_ready() {
+            "ready"
+        } else if !self.alive {
+            "dead"
+        } else if !self.responsive {
+            "unresponsive"
+        } else {
+            "not ready"
+        };
+        write!(f, "{:<24} {status}", self.instance_name)?;
+        if let (Some(current), Some(target)) = (&self.current_state, &self.target_state) {
+            write!(f, " (state: {current}, target: {target})")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Health of all cluster instances reported by [`Cluster::health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterHealth {
+    pub instances: Vec<InstanceHealth>,
+}
+
+impl ClusterHealth {
+    /// Returns `true` if all instances are ready.
+    pub fn is_ready(&self) -> bool {
+        self.instances.iter().all(InstanceHealth::is_ready)
+    }
+
+    /// Returns instances, which aren't ready.
+    pub fn not_ready(&self) -> Vec<&InstanceHealth> {
+        self.instances
+            .iter()
+            .filter(|instance| !instance.is_ready())
+            .collect()
+    }
+}
+
+impl ClusterHealth {
+    /// Describes instances, which aren't ready, for progress reporting.
+    fn progress_findings(&self) -> Vec<String> {
+        self.not_ready()
+            .into_iter()
+            .map(|instance| {
+                let status = match (&instance.current_state, &instance.target_state) {
+                    _ if !instance.alive => "dead".to_string(),
+                    _ if !instance.responsive => "unresponsive".to_string(),
+                    (Some(current), Some(target)) => format!("{current} -> {target}"),
+                    _ => "not ready".to_string(),
+                };
+                format!("{} ({status})", instance.instance_name)
+            })
+            .collect()
+    }
+}
+
+impl Display for ClusterHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for instance in &self.instances {
+            writeln!(f, "{instance}")?;
+        }
+        Ok(())
+    }
+}
+
+impl PicotestInstance {
+    /// Returns `true` if process of the instance is running.
+    ///
+    /// Process of local instance is checked by connecting to its admin socket,
+    /// remote instance is checked by its admin console.
+    pub fn is_alive(&self) -> bool {
+        if self.is_remote() {
+            return self.run_lua("return true").is_ok();
+        }
+        UnixStream::connect(&self.socket_path).is_ok()
+    }
+
+    /// Returns `true` if instance is alive, its admin console answers
+    /// and its current state is `Online`.
+    pub fn is_ready(&self) -> bool {
+        self.health().is_ready()
+    }
+
+    /// Probes liveness and readiness of the instance.
+    pub fn health(&self) -> InstanceHealth {
+        let mut health = InstanceHealth {
+            instance_name: self.instance_name.clone(),
+            alive: self.is_alive(),
+            responsive: false,
+            current_state: None,
+            target_state: None,
+            error: None,
+        };
+        if !health.alive {
+            return health;
+        }
+
+        match eval_lua_yaml::<InstanceStates>(self, INSTANCE_STATES_EXPRESSION) {
+            Ok(states) => {
+                health.responsive = true;
+                health.current_state = Some(states.current);
+                health.target_state = Some(states.target);
+            }
+            Err(err) => health.error = Some(format!("{err:#}")),
+        }
+        health
+    }
+}
+
+impl Cluster {
+    /// Probes liveness and readiness of all cluster instances.
+    pub fn health(&self) -> ClusterHealth {
+        ClusterHealth {
+            instances: self
+                .instances()
+                .iter()
+                .map(PicotestInstance::health)
+                .collect(),
+        }
+    }
+
+    /// Waits until all cluster instances are ready.
+    ///
+    /// Instances, which aren't ready yet, are reported by every poll,
+    /// see [`Cluster::on_progress`].
+    ///
+    /// ### Returns
+    /// Error with health of instances if some of them aren't ready within `timeout`.
+    pub fn wait_ready(&self, timeout: Duration) -> anyhow::Result<()> {
+        let mut progress = self.progress("cluster readiness");
+        let start_time = Instant::now();
+        loop {
+            let health = self.health();
+            progress.report(health.progress_findings());
+            if health.is_ready() {
+                return Ok(());
+            }
+            if start_time.elapsed() > timeout {
+                bail!(
+                    "cluster isn't ready within {}s:\n{health}",
+                    timeout.as_secs_f64()
+                );
+            }
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+
+    /// Waits until started cluster is ready and plugins of its topology are enabled.
+    pub(crate) fn wait_started(&self, timeout: Duration) -> anyhow::Result<()> {
+        let mut progress = self.progress("cluster start");
+        let start_time = Instant::now();
+        loop {
+            let health = self.health();
+            let mut pending = health.progress_findings();
+            if health.is_ready() {
+                pending.extend(self.disabled_plugins());
+            }
+            let done = pending.is_empty();
+            progress.report(pending.clone());
+            if done {
+                return Ok(());
+            }
+            if start_time.elapsed() > timeout {
+                bail!(
+                    "cluster isn't started within {}s, waiting on {}:\n{health}",
+                    timeout.as_secs_f64(),
+                    pending.join(", ")
+                );
+            }
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns findings about plugins of the topology, which aren't enabled yet.
+    fn disabled_plugins(&self) -> Vec<String> {
+        if self.topology.plugins.is_empty() {
+            return Vec::new();
+        }
+        let plugins = match self.catalog().plugins() {
+            Ok(plugins) => plugins,
+            Err(err) => return vec![format!("plugins: {err:#}")],
+        };
+        self.topology
+            .plugins
+            .keys()
+            .filter_map(|plugin_name| {
+                match plugins.iter().find(|plugin| &plugin.name == plugin_name) {
+                    Some(plugin) if plugin.enabled => None,
+                    Some(_) => Some(format!("plugin '{plugin_name}' is disabled")),
+                    None => Some(format!("plugin '{plugin_name}' is not installed")),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClusterHealth, InstanceHealth};
+    use rstest::rstest;
+
+    fn instance(instance_name: &str, alive: bool, state: Option<&str>) -> InstanceHealth {
+        InstanceHealth {
+            instance_name: instance_name.to_string(),
+            alive,
+            responsive: state.is_some(),
+            current_state: state.map(String::from),
+            target_state: state.map(|_| "Online".to_string()),
+            error: None,
+        }
+    }
+
+    #[rstest]
+    fn test_cluster_health() {
+        let health = ClusterHealth {
+            instances: vec![
+                instance("default_1_1", true, Some("Online")),
+                instance("default_2_1", true, Some("Offline")),
+                instance("default_3_1", false, None),
+            ],
+        };
+        assert!(!health.is_ready());
+        let not_ready: Vec<_> = health
+            .not_ready()
+            .iter()
+            .map(|instance| instance.instance_name.as_str())
+            .collect();
+        assert_eq!(not_ready, ["default_2_1", "default_3_1"]);
+        assert_eq!(
+            health.progress_findings(),
+            ["default_2_1 (Offline -> Online)", "default_3_1 (dead)"]
+        );
+
+        let lines: Vec<String> = health.to_string().lines().map(String::from).collect();
+        assert_eq!(
+            lines,
+            [
+                "default_1_1              ready (state: Online, target: Online)",
+                "default_2_1              not ready (state: Offline, target: Online)",
+                "default_3_1              dead",
+            ]
+        );
+
+        let mut unresponsive = instance("default_1_1", true, None);
+        unresponsive.error = Some("timeout".to_string());
+        assert!(!unresponsive.is_ready());
+        assert_eq!(
+            unresponsive.to_string(),
+            "default_1_1              unresponsive: timeout"
+        );
+    }
+}
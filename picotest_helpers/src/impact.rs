@@ -0,0 +1,612 @@
+//! Test impact analysis: running only tests affected by the changes.
+//!
+//! When [`PICOTEST_TRACK_IMPACT`] is set, services called over RPC, tables
+//! used by SQL queries and source file of every passed test are appended to
+//! the impact map as its footprint (one JSON object per line). Given files
+//! changed since then in [`PICOTEST_CHANGED_FILES`], tests, whose footprint
+//! isn't affected by the changes, are skipped without starting the cluster.
+//! Sources of the services are listed in `picotest.toml`:
+//!
+//! ```toml
+//! [impact]
+//! ignore = ["README.md", "docs/"]
+//!
+//! [impact.services]
+//! router = ["src/router/", "src/http.rs"]
+//! storage = ["src/storage/"]
+//! ```
+//!
+//! Selection is conservative: test is run if it has no footprint yet,
+//! if its footprint has no services (plugin code may still be run by it,
+//! e.g. by SQL queries or background jobs of the services),
+//! if its source file has changed or if some changed file is neither ignored,
+//! nor a source of a service, nor a migration, e.g. `Cargo.toml`. Changed
+//! migrations affect tests using tables they mention.
+//!
+//! **Note:** queries sent by `postgres` clients aren't recorded, so tests
+//! using them only are selected by their source files.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::impact::{parse_changed_files, ImpactSelection};
+//!
+//! let changed = parse_changed_files("src/router/mod.rs\nmigrations/0002_orders.sql");
+//! let selection = ImpactSelection::load(Path::new("."), changed)?;
+//! for test in selection.affected_tests() {
+//!     println!("{test}");
+//! }
+//! ```
+
+use crate::tags::PicotestConfig;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+use std::{env, io};
+
+/// Environment variable, which enables recording of test footprints.
+pub const PICOTEST_TRACK_IMPACT: &str = "PICOTEST_TRACK_IMPACT";
+
+/// Environment variable with files changed since footprints were recorded,
+/// separated by newlines or commas, relative to the plugin root.
+pub const PICOTEST_CHANGED_FILES: &str = "PICOTEST_CHANGED_FILES";
+
+/// Environment variable with path to the impact map.
+pub const PICOTEST_IMPACT_MAP: &str = "PICOTEST_IMPACT_MAP";
+
+/// Default path to the impact map, relative to the plugin root.
+pub const DEFAULT_IMPACT_MAP_PATH: &str = "tmp/test_impact.jsonl";
+
+/// SQL keywords followed by a table name.
+const TABLE_KEYWORDS: [&str; 6] = ["FROM", "JOIN", "INTO", "UPDATE", "TABLE", "TRUNCATE"];
+
+/// Words skipped between the keyword and the table name.
+const TABLE_PREFIX_WORDS: [&str; 5] = ["IF", "NOT", "EXISTS", "TABLE", "ONLY"];
+
+/// Services and tables the test has used while running.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestFootprint {
+    /// Test name including module path.
+    pub test: String,
+    /// Source file of the test as reported by `file!()`.
+    pub source_file: String,
+    /// Services called over RPC.
+    #[serde(default)]
+    pub services: BTreeSet<String>,
+    /// RPC endpoints called, prefixed by service name, e.g. `router/api/v1/orders`.
+    #[serde(default)]
+    pub rpc_paths: BTreeSet<String>,
+    /// Tables used by SQL queries, except system ones.
+    #[serde(default)]
+    pub tables: BTreeSet<String>,
+}
+
+/// Settings of impact analysis in `picotest.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ImpactConfig {
+    /// Path prefixes of the sources of every service.
+    #[serde(default)]
+    pub services: BTreeMap<String, Vec<String>>,
+    /// Path prefixes of the files, which don't affect tests, e.g. docs.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+/// File with footprints of the tests.
+#[derive(Debug)]
+pub struct ImpactMap {
+    path: PathBuf,
+    // Serializes appends of tests running in parallel threads.
+    lock: Mutex<()>,
+}
+
+impl ImpactMap {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Impact map located in [`PICOTEST_IMPACT_MAP`] or
+    /// in [`DEFAULT_IMPACT_MAP_PATH`] of the plugin.
+    pub fn from_env(plugin_path: &Path) -> Self {
+        let path = env::var(PICOTEST_IMPACT_MAP)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| plugin_path.join(DEFAULT_IMPACT_MAP_PATH));
+        Self::new(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends footprint of the test to the map.
+    pub fn append(&self, footprint: &TestFootprint) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(footprint)?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("failed to write impact map '{}'", self.path.display()))
+    }
+
+    /// Reads the latest footprint of every test keyed by test name.
+    /// Missing map is empty, malformed lines are skipped.
+    pub fn load(&self) -> anyhow::Result<BTreeMap<String, TestFootprint>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to open impact map '{}'", self.path.display())
+                })
+            }
+        };
+        let lines = BufReader::new(file)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()
+            .with_context(|| format!("failed to read impact map '{}'", self.path.display()))?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| serde_json::from_str::<TestFootprint>(line).ok())
+            .map(|footprint| (footprint.test.clone(), footprint))
+            .collect())
+    }
+}
+
+/// Footprints of the tracked tests with threads running them.
+static ACTIVE: Mutex<Vec<(u64, ThreadId, TestFootprint)>> = Mutex::new(Vec::new());
+
+/// Records footprint of the running test, which is appended
+/// to the impact map by [`ImpactTracker::finish`].
+///
+/// Interactions are attributed to the test by the thread making them.
+/// Interactions of other threads, e.g. spawned by the test, are attributed
+/// only if a single test is tracked, i.e. tests are run one by one.
+pub struct ImpactTracker<'a> {
+    map: &'a ImpactMap,
+    id: u64,
+}
+
+impl<'a> ImpactTracker<'a> {
+    /// Starts recording footprint of the test `test_name` located in `source_file`.
+    pub fn start(map: &'a ImpactMap, test_name: &str, source_file: &str) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let footprint = TestFootprint {
+            test: test_name.to_string(),
+            source_file: source_file.to_string(),
+            ..Default::default()
+        };
+        ACTIVE.lock().unwrap_or_else(PoisonError::into_inner).push((
+            id,
+            thread::current().id(),
+            footprint,
+        ));
+        Self { map, id }
+    }
+
+    /// Appends footprint of the test to the impact map, unless it has `failed`,
+    /// as failed test might have stopped before using everything it tests.
+    pub fn finish(self, failed: bool) -> anyhow::Result<()> {
+        match take_footprint(self.id) {
+            Some(footprint) if !failed => self.map.append(&footprint),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for ImpactTracker<'_> {
+    fn drop(&mut self) {
+        take_footprint(self.id);
+    }
+}
+
+fn take_footprint(id: u64) -> Option<TestFootprint> {
+    let mut active = ACTIVE.lock().unwrap_or_else(PoisonError::into_inner);
+    let index = active.iter().position(|(active_id, ..)| *active_id == id)?;
+    Some(active.remove(index).2)
+}
+
+/// Adds interaction to the footprint of the test running in the current thread.
+fn record(update: impl FnOnce(&mut TestFootprint)) {
+    let mut active = ACTIVE.lock().unwrap_or_else(PoisonError::into_inner);
+    let current = thread::current().id();
+    let index = match active.iter().position(|(_, thread, _)| *thread == current) {
+        Some(index) => index,
+        None if active.len() == 1 => 0,
+        None => return,
+    };
+    update(&mut active[index].2);
+}
+
+/// Records RPC call of the `service_name` endpoint `path` by the running test.
+pub(crate) fn record_rpc(service_name: &str, path: &str) {
+    record(|footprint| {
+        footprint.services.insert(service_name.to_string());
+        footprint.rpc_paths.insert(format!("{service_name}{path}"));
+    });
+}
+
+/// Records tables used by SQL `query` of the running test.
+pub(crate) fn record_query(query: &str) {
+    record(|footprint| footprint.tables.extend(table_names(query)));
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Punct,
+}
+
+/// Splits SQL into identifiers and punctuation, string literals are skipped.
+fn tokenize(sql: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' | '\'' => {
+                let mut text = String::new();
+                while let Some(next) = chars.next() {
+                    if next != c {
+                        text.push(next);
+                    } else if chars.peek() == Some(&c) {
+                        // Quote is escaped by doubling it.
+                        text.push(chars.next().unwrap());
+                    } else {
+                        break;
+                    }
+                }
+                if c == '"' {
+                    tokens.push(Token::Quoted(text));
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = String::from(c);
+                while let Some(next) = chars.next_if(|next| next.is_alphanumeric() || *next == '_')
+                {
+                    word.push(next);
+                }
+                tokens.push(Token::Word(word));
+            }
+            _ => tokens.push(Token::Punct),
+        }
+    }
+    tokens
+}
+
+/// Returns names of the tables used by SQL `query`, except system ones
+/// starting with `_`. Unquoted names are lowercased like picodata does.
+pub fn table_names(query: &str) -> BTreeSet<String> {
+    let tokens = tokenize(query);
+    let mut tables = BTreeSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let Token::Word(keyword) = token else {
+            continue;
+        };
+        if !TABLE_KEYWORDS.contains(&keyword.to_uppercase().as_str()) {
+            continue;
+        }
+        let name = tokens[i + 1..]
+            .iter()
+            .find(|token| {
+                !matches!(token, Token::Word(word)
+                    if TABLE_PREFIX_WORDS.contains(&word.to_uppercase().as_str()))
+            })
+            .and_then(|token| match token {
+                Token::Word(word) => Some(word.to_lowercase()),
+                Token::Quoted(name) => Some(name.clone()),
+                Token::Punct => None,
+            });
+        if let Some(name) = name.filter(|name| !name.starts_with('_')) {
+            tables.insert(name);
+        }
+    }
+    tables
+}
+
+/// Parses list of changed files separated by newlines or commas,
+/// e.g. output of `git diff --name-only --relative`.
+pub fn parse_changed_files(value: &str) -> Vec<String> {
+    value
+        .split(['\n', ','])
+        .map(str::trim)
+        .filter(|file| !file.is_empty())
+        .map(|file| file.trim_start_matches("./").to_string())
+        .collect()
+}
+
+/// Returns `true` if `file` is located under `prefix`.
+fn has_prefix(file: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_start_matches("./").trim_end_matches('/');
+    file.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Returns `true` if both paths refer to the same file,
+/// while one of them may be relative to a nested directory.
+fn same_file(a: &str, b: &str) -> bool {
+    Path::new(a).ends_with(b) || Path::new(b).ends_with(a)
+}
+
+/// Services and tables affected by the changed files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    pub files: Vec<String>,
+    pub services: BTreeSet<String>,
+    pub tables: BTreeSet<String>,
+    /// Changed files, which aren't known to affect particular services or tables.
+    pub unmapped: Vec<String>,
+}
+
+impl ChangeSet {
+    /// Maps changed `files` to services by `config` and changed
+    /// migrations of the plugin in `plugin_path` to tables.
+    pub fn analyze(files: Vec<String>, config: &ImpactConfig, plugin_path: &Path) -> Self {
+        let mut changes = Self::default();
+        for file in &files {
+            if config.ignore.iter().any(|prefix| has_prefix(file, prefix)) {
+                continue;
+            }
+            let services: Vec<_> = config
+                .services
+                .iter()
+                .filter(|(_, prefixes)| prefixes.iter().any(|prefix| has_prefix(file, prefix)))
+                .map(|(service, _)| service.clone())
+                .collect();
+            if !services.is_empty() {
+                changes.services.extend(services);
+                continue;
+            }
+            // Deleted migration or one without tables can't be attributed.
+            let tables = file
+                .ends_with(".sql")
+                .then(|| fs::read_to_string(plugin_path.join(file)).ok())
+                .flatten()
+                .map(|sql| table_names(&sql))
+                .unwrap_or_default();
+            if tables.is_empty() {
+                changes.unmapped.push(file.clone());
+            } else {
+                changes.tables.extend(tables);
+            }
+        }
+        changes.files = files;
+        changes
+    }
+}
+
+/// Tests selected by the changed files, see [module docs](self).
+#[derive(Debug, Clone)]
+pub struct ImpactSelection {
+    changes: ChangeSet,
+    footprints: BTreeMap<String, TestFootprint>,
+    /// Changed file, which makes every test run.
+    run_all_by: Option<String>,
+}
+
+impl ImpactSelection {
+    pub fn new(changes: ChangeSet, footprints: BTreeMap<String, TestFootprint>) -> Self {
+        // Changed sources of the tests affect the tests themselves only.
+        let run_all_by = changes
+            .unmapped
+            .iter()
+            .find(|file| {
+                !footprints
+                    .values()
+                    .any(|footprint| same_file(file, &footprint.source_file))
+            })
+            .cloned();
+        Self {
+            changes,
+            footprints,
+            run_all_by,
+        }
+    }
+
+    /// Selects tests of the plugin in `plugin_path` by `changed_files`
+    /// using `picotest.toml` and the impact map of the plugin.
+    pub fn load(plugin_path: &Path, changed_files: Vec<String>) -> anyhow::Result<Self> {
+        let config = PicotestConfig::load(plugin_path)?;
+        let footprints = ImpactMap::from_env(plugin_path).load()?;
+        let changes = ChangeSet::analyze(changed_files, &config.impact, plugin_path);
+        Ok(Self::new(changes, footprints))
+    }
+
+    /// Selection by [`PICOTEST_CHANGED_FILES`], `None` if it isn't set.
+    pub fn from_env(plugin_path: &Path) -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var(PICOTEST_CHANGED_FILES) else {
+            return Ok(None);
+        };
+        Self::load(plugin_path, parse_changed_files(&value)).map(Some)
+    }
+
+    pub fn changes(&self) -> &ChangeSet {
+        &self.changes
+    }
+
+    /// Returns changed file, which isn't attributed to particular
+    /// services or tables, so every test is run.
+    pub fn run_all_by(&self) -> Option<&str> {
+        self.run_all_by.as_deref()
+    }
+
+    /// Returns `true` if the test `test_name` located in `source_file` is affected.
+    pub fn is_selected(&self, test_name: &str, source_file: &str) -> bool {
+        if self.run_all_by.is_some()
+            || self
+                .changes
+                .files
+                .iter()
+                .any(|file| same_file(file, source_file))
+        {
+            return true;
+        }
+        let Some(footprint) = self.footprints.get(test_name) else {
+            return true;
+        };
+        footprint.services.is_empty()
+            || !footprint.services.is_disjoint(&self.changes.services)
+            || !footprint.tables.is_disjoint(&self.changes.tables)
+    }
+
+    /// Returns affected tests among the ones with recorded footprints.
+    /// Tests without footprints are always selected, but aren't listed.
+    /// Tests, whose footprints have no services, are always listed.
+    pub fn affected_tests(&self) -> Vec<&str> {
+        self.footprints
+            .values()
+            .filter(|footprint| self.is_selected(&footprint.test, &footprint.source_file))
+            .map(|footprint| footprint.test.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_changed_files, record_query, record_rpc, table_names, ChangeSet, ImpactConfig,
+        ImpactMap, ImpactSelection, ImpactTracker, TestFootprint,
+    };
+    use rstest::rstest;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs;
+    use std::path::Path;
+
+    #[rstest]
+    #[case("SELECT * FROM users WHERE id = 1", &["users"])]
+    #[case(
+        r#"SELECT o.id FROM "Orders" o JOIN items i ON o.id = i.order_id"#,
+        &["Orders", "items"]
+    )]
+    #[case("insert into Warehouse values (1, 'FROM ghost')", &["warehouse"])]
+    #[case("UPDATE stock SET amount = 0", &["stock"])]
+    #[case("CREATE TABLE IF NOT EXISTS events (id INT PRIMARY KEY)", &["events"])]
+    #[case("DELETE FROM orders WHERE id IN (SELECT id FROM _pico_table)", &["orders"])]
+    #[case("SELECT count(*) FROM (SELECT 1)", &[])]
+    fn test_table_names(#[case] query: &str, #[case] expected: &[&str]) {
+        let expected: BTreeSet<String> = expected.iter().map(|table| table.to_string()).collect();
+        assert_eq!(table_names(query), expected);
+    }
+
+    #[rstest]
+    fn test_parse_changed_files() {
+        assert_eq!(
+            parse_changed_files("./src/lib.rs\n\nCargo.toml, tests/test_api.rs\n"),
+            ["src/lib.rs", "Cargo.toml", "tests/test_api.rs"]
+        );
+    }
+
+    fn footprint(test: &str, services: &[&str], tables: &[&str]) -> TestFootprint {
+        TestFootprint {
+            test: test.to_string(),
+            source_file: format!("tests/{test}.rs"),
+            services: services.iter().map(|s| s.to_string()).collect(),
+            tables: tables.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn selection(changed: &str, plugin_path: &Path) -> ImpactSelection {
+        let config: ImpactConfig = toml::from_str(
+            r#"
+ignore = ["docs/", "README.md"]
+
+[services]
+router = ["src/router/", "src/http.rs"]
+storage = ["src/storage/"]
+"#,
+        )
+        .unwrap();
+        let footprints = [
+            footprint("test_router", &["router"], &[]),
+            footprint("test_storage", &["storage"], &["orders"]),
+            footprint("test_items", &[], &["items"]),
+        ]
+        .into_iter()
+        .map(|footprint| (footprint.test.clone(), footprint))
+        .collect();
+        let changes = ChangeSet::analyze(parse_changed_files(changed), &config, plugin_path);
+        ImpactSelection::new(changes, footprints)
+    }
+
+    #[rstest]
+    #[case("src/router/handlers.rs,README.md", &["test_items", "test_router"])]
+    #[case("src/http.rs", &["test_items", "test_router"])]
+    #[case("docs/guide.md", &["test_items"])]
+    #[case("tests/test_items.rs", &["test_items"])]
+    #[case("migrations/0002_orders.sql", &["test_items", "test_storage"])]
+    #[case("Cargo.toml", &["test_items", "test_router", "test_storage"])]
+    fn test_affected_tests(#[case] changed: &str, #[case] expected: &[&str]) {
+        let plugin_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(plugin_path.join("migrations")).unwrap();
+        fs::write(
+            plugin_path.join("migrations/0002_orders.sql"),
+            "-- pico.UP\nALTER TABLE orders ADD COLUMN note TEXT;",
+        )
+        .unwrap();
+
+        let selection = selection(changed, &plugin_path);
+        assert_eq!(selection.affected_tests(), expected);
+        assert!(selection.is_selected("test_new", "tests/test_new.rs"));
+        assert_eq!(selection.run_all_by().is_some(), changed == "Cargo.toml");
+
+        fs::remove_dir_all(plugin_path).unwrap();
+    }
+
+    #[rstest]
+    #[case("src/storage/engine.rs")]
+    #[case("docs/guide.md")]
+    fn test_footprint_without_services(#[case] changed: &str) {
+        let selection = selection(changed, &std::env::temp_dir());
+        assert!(selection.is_selected("test_items", "tests/test_items.rs"));
+        assert!(!selection.is_selected("test_router", "tests/test_router.rs"));
+    }
+
+    #[rstest]
+    fn test_record_footprint() {
+        let path = std::env::temp_dir().join(format!("picotest_{}.jsonl", uuid::Uuid::new_v4()));
+        let map = ImpactMap::new(path.clone());
+
+        let tracker = ImpactTracker::start(&map, "suite::test_orders", "tests/test_orders.rs");
+        record_rpc("router", "/api/orders");
+        record_query("SELECT * FROM orders");
+        tracker.finish(false).unwrap();
+
+        let tracker = ImpactTracker::start(&map, "suite::test_failed", "tests/test_orders.rs");
+        record_rpc("storage", "/api/items");
+        tracker.finish(true).unwrap();
+
+        let footprints = map.load().unwrap();
+        assert_eq!(
+            footprints,
+            BTreeMap::from([(
+                "suite::test_orders".to_string(),
+                TestFootprint {
+                    test: "suite::test_orders".into(),
+                    source_file: "tests/test_orders.rs".into(),
+                    services: BTreeSet::from(["router".into()]),
+                    rpc_paths: BTreeSet::from(["router/api/orders".into()]),
+                    tables: BTreeSet::from(["orders".into()]),
+                }
+            )])
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Lookup of cluster instances by name, replicaset and raft id.
+//!
+//! [`Cluster::instances`] are sorted by tier and then by instance id,
+//! whatever order pike has started them in, so index-based access is
+//! deterministic. Still, lookup by a stable key is preferable:
+//! names come from properties of the instances, while replicasets
+//! and raft ids are read from `_pico_instance` system table.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_replicasets() {
+//!     let replicasets = cluster.by_replicaset().unwrap();
+//!     assert_eq!(replicasets["default_1"].len(), 2);
+//!
+//!     let leader = cluster.by_raft_id().unwrap()[&1];
+//!     assert_eq!(leader.instance_name, cluster.by_name()["default_1_1"].instance_name);
+//! }
+//! ```
+
+use crate::{Cluster, PicotestInstance};
+use std::collections::BTreeMap;
+
+impl Cluster {
+    /// Returns instances keyed by their names.
+    pub fn by_name(&self) -> BTreeMap<&str, &PicotestInstance> {
+        self.instances()
+            .iter()
+            .map(|instance| (instance.instance_name.as_str(), instance))
+            .collect()
+    }
+
+    /// Returns instances grouped by replicaset names from `_pico_instance`.
+    /// Instances of the replicaset are in order of [`Cluster::instances`].
+    ///
+    /// Instances, which aren't handled by the cluster (e.g. joined
+    /// by the test itself), are skipped.
+    pub fn by_replicaset(&self) -> anyhow::Result<BTreeMap<String, Vec<&PicotestInstance>>> {
+        let by_name = self.by_name();
+        let mut by_replicaset: BTreeMap<String, Vec<&PicotestInstance>> = BTreeMap::new();
+        for info in self.catalog().instances()? {
+            if let Some(instance) = by_name.get(info.name.as_str()) {
+                by_replicaset
+                    .entry(info.replicaset_name)
+                    .or_default()
+                    .push(instance);
+            }
+        }
+        for instances in by_replicaset.values_mut() {
+            instances.sort_by(|a, b| instance_order(a).cmp(&instance_order(b)));
+        }
+        Ok(by_replicaset)
+    }
+
+    /// Returns instances keyed by raft ids from `_pico_instance`.
+    ///
+    /// Instances, which aren't handled by the cluster, are skipped.
+    pub fn by_raft_id(&self) -> anyhow::Result<BTreeMap<u64, &PicotestInstance>> {
+        let by_name = self.by_name();
+        Ok(self
+            .catalog()
+            .instances()?
+            .into_iter()
+            .filter_map(|info| Some((info.raft_id, *by_name.get(info.name.as_str())?)))
+            .collect())
+    }
+}
+
+/// Sorts `instances` by tier and then by instance id.
+pub(crate) fn sort_instances(instances: &mut [PicotestInstance]) {
+    instances.sort_by(|a, b| instance_order(a).cmp(&instance_order(b)));
+}
+
+fn instance_order(instance: &PicotestInstance) -> (&str, u16) {
+    (&instance.tier, instance.instance_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sort_instances;
+    use crate::PicotestInstance;
+    use rstest::rstest;
+    use std::path::PathBuf;
+
+    #[rstest]
+    fn test_sort_instances() {
+        let mut instances: Vec<PicotestInstance> = [
+            ("storage_1_1", "storage", 3),
+            ("router_1_1", "router", 4),
+            ("default_1_2", "default", 2),
+            ("default_1_1", "default", 1),
+        ]
+        .into_iter()
+        .map(|(name, tier, instance_id)| {
+            let mut instance = PicotestInstance::detached(PathBuf::new(), None);
+            instance.instance_name = name.to_string();
+            instance.tier = tier.to_string();
+            instance.instance_id = instance_id;
+            instance
+        })
+        .collect();
+
+        sort_instances(&mut instances);
+        let names: Vec<&str> = instances
+            .iter()
+            .map(|instance| instance.instance_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["default_1_1", "default_1_2", "router_1_1", "storage_1_1"]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Interactive debugging of the cluster from tests.
+//!
+//! When `PICOTEST_INTERACTIVE` environment variable is set, failed test prints
+//! ready-to-paste commands attaching admin console of every instance of its
+//! cluster. If cluster is created with [`Cluster::pause_on_failure`] or the
+//! variable is set to `pause`, test process waits for Enter before the cluster
+//! is cleaned up or stopped, so its state can be inspected.
+//!
+//! [`PicotestInstance::interactive`] and [`Cluster::interactive`] work as a
+//! breakpoint in the middle of the test. Test is paused only if its stdin is
+//! a terminal, so breakpoints don't block CI runs.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_replication() {
+//!     cluster.run_sql("INSERT INTO warehouse VALUES (1, 'bricks')").unwrap();
+//!     // $ PICOTEST_INTERACTIVE=1 cargo test test_replication
+//!     cluster.instances()[1].interactive();
+//! }
+//! ```
+
+use crate::{Cluster, PicotestInstance};
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::iter;
+use std::process::Command;
+use std::sync::{Mutex, PoisonError};
+
+/// Environment variable, which enables interactive debugging:
+/// `1` prints attach commands on failure, `pause` also pauses the test.
+pub const PICOTEST_INTERACTIVE: &str = "PICOTEST_INTERACTIVE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InteractiveMode {
+    Disabled,
+    /// Attach commands are printed when the test fails.
+    Enabled,
+    /// Test is paused when it fails, whatever [`Cluster::pause_on_failure`] is.
+    Pause,
+}
+
+impl InteractiveMode {
+    pub fn from_env() -> Self {
+        env::var(PICOTEST_INTERACTIVE)
+            .map(|value| Self::parse(&value))
+            .unwrap_or(Self::Disabled)
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "" | "0" | "false" => Self::Disabled,
+            "pause" => Self::Pause,
+            _ => Self::Enabled,
+        }
+    }
+}
+
+impl PicotestInstance {
+    /// Returns ready-to-paste command attaching admin console of the instance,
+    /// e.g. `picodata admin /plugin/tmp/cluster/default_1_1/admin.sock`.
+    pub fn attach_command(&self) -> String {
+        let command = match &self.remote {
+            Some(remote) => remote.attach_command(&self.socket_path),
+            None => self.admin_command(),
+        };
+        shell_command(&command)
+    }
+
+    /// Prints command attaching admin console of the instance and waits
+    /// for Enter, see [module docs](self).
+    ///
+    /// Does nothing unless `PICOTEST_INTERACTIVE` environment variable is set.
+    pub fn interactive(&self) {
+        if InteractiveMode::from_env() != InteractiveMode::Disabled {
+            attach(&[self], true);
+        }
+    }
+}
+
+impl Cluster {
+    /// Pauses failed test before the cluster is cleaned up or stopped,
+    /// if `PICOTEST_INTERACTIVE` environment variable is set.
+    pub fn pause_on_failure(mut self, pause: bool) -> Self {
+        self.pause_on_failure = pause;
+        self
+    }
+
+    /// Prints commands attaching admin console of every instance
+    /// and waits for Enter, like [`PicotestInstance::interactive`].
+    pub fn interactive(&self) {
+        if InteractiveMode::from_env() != InteractiveMode::Disabled {
+            attach(&self.instances().iter().collect::<Vec<_>>(), true);
+        }
+    }
+
+    /// Prints commands attaching admin console of every instance after
+    /// the test has failed and pauses it, see [module docs](self).
+    pub fn inspect_failure(&self) {
+        let mode = InteractiveMode::from_env();
+        if mode != InteractiveMode::Disabled {
+            let pause = self.pause_on_failure || mode == InteractiveMode::Pause;
+            attach(&self.instances().iter().collect::<Vec<_>>(), pause);
+        }
+    }
+}
+
+/// Prints attach commands of the `instances` and waits for Enter if `pause` is set.
+fn attach(instances: &[&PicotestInstance], pause: bool) {
+    // Tests run concurrently are paused one by one,
+    // so every prompt is answered separately.
+    static PAUSE: Mutex<()> = Mutex::new(());
+    let _guard = PAUSE.lock().unwrap_or_else(PoisonError::into_inner);
+
+    // Written to stderr directly, as output of `eprintln!`
+    // is captured by test harness until the test finishes.
+    let mut stderr = io::stderr().lock();
+    let _ = writeln!(stderr, "Attach to admin console of the instances:");
+    for instance in instances {
+        let _ = writeln!(
+            stderr,
+            "  {}: {}",
+            instance.instance_name,
+            instance.attach_command()
+        );
+    }
+    if !pause {
+        return;
+    }
+    if !io::stdin().is_terminal() {
+        let _ = writeln!(stderr, "Test isn't paused, as stdin is not a terminal");
+        return;
+    }
+    let _ = writeln!(stderr, "Test is paused, press Enter to continue...");
+    drop(stderr);
+    let _ = io::stdin().lock().read_line(&mut String::new());
+}
+
+/// Formats `command` to be pasted into shell.
+fn shell_command(command: &Command) -> String {
+    iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shell_command, InteractiveMode};
+    use rstest::rstest;
+    use std::process::Command;
+
+    #[rstest]
+    #[case("", InteractiveMode::Disabled)]
+    #[case("0", InteractiveMode::Disabled)]
+    #[case("false", InteractiveMode::Disabled)]
+    #[case("1", InteractiveMode::Enabled)]
+    #[case("true", InteractiveMode::Enabled)]
+    #[case("pause", InteractiveMode::Pause)]
+    fn test_parse_interactive_mode(#[case] value: &str, #[case] expected: InteractiveMode) {
+        assert_eq!(InteractiveMode::parse(value), expected);
+    }
+
+    #[rstest]
+    fn test_shell_command() {
+        let mut command = Command::new("picodata");
+        command
+            .arg("admin")
+            .arg("/plugin/tmp/cluster/default_1_1/admin.sock");
+        assert_eq!(
+            shell_command(&command),
+            "picodata admin /plugin/tmp/cluster/default_1_1/admin.sock"
+        );
+
+        let mut command = Command::new("ssh");
+        command
+            .args(["-t", "-o", "ProxyCommand=nc %h %p"])
+            .arg("qa@staging-1")
+            .arg("/var/lib/it's here/admin.sock");
+        assert_eq!(
+            shell_command(&command),
+            r"ssh -t -o 'ProxyCommand=nc %h %p' qa@staging-1 '/var/lib/it'\''s here/admin.sock'"
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Typed views of Tarantool runtime introspection data.
+//!
+//! Values are obtained through admin console by evaluating
+//! `box.info()`, `box.slab.info()`, `box.stat()` and `fiber.info()`
+//! and then deserialized from YAML output of the console.
+
+use crate::{Cluster, PicotestInstance};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time given to fibers to finish before they're reported as leaked.
+const FIBER_LEAK_GRACE_PERIOD: Duration = Duration::from_secs(5);
+const FIBER_LEAK_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Lists fibers from `fiber.info()` with their statuses.
+/// Fiber of the admin console session itself is skipped.
+const FIBERS_EXPRESSION: &str = "(function() \
+    local fiber = require('fiber'); \
+    local self_id = fiber.self():id(); \
+    local fibers = {}; \
+    for id, info in pairs(fiber.info({backtrace = false})) do \
+        if id ~= self_id then \
+            local f = fiber.find(id); \
+            table.insert(fibers, { \
+                id = id, name = info.name, csw = info.csw, memory = info.memory, \
+                status = f ~= nil and f:status() or 'dead', \
+            }); \
+        end; \
+    end; \
+    return fibers; \
+end)()";
+
+/// Subset of `box.info()` output.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BoxInfo {
+    pub id: Option<u32>,
+    pub uuid: String,
+    pub version: String,
+    pub status: String,
+    pub ro: bool,
+    pub pid: u32,
+    pub lsn: u64,
+    pub uptime: u64,
+    #[serde(default)]
+    pub vclock: BTreeMap<u32, u64>,
+    #[serde(default)]
+    pub replication: BTreeMap<u32, ReplicaInfo>,
+}
+
+impl BoxInfo {
+    /// Returns maximum replication lag (in seconds) among all upstreams
+    /// of the instance or `None` if there're no upstreams.
+    pub fn max_upstream_lag(&self) -> Option<f64> {
+        self.replication
+            .values()
+            .filter_map(|replica| replica.upstream.as_ref())
+            .filter_map(|upstream| upstream.lag)
+            .reduce(f64::max)
+    }
+}
+
+/// Entry of `box.info().replication` mapping.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplicaInfo {
+    pub id: u32,
+    pub uuid: String,
+    pub lsn: u64,
+    pub name: Option<String>,
+    pub upstream: Option<UpstreamInfo>,
+    pub downstream: Option<DownstreamInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamInfo {
+    pub status: String,
+    pub peer: Option<String>,
+    pub idle: Option<f64>,
+    pub lag: Option<f64>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DownstreamInfo {
+    pub status: String,
+    pub idle: Option<f64>,
+    pub lag: Option<f64>,
+    pub message: Option<String>,
+}
+
+/// Output of `box.slab.info()`.
+///
+/// Ratios are kept as returned by Tarantool, i.e. strings like `"1.23%"`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BoxSlabInfo {
+    pub items_size: u64,
+    pub items_used: u64,
+    pub items_used_ratio: String,
+    pub quota_size: u64,
+    pub quota_used: u64,
+    pub quota_used_ratio: String,
+    pub arena_size: u64,
+    pub arena_used: u64,
+    pub arena_used_ratio: String,
+}
+
+/// Output of `box.stat()` - request counters keyed by request type
+/// (`SELECT`, `INSERT`, `CALL`, etc).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct BoxStat(pub BTreeMap<String, RequestStat>);
+
+impl BoxStat {
+    /// Returns counters of the request type. Lookup is case-insensitive.
+    pub fn get(&self, request_type: &str) -> Option<&RequestStat> {
+        self.0.get(&request_type.to_uppercase())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestStat {
+    pub total: u64,
+    pub rps: u64,
+}
+
+/// Fiber of the instance, entry of `fiber.info()`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FiberInfo {
+    pub id: u64,
+    pub name: String,
+    /// One of `running`, `suspended` or `dead`.
+    pub status: String,
+    /// Number of context switches.
+    pub csw: u64,
+    pub memory: FiberMemory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FiberMemory {
+    pub total: u64,
+    pub used: u64,
+}
+
+/// Evaluates Lua expression on the instance and deserializes
+/// its single return value from console YAML output.
+pub(crate) fn eval_lua_yaml<T: DeserializeOwned>(
+    instance: &PicotestInstance,
+    expression: &str,
+) -> anyhow::Result<T> {
+    instance
+        .run_lua_typed(format!("return {expression}"))
+        .with_context(|| format!("failed to evaluate '{expression}'"))
+}
+
+impl PicotestInstance {
+    /// Returns typed `box.info()` of the instance.
+    pub fn box_info(&self) -> anyhow::Result<BoxInfo> {
+        eval_lua_yaml(self, "box.info()")
+    }
+
+    /// Returns typed `box.slab.info()` of the instance.
+    pub fn box_slab_info(&self) -> anyhow::Result<BoxSlabInfo> {
+        eval_lua_yaml(self, "box.slab.info()")
+    }
+
+    /// Returns typed `box.stat()` of the instance.
+    pub fn box_stat(&self) -> anyhow::Result<BoxStat> {
+        eval_lua_yaml(self, "box.stat()")
+    }
+
+    /// Returns fibers of the instance.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    ///
+    /// #[picotest]
+    /// fn test_worker_is_spawned() {
+    ///     let fibers = cluster.instances()[1].fibers().unwrap();
+    ///     assert!(fibers.iter().any(|fiber| fiber.name == "plugin_worker"));
+    /// }
+    /// ```
+    pub fn fibers(&self) -> anyhow::Result<Vec<FiberInfo>> {
+        eval_lua_yaml(self, FIBERS_EXPRESSION)
+    }
+
+    /// Asserts that instance has no more fibers of each name than `baseline`
+    /// taken by [`PicotestInstance::fibers`], e.g. at the start of the test.
+    ///
+    /// Fibers are given a few seconds to finish before being reported as leaked.
+    #[track_caller]
+    pub fn assert_no_fiber_leak(&self, baseline: &[FiberInfo]) {
+        let start_time = Instant::now();
+        loop {
+            let fibers = self.fibers().unwrap_or_else(|err| {
+                panic!("Failed to get fibers of '{}': {err:#}", self.instance_name)
+            });
+            let leaked = leaked_fibers(baseline, &fibers);
+            if leaked.is_empty() {
+                return;
+            }
+            if start_time.elapsed() > FIBER_LEAK_GRACE_PERIOD {
+                panic!(
+                    "Instance '{}' has leaked fibers: {leaked:#?}",
+                    self.instance_name
+                );
+            }
+            thread::sleep(FIBER_LEAK_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Cluster {
+    /// Returns typed `box.info()` of the main instance.
+    pub fn box_info(&self) -> anyhow::Result<BoxInfo> {
+        self.main().box_info()
+    }
+
+    /// Returns typed `box.slab.info()` of the main instance.
+    pub fn box_slab_info(&self) -> anyhow::Result<BoxSlabInfo> {
+        self.main().box_slab_info()
+    }
+
+    /// Returns typed `box.stat()` of the main instance.
+    pub fn box_stat(&self) -> anyhow::Result<BoxStat> {
+        self.main().box_stat()
+    }
+
+    /// Returns fibers of the main instance.
+    pub fn fibers(&self) -> anyhow::Result<Vec<FiberInfo>> {
+        self.main().fibers()
+    }
+
+    /// Asserts that main instance has no leaked fibers.
+    /// See [`PicotestInstance::assert_no_fiber_leak`].
+    #[track_caller]
+    pub fn assert_no_fiber_leak(&self, baseline: &[FiberInfo]) {
+        self.main().assert_no_fiber_leak(baseline)
+    }
+}
+
+/// Returns fibers, which exceed number of fibers with the same name in `baseline`.
+fn leaked_fibers<'a>(baseline: &[FiberInfo], fibers: &'a [FiberInfo]) -> Vec<&'a FiberInfo> {
+    let mut allowed: BTreeMap<&str, usize> = BTreeMap::new();
+    for fiber in baseline {
+        *allowed.entry(&fiber.name).or_default() += 1;
+    }
+
+    let mut leaked = Vec::new();
+    for fiber in fibers.iter().filter(|fiber| fiber.status != "dead") {
+        match allowed.get_mut(fiber.name.as_str()) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => leaked.push(fiber),
+        }
+    }
+    leaked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leaked_fibers, BoxInfo, BoxSlabInfo, BoxStat, FiberInfo};
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_deserialize_box_info() {
+        let output = r#"---
+- version: 2.11.5-picodata
+  id: 1
+  ro: false
+  uuid: 68d4a766-4144-3248-aeb4-e212356716e4
+  pid: 12345
+  status: running
+  lsn: 120
+  uptime: 42
+  vclock: {1: 120}
+  replication:
+    1:
+      id: 1
+      uuid: 68d4a766-4144-3248-aeb4-e212356716e4
+      lsn: 120
+      name: i1
+    2:
+      id: 2
+      uuid: 2a0a7f3c-8f40-3d0b-9a3e-8c4f2b1d5e6f
+      lsn: 0
+      upstream:
+        status: follow
+        idle: 0.4
+        peer: 127.0.0.1:3302
+        lag: 0.0005
+      downstream:
+        status: follow
+        idle: 0.2
+        lag: 0
+...
+"#;
+        let info: Vec<BoxInfo> = serde_norway::from_str(output).unwrap();
+        let info = &info[0];
+
+        assert_eq!(info.id, Some(1));
+        assert_eq!(info.status, "running");
+        assert!(!info.ro);
+        assert_eq!(info.vclock.get(&1), Some(&120));
+        assert_eq!(info.replication.len(), 2);
+        assert_eq!(info.max_upstream_lag(), Some(0.0005));
+    }
+
+    #[rstest]
+    fn test_deserialize_box_slab_info() {
+        let output = r#"---
+- items_size: 228128
+  items_used_ratio: 1.8%
+  quota_size: 1073741824
+  quota_used_ratio: 0.8%
+  arena_used_ratio: 43.2%
+  items_used: 4208
+  quota_used: 8388608
+  arena_size: 2325176
+  arena_used: 1003632
+...
+"#;
+        let info: Vec<BoxSlabInfo> = serde_norway::from_str(output).unwrap();
+
+        assert_eq!(info[0].quota_size, 1073741824);
+        assert_eq!(info[0].arena_used_ratio, "43.2%");
+    }
+
+    #[rstest]
+    fn test_deserialize_box_stat() {
+        let output = r#"---
+- DELETE:
+    total: 48902544
+    rps: 0
+  SELECT:
+    total: 388322317
+    rps: 12
+  CALL:
+    total: 8
+    rps: 0
+...
+"#;
+        let stat: Vec<BoxStat> = serde_norway::from_str(output).unwrap();
+
+        assert_eq!(stat[0].get("select").unwrap().rps, 12);
+        assert_eq!(stat[0].get("CALL").unwrap().total, 8);
+        assert!(stat[0].get("insert").is_none());
+    }
+
+    #[rstest]
+    fn test_leaked_fibers() {
+        let output = r#"---
+- - {id: 101, name: main, status: suspended, csw: 10, memory: {total: 524288, used: 0}}
+  - {id: 102, name: worker, status: suspended, csw: 3, memory: {total: 524288, used: 0}}
+  - {id: 103, name: worker, status: suspended, csw: 1, memory: {total: 524288, used: 0}}
+  - {id: 104, name: job, status: dead, csw: 1, memory: {total: 524288, used: 0}}
+...
+"#;
+        let fibers: Vec<Vec<FiberInfo>> = serde_norway::from_str(output).unwrap();
+        let fibers = &fibers[0];
+
+        assert!(leaked_fibers(fibers, fibers).is_empty());
+        assert_eq!(leaked_fibers(&fibers[..2], fibers), vec![&fibers[2]]);
+        assert!(leaked_fibers(&fibers[..3], fibers).is_empty());
+    }
+}
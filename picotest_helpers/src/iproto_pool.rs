@@ -0,0 +1,213 @@
+//! Pool of iproto connections to an instance.
+//!
+//! Connections are opened lazily on first use and reused by subsequent
+//! calls. Connection, which has failed with a connection error, is
+//! reopened in place, see [`PicotestInstance::execute_rpc`](crate::PicotestInstance::execute_rpc).
+//!
+//! Client runs its connection in a task spawned on the tokio runtime of
+//! its first call, so it dies along with that runtime, e.g. when pooled
+//! connection is reused by the next `#[tokio::test]`. Such client fails
+//! before sending the call, so it's reopened like a broken connection.
+
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Error of `rusty_tarantool` client, whose connection task has finished,
+/// e.g. because its runtime has been shut down.
+const ERROR_DISPATCH_THREAD_IS_DEAD: &str = "DISPATCH THREAD IS DEAD!";
+
+/// Default number of iproto connections to an instance.
+pub const DEFAULT_IPROTO_POOL_SIZE: usize = 4;
+
+/// Client taken from [`IprotoPool`] along with the slot it occupies.
+pub(crate) struct PooledClient<C> {
+    pub(crate) slot: usize,
+    pub(crate) client: C,
+}
+
+/// Round-robin pool of clients.
+pub(crate) struct IprotoPool<C> {
+    slots: Mutex<Vec<Option<C>>>,
+    next: AtomicUsize,
+}
+
+impl<C: Clone> IprotoPool<C> {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            slots: Mutex::new(vec![None; size.max(1)]),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns client of the next slot, calls `connect` if slot is empty.
+    pub(crate) fn get(&self, connect: impl FnOnce() -> C) -> PooledClient<C> {
+        let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % slots.len();
+        let client = slots[slot].get_or_insert_with(connect).clone();
+        PooledClient { slot, client }
+    }
+
+    /// Replaces client of the `slot` with a new one returned by `connect`.
+    pub(crate) fn reconnect(&self, slot: usize, connect: impl FnOnce() -> C) -> C {
+        let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        slots[slot].insert(connect()).clone()
+    }
+
+    /// Performs `call` with client of the next slot. If connection is broken,
+    /// `on_broken` is called with the error, the slot is reconnected and the
+    /// call is repeated once.
+    pub(crate) async fn call<T, Fut>(
+        &self,
+        connect: impl Fn() -> C,
+        on_broken: impl FnOnce(&anyhow::Error),
+        call: impl Fn(C) -> Fut,
+    ) -> anyhow::Result<T>
+    where
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let pooled = self.get(&connect);
+        match call(pooled.client).await {
+            Err(err) if is_connection_error(&err) => {
+                on_broken(&err);
+                call(self.reconnect(pooled.slot, connect)).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// Returns `true` if error is caused by the connection rather than by the call itself.
+pub(crate) fn is_connection_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| {
+            matches!(
+                err.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+            ) || err.to_string() == ERROR_DISPATCH_THREAD_IS_DEAD
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_connection_error, IprotoPool, ERROR_DISPATCH_THREAD_IS_DEAD};
+    use anyhow::Context;
+    use rmpv::Value;
+    use rstest::rstest;
+    use rusty_tarantool::tarantool::{Client, ClientConfig};
+    use std::cell::Cell;
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Serves iproto connections answering every request by its sync.
+    fn serve_iproto() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || serve_connection(stream));
+            }
+        });
+        address
+    }
+
+    fn serve_connection(mut stream: TcpStream) -> io::Result<()> {
+        let salt = "A".repeat(44);
+        write!(stream, "{:<63}\n{salt:<63}\n", "Tarantool 2.11.0 (Binary)")?;
+        loop {
+            let mut size = [0; 5];
+            stream.read_exact(&mut size)?;
+            let mut request = vec![0; u32::from_be_bytes(size[1..].try_into().unwrap()) as usize];
+            stream.read_exact(&mut request)?;
+            let header =
+                rmpv::decode::read_value(&mut request.as_slice()).map_err(io::Error::other)?;
+            let sync = header
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_u64() == Some(1))
+                .unwrap()
+                .1
+                .clone();
+
+            let mut response = Vec::new();
+            let header = Value::Map(vec![(0.into(), 0.into()), (1.into(), sync.clone())]);
+            let body = Value::Map(vec![(0x30.into(), Value::Array(vec![sync]))]);
+            rmpv::encode::write_value(&mut response, &header)?;
+            rmpv::encode::write_value(&mut response, &body)?;
+            stream.write_all(&[0xce])?;
+            stream.write_all(&(response.len() as u32).to_be_bytes())?;
+            stream.write_all(&response)?;
+        }
+    }
+
+    #[rstest]
+    fn test_iproto_pool() {
+        let connections = Cell::new(0);
+        let connect = || {
+            connections.set(connections.get() + 1);
+            connections.get()
+        };
+        let pool = IprotoPool::new(2);
+
+        let clients: Vec<_> = (0..4).map(|_| pool.get(connect).client).collect();
+        assert_eq!(clients, [1, 2, 1, 2]);
+
+        assert_eq!(pool.reconnect(1, connect), 3);
+        assert_eq!(pool.get(connect).client, 1);
+        let reconnected = pool.get(connect);
+        assert_eq!((reconnected.slot, reconnected.client), (1, 3));
+    }
+
+    #[rstest]
+    fn test_is_connection_error() {
+        let err = Err::<(), _>(io::Error::from(ErrorKind::BrokenPipe))
+            .context("Rpc calls should not fail")
+            .unwrap_err();
+        assert!(is_connection_error(&err));
+
+        let err = Err::<(), _>(io::Error::other("Procedure '.proc_rpc_dispatch' failed"))
+            .context("Rpc calls should not fail")
+            .unwrap_err();
+        assert!(!is_connection_error(&err));
+        assert!(!is_connection_error(&anyhow::anyhow!(
+            "Rpc calls should not fail"
+        )));
+    }
+
+    #[rstest]
+    fn test_iproto_pool_across_runtimes() {
+        let address = serve_iproto();
+        let pool = IprotoPool::new(1);
+        let broken = Cell::new(0);
+        let call = |client: Client| async move {
+            let response = client.call_fn("echo", &()).await?;
+            Ok(response.code)
+        };
+
+        for _ in 0..2 {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let code = runtime.block_on(pool.call(
+                || ClientConfig::new(&address, "guest", "").build(),
+                |err| {
+                    assert_eq!(err.to_string(), ERROR_DISPATCH_THREAD_IS_DEAD);
+                    broken.set(broken.get() + 1);
+                },
+                call,
+            ));
+            assert_eq!(code.unwrap(), 0);
+        }
+        assert_eq!(broken.get(), 1);
+    }
+}
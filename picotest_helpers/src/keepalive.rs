@@ -0,0 +1,228 @@
+//! Reuse of the cluster between test runs.
+//!
+//! Cluster started in keep-alive mode isn't stopped on teardown. Instead,
+//! its location is recorded in a state file along with the hash of
+//! cluster topology and fingerprint of the plugin build. Next run compares
+//! recorded state with the actual one and either attaches to the running
+//! cluster or stops it if it's stale.
+
+use crate::migration::is_plugin_shipping_dir;
+use crate::stable_hash::StableHasher;
+use crate::topology::PluginTopology;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Path to the state file, relative to the plugin path.
+pub const KEEPALIVE_STATE_PATH: &str = "tmp/picotest_keepalive.toml";
+
+/// Data directory of kept alive cluster, relative to the plugin path.
+pub const KEEPALIVE_DATA_DIR: &str = "tmp/keepalive_cluster";
+
+/// Description of kept alive cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepaliveState {
+    /// Data directory of the cluster, relative to the plugin path.
+    pub data_dir: PathBuf,
+    pub topology_hash: String,
+    pub plugin_fingerprint: String,
+}
+
+impl KeepaliveState {
+    /// Describes cluster to be started in `data_dir` from the actual
+    /// topology and plugin build located in `profile_path`.
+    pub fn new(data_dir: PathBuf, topology: &PluginTopology, profile_path: &Path) -> Self {
+        Self {
+            data_dir,
+            topology_hash: topology_hash(topology),
+            plugin_fingerprint: plugin_fingerprint(profile_path),
+        }
+    }
+
+    /// Loads state of previously kept alive cluster if any.
+    pub fn load(plugin_path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(plugin_path.join(KEEPALIVE_STATE_PATH)).ok()?;
+        toml::from_str(&content).ok()
+    }
+
+    pub fn save(&self, plugin_path: &Path) -> anyhow::Result<()> {
+        let path = plugin_path.join(KEEPALIVE_STATE_PATH);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write keep-alive state to '{}'", path.display()))
+    }
+
+    pub fn remove(plugin_path: &Path) {
+        let _ = fs::remove_file(plugin_path.join(KEEPALIVE_STATE_PATH));
+    }
+}
+
+/// Hashes topology, so that clusters started with
+/// different topologies can be told apart.
+pub fn topology_hash(topology: &PluginTopology) -> String {
+    let mut hasher = StableHasher::default();
+    hasher.write_bytes(format!("{topology:?}").as_bytes());
+    hasher.finish_hex()
+}
+
+/// Fingerprints plugin build artifacts (shared libraries and shipping
+/// directories) located in the profile build directory by their names,
+/// sizes and modification times.
+pub fn plugin_fingerprint(profile_path: &Path) -> String {
+    fingerprint_artifacts(profile_path, &hash_file)
+}
+
+/// Fingerprints plugin build artifacts like [`plugin_fingerprint`], but by their
+/// contents and paths relative to the profile build directory, so the same build
+/// made in another checkout (e.g. by another CI job) has the same fingerprint.
+pub fn plugin_content_fingerprint(profile_path: &Path) -> String {
+    fingerprint_artifacts(profile_path, &|path, hasher| {
+        hash_path(path.strip_prefix(profile_path).unwrap_or(path), hasher);
+        if let Ok(content) = fs::read(path) {
+            hasher.write_bytes(&content);
+        }
+    })
+}
+
+fn fingerprint_artifacts(profile_path: &Path, hash_file: &HashFile<'_>) -> String {
+    let mut hasher = StableHasher::default();
+    let Ok(entries) = fs::read_dir(profile_path) else {
+        return hasher.finish_hex();
+    };
+
+    let mut entries = entries.filter_map(Result::ok).collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if is_plugin_shipping_dir(&entry) {
+            hash_dir(&path, &mut hasher, hash_file);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "so" || ext == "dylib")
+        {
+            hash_file(&path, &mut hasher);
+        }
+    }
+    hasher.finish_hex()
+}
+
+type HashFile<'a> = dyn Fn(&Path, &mut StableHasher) + 'a;
+
+fn hash_dir(path: &Path, hasher: &mut StableHasher, hash_file: &HashFile<'_>) {
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    let mut paths = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            hash_dir(&path, hasher, hash_file);
+        } else {
+            hash_file(&path, hasher);
+        }
+    }
+}
+
+fn hash_file(path: &Path, hasher: &mut StableHasher) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    hash_path(path, hasher);
+    hasher.write_u64(metadata.len());
+    if let Some(modified) = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    {
+        hasher.write_u64(modified.as_secs());
+        hasher.write_u64(u64::from(modified.subsec_nanos()));
+    }
+}
+
+fn hash_path(path: &Path, hasher: &mut StableHasher) {
+    hasher.write_bytes(path.as_os_str().as_encoded_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plugin_content_fingerprint, plugin_fingerprint, topology_hash, KeepaliveState};
+    use crate::topology::PluginTopology;
+    use rstest::rstest;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[rstest]
+    fn test_state_round_trip() {
+        let plugin_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let profile_path = plugin_path.join("target").join("debug");
+        let state = KeepaliveState::new(
+            PathBuf::from("tmp/cluster"),
+            &PluginTopology::default(),
+            &profile_path,
+        );
+
+        assert!(KeepaliveState::load(&plugin_path).is_none());
+        state.save(&plugin_path).unwrap();
+        assert_eq!(KeepaliveState::load(&plugin_path), Some(state));
+
+        KeepaliveState::remove(&plugin_path);
+        assert!(KeepaliveState::load(&plugin_path).is_none());
+
+        fs::remove_dir_all(plugin_path).unwrap();
+    }
+
+    #[rstest]
+    fn test_plugin_fingerprint_changes_with_build() {
+        let profile_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let shipping_path = profile_path.join("plugin").join("0.1.0");
+        fs::create_dir_all(&shipping_path).unwrap();
+        fs::create_dir_all(profile_path.join("deps")).unwrap();
+
+        let empty = plugin_fingerprint(&profile_path);
+
+        fs::write(profile_path.join("deps").join("libdep.so"), "dep").unwrap();
+        assert_eq!(empty, plugin_fingerprint(&profile_path), "deps are ignored");
+
+        fs::write(profile_path.join("libplugin.so"), "v1").unwrap();
+        let built = plugin_fingerprint(&profile_path);
+        assert_ne!(empty, built);
+
+        fs::write(shipping_path.join("manifest.yaml"), "version: 0.1.0").unwrap();
+        assert_ne!(built, plugin_fingerprint(&profile_path));
+
+        fs::remove_dir_all(profile_path).unwrap();
+    }
+
+    #[rstest]
+    fn test_plugin_content_fingerprint() {
+        let build = |content: &str| {
+            let profile_path =
+                std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(profile_path.join("plugin").join("0.1.0")).unwrap();
+            fs::write(profile_path.join("libplugin.so"), content).unwrap();
+            let fingerprint = plugin_content_fingerprint(&profile_path);
+            fs::remove_dir_all(profile_path).unwrap();
+            fingerprint
+        };
+
+        assert_eq!(build("v1"), build("v1"), "location is ignored");
+        assert_ne!(build("v1"), build("v2"));
+    }
+
+    #[rstest]
+    fn test_topology_hash_is_stable() {
+        // Hash is stored in the state file, so it must not change between toolchains.
+        assert_eq!(
+            topology_hash(&PluginTopology::default()),
+            "b9384f0f487f7e53"
+        );
+    }
+}
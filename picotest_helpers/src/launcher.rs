@@ -0,0 +1,122 @@
+//! Launcher scripts used as picodata binary.
+//!
+//! Pike starts instances by executing picodata binary, so modes changing how
+//! instances are run (container backend, audit log, coverage, sanitizers,
+//! custom hosts) point it to a generated shell script instead. Script of
+//! every mode ends by executing the binary it wraps, so modes are chained.
+
+use anyhow::Context;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Shell snippet setting `instance_dir` variable to the absolute path of
+/// the instance directory passed to picodata, or to empty string.
+pub(crate) const INSTANCE_DIR_SCRIPT: &str = r#"instance_dir=
+previous=
+for arg in "$@"; do
+    case "$previous" in
+        --instance-dir|--data-dir) instance_dir=$arg ;;
+    esac
+    case "$arg" in
+        --instance-dir=*|--data-dir=*) instance_dir=${arg#*=} ;;
+    esac
+    previous=$arg
+done
+case "$instance_dir" in
+    ''|/*) ;;
+    *) instance_dir="$PWD/$instance_dir" ;;
+esac"#;
+
+/// Writes executable launcher script `name` with `body` into `dir`.
+///
+/// ### Returns
+/// Absolute path to the script to be used as picodata binary.
+pub(crate) fn write_launcher(dir: &Path, name: &str, body: &str) -> anyhow::Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create '{}'", dir.display()))?;
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("failed to resolve '{}'", dir.display()))?;
+
+    let launcher_path = dir.join(name);
+    fs::write(&launcher_path, body)
+        .with_context(|| format!("failed to write '{}'", launcher_path.display()))?;
+    fs::set_permissions(&launcher_path, fs::Permissions::from_mode(0o755))?;
+    Ok(launcher_path)
+}
+
+/// Returns the last line of launcher script executing `picodata_path`
+/// with arguments of the script.
+pub(crate) fn exec_line(picodata_path: &Path) -> String {
+    format!(
+        "exec {} \"$@\"\n",
+        shell_quote(&picodata_path.to_string_lossy())
+    )
+}
+
+/// Quotes `value` as a single word of shell command.
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exec_line, shell_quote, write_launcher, INSTANCE_DIR_SCRIPT};
+    use rstest::rstest;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    #[rstest]
+    #[case("/usr/bin/picodata", "'/usr/bin/picodata'")]
+    #[case("/opt/it's/picodata", r"'/opt/it'\''s/picodata'")]
+    fn test_shell_quote(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(shell_quote(value), expected);
+    }
+
+    #[rstest]
+    fn test_write_launcher() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let picodata_dir = dir.join("it's");
+        let picodata_path = picodata_dir.join("picodata");
+        fs::create_dir_all(&picodata_dir).unwrap();
+        fs::write(&picodata_path, "#!/bin/sh\necho \"$@\"\n").unwrap();
+        fs::set_permissions(&picodata_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let body = format!("#!/bin/sh\n{}", exec_line(&picodata_path));
+        let launcher = write_launcher(&dir.join("launchers"), "picodata", &body).unwrap();
+        let output = Command::new(&launcher)
+            .args(["run", "--instance-dir", "data"])
+            .output()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(launcher.is_absolute());
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"run --instance-dir data\n");
+    }
+
+    #[rstest]
+    #[case(&["run", "--instance-dir", "/data/i1"], "/data/i1")]
+    #[case(&["run", "--data-dir=/data/i1"], "/data/i1")]
+    #[case(&["run", "--instance-dir", "i1"], "{cwd}/i1")]
+    #[case(&["run"], "")]
+    fn test_instance_dir_script(#[case] args: &[&str], #[case] expected: &str) {
+        let cwd = std::env::temp_dir().canonicalize().unwrap();
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!(
+                "{INSTANCE_DIR_SCRIPT}\nprintf %s \"$instance_dir\""
+            ))
+            .arg("picodata")
+            .args(args)
+            .current_dir(&cwd)
+            .env_remove("PWD")
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected.replace("{cwd}", &cwd.to_string_lossy())
+        );
+    }
+}
@@ -1,41 +1,122 @@
+use address::AddressConfig;
+use admin_socket::{resolve_socket, SocketResolver};
 use anyhow::{bail, Context};
 use bytes::Bytes;
+use cassette::InteractionKind;
+use compat::{Capability, PicodataVersion};
+use container::{Backend, ContainerConfig};
+use coverage::CoverageConfig;
+use iproto_pool::{IprotoPool, DEFAULT_IPROTO_POOL_SIZE};
 use log::{debug, info, warn};
+use lua::{LuaFrame, LuaResponse};
 use pike::cluster::{
     PicodataInstance, PicodataInstanceProperties, RunParamsBuilder, StopParamsBuilder, Topology,
 };
 use pike::config::ApplyParamsBuilder;
+use plugin_build::BuildProfile;
+use plugin_package::PluginSource;
+use progress::ProgressCallback;
 use rand::distr::Alphanumeric;
 use rand::RngExt;
+use remote::RemoteTarget;
+use retry::RetryPolicy;
 use rmpv::Value;
-use rusty_tarantool::tarantool::{ClientConfig, ExecWithParamaters, TarantoolResponse};
+use rusty_tarantool::tarantool::{Client, ClientConfig, ExecWithParamaters, TarantoolResponse};
+use sanitizer::Sanitizer;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::fs;
+use std::future::Future;
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::{
-    io::{Error, Read},
-    process::{Child, Command, Stdio},
+    io::{Error, ErrorKind, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex, Once, OnceLock, PoisonError},
+    thread,
     time::{Duration, Instant},
 };
+use tls::{TlsConfig, TlsTunnel};
 use topology::PluginTopology;
+use users::AuthMethod;
 use uuid::Uuid;
 
+pub mod address;
+pub mod admin_socket;
+pub mod assertions;
+pub mod audit;
+pub mod backup;
+pub mod cassette;
+pub mod catalog;
+pub mod clock;
+pub mod cluster_cache;
+pub mod compat;
+pub mod container;
+pub mod coverage;
+pub mod datagen;
+pub mod embedded;
+pub mod explain;
+pub mod failpoint;
+pub mod failure;
+pub mod flaky;
+pub mod freeze;
+pub mod governor;
+pub mod health;
+pub mod impact;
+pub mod instance_map;
+pub mod interactive;
+pub mod introspection;
+pub mod iproto_pool;
+pub mod keepalive;
+mod launcher;
+pub mod log_stream;
+pub mod lua;
+pub mod lua_modules;
+pub mod manifest;
 pub mod migration;
+#[cfg(feature = "unit_runner")]
+pub mod mock;
+pub mod panic_report;
+pub mod perf;
+pub mod pike_command;
+pub mod plugin_build;
+pub mod plugin_config;
+pub mod plugin_package;
+pub mod progress;
+pub mod query_stream;
+pub mod remote;
+pub mod retry;
+pub mod rpc_batch;
+pub mod sanitizer;
+pub mod scheduler;
+pub mod seed;
+pub mod shutdown;
+pub mod snapshot;
+pub mod sql;
+mod stable_hash;
+pub mod state_dump;
+pub mod tags;
+pub mod temp_schema;
+pub mod tls;
 pub mod topology;
+pub mod transaction;
+pub mod unit;
+pub mod users;
+pub mod watch;
+pub mod watchdog;
 
 pub type PluginConfigMap = pike::config::PluginConfigMap;
 
-const ADMIN_SOCKET_NAME: &str = "admin.sock";
-const LOCALHOST_IP: &str = "127.0.0.1";
+pub const ADMIN_SOCKET_NAME: &str = "admin.sock";
+pub const INSTANCE_LOG_NAME: &str = "picodata.log";
 pub const PICOTEST_USER: &str = "Picotest";
 pub const PICOTEST_USER_IPROTO: &str = "PicotestBin";
 pub const PICOTEST_USER_PASSWORD: &str = "Pic0test";
 
 // Footer and header returned from picodata admin after Lua query is executed.
+// Lua results are framed (see `lua` module), so these are only chomped if present.
 pub const LUA_OUTPUT_HEADER: &str = "Language switched to lua";
 pub const OUTPUT_FOOTER: &str = "Bye";
 
@@ -44,6 +125,95 @@ pub const OUTPUT_FOOTER: &str = "Bye";
 pub const DEFAULT_WAIT_VSHARD_TIMEOUT_SECS: u64 = 60;
 pub const DEFAULT_WAIT_VSHARD_ENABLED: bool = true;
 
+/// Interval between checks whether admin console has finished the query.
+const ADMIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Environment variable with number of attempts of the query,
+/// which admin console has exited in the middle of.
+pub const PICOTEST_CONSOLE_ATTEMPTS: &str = "PICOTEST_CONSOLE_ATTEMPTS";
+/// Interrupted query might have been executed, so it isn't repeated by default.
+pub const DEFAULT_CONSOLE_ATTEMPTS: u32 = 1;
+
+/// Environment variable with maximum size (in bytes) of the query output
+/// buffered by admin console queries.
+pub const PICOTEST_MAX_QUERY_OUTPUT: &str = "PICOTEST_MAX_QUERY_OUTPUT";
+pub const DEFAULT_MAX_QUERY_OUTPUT: usize = 64 * 1024 * 1024;
+
+/// Ports used by pike to start cluster instances from.
+/// Lua expression returning [`AttachedInstanceInfo`] of the instance.
+/// Listen addresses of pgproto and HTTP server are read from the
+/// effective config of the instance, they're absent if it can't be read.
+const INSTANCE_INFO_LUA: &str = r#"(function()
+    local ok, config = pcall(pico.config)
+    local instance = ok and type(config) == 'table' and config.instance or {}
+    local function value(option)
+        if type(option) == 'table' then return option.value end
+        return option
+    end
+    return {
+        info = pico.instance_info(),
+        listen = box.info.listen,
+        config_read = ok,
+        pg_listen = value(instance.pg and instance.pg.listen),
+        http_listen = value(instance.http_listen),
+    }
+end)()"#;
+
+#[derive(Deserialize)]
+struct AttachedInstanceInfo {
+    info: AttachedInstanceProperties,
+    listen: String,
+    #[serde(default)]
+    config_read: bool,
+    #[serde(default)]
+    pg_listen: Option<String>,
+    #[serde(default)]
+    http_listen: Option<String>,
+}
+
+impl AttachedInstanceInfo {
+    /// Returns pgproto and HTTP ports the instance listens on.
+    /// HTTP port is zero if the instance doesn't serve HTTP.
+    fn listen_ports(&self) -> anyhow::Result<(u16, u16)> {
+        if !self.config_read {
+            bail!(
+                "failed to read config of instance '{}' to find its listen addresses",
+                self.info.name
+            );
+        }
+        let Some(pg_listen) = &self.pg_listen else {
+            bail!(
+                "instance '{}' has no pgproto listen address",
+                self.info.name
+            );
+        };
+        let http_port = match &self.http_listen {
+            Some(http_listen) => parse_listen_port(http_listen)?,
+            None => 0,
+        };
+        Ok((parse_listen_port(pg_listen)?, http_port))
+    }
+}
+
+#[derive(Deserialize)]
+struct AttachedInstanceProperties {
+    raft_id: u16,
+    name: String,
+    tier: String,
+}
+
+/// Parses port from listen address, e.g. `127.0.0.1:3301` or `[::1]:3301`.
+fn parse_listen_port(listen: &str) -> anyhow::Result<u16> {
+    let Some(port) = listen.rsplit(':').next().and_then(|p| p.parse().ok()) else {
+        bail!("failed to parse port from '{listen}'");
+    };
+    Ok(port)
+}
+
+/// Returns random path of the data directory relative to the plugin path.
+///
+/// Name is random regardless of [`seed::PICOTEST_SEED`], so that
+/// a run reproduced with the same seed gets its own directory.
 pub fn tmp_dir() -> PathBuf {
     let mut rng = rand::rng();
     PathBuf::from(format!(
@@ -55,8 +225,40 @@ pub fn tmp_dir() -> PathBuf {
     ))
 }
 
+/// Stops cluster located in `data_dir` (relative to the plugin path)
+/// without having its [`Cluster`] handle.
+pub fn stop_cluster(plugin_path: &Path, data_dir: &Path) -> anyhow::Result<()> {
+    let params = StopParamsBuilder::default()
+        .plugin_path(plugin_path.to_path_buf())
+        .data_dir(data_dir.to_path_buf())
+        .build()?;
+
+    debug!("Stopping the cluster with parameters {params:?}");
+    pike::cluster::stop(&params).with_context(|| {
+        format!(
+            "failed to stop the cluster in '{}'",
+            plugin_path.join(data_dir).display()
+        )
+    })
+}
+
+/// Lists directories of cluster instances located in `data_dir`.
+///
+/// Directories are sorted by instance name.
+pub fn instance_dirs(data_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs = fs::read_dir(data_dir.join("cluster"))?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    Ok(dirs)
+}
+
 pub struct PicotestInstance {
-    inner: PicodataInstance,
+    /// Handle of the instance started by pike.
+    /// It's `None` for instances of an attached cluster.
+    inner: Option<PicodataInstance>,
     pub socket_path: PathBuf,
     pub bin_port: u16,
     pub pg_port: u16,
@@ -64,6 +266,33 @@ pub struct PicotestInstance {
     pub instance_name: String,
     pub tier: String,
     pub instance_id: u16,
+    /// Default timeout of queries executed through admin console.
+    /// Queries may run indefinitely if it's `None`.
+    pub query_timeout: Option<Duration>,
+    /// Number of attempts of the query, which admin console has exited in the middle of.
+    pub console_attempts: u32,
+    /// Maximum size (in bytes) of the query output buffered in memory.
+    pub max_query_output: usize,
+    /// Number of iproto connections opened to the instance.
+    pub iproto_pool_size: usize,
+    /// iproto connections are encrypted if it's set, see [`tls`].
+    pub iproto_tls: Option<TlsConfig>,
+    /// Host local instance is reachable at by clients.
+    connect_host: String,
+    /// Remote host the instance is running on, `None` for local instances.
+    remote: Option<RemoteTarget>,
+    /// Launcher running admin console in the container of the instance,
+    /// `None` unless instance is run by [`container`] backend.
+    container_launcher: Option<PathBuf>,
+    /// Connections are opened on the first iproto call.
+    iproto_pool: OnceLock<IprotoPool<Client>>,
+    /// Tunnel forwarding iproto connections over TLS, started on the first call.
+    iproto_tunnel: Mutex<Option<TlsTunnel>>,
+    /// Binary port re-resolved on reconnect, if instance
+    /// listens on another one since properties were resolved.
+    resolved_bin_port: Mutex<Option<u16>>,
+    /// Version of picodata is requested on the first check of capabilities.
+    picodata_version: OnceLock<PicodataVersion>,
 }
 
 impl From<(PicodataInstance, &PathBuf)> for PicotestInstance {
@@ -81,8 +310,20 @@ impl From<(PicodataInstance, &PathBuf)> for PicotestInstance {
             instance_name: instance_name.to_string(),
             tier: properties.tier.to_string(),
             instance_id: *properties.instance_id,
-            inner: instance,
+            inner: Some(instance),
             socket_path,
+            query_timeout: None,
+            console_attempts: console_attempts_from_env(),
+            max_query_output: max_query_output_from_env(),
+            iproto_pool_size: DEFAULT_IPROTO_POOL_SIZE,
+            iproto_tls: None,
+            connect_host: AddressConfig::default().client_host().to_string(),
+            remote: None,
+            container_launcher: None,
+            iproto_pool: OnceLock::new(),
+            iproto_tunnel: Mutex::new(None),
+            resolved_bin_port: Mutex::new(None),
+            picodata_version: OnceLock::new(),
         }
     }
 }
@@ -96,14 +337,170 @@ impl PicotestInstance {
         &self.pg_port
     }
 
+    /// Returns properties of the instance started by pike.
+    ///
+    /// Panics if instance belongs to an attached cluster.
     pub fn properties(&self) -> PicodataInstanceProperties<'_> {
-        self.inner.properties()
+        self.inner().properties()
     }
 
+    /// Returns handle of the instance started by pike.
+    ///
+    /// Panics if instance belongs to an attached cluster.
     pub fn inner(&self) -> &PicodataInstance {
-        &self.inner
+        self.inner
+            .as_ref()
+            .expect("instance of attached cluster is not managed by pike")
+    }
+
+    /// Connects to already running instance through its admin socket
+    /// and discovers instance properties.
+    pub fn attach(socket_path: PathBuf) -> anyhow::Result<Self> {
+        if !socket_path.exists() {
+            bail!("admin socket '{}' is not found", socket_path.display());
+        }
+        Self::discover(socket_path, None)
+    }
+
+    /// Creates instance, properties of which are unknown yet.
+    fn detached(socket_path: PathBuf, remote: Option<RemoteTarget>) -> Self {
+        PicotestInstance {
+            inner: None,
+            socket_path,
+            bin_port: 0,
+            pg_port: 0,
+            http_port: 0,
+            instance_name: String::new(),
+            tier: String::new(),
+            instance_id: 0,
+            query_timeout: None,
+            console_attempts: console_attempts_from_env(),
+            max_query_output: max_query_output_from_env(),
+            iproto_pool_size: DEFAULT_IPROTO_POOL_SIZE,
+            iproto_tls: None,
+            connect_host: AddressConfig::default().client_host().to_string(),
+            remote,
+            container_launcher: None,
+            iproto_pool: OnceLock::new(),
+            iproto_tunnel: Mutex::new(None),
+            resolved_bin_port: Mutex::new(None),
+            picodata_version: OnceLock::new(),
+        }
+    }
+
+    fn discover(socket_path: PathBuf, remote: Option<RemoteTarget>) -> anyhow::Result<Self> {
+        let mut instance = Self::detached(socket_path, remote);
+        instance.refresh()?;
+        Ok(instance)
+    }
+
+    /// Re-resolves properties of the instance from the running instance,
+    /// e.g. after it has been restarted and listens on other ports.
+    ///
+    /// Ports of pgproto and HTTP server are read from the effective config
+    /// of the instance. Iproto connections opened to the previous address
+    /// are reopened on the next call. Note, that iproto calls re-resolve
+    /// binary port of the instance by themselves, if connection to it is refused.
+    ///
+    /// ### Returns
+    /// - On success, unit type.
+    /// - On failure, instance of [`anyhow::Error`], properties are left intact.
+    ///   It's also returned if listen address of pgproto isn't found.
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        let info = self.live_info()?;
+        let bin_port = parse_listen_port(&info.listen)?;
+        let (pg_port, http_port) = match (info.listen_ports(), &self.inner) {
+            (Ok(ports), _) => ports,
+            // Ports assigned by pike are used if picodata doesn't expose its config.
+            (Err(err), Some(instance)) if !info.config_read => {
+                debug!("{err:#}, using ports assigned by pike");
+                let properties = instance.properties();
+                (*properties.pg_port, *properties.http_port)
+            }
+            (Err(err), _) => return Err(err),
+        };
+
+        if bin_port != self.bin_port {
+            debug!(
+                "Binary port of instance '{}' has changed from {} to {bin_port}",
+                info.info.name, self.bin_port
+            );
+            self.iproto_pool = OnceLock::new();
+        }
+        self.bin_port = bin_port;
+        *self
+            .resolved_bin_port
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+        self.pg_port = pg_port;
+        self.http_port = http_port;
+        self.instance_name = info.info.name;
+        self.tier = info.info.tier;
+        self.instance_id = info.info.raft_id;
+        // Instance could be restarted with another picodata binary.
+        self.picodata_version = OnceLock::new();
+
+        Ok(())
+    }
+
+    /// Requests properties of the instance through its admin console.
+    fn live_info(&self) -> anyhow::Result<AttachedInstanceInfo> {
+        introspection::eval_lua_yaml(self, INSTANCE_INFO_LUA).with_context(|| {
+            format!(
+                "failed to discover instance behind '{}'",
+                self.socket_path.display()
+            )
+        })
+    }
+
+    /// Returns binary port iproto clients connect to.
+    fn iproto_port(&self) -> u16 {
+        self.resolved_bin_port
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .unwrap_or(self.bin_port)
+    }
+
+    /// Re-resolves binary port of the instance after connection to it has failed.
+    fn resolve_bin_port(&self) {
+        let bin_port = match self
+            .live_info()
+            .and_then(|info| parse_listen_port(&info.listen))
+        {
+            Ok(bin_port) => bin_port,
+            Err(err) => {
+                debug!(
+                    "Failed to re-resolve binary port of '{}': {err:#}",
+                    self.instance_name
+                );
+                return;
+            }
+        };
+        if bin_port != self.iproto_port() {
+            warn!(
+                "Instance '{}' listens on port {bin_port} instead of {}, reconnecting",
+                self.instance_name,
+                self.iproto_port()
+            );
+            *self
+                .resolved_bin_port
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(bin_port);
+        }
     }
 
+    /// Returns address iproto and pgproto ports of the instance are reachable at.
+    pub fn host(&self) -> &str {
+        self.remote
+            .as_ref()
+            .map_or(&self.connect_host, |remote| &remote.host)
+    }
+
+    /// Calls RPC endpoint of the plugin over iproto.
+    ///
+    /// Connections are taken from the pool of the instance, see [`Cluster::iproto_pool_size`].
+    /// If connection turns out to be broken, e.g. because instance was restarted,
+    /// it's reopened and the call is repeated once.
     pub async fn execute_rpc<S, G>(
         &self,
         plugin_name: &str,
@@ -116,65 +513,239 @@ impl PicotestInstance {
         G: DeserializeOwned,
         S: Serialize,
     {
-        let bin_port = self.bin_port;
-        let client = ClientConfig::new(
-            format!("{LOCALHOST_IP}:{bin_port}"),
-            PICOTEST_USER_IPROTO,
-            PICOTEST_USER_PASSWORD,
-        )
-        .build();
-
-        let input_encoded =
-            rmp_serde::encode::to_vec_named(input).context("failed to encode input to msgpack")?;
-
-        // In beloved Picodata, the rpc request args have custom serialisation function
-        // See: https://github.com/picodata/picodata/blob/1e89dd6a4634f3a8be065fadaa522b2f37d3719c/picodata-plugin/src/transport/context.rs#L167
-
-        let mut context_map = BTreeMap::new();
-        let request_id_bytes = Uuid::new_v4().as_bytes().to_vec();
-        context_map.insert(1, Value::Ext(2, request_id_bytes));
-        context_map.insert(2, Value::String(plugin_name.into()));
-        context_map.insert(3, Value::String(service_name.into()));
-        context_map.insert(4, Value::String(plugin_version.into()));
-
-        let response: TarantoolResponse = client
-            .prepare_fn_call(".proc_rpc_dispatch")
-            .bind(path)?
-            .bind(Bytes::copy_from_slice(&input_encoded))?
-            .bind_ref(&context_map)?
-            .execute()
+        impact::record_rpc(service_name, path);
+        let cassette = cassette::active();
+        let request = cassette
+            .map(|_| rpc_request_key(plugin_name, path, service_name, plugin_version, input))
+            .transpose()?;
+        let response: Vec<u8> = match (cassette, &request) {
+            (Some(cassette), Some(request))
+                if cassette.mode() == cassette::CassetteMode::Replay =>
+            {
+                cassette.replay_interaction::<_, anyhow::Error>(
+                    InteractionKind::Rpc,
+                    &self.instance_name,
+                    request,
+                )?
+            }
+            _ => {
+                self.ensure_supports(Capability::RpcDispatchContext)?;
+                let response = self
+                    .call_pooled(|client| async move {
+                        call_rpc(
+                            &client,
+                            plugin_name,
+                            path,
+                            service_name,
+                            plugin_version,
+                            input,
+                        )
+                        .await
+                    })
+                    .await;
+                if let (Some(cassette), Some(request)) = (cassette, &request) {
+                    cassette.record_interaction(
+                        InteractionKind::Rpc,
+                        &self.instance_name,
+                        request,
+                        &response,
+                    );
+                }
+                response?
+            }
+        };
+
+        // Second layer is the struct itself
+        rmp_serde::from_slice(&response).context("Failed to deserialise rpc response")
+    }
+
+    /// Calls stored procedure `proc_name` of the instance over iproto,
+    /// e.g. picodata's `.proc_instance_info` or a function created by `box.schema.func.create`.
+    ///
+    /// Procedure is called by picotest iproto user, so it must have execute privilege on it.
+    /// Connections are reused like by [`PicotestInstance::execute_rpc`].
+    ///
+    /// ### Arguments:
+    /// - `proc_name` - name of the procedure.
+    /// - `args` - arguments of the procedure encoded as msgpack array, e.g. a tuple.
+    ///
+    /// ### Returns
+    ///
+    /// - On success, values returned by the procedure decoded from msgpack array,
+    ///   so single returned value should be decoded as one-element tuple.
+    /// - On failure, instance of [`anyhow::Error`].
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// let (sum,): (i64,) = cluster.main().call_proc("picotest_sum", &(1, 2)).await?;
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub async fn call_proc<T, R>(&self, proc_name: &str, args: &T) -> anyhow::Result<R>
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        self.call_pooled(|client| async move {
+            let response = client
+                .call_fn(proc_name, args)
+                .await
+                .with_context(|| format!("failed to call procedure '{proc_name}'"))?;
+            if response.code != 0 {
+                bail!(
+                    "procedure '{proc_name}' has failed with code {}",
+                    response.code
+                );
+            }
+            rmp_serde::from_slice(response.data.as_ref())
+                .with_context(|| format!("failed to decode result of procedure '{proc_name}'"))
+        })
+        .await
+    }
+
+    /// Performs iproto `call` with a client from the pool. If connection
+    /// is broken, it's reopened and the call is repeated once.
+    async fn call_pooled<T, F, Fut>(&self, call: F) -> anyhow::Result<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        self.iproto_pool()
+            .call(
+                || self.rpc_client(),
+                |err| {
+                    warn!(
+                        "Iproto connection to '{}' is broken, reconnecting: {err:#}",
+                        self.instance_name
+                    );
+                    self.resolve_bin_port();
+                },
+                call,
+            )
             .await
-            .context("Rpc calls should not fail")?;
+    }
 
-        if response.code != 0 {
-            bail!("Rpc calls should not fail");
-        }
+    /// Returns pool of iproto connections to the instance.
+    pub(crate) fn iproto_pool(&self) -> &IprotoPool<Client> {
+        self.iproto_pool
+            .get_or_init(|| IprotoPool::new(self.iproto_pool_size))
+    }
 
-        // RustyTarantool library uses binary protocol, thus the return value from RPC is
-        // encoded to MsgPack twice. First layer is an array of binary data.
-        let response: Vec<rmpv::Value> = rmp_serde::from_slice(response.data.as_ref())
-            .context("Failed to deserialise rpc response")?;
-        let Value::Binary(response_bin) = &response[0] else {
-            bail!("Expected to recieve binary input")
+    /// Returns iproto client of picotest user connected to the instance.
+    pub(crate) fn rpc_client(&self) -> Client {
+        self.rpc_client_as(PICOTEST_USER_IPROTO, PICOTEST_USER_PASSWORD)
+    }
+
+    /// Returns iproto client of `user` connected to the instance.
+    pub(crate) fn rpc_client_as(&self, user: &str, password: &str) -> Client {
+        let address = address::socket_address(self.host(), self.iproto_port());
+        let address = match &self.iproto_tls {
+            Some(tls) => self
+                .iproto_tunnel(tls, address)
+                .expect("Failed to start TLS tunnel to iproto"),
+            None => address,
         };
+        ClientConfig::new(address, user, password).build()
+    }
 
-        // Second layer is the struct itself
-        let response_decoded: G =
-            rmp_serde::from_slice(response_bin).context("Failed to deserialise rpc response")?;
+    /// Returns local address of the tunnel forwarding connections
+    /// to iproto `address` over TLS, see [`tls`].
+    fn iproto_tunnel(&self, tls: &TlsConfig, address: String) -> anyhow::Result<String> {
+        let mut tunnel = self
+            .iproto_tunnel
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match &*tunnel {
+            // Binary port may be re-resolved, then tunnel is restarted.
+            Some(tunnel) if tunnel.target() == address => Ok(tunnel.local_address()),
+            _ => {
+                let started = TlsTunnel::start(tls, self.host(), address)?;
+                let local_address = started.local_address();
+                *tunnel = Some(started);
+                Ok(local_address)
+            }
+        }
+    }
 
-        Ok(response_decoded)
+    fn run_query<T: AsRef<[u8]>>(&self, query: T) -> Result<String, Error> {
+        self.execute_query(query, self.query_timeout)
     }
 
-    fn read_output<T: Read>(&self, reader: T) -> Result<String, Error> {
-        BufReader::new(reader)
-            .lines()
-            .skip(2)
-            .take_while(|line| line.as_ref().is_ok_and(|l| l != OUTPUT_FOOTER))
-            .collect::<Result<Vec<_>, _>>()
-            .map(|lines| lines.join("\n"))
+    /// Executes raw query through admin console like `run_sql`, but kills
+    /// admin console and returns [`ErrorKind::TimedOut`] error if it
+    /// hasn't finished within `timeout`.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// #[picotest]
+    /// fn test_slow_query() {
+    ///     let err = cluster.instances()[0]
+    ///         .run_query_with_timeout("\\lua\nrequire('fiber').sleep(10)\n", Duration::from_secs(1))
+    ///         .unwrap_err();
+    ///     assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    /// }
+    /// ```
+    pub fn run_query_with_timeout<T: AsRef<[u8]>>(
+        &self,
+        query: T,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        self.execute_query(query, Some(timeout))
     }
 
-    fn run_query<T: AsRef<[u8]>>(&self, query: T) -> Result<String, Error> {
+    fn execute_query<T: AsRef<[u8]>>(
+        &self,
+        query: T,
+        timeout: Option<Duration>,
+    ) -> Result<String, Error> {
+        let request = String::from_utf8_lossy(query.as_ref());
+        impact::record_query(&request);
+        let Some(cassette) = cassette::active() else {
+            return self.execute_admin_query(query, timeout);
+        };
+        cassette.interact(
+            InteractionKind::Query,
+            &self.instance_name,
+            &request,
+            || self.execute_admin_query(query.as_ref(), timeout),
+        )
+    }
+
+    /// Executes query by admin console of the instance.
+    ///
+    /// Query is repeated up to `console_attempts` times, if admin console
+    /// has exited before printing the whole output, e.g. because connection
+    /// to the instance was broken. Note that the query might have been
+    /// executed by the instance anyway, so only one attempt is made by default.
+    fn execute_admin_query<T: AsRef<[u8]>>(
+        &self,
+        query: T,
+        timeout: Option<Duration>,
+    ) -> Result<String, Error> {
+        let mut attempt = 1;
+        loop {
+            match self.try_execute_admin_query(query.as_ref(), timeout) {
+                Err(err) if is_console_disconnect(&err) && attempt < self.console_attempts => {
+                    warn!(
+                        "Admin console of '{}' has disconnected (attempt {attempt}/{}), retrying: {err}",
+                        self.instance_name, self.console_attempts
+                    );
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn try_execute_admin_query(
+        &self,
+        query: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<String, Error> {
         let mut picodata_admin = self.await_picodata_admin()?;
 
         let stdout = picodata_admin
@@ -185,40 +756,72 @@ impl PicotestInstance {
             .stderr
             .take()
             .expect("Failed to capture stderr");
-        {
-            let picodata_stdin = picodata_admin.stdin.as_mut().unwrap();
-            picodata_stdin.write_all(query.as_ref())?;
-            picodata_admin.wait()?;
-        }
+        picodata_admin.stdin.as_mut().unwrap().write_all(query)?;
 
-        let result = self.read_output(stdout)?;
-        if result.is_empty() {
-            let err_output = self.read_output(stderr)?;
-            if !err_output.is_empty() {
+        // Output is read while waiting, as admin console blocks
+        // once pipe is full and wouldn't exit otherwise.
+        let max_size = self.max_query_output;
+        let (status, output) = thread::scope(|scope| {
+            let reader = scope.spawn(move || read_output(stdout, max_size));
+            let status = wait_admin(&mut picodata_admin, timeout);
+            if status.is_err() {
+                let _ = picodata_admin.kill();
+            }
+            let output = reader
+                .join()
+                .expect("Reader of admin console output has panicked");
+            (status, output)
+        });
+        let output = output?;
+        if output.truncated {
+            return Err(Error::new(
+                ErrorKind::OutOfMemory,
+                format!(
+                    "output of the query on instance '{}' exceeds {} bytes and is truncated, \
+                    use `run_query_stream` to read it by lines or raise the limit",
+                    self.instance_name, self.max_query_output
+                ),
+            ));
+        }
+        let status = status?;
+        if output.text.is_empty() {
+            let err_output = read_output(stderr, self.max_query_output)?;
+            if !err_output.text.is_empty() {
                 picodata_admin.kill()?;
-                return Err(Error::other(err_output));
+                return Err(Error::other(err_output.text));
             }
         }
         picodata_admin.kill()?;
 
-        Ok(result)
+        if !output.complete || !status.success() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "admin console of instance '{}' has exited abnormally ({status}), output is incomplete: {}",
+                    self.instance_name, output.text
+                ),
+            ));
+        }
+        Ok(output.text)
     }
 
     /// Executes Lua script through picodata's query mechanism.
     ///
-    /// Prepends `\lua\n` to the query and passes it to `run_query`.
+    /// Script is wrapped into a frame (see [`lua`] module) and passed
+    /// to `run_query` after `\lua\n` language switch.
     ///
     /// # Arguments
     /// * `query` - Lua code as a byte slice or convertible type
     ///
     /// # Return Value
     /// `Result<String, Error>` where:
-    /// * `Ok(String)` - script execution result
-    /// * `Err(Error)` - execution error (inherited from `run_query`)
+    /// * `Ok(String)` - YAML document with values returned by the script
+    /// * `Err(Error)` - execution error (inherited from `run_query`) or
+    ///   error raised by the script
     ///
     /// # Examples
     /// ```rust,ignore
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn test_run_lua_query() {
@@ -227,11 +830,62 @@ impl PicotestInstance {
     /// }
     /// ```
     pub fn run_lua<T: AsRef<[u8]>>(&self, query: T) -> Result<String, Error> {
-        let output = self.run_query([b"\\lua\n", query.as_ref()].concat())?;
-        // Chomp header if exists or keep output as is.
-        let output = output.strip_prefix(LUA_OUTPUT_HEADER).unwrap_or(&output);
+        self.run_lua_with_status(query)?.into_result()
+    }
+
+    /// Executes Lua script like [`PicotestInstance::run_lua`], but returns
+    /// errors raised by the script as a [`lua::LuaStatus`] of the response.
+    pub fn run_lua_with_status<T: AsRef<[u8]>>(&self, query: T) -> Result<LuaResponse, Error> {
+        let script = String::from_utf8_lossy(query.as_ref());
+        let execute = || {
+            let frame = LuaFrame::default();
+            let output = self.execute_admin_query(
+                format!("\\lua\n{}\n", frame.wrap(&script)),
+                self.query_timeout,
+            )?;
+            // Chomp header if exists or keep output as is.
+            let output = output.strip_prefix(LUA_OUTPUT_HEADER).unwrap_or(&output);
 
-        Ok(output.to_owned())
+            frame.unwrap(output)
+        };
+
+        // Script is recorded instead of the framed one, which differs every time.
+        match cassette::active() {
+            Some(cassette) => {
+                cassette.interact(InteractionKind::Lua, &self.instance_name, &script, execute)
+            }
+            None => execute(),
+        }
+    }
+
+    /// Executes Lua script and deserializes the single value it returns,
+    /// instead of parsing YAML output of [`PicotestInstance::run_lua`].
+    ///
+    /// Script is either an expression or a chunk returning one value.
+    /// `nil` is deserialized as `None` of `Option<T>`.
+    ///
+    /// ### Returns
+    /// - On failure, instance of [`anyhow::Error`]: error raised by
+    ///   the script or error of deserialization with the raw output.
+    ///
+    /// ### Examples:
+    /// ```rust,ignore
+    /// #[picotest]
+    /// fn test_typed_lua() {
+    ///     let count: u64 = cluster.main().run_lua_typed("box.space.users:count()").unwrap();
+    ///     let rows: Vec<(String, i64)> = cluster
+    ///         .main()
+    ///         .run_lua_typed("return box.space.users:select()")
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn run_lua_typed<T: DeserializeOwned>(
+        &self,
+        script: impl AsRef<[u8]>,
+    ) -> anyhow::Result<T> {
+        self.run_lua_with_status(script)
+            .with_context(|| format!("failed to run Lua script on '{}'", self.instance_name))?
+            .into_value()
     }
 
     /// Executes an SQL query through the picodata admin console.
@@ -252,7 +906,7 @@ impl PicotestInstance {
     ///
     /// # Examples
     /// ```rust,ignore
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn run_sql_query() {
@@ -268,14 +922,8 @@ impl PicotestInstance {
         let timeout = Duration::from_secs(60);
         let start_time = Instant::now();
         loop {
-            assert!(
-                start_time.elapsed() < timeout,
-                "process hanging for too long"
-            );
-
-            let picodata_admin = Command::new("picodata")
-                .arg("admin")
-                .arg(self.socket_path.clone())
+            let mut command = self.admin_command();
+            let picodata_admin = command
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
@@ -286,12 +934,205 @@ impl PicotestInstance {
                     info!("Successfully connected to picodata cluster.");
                     return Ok(process);
                 }
-                Err(_) => {
+                // Missing binary won't appear by itself, so there is no point in retrying.
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    let program = Path::new(command.get_program());
+                    let message = match &self.remote {
+                        Some(_) => format!("'{}' is not found", program.display()),
+                        None => compat::missing_picodata_message(program),
+                    };
+                    return Err(Error::new(ErrorKind::NotFound, message));
+                }
+                Err(err) => {
+                    if start_time.elapsed() > timeout {
+                        return Err(Error::new(
+                            ErrorKind::TimedOut,
+                            format!(
+                                "failed to run admin console of instance '{}' within {}s: {err}",
+                                self.instance_name,
+                                timeout.as_secs()
+                            ),
+                        ));
+                    }
+                    debug!("Failed to run admin console, retrying: {err}");
                     std::thread::sleep(Duration::from_secs(1));
                 }
             }
         }
     }
+
+    /// Returns command running admin console of the instance.
+    fn admin_command(&self) -> Command {
+        if let Some(remote) = &self.remote {
+            return remote.admin_command(&self.socket_path);
+        }
+        let program = self
+            .container_launcher
+            .as_deref()
+            .unwrap_or(Path::new("picodata"));
+        let mut command = Command::new(program);
+        command.arg("admin").arg(&self.socket_path);
+        command
+    }
+}
+
+/// Describes RPC request to match it in the cassette.
+fn rpc_request_key<S: Serialize>(
+    plugin_name: &str,
+    path: &str,
+    service_name: &str,
+    plugin_version: &str,
+    input: &S,
+) -> anyhow::Result<String> {
+    let input = serde_json::to_string(input).context("failed to encode input to JSON")?;
+    Ok(format!(
+        "{plugin_name}.{service_name}@{plugin_version}{path} {input}"
+    ))
+}
+
+/// Calls RPC endpoint of the plugin through `client`.
+/// See [`PicotestInstance::execute_rpc`].
+///
+/// ### Returns
+/// Response encoded into msgpack by the handler.
+pub(crate) async fn call_rpc<S>(
+    client: &Client,
+    plugin_name: &str,
+    path: &str,
+    service_name: &str,
+    plugin_version: &str,
+    input: &S,
+) -> anyhow::Result<Vec<u8>>
+where
+    S: Serialize,
+{
+    let input_encoded =
+        rmp_serde::encode::to_vec_named(input).context("failed to encode input to msgpack")?;
+
+    // In beloved Picodata, the rpc request args have custom serialisation function
+    // See: https://github.com/picodata/picodata/blob/1e89dd6a4634f3a8be065fadaa522b2f37d3719c/picodata-plugin/src/transport/context.rs#L167
+
+    let mut context_map = BTreeMap::new();
+    let request_id_bytes = Uuid::new_v4().as_bytes().to_vec();
+    context_map.insert(1, Value::Ext(2, request_id_bytes));
+    context_map.insert(2, Value::String(plugin_name.into()));
+    context_map.insert(3, Value::String(service_name.into()));
+    context_map.insert(4, Value::String(plugin_version.into()));
+
+    let response: TarantoolResponse = client
+        .prepare_fn_call(".proc_rpc_dispatch")
+        .bind(path)?
+        .bind(Bytes::copy_from_slice(&input_encoded))?
+        .bind_ref(&context_map)?
+        .execute()
+        .await
+        .context("Rpc calls should not fail")?;
+
+    if response.code != 0 {
+        bail!("Rpc calls should not fail");
+    }
+
+    // RustyTarantool library uses binary protocol, thus the return value from RPC is
+    // encoded to MsgPack twice. First layer is an array of binary data.
+    let response: Vec<rmpv::Value> = rmp_serde::from_slice(response.data.as_ref())
+        .context("Failed to deserialise rpc response")?;
+    let Some(Value::Binary(response_bin)) = response.into_iter().next() else {
+        bail!("Expected to recieve binary input")
+    };
+
+    Ok(response_bin)
+}
+
+/// Output of the admin console.
+#[derive(Default)]
+struct ConsoleOutput {
+    text: String,
+    /// Footer printed by admin console on exit has been read.
+    complete: bool,
+    /// Output exceeds maximum size, so the rest of it isn't read.
+    truncated: bool,
+}
+
+/// Reads output of the admin console up to `max_size` bytes. Reader is
+/// dropped after the limit, so admin console is terminated by `SIGPIPE`.
+fn read_output<T: Read>(reader: T, max_size: usize) -> Result<ConsoleOutput, Error> {
+    let mut output = ConsoleOutput::default();
+    let mut reader = BufReader::new(reader.take(max_size as u64 + 1));
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut size = 0;
+    for index in 0.. {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => size += read,
+        }
+        if size > max_size {
+            output.truncated = true;
+            break;
+        }
+        // Header of admin console is skipped.
+        if index < 2 {
+            continue;
+        }
+        let line = line.trim_end_matches('\n');
+        if line == OUTPUT_FOOTER {
+            output.complete = true;
+            break;
+        }
+        lines.push(line.to_string());
+    }
+    output.text = lines.join("\n");
+    Ok(output)
+}
+
+/// Returns `true` if query has failed because admin console has exited
+/// in the middle of the query.
+pub fn is_console_disconnect(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe)
+}
+
+fn max_query_output_from_env() -> usize {
+    std::env::var(PICOTEST_MAX_QUERY_OUTPUT).map_or(DEFAULT_MAX_QUERY_OUTPUT, |value| {
+        value
+            .parse::<usize>()
+            .expect("invalid maximum size of query output")
+    })
+}
+
+fn console_attempts_from_env() -> u32 {
+    std::env::var(PICOTEST_CONSOLE_ATTEMPTS).map_or(DEFAULT_CONSOLE_ATTEMPTS, |value| {
+        value
+            .parse::<u32>()
+            .expect("invalid number of console attempts")
+            .max(1)
+    })
+}
+
+/// Waits for admin console process to exit. Kills it if `timeout` has expired.
+fn wait_admin(picodata_admin: &mut Child, timeout: Option<Duration>) -> Result<ExitStatus, Error> {
+    // Closed stdin makes admin console exit once the query is done.
+    drop(picodata_admin.stdin.take());
+
+    let Some(timeout) = timeout else {
+        return picodata_admin.wait();
+    };
+
+    let start_time = Instant::now();
+    loop {
+        if let Some(status) = picodata_admin.try_wait()? {
+            return Ok(status);
+        }
+        if start_time.elapsed() > timeout {
+            picodata_admin.kill()?;
+            picodata_admin.wait()?;
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("query hasn't finished within {}s", timeout.as_secs_f64()),
+            ));
+        }
+        thread::sleep(ADMIN_POLL_INTERVAL);
+    }
 }
 
 pub struct Cluster {
@@ -302,11 +1143,46 @@ pub struct Cluster {
     instances: Vec<PicotestInstance>,
     picodata_path: PathBuf,
     wait_vshard_discovery: bool,
+    daemon: bool,
+    attached: bool,
+    keep_alive: bool,
+    query_timeout: Option<Duration>,
+    console_attempts: u32,
+    max_query_output: usize,
+    iproto_pool_size: usize,
+    stop_timeout: Duration,
+    install_from: PluginSource,
+    build_profile: BuildProfile,
+    retry_policy: RetryPolicy,
+    backend: Backend,
+    container_config: ContainerConfig,
+    /// Instances write audit log, see [`audit`].
+    audit: bool,
+    /// Instances write coverage profiles, see [`coverage`].
+    coverage: Option<CoverageConfig>,
+    /// Instances are run with sanitizer options, see [`sanitizer`].
+    sanitizer: Option<Sanitizer>,
+    socket_resolver: Arc<dyn SocketResolver>,
+    address: AddressConfig,
+    /// Receives progress of waiting for the cluster, see [`progress`].
+    progress: Option<ProgressCallback>,
+    /// Failed test is paused for inspection, see [`interactive`].
+    pause_on_failure: bool,
+    /// pgproto connections are encrypted if it's set, see [`tls`].
+    pg_tls: Option<TlsConfig>,
+    /// iproto connections are encrypted if it's set, see [`tls`].
+    iproto_tls: Option<TlsConfig>,
 }
 
 impl Drop for Cluster {
     fn drop(&mut self) {
-        if let Err(err) = self.stop() {
+        if self.is_kept_alive() {
+            return;
+        }
+        if std::thread::panicking() {
+            self.inspect_failure();
+        }
+        if let Err(err) = self.shutdown() {
             warn!("Failed to stop picodata cluster: {err}");
         }
     }
@@ -320,9 +1196,14 @@ impl Cluster {
     ) -> anyhow::Result<Self> {
         let data_dir = tmp_dir();
 
-        if let Err(err) = fs::remove_dir_all(plugin_path.join(data_dir.parent().unwrap())) {
-            warn!("Failed to remove cluster data directory: {err}");
-        }
+        // Data directories left by previous runs are removed once,
+        // so clusters started side by side don't remove each other's.
+        static STALE_DATA_REMOVED: Once = Once::new();
+        STALE_DATA_REMOVED.call_once(|| {
+            if let Err(err) = fs::remove_dir_all(plugin_path.join(data_dir.parent().unwrap())) {
+                warn!("Failed to remove cluster data directory: {err}");
+            }
+        });
 
         let cluster = Self {
             uuid: Uuid::new_v4(),
@@ -332,31 +1213,302 @@ impl Cluster {
             instances: Default::default(),
             picodata_path,
             wait_vshard_discovery: DEFAULT_WAIT_VSHARD_ENABLED,
+            daemon: false,
+            attached: false,
+            keep_alive: false,
+            query_timeout: None,
+            console_attempts: console_attempts_from_env(),
+            max_query_output: max_query_output_from_env(),
+            iproto_pool_size: DEFAULT_IPROTO_POOL_SIZE,
+            stop_timeout: shutdown::DEFAULT_STOP_TIMEOUT,
+            install_from: PluginSource::default(),
+            build_profile: BuildProfile::default(),
+            retry_policy: RetryPolicy::default(),
+            backend: Backend::default(),
+            container_config: ContainerConfig::default(),
+            audit: false,
+            coverage: None,
+            sanitizer: None,
+            socket_resolver: admin_socket::resolver_from_env(),
+            address: AddressConfig::default(),
+            progress: None,
+            pause_on_failure: false,
+            pg_tls: None,
+            iproto_tls: None,
         };
 
         Ok(cluster)
     }
 
+    /// Attaches to already running cluster instead of starting a new one.
+    ///
+    /// ### Arguments
+    /// - `plugin_path` - path to the plugin root directory.
+    /// - `target` - either path to the cluster data directory (e.g. the one
+    ///   started by `picotest up`) or comma-separated list of paths to
+    ///   admin sockets of cluster instances.
+    ///
+    /// Attached cluster is not stopped when dropped.
+    pub fn attach(plugin_path: PathBuf, target: &str) -> anyhow::Result<Self> {
+        let target_path = PathBuf::from(target);
+        let (data_dir, socket_paths) = if target_path.is_dir() {
+            let resolver = admin_socket::resolver_from_env();
+            // Stopped instances have no socket.
+            let socket_paths: Vec<PathBuf> = instance_dirs(&target_path)
+                .with_context(|| format!("no cluster found in '{target}'"))?
+                .into_iter()
+                .filter_map(|dir| {
+                    let instance_name = dir.file_name()?.to_string_lossy().into_owned();
+                    resolve_socket(&*resolver, &target_path, &instance_name).ok()
+                })
+                .collect();
+            (target_path, socket_paths)
+        } else {
+            let socket_paths = target
+                .split(',')
+                .map(str::trim)
+                .map(PathBuf::from)
+                .collect();
+            (PathBuf::new(), socket_paths)
+        };
+
+        let instances = socket_paths
+            .into_iter()
+            .map(PicotestInstance::attach)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Self::attached(plugin_path, data_dir, instances)
+            .with_context(|| format!("no running instances found in '{target}'"))
+    }
+
+    /// Creates handle of the cluster consisting of already running `instances`.
+    fn attached(
+        plugin_path: PathBuf,
+        data_dir: PathBuf,
+        mut instances: Vec<PicotestInstance>,
+    ) -> anyhow::Result<Self> {
+        if instances.is_empty() {
+            bail!("cluster has no instances");
+        }
+        instance_map::sort_instances(&mut instances);
+
+        info!("Attached to the cluster of {} instances", instances.len());
+
+        Ok(Self {
+            uuid: Uuid::new_v4(),
+            plugin_path,
+            data_dir,
+            topology: Topology::default(),
+            instances,
+            picodata_path: PathBuf::from("picodata"),
+            wait_vshard_discovery: DEFAULT_WAIT_VSHARD_ENABLED,
+            daemon: false,
+            attached: true,
+            keep_alive: false,
+            query_timeout: None,
+            console_attempts: console_attempts_from_env(),
+            max_query_output: max_query_output_from_env(),
+            iproto_pool_size: DEFAULT_IPROTO_POOL_SIZE,
+            stop_timeout: shutdown::DEFAULT_STOP_TIMEOUT,
+            install_from: PluginSource::default(),
+            build_profile: BuildProfile::default(),
+            retry_policy: RetryPolicy::default(),
+            backend: Backend::default(),
+            container_config: ContainerConfig::default(),
+            audit: false,
+            coverage: None,
+            sanitizer: None,
+            socket_resolver: admin_socket::resolver_from_env(),
+            address: AddressConfig::default(),
+            progress: None,
+            pause_on_failure: false,
+            pg_tls: None,
+            iproto_tls: None,
+        })
+    }
+
+    /// Returns `true` if cluster was attached by [`Cluster::attach`] or [`Cluster::remote`].
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Keeps cluster running after the handle is dropped or tests are finished.
+    pub fn keep_alive(mut self, is_enabled: bool) -> Self {
+        self.keep_alive = is_enabled;
+        self
+    }
+
+    /// Returns `true` if cluster should outlive the tests, i.e. it's either
+    /// attached or started in keep-alive mode.
+    pub fn is_kept_alive(&self) -> bool {
+        self.attached || self.keep_alive
+    }
+
     pub fn wait_vshard_discovery(mut self, is_enabled: bool) -> Self {
         self.wait_vshard_discovery = is_enabled;
         self
     }
 
+    /// Overrides data directory of the cluster, which is relative to the plugin path.
+    ///
+    /// Unlike default randomly named directory, it's not removed on cluster creation.
+    pub fn with_data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.data_dir = data_dir;
+        self
+    }
+
+    /// Sets default timeout of queries executed through admin console
+    /// of cluster instances. See [`PicotestInstance::run_query_with_timeout`].
+    pub fn query_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.query_timeout = timeout;
+        for instance in &mut self.instances {
+            instance.query_timeout = timeout;
+        }
+        self
+    }
+
+    /// Sets number of attempts of the query, which admin console of the instance
+    /// has exited in the middle of. See [`PicotestInstance::run_query_with_timeout`].
+    /// Interrupted query might have been executed, so enable retries only for
+    /// idempotent queries.
+    ///
+    /// Default is [`DEFAULT_CONSOLE_ATTEMPTS`] or the value of [`PICOTEST_CONSOLE_ATTEMPTS`].
+    pub fn console_attempts(mut self, attempts: u32) -> Self {
+        self.console_attempts = attempts.max(1);
+        for instance in &mut self.instances {
+            instance.console_attempts = self.console_attempts;
+        }
+        self
+    }
+
+    /// Sets maximum size (in bytes) of the query output buffered by
+    /// [`PicotestInstance::run_query_with_timeout`], `run_sql` and `run_lua`.
+    /// Larger output fails the query, use [`PicotestInstance::run_query_stream`]
+    /// to read it.
+    ///
+    /// Default is [`DEFAULT_MAX_QUERY_OUTPUT`] or the value of [`PICOTEST_MAX_QUERY_OUTPUT`].
+    pub fn max_query_output(mut self, size: usize) -> Self {
+        self.max_query_output = size;
+        for instance in &mut self.instances {
+            instance.max_query_output = size;
+        }
+        self
+    }
+
+    /// Sets number of iproto connections opened to every instance
+    /// by [`PicotestInstance::execute_rpc`] and [`PicotestInstance::rpc_batch`].
+    ///
+    /// Default is [`DEFAULT_IPROTO_POOL_SIZE`].
+    pub fn iproto_pool_size(mut self, size: usize) -> Self {
+        self.iproto_pool_size = size.max(1);
+        for instance in &mut self.instances {
+            instance.iproto_pool_size = self.iproto_pool_size;
+            instance.iproto_pool = OnceLock::new();
+        }
+        self
+    }
+
+    /// Sets time given to instances to exit gracefully, when cluster
+    /// is dropped, before they're killed. See [`Cluster::shutdown`].
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_timeout = timeout;
+        self
+    }
+
+    /// Sets where the plugin is installed from on cluster start.
+    ///
+    /// With [`PluginSource::Package`], plugin is packed by `pike plugin pack`
+    /// and cluster is started from the unpacked archive.
+    pub fn install_from(mut self, source: PluginSource) -> Self {
+        self.install_from = source;
+        self
+    }
+
+    /// Sets cargo profile the plugin is built, packed and loaded with.
+    pub fn build_profile(mut self, profile: BuildProfile) -> Self {
+        self.build_profile = profile;
+        self
+    }
+
+    /// Sets policy of retrying operations prone to transient failures:
+    /// cluster start, creation of picotest users and applying of plugin config.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets where cluster instances are running.
+    ///
+    /// With [`Backend::Container`], instances are started from picodata image
+    /// described by [`Cluster::container_config`], so local picodata binary
+    /// isn't required.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets container runtime and image used by [`Backend::Container`].
+    pub fn container_config(mut self, config: ContainerConfig) -> Self {
+        self.container_config = config;
+        self
+    }
+
+    /// Sets how admin sockets of started instances are located.
+    ///
+    /// Default is the layout of pike or the template set by
+    /// [`admin_socket::PICOTEST_ADMIN_SOCKET`] environment variable.
+    pub fn socket_resolver(mut self, resolver: impl SocketResolver + 'static) -> Self {
+        self.socket_resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Sets hosts instances listen on and clients connect to them by.
+    ///
+    /// Default is `127.0.0.1` or the hosts set by [`address::PICOTEST_BIND_HOST`]
+    /// and [`address::PICOTEST_CONNECT_HOST`] environment variables.
+    pub fn address(mut self, config: AddressConfig) -> Self {
+        for instance in &mut self.instances {
+            instance.connect_host = config.client_host().to_string();
+        }
+        self.address = config;
+        self
+    }
+
+    /// Shifts ports instances listen on by `offset`, so the cluster may run
+    /// side by side with another one. See [`AddressConfig::port_offset`].
+    pub fn port_offset(mut self, offset: u16) -> Self {
+        self.address.port_offset = offset;
+        self
+    }
+
+    /// Runs cluster instances as daemons, so they outlive the current process.
+    pub fn daemon(mut self, is_enabled: bool) -> Self {
+        self.daemon = is_enabled;
+        self
+    }
+
+    /// Releases the handle of running cluster without stopping it.
+    ///
+    /// Cluster can be stopped later with [`Cluster::stop`] of another handle
+    /// pointing to the same plugin path and data directory.
+    pub fn detach(self) {
+        debug!(
+            "Detaching from the cluster with data directory '{}'",
+            self.data_dir_path().display()
+        );
+        std::mem::forget(self);
+    }
+
     pub fn data_dir_path(&self) -> PathBuf {
         self.plugin_path.join(self.data_dir.clone())
     }
 
     pub fn stop(&self) -> anyhow::Result<()> {
-        let params = StopParamsBuilder::default()
-            .plugin_path(self.plugin_path.clone())
-            .data_dir(self.data_dir.clone())
-            .build()?;
-
-        debug!("Stopping the cluster with parameters {params:?}");
-        pike::cluster::stop(&params)
+        self.ensure_not_remote("Stopping the cluster")?;
+        stop_cluster(&self.plugin_path, &self.data_dir)
     }
 
     pub fn stop_instance(&self, instance: &PicotestInstance) -> anyhow::Result<()> {
+        self.ensure_not_remote("Stopping the instance")?;
         let params = StopParamsBuilder::default()
             .plugin_path(self.plugin_path.clone())
             .data_dir(self.data_dir.clone())
@@ -365,6 +1517,7 @@ impl Cluster {
 
         debug!("Stopping the cluster instance with parameters {params:?}");
         pike::cluster::stop(&params)
+            .with_context(|| format!("failed to stop instance '{}'", instance.instance_name))
     }
 
     /// Applies passed plugin config to the running cluster through the interface of command
@@ -415,7 +1568,7 @@ impl Cluster {
     /// use rmpv::Value;
     /// use std::collections::HashMap;
     /// use serde_norway::Value;
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn test_apply_plugin_from_yaml_string() {
@@ -450,7 +1603,7 @@ impl Cluster {
     /// use rmpv::Value;
     /// use std::collections::HashMap;
     /// use serde_norway::Value;
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn test_apply_plugin_from_hashmap() {
@@ -498,7 +1651,7 @@ impl Cluster {
     /// use rmpv::Value;
     /// use std::collections::HashMap;
     /// use serde_norway::Value;
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn test_apply_plugin_nested_config() {
@@ -529,26 +1682,129 @@ impl Cluster {
             .build()?;
 
         debug!("Applying plugin configuration with parameters {params:?}");
-        pike::config::apply(&params)
+        self.retry_policy
+            .run("Applying plugin configuration", |_| {
+                pike::config::apply(&params)
+            })
+            .with_context(|| {
+                format!(
+                    "failed to apply plugin configuration, see logs of instances in '{}'",
+                    self.data_dir_path().display()
+                )
+            })
     }
 
-    pub fn run(mut self) -> anyhow::Result<Self> {
-        let params = RunParamsBuilder::default()
+    pub fn run(self) -> anyhow::Result<Self> {
+        self.start(&|_| Ok(()))
+    }
+
+    /// Starts the cluster, see [`Cluster::run`]. Before every retry of
+    /// the start data directory is recreated and filled by `restore`.
+    pub(crate) fn start(
+        mut self,
+        restore: &dyn Fn(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Self> {
+        let mut params = RunParamsBuilder::default();
+        params
             .plugin_path(self.plugin_path.clone())
             .data_dir(self.data_dir.clone())
             .topology(self.topology.clone())
-            .picodata_path(self.picodata_path.clone())
             .wait_vshard_discovery(self.wait_vshard_discovery)
             .wait_vshard_discovery_timeout(DEFAULT_WAIT_VSHARD_TIMEOUT_SECS)
-            .use_release(false)
-            .build()?;
+            .daemon(self.daemon)
+            .use_release(self.build_profile.is_release());
 
-        let data_dir = self.data_dir_path();
+        if self.install_from == PluginSource::Package {
+            let archive = plugin_package::pack_plugin(&self.plugin_path, self.build_profile)?;
+            debug!("Installing the plugin from package '{}'", archive.display());
+            plugin_package::unpack_plugin(&self.plugin_path, &archive, self.build_profile)?;
+            params.target_dir(PathBuf::from(plugin_package::PACKAGE_TARGET_DIR));
+        }
+
+        let mut picodata_path = self.picodata_path.clone();
+        let mut container_launcher = None;
+        if self.backend == Backend::Local {
+            compat::ensure_compatible_picodata(&picodata_path)?;
+        }
+        if self.backend == Backend::Container {
+            picodata_path =
+                container::write_launcher(&self.plugin_path, &self.uuid, &self.container_config)?;
+            compat::ensure_compatible_picodata(&picodata_path).with_context(|| {
+                format!(
+                    "picodata image '{}' can't be used",
+                    self.container_config.image
+                )
+            })?;
+            container::create_network(&self.uuid, &self.container_config)?;
+            container_launcher = Some(picodata_path.clone());
+        }
+        if self.audit {
+            picodata_path = audit::write_launcher(&self.plugin_path, &self.uuid, &picodata_path)?;
+        }
+        if self.coverage.is_some() {
+            picodata_path =
+                coverage::write_launcher(&self.plugin_path, &self.uuid, &picodata_path)?;
+        }
+        if let Some(sanitizer) = self.sanitizer {
+            if self.backend == Backend::Container {
+                bail!("sanitizer mode isn't supported by container backend");
+            }
+            picodata_path = sanitizer::write_launcher(
+                &self.plugin_path,
+                &self.uuid,
+                &picodata_path,
+                sanitizer,
+            )?;
+        }
+        if !self.address.is_default() {
+            picodata_path = address::write_launcher(
+                &self.plugin_path,
+                &self.uuid,
+                &picodata_path,
+                &self.address,
+            )?;
+        }
+        params.picodata_path(picodata_path);
 
-        debug!("Starting the cluster with parameters {params:?}");
-        let mut instances: Vec<PicotestInstance> = pike::cluster::run(params)?
+        let data_dir = self.data_dir_path();
+        let instances = self.retry_policy.run("Starting the cluster", |attempt| {
+            // Instances started by the failed attempt would hold ports
+            // and data directory of the next one.
+            if attempt > 1 {
+                if let Err(err) = self.stop() {
+                    warn!("Failed to stop partially started cluster: {err}");
+                }
+                // Names of the containers are taken until they're removed.
+                if self.backend == Backend::Container {
+                    container::remove_containers(&self.uuid, &self.container_config)?;
+                }
+                retry::reset_dir(&data_dir)?;
+                restore(&data_dir)?;
+            }
+            let params = params.build()?;
+            debug!("Starting the cluster with parameters {params:?}");
+            pike::cluster::run(params)
+        })?;
+        let mut instances: Vec<PicotestInstance> = instances
             .into_iter()
-            .map(|instance| PicotestInstance::from((instance, &data_dir)))
+            .map(|instance| {
+                // Properties report ports assigned by pike, which are
+                // shifted by the launcher, see `address` module.
+                let instance = PicotestInstance::from((instance, &data_dir));
+                PicotestInstance {
+                    bin_port: instance.bin_port + self.address.port_offset,
+                    pg_port: instance.pg_port + self.address.port_offset,
+                    http_port: instance.http_port + self.address.port_offset,
+                    query_timeout: self.query_timeout,
+                    console_attempts: self.console_attempts,
+                    max_query_output: self.max_query_output,
+                    iproto_pool_size: self.iproto_pool_size,
+                    iproto_tls: self.iproto_tls.clone(),
+                    connect_host: self.address.client_host().to_string(),
+                    container_launcher: container_launcher.clone(),
+                    ..instance
+                }
+            })
             .collect();
 
         debug_assert!(
@@ -556,7 +1812,14 @@ impl Cluster {
             "trying to replace already running cluster?"
         );
         std::mem::swap(&mut self.instances, &mut instances);
+        instance_map::sort_instances(&mut self.instances);
 
+        for instance in &mut self.instances {
+            instance.socket_path =
+                resolve_socket(&*self.socket_resolver, &data_dir, &instance.instance_name)?;
+        }
+
+        self.wait_started(Duration::from_secs(DEFAULT_WAIT_VSHARD_TIMEOUT_SECS))?;
         self.create_picotest_users();
 
         Ok(self)
@@ -571,21 +1834,31 @@ impl Cluster {
         self.main().run_query(query)
     }
 
-    /// Executes Lua script through picodata's query mechanism.
+    /// Executes raw query on the main instance with `timeout`.
+    /// See [`PicotestInstance::run_query_with_timeout`].
+    pub fn run_query_with_timeout<T: AsRef<[u8]>>(
+        &self,
+        query: T,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        self.main().run_query_with_timeout(query, timeout)
+    }
+
+    /// Executes Lua script on the main instance.
     ///
-    /// Prepends `\lua\n` to the query and passes it to `run_query`.
+    /// See [`PicotestInstance::run_lua`] for details.
     ///
     /// # Arguments
     /// * `query` - Lua code as a byte slice or convertible type
     ///
     /// # Return Value
     /// `Result<String, Error>` where:
-    /// * `Ok(String)` - script execution result
-    /// * `Err(Error)` - execution error (inherited from `run_query`)
+    /// * `Ok(String)` - YAML document with values returned by the script
+    /// * `Err(Error)` - execution error or error raised by the script
     ///
     /// # Examples
     /// ```rust,ignore
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn test_run_lua_query() {
@@ -597,6 +1870,21 @@ impl Cluster {
         self.main().run_lua(query)
     }
 
+    /// Executes Lua script on the main instance.
+    /// See [`PicotestInstance::run_lua_with_status`].
+    pub fn run_lua_with_status<T: AsRef<[u8]>>(&self, query: T) -> Result<LuaResponse, Error> {
+        self.main().run_lua_with_status(query)
+    }
+
+    /// Executes Lua script on the main instance and deserializes its result.
+    /// See [`PicotestInstance::run_lua_typed`].
+    pub fn run_lua_typed<T: DeserializeOwned>(
+        &self,
+        script: impl AsRef<[u8]>,
+    ) -> anyhow::Result<T> {
+        self.main().run_lua_typed(script)
+    }
+
     /// Executes an SQL query through the picodata admin console.
     ///
     /// # Workflow
@@ -615,7 +1903,7 @@ impl Cluster {
     ///
     /// # Examples
     /// ```rust,ignore
-    /// use picotest::*;
+    /// use picotest::prelude::*;
     ///
     /// #[picotest]
     /// fn run_sql_query() {
@@ -636,37 +1924,109 @@ impl Cluster {
 
     /// Method returns all instances, which belong to certain tier
     pub fn get_instances_by_tier(&self, tier_name: &str) -> Vec<&PicotestInstance> {
+        self.instances_in_tier(tier_name)
+    }
+
+    /// Method returns all instances, which belong to certain tier
+    pub fn instances_in_tier(&self, tier_name: &str) -> Vec<&PicotestInstance> {
         self.instances()
             .iter()
             .filter(|&instance| instance.tier == tier_name)
             .collect()
     }
 
+    /// Method returns instance by its name.
+    ///
+    /// Panics if there's no such instance in the cluster.
+    pub fn instance(&self, instance_name: &str) -> &PicotestInstance {
+        self.find_instance(instance_name)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Method returns current master of the replicaset.
+    pub fn master_of(&self, replicaset_name: &str) -> anyhow::Result<&PicotestInstance> {
+        let Some(replicaset) = self.catalog().replicaset(replicaset_name)? else {
+            bail!("replicaset '{replicaset_name}' is not found in the cluster");
+        };
+        self.find_instance(&replicaset.current_master_name)
+    }
+
+    /// Method returns any instance of the replicaset except its master.
+    pub fn any_replica_of(&self, replicaset_name: &str) -> anyhow::Result<&PicotestInstance> {
+        let master = self.master_of(replicaset_name)?;
+        self.replicaset_instances(replicaset_name)?
+            .into_iter()
+            .find(|instance| instance.instance_name != master.instance_name)
+            .with_context(|| format!("replicaset '{replicaset_name}' has no replicas"))
+    }
+
+    /// Method returns all instances, which belong to certain replicaset
+    pub fn replicaset_instances(
+        &self,
+        replicaset_name: &str,
+    ) -> anyhow::Result<Vec<&PicotestInstance>> {
+        self.catalog()
+            .replicaset_instances(replicaset_name)?
+            .iter()
+            .map(|instance| self.find_instance(&instance.name))
+            .collect()
+    }
+
+    fn find_instance(&self, instance_name: &str) -> anyhow::Result<&PicotestInstance> {
+        self.instances()
+            .iter()
+            .find(|instance| instance.instance_name == instance_name)
+            .with_context(|| format!("instance '{instance_name}' is not found in the cluster"))
+    }
+
     /// Method returns all running instances of cluster
+    /// sorted by tier and then by instance id.
     pub fn instances(&self) -> &Vec<PicotestInstance> {
         &self.instances
     }
 
-    // Create two users for pgproto and iproto with different password encryption
+    /// Re-resolves properties of all instances of the cluster,
+    /// see [`PicotestInstance::refresh`].
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        for instance in &mut self.instances {
+            instance.refresh()?;
+        }
+        instance_map::sort_instances(&mut self.instances);
+        Ok(())
+    }
+
+    // Create two users for pgproto and iproto with different password encryption.
+    // Users with other auth methods are created by `create_user`, see `users` module.
     fn create_picotest_users(&self) {
-        for (user, auth_method) in [(PICOTEST_USER, "md5"), (PICOTEST_USER_IPROTO, "chap-sha1")] {
-            self.run_query(format!(
-                r#"CREATE USER "{user}" with password '{PICOTEST_USER_PASSWORD}' using {auth_method};"#
+        for (user, auth_method) in [
+            (PICOTEST_USER, AuthMethod::Md5),
+            (PICOTEST_USER_IPROTO, AuthMethod::ChapSha1),
+        ] {
+            self.run_query_with_retries(format!(
+                r#"CREATE USER IF NOT EXISTS "{user}" with password '{PICOTEST_USER_PASSWORD}' using {auth_method};"#
             ))
             .expect("Picotest user create should not fail");
 
-            self.run_query(format!(r#"GRANT CREATE TABLE TO "{user}""#))
+            self.run_query_with_retries(format!(r#"GRANT CREATE TABLE TO "{user}""#))
                 .expect("Picotest user grant should not fail");
 
-            self.run_query(format!(r#"GRANT READ TABLE TO "{user}""#))
+            self.run_query_with_retries(format!(r#"GRANT READ TABLE TO "{user}""#))
                 .expect("Picotest user grant should not fail");
 
-            self.run_query(format!(r#"GRANT WRITE TABLE TO "{user}""#))
+            self.run_query_with_retries(format!(r#"GRANT WRITE TABLE TO "{user}""#))
                 .expect("Picotest user grant should not fail");
         }
     }
+
+    fn run_query_with_retries(&self, query: String) -> Result<String, Error> {
+        self.retry_policy
+            .run(&format!("Query '{query}'"), |_| self.run_query(&query))
+    }
 }
 
+#[deprecated(
+    note = "Use pike_command::run_pike_captured, which waits for the command and captures its output"
+)]
 pub fn run_pike<A, P>(args: Vec<A>, current_dir: P) -> Result<std::process::Child, Error>
 where
     A: AsRef<OsStr>,
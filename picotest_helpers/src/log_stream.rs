@@ -0,0 +1,264 @@
+//! Streaming of instance logs into the output of the test.
+//!
+//! Logs of instances are written by pike into files under the data directory
+//! of the cluster (see [`INSTANCE_LOG_NAME`]). [`Cluster::stream_logs`] tails
+//! these files in background and prints appended lines prefixed by instance
+//! name, so hanging test can be debugged without looking for the files.
+//!
+//! Lines are written to stdout directly, bypassing capture of the test
+//! output, so they're visible while the test is running.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::log_stream::LogFilter;
+//!
+//! let _logs = cluster.stream_logs(LogFilter::all().instance("default_1_1").contains("ERROR"));
+//! cluster.run_sql("INSERT INTO users VALUES (1, 'Alice')").unwrap();
+//! // Streaming stops once the guard is dropped.
+//! ```
+
+use crate::{Cluster, INSTANCE_LOG_NAME};
+use std::env;
+use std::fs::File;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Environment variable, which enables streaming of logs in every test.
+pub const PICOTEST_STREAM_LOGS: &str = "PICOTEST_STREAM_LOGS";
+
+/// Interval between checks of log files for new lines.
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Colors of instance name prefixes, assigned to instances in turn.
+const PREFIX_COLORS: [&str; 6] = [
+    "\x1b[36m", "\x1b[33m", "\x1b[35m", "\x1b[32m", "\x1b[34m", "\x1b[31m",
+];
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// Lines of instance logs to be streamed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    instances: Vec<String>,
+    text: Option<String>,
+}
+
+impl LogFilter {
+    /// Matches all lines of all instances.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Streams logs of the instance `instance_name`.
+    /// If called several times, logs of all listed instances are streamed.
+    pub fn instance(mut self, instance_name: impl Into<String>) -> Self {
+        self.instances.push(instance_name.into());
+        self
+    }
+
+    /// Requires line to contain `text`.
+    pub fn contains(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    fn matches_instance(&self, instance_name: &str) -> bool {
+        self.instances.is_empty() || self.instances.iter().any(|name| name == instance_name)
+    }
+
+    fn matches_line(&self, line: &str) -> bool {
+        self.text.as_ref().is_none_or(|text| line.contains(text))
+    }
+}
+
+/// Log file being tailed.
+pub(crate) struct TailedLog {
+    pub(crate) prefix: String,
+    path: PathBuf,
+    offset: u64,
+}
+
+impl TailedLog {
+    pub(crate) fn new(instance_name: &str, path: PathBuf, color: Option<&str>) -> Self {
+        let prefix = match color {
+            Some(color) => format!("{color}[{instance_name}]{COLOR_RESET}"),
+            None => format!("[{instance_name}]"),
+        };
+        // Only lines appended after streaming is started are of interest.
+        let offset = path.metadata().map_or(0, |metadata| metadata.len());
+        Self {
+            prefix,
+            path,
+            offset,
+        }
+    }
+
+    /// Returns complete lines appended since the last read.
+    pub(crate) fn read_lines(&mut self) -> Vec<String> {
+        let Ok(mut file) = File::open(&self.path) else {
+            return Vec::new();
+        };
+        // Log has been rotated or truncated.
+        if file
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() < self.offset)
+        {
+            self.offset = 0;
+        }
+
+        let mut buf = Vec::new();
+        if file.seek(SeekFrom::Start(self.offset)).is_err() || file.read_to_end(&mut buf).is_err() {
+            return Vec::new();
+        }
+        // Incomplete last line is read on the next poll.
+        let Some(end) = buf.iter().rposition(|byte| *byte == b'\n') else {
+            return Vec::new();
+        };
+        self.offset += end as u64 + 1;
+        String::from_utf8_lossy(&buf[..end])
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Guard of log streaming started by [`Cluster::stream_logs`].
+/// Streaming stops when the guard is dropped.
+pub struct LogStream {
+    stopped: Arc<AtomicBool>,
+    tailer: Option<JoinHandle<()>>,
+}
+
+impl LogStream {
+    fn start(logs: Vec<TailedLog>, filter: LogFilter) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let tailer = {
+            let stopped = stopped.clone();
+            thread::spawn(move || tail_logs(logs, &filter, &stopped))
+        };
+        Self {
+            stopped,
+            tailer: Some(tailer),
+        }
+    }
+}
+
+impl Drop for LogStream {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(tailer) = self.tailer.take() {
+            let _ = tailer.join();
+        }
+    }
+}
+
+fn tail_logs(mut logs: Vec<TailedLog>, filter: &LogFilter, stopped: &AtomicBool) {
+    loop {
+        // Flag is checked before the last poll, so lines written
+        // right before the stop are printed too.
+        let is_last_poll = stopped.load(Ordering::Relaxed);
+        let mut stdout = std::io::stdout().lock();
+        for log in &mut logs {
+            for line in log.read_lines() {
+                if filter.matches_line(&line) {
+                    let _ = writeln!(stdout, "{} {line}", log.prefix);
+                }
+            }
+        }
+        drop(stdout);
+
+        if is_last_poll {
+            return;
+        }
+        thread::sleep(LOG_POLL_INTERVAL);
+    }
+}
+
+/// Returns path to the log of the instance `instance_name` located in `data_dir`.
+pub(crate) fn instance_log_path(data_dir: &Path, instance_name: &str) -> PathBuf {
+    data_dir
+        .join("cluster")
+        .join(instance_name)
+        .join(INSTANCE_LOG_NAME)
+}
+
+impl Cluster {
+    /// Starts printing lines appended to logs of the instances to stdout.
+    /// Lines are prefixed by instance name, colorized if stdout
+    /// is a terminal and `NO_COLOR` isn't set.
+    ///
+    /// ### Arguments:
+    /// - `filter` - instances and lines to be streamed.
+    ///
+    /// ### Returns
+    /// Guard, which stops streaming when dropped.
+    pub fn stream_logs(&self, filter: LogFilter) -> LogStream {
+        let color = env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal();
+        let data_dir = self.data_dir_path();
+        let logs = self
+            .instances()
+            .iter()
+            .filter(|instance| filter.matches_instance(&instance.instance_name))
+            .enumerate()
+            .map(|(index, instance)| {
+                TailedLog::new(
+                    &instance.instance_name,
+                    instance_log_path(&data_dir, &instance.instance_name),
+                    color.then_some(PREFIX_COLORS[index % PREFIX_COLORS.len()]),
+                )
+            })
+            .collect();
+        LogStream::start(logs, filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogFilter, TailedLog};
+    use rstest::rstest;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[rstest]
+    fn test_log_filter() {
+        let filter = LogFilter::all();
+        assert!(filter.matches_instance("default_1_1"));
+        assert!(filter.matches_line("anything"));
+
+        let filter = LogFilter::all()
+            .instance("default_1_1")
+            .instance("default_2_1")
+            .contains("ERROR");
+        assert!(filter.matches_instance("default_2_1"));
+        assert!(!filter.matches_instance("default_1_2"));
+        assert!(filter.matches_line("main/103/main E> ERROR: boom"));
+        assert!(!filter.matches_line("main/103/main I> started"));
+    }
+
+    #[rstest]
+    fn test_tailed_log() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("picodata.log");
+        fs::write(&path, "before streaming\n").unwrap();
+
+        let mut log = TailedLog::new("default_1_1", path.clone(), None);
+        assert_eq!(log.prefix, "[default_1_1]");
+        assert!(log.read_lines().is_empty());
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "first\nsecond\nincomp").unwrap();
+        assert_eq!(log.read_lines(), ["first", "second"]);
+        writeln!(file, "lete").unwrap();
+        assert_eq!(log.read_lines(), ["incomplete"]);
+
+        // Truncated log is read from the start.
+        fs::write(&path, "rotated\n").unwrap();
+        assert_eq!(log.read_lines(), ["rotated"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+//! Framing protocol of Lua scripts executed through admin console.
+//!
+//! Admin console output differs between Picodata versions: it may contain
+//! greeting lines, language switch notifications, errors printed as
+//! regular YAML values and so on. To reliably tell user payload apart from
+//! console noise, user script is wrapped into a chunk that evaluates it
+//! in protected mode and returns a single string enclosed in unique
+//! delimiters:
+//!
+//! ```text
+//! <delimiter>ok
+//! <YAML encoded return values><delimiter>
+//! ```
+//!
+//! or, if script has raised an error:
+//!
+//! ```text
+//! <delimiter>error
+//! <error message><delimiter>
+//! ```
+
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Error;
+use uuid::Uuid;
+
+const STATUS_OK: &str = "ok";
+const STATUS_ERROR: &str = "error";
+
+/// Status of executed Lua script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LuaStatus {
+    /// Script has finished successfully.
+    Ok,
+    /// Script has raised an error with the message.
+    Error(String),
+}
+
+/// Unframed result of Lua script execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LuaResponse {
+    pub status: LuaStatus,
+    /// YAML document with values returned by the script.
+    /// Empty if script has raised an error.
+    pub payload: String,
+}
+
+impl LuaResponse {
+    pub fn is_ok(&self) -> bool {
+        self.status == LuaStatus::Ok
+    }
+
+    /// Converts response into payload or error raised by the script.
+    pub fn into_result(self) -> Result<String, Error> {
+        match self.status {
+            LuaStatus::Ok => Ok(self.payload),
+            LuaStatus::Error(message) => Err(Error::other(message)),
+        }
+    }
+
+    /// Converts response into the single value returned by the script.
+    ///
+    /// ### Returns
+    /// - On failure, error raised by the script, or error with the raw
+    ///   payload if script hasn't returned exactly one value or it
+    ///   can't be deserialized into `T`.
+    pub fn into_value<T: DeserializeOwned>(self) -> anyhow::Result<T> {
+        let payload = self.into_result()?;
+        let values: Vec<serde_norway::Value> = serde_norway::from_str(&payload)
+            .with_context(|| format!("Lua output is not a list of values: {payload}"))?;
+        let [value] = <[_; 1]>::try_from(values).map_err(|values| {
+            anyhow!(
+                "Lua script has returned {} values instead of one: {payload}",
+                values.len()
+            )
+        })?;
+        serde_norway::from_value(value).with_context(|| {
+            format!(
+                "failed to deserialize {} from Lua output: {payload}",
+                std::any::type_name::<T>()
+            )
+        })
+    }
+}
+
+/// Single use frame of Lua script identified by unique delimiter.
+#[derive(Debug, Clone)]
+pub struct LuaFrame {
+    delimiter: String,
+}
+
+impl Default for LuaFrame {
+    fn default() -> Self {
+        Self {
+            delimiter: format!("<picotest:{}>", Uuid::new_v4().simple()),
+        }
+    }
+}
+
+impl LuaFrame {
+    /// Wraps script into a single line chunk, which returns framed result.
+    ///
+    /// Script is evaluated as an expression first, so that bare expressions
+    /// like `box.info()` return their value as admin console does.
+    pub fn wrap(&self, script: &str) -> String {
+        let script = lua_string_literal(script);
+        let delimiter = lua_string_literal(&self.delimiter);
+        [
+            "local function pack(...) return {n = select('#', ...), ...} end;",
+            &format!("local code, delimiter = {script}, {delimiter};"),
+            "local fn, err = load('return ' .. code, '=picotest');",
+            "if fn == nil then fn, err = load(code, '=picotest') end;",
+            "local res = fn == nil and pack(false, err) or pack(pcall(fn));",
+            "local body;",
+            "if res[1] then",
+            " local values = {};",
+            " for i = 2, res.n do values[i - 1] = res[i] == nil and box.NULL or res[i] end;",
+            &format!(" body = '{STATUS_OK}\\n' .. require('yaml').encode(values);"),
+            "else",
+            &format!(" body = '{STATUS_ERROR}\\n' .. tostring(res[2]);"),
+            "end;",
+            "return delimiter .. body .. delimiter",
+        ]
+        .concat()
+    }
+
+    /// Extracts framed result from admin console output.
+    pub fn unwrap(&self, output: &str) -> Result<LuaResponse, Error> {
+        let frame = serde_norway::Deserializer::from_str(output)
+            .filter_map(|document| Vec::<String>::deserialize(document).ok())
+            .flatten()
+            .find(|value| value.starts_with(&self.delimiter))
+            .ok_or_else(|| {
+                Error::other(format!("Lua output is not framed as expected: {output}"))
+            })?;
+
+        let body = frame[self.delimiter.len()..]
+            .strip_suffix(&self.delimiter)
+            .ok_or_else(|| Error::other(format!("Lua output frame is not closed: {frame}")))?;
+
+        let (status, payload) = body.split_once('\n').unwrap_or((body, ""));
+        match status {
+            STATUS_OK => Ok(LuaResponse {
+                status: LuaStatus::Ok,
+                payload: payload.to_string(),
+            }),
+            STATUS_ERROR => Ok(LuaResponse {
+                status: LuaStatus::Error(payload.to_string()),
+                payload: String::new(),
+            }),
+            _ => Err(Error::other(format!(
+                "unknown status of Lua output frame: {status}"
+            ))),
+        }
+    }
+}
+
+/// Escapes text into single line double quoted Lua string literal.
+pub(crate) fn lua_string_literal(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '"' => literal.push_str("\\\""),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            c if c.is_ascii_control() => literal.push_str(&format!("\\{:03}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lua_string_literal, LuaFrame, LuaResponse, LuaStatus};
+    use rstest::rstest;
+    use std::collections::BTreeMap;
+
+    #[rstest]
+    #[case::plain("return 1", r#""return 1""#)]
+    #[case::quotes(r#"print("a\b")"#, r#""print(\"a\\b\")""#)]
+    #[case::new_lines("a = 1\r\nb = 2", r#""a = 1\r\nb = 2""#)]
+    #[case::control("\t1", r#""\0091""#)]
+    fn test_lua_string_literal(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(lua_string_literal(text), expected);
+    }
+
+    #[rstest]
+    fn test_wrap_is_single_line() {
+        let frame = LuaFrame::default();
+        let chunk = frame.wrap("local a = 1\nreturn a\n");
+        assert!(!chunk.contains('\n'));
+        assert!(chunk.contains(&frame.delimiter));
+    }
+
+    #[rstest]
+    fn test_unwrap_ok() {
+        let frame = LuaFrame::default();
+        let d = &frame.delimiter;
+        let output = format!("---\n- \"{d}ok\\n---\\n- 2\\n...\\n{d}\"\n...\n");
+
+        let response = frame.unwrap(&output).unwrap();
+        assert_eq!(response.status, LuaStatus::Ok);
+        assert_eq!(response.payload, "---\n- 2\n...\n");
+    }
+
+    #[rstest]
+    fn test_unwrap_block_scalar() {
+        let frame = LuaFrame::default();
+        let d = &frame.delimiter;
+        let output = format!("---\n- |-\n  {d}ok\n  --- []\n  ...\n  {d}\n...\n");
+
+        let response = frame.unwrap(&output).unwrap();
+        assert!(response.is_ok());
+        assert_eq!(response.payload, "--- []\n...\n");
+    }
+
+    #[rstest]
+    fn test_unwrap_error() {
+        let frame = LuaFrame::default();
+        let d = &frame.delimiter;
+        let output = format!("---\n- '{d}error\n\n  picotest:1: boom{d}'\n...\n");
+
+        let response = frame.unwrap(&output).unwrap();
+        assert_eq!(response.status, LuaStatus::Error("picotest:1: boom".into()));
+        assert!(response.into_result().is_err());
+    }
+
+    #[rstest]
+    #[case::no_frame("---\n- error: something went wrong\n...\n")]
+    #[case::foreign_frame("---\n- <picotest:other>ok\n...\n")]
+    #[case::empty("")]
+    fn test_unwrap_missing_frame(#[case] output: &str) {
+        assert!(LuaFrame::default().unwrap(output).is_err());
+    }
+
+    fn response(payload: &str) -> LuaResponse {
+        LuaResponse {
+            status: LuaStatus::Ok,
+            payload: payload.to_string(),
+        }
+    }
+
+    #[rstest]
+    fn test_into_value() {
+        let value: BTreeMap<String, u32> =
+            response("---\n- {a: 1, b: 2}\n...\n").into_value().unwrap();
+        assert_eq!(value, BTreeMap::from([("a".into(), 1), ("b".into(), 2)]));
+
+        let value: Option<u32> = response("---\n- null\n...\n").into_value().unwrap();
+        assert_eq!(value, None);
+    }
+
+    #[rstest]
+    #[case::no_values("--- []\n...\n", "has returned 0 values instead of one: --- []")]
+    #[case::many_values("---\n- 1\n- 2\n...\n", "has returned 2 values instead of one")]
+    #[case::wrong_type("---\n- text\n...\n", "failed to deserialize u32 from Lua output")]
+    fn test_into_value_errors(#[case] payload: &str, #[case] expected: &str) {
+        let err = response(payload).into_value::<u32>().unwrap_err();
+        assert!(err.to_string().contains(expected), "{err:#}");
+    }
+
+    #[rstest]
+    fn test_into_value_script_error() {
+        let response = LuaResponse {
+            status: LuaStatus::Error("picotest:1: boom".to_string()),
+            payload: String::new(),
+        };
+        let err = response.into_value::<u32>().unwrap_err();
+        assert_eq!(err.to_string(), "picotest:1: boom");
+    }
+}
@@ -0,0 +1,288 @@
+//! Lua scripts and helper modules shared by the tests.
+//!
+//! Complex setups may be written in Lua files instead of string literals
+//! embedded into Rust code: scripts are executed by [`Cluster::run_lua_file`],
+//! and modules installed by [`Cluster::install_lua_modules`] are available
+//! to any Lua code of the instances through `require()`.
+//!
+//! Module sources are uploaded through admin console and written into
+//! [`LUA_MODULES_DIR`] of the instance working directory, which is prepended
+//! to `package.path`. So modules are installed into remote and containerized
+//! instances as well. `package.path` isn't persisted, modules must be
+//! installed again if the instance is restarted.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! // tests/lua/fixtures/users.lua
+//! // local M = {}
+//! // function M.create(count) ... end
+//! // return M
+//!
+//! #[picotest]
+//! fn test_users() {
+//!     let modules = LuaModule::from_dir("tests/lua").unwrap();
+//!     cluster.install_lua_modules(&modules).unwrap();
+//!     cluster
+//!         .run_lua("return require('fixtures.users').create(100)")
+//!         .unwrap();
+//!     cluster.run_lua_file("tests/lua/check_users.lua").unwrap();
+//! }
+//! ```
+
+use crate::lua::lua_string_literal;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use log::debug;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+/// Directory of installed modules, relative to the instance working directory.
+pub const LUA_MODULES_DIR: &str = "picotest_lua";
+
+/// Extension of Lua source files.
+const LUA_EXTENSION: &str = "lua";
+
+/// Lua module, which may be installed into the instances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LuaModule {
+    /// Name the module is required by, e.g. `fixtures.users`.
+    pub name: String,
+    /// Source code of the module.
+    pub source: String,
+}
+
+impl LuaModule {
+    /// Creates module `name` from its source code.
+    pub fn new(name: impl Into<String>, source: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Reads module from the file, named after the file, e.g. `users`
+    /// for `tests/lua/users.lua`.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .with_context(|| format!("invalid name of Lua module '{}'", path.display()))?;
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("failed to read Lua module '{}'", path.display()))?;
+        Ok(Self::new(name, source))
+    }
+
+    /// Reads all `*.lua` files of the directory and its subdirectories.
+    ///
+    /// Modules are named after their path relative to `dir` like `require()`
+    /// resolves them: `fixtures/users.lua` is `fixtures.users`, and
+    /// `fixtures/init.lua` is `fixtures`.
+    ///
+    /// ### Returns
+    /// Modules sorted by name.
+    pub fn from_dir(dir: impl AsRef<Path>) -> anyhow::Result<Vec<Self>> {
+        let dir = dir.as_ref();
+        let mut modules = Vec::new();
+        collect_modules(dir, dir, &mut modules)
+            .with_context(|| format!("failed to read Lua modules from '{}'", dir.display()))?;
+        modules.sort_by(|left, right| left.name.cmp(&right.name));
+        Ok(modules)
+    }
+}
+
+fn collect_modules(root: &Path, dir: &Path, modules: &mut Vec<LuaModule>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_modules(root, &path, modules)?;
+            continue;
+        }
+        if path.extension().and_then(|ext| ext.to_str()) != Some(LUA_EXTENSION) {
+            continue;
+        }
+        let mut module = LuaModule::from_file(&path)?;
+        module.name = module_name(&path.strip_prefix(root)?.with_extension(""))?;
+        modules.push(module);
+    }
+    Ok(())
+}
+
+/// Converts path of the module relative to the modules root into its name.
+fn module_name(relative_path: &Path) -> anyhow::Result<String> {
+    let mut components = relative_path
+        .iter()
+        .map(|component| component.to_str())
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("invalid path of Lua module '{}'", relative_path.display()))?;
+    if components.len() > 1 && components.last() == Some(&"init") {
+        components.pop();
+    }
+    if let Some(component) = components.iter().find(|component| component.contains('.')) {
+        bail!(
+            "Lua module path '{}' contains '.' in '{component}', \
+            which can't be resolved by require()",
+            relative_path.display()
+        );
+    }
+    Ok(components.join("."))
+}
+
+/// Creates Lua script writing `modules` into [`LUA_MODULES_DIR`]
+/// and prepending the directory to `package.path`.
+///
+/// Modules are unloaded, so the next `require()` picks up their new version.
+fn install_modules_lua(modules: &[LuaModule]) -> String {
+    let mut script = format!(
+        r#"
+local fio = require("fio")
+local dir = fio.pathjoin(fio.abspath(box.cfg.work_dir or fio.cwd()), "{LUA_MODULES_DIR}")
+local function install(name, source)
+    local path = fio.pathjoin(dir, name:gsub("%.", "/") .. ".lua")
+    local ok, err = fio.mktree(fio.dirname(path))
+    if not ok then
+        error(("failed to create directory of Lua module '%s': %s"):format(name, err), 0)
+    end
+    local file = assert(io.open(path, "w"))
+    file:write(source)
+    file:close()
+    package.loaded[name] = nil
+end
+"#
+    );
+    for module in modules {
+        script.push_str(&format!(
+            "install({}, {})\n",
+            lua_string_literal(&module.name),
+            lua_string_literal(&module.source)
+        ));
+    }
+    script.push_str(
+        r#"local prefix = dir .. "/?.lua;" .. dir .. "/?/init.lua;"
+if package.path:find(prefix, 1, true) == nil then
+    package.path = prefix .. package.path
+end
+return true"#,
+    );
+    script
+}
+
+impl PicotestInstance {
+    /// Executes Lua script from the file, see [`PicotestInstance::run_lua`].
+    ///
+    /// ### Arguments:
+    /// - `path` - path to the script on the host running the tests.
+    pub fn run_lua_file(&self, path: impl AsRef<Path>) -> Result<String, Error> {
+        let path = path.as_ref();
+        let script = fs::read_to_string(path).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("failed to read Lua script '{}': {err}", path.display()),
+            )
+        })?;
+        self.run_lua(script)
+    }
+
+    /// Installs Lua `modules` into the instance, so they may be
+    /// required by Lua code, see [module docs](self).
+    ///
+    /// ### Returns
+    /// - On failure, instance of [`anyhow::Error`], e.g. if module
+    ///   can't be written into the working directory of the instance.
+    pub fn install_lua_modules(&self, modules: &[LuaModule]) -> anyhow::Result<()> {
+        debug!(
+            "Installing Lua modules {:?} into '{}'",
+            modules
+                .iter()
+                .map(|module| &module.name)
+                .collect::<Vec<_>>(),
+            self.instance_name
+        );
+        self.run_lua(install_modules_lua(modules))
+            .with_context(|| {
+                format!(
+                    "failed to install Lua modules into '{}'",
+                    self.instance_name
+                )
+            })?;
+        Ok(())
+    }
+}
+
+impl Cluster {
+    /// Executes Lua script from the file on the main instance.
+    /// See [`PicotestInstance::run_lua_file`].
+    ///
+    /// ### Examples:
+    /// ```rust,ignore
+    /// #[picotest]
+    /// fn test_setup_from_file() {
+    ///     cluster.run_lua_file("tests/lua/create_spaces.lua").unwrap();
+    /// }
+    /// ```
+    pub fn run_lua_file(&self, path: impl AsRef<Path>) -> Result<String, Error> {
+        self.main().run_lua_file(path)
+    }
+
+    /// Installs Lua `modules` into all instances of the cluster.
+    /// See [`PicotestInstance::install_lua_modules`].
+    pub fn install_lua_modules(&self, modules: &[LuaModule]) -> anyhow::Result<()> {
+        for instance in self.instances() {
+            instance.install_lua_modules(modules)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{install_modules_lua, module_name, LuaModule};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::Path;
+
+    #[rstest]
+    #[case::plain("users", "users")]
+    #[case::nested("fixtures/users", "fixtures.users")]
+    #[case::init("fixtures/init", "fixtures")]
+    #[case::root_init("init", "init")]
+    fn test_module_name(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(module_name(Path::new(path)).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_module_name_with_dot() {
+        assert!(module_name(Path::new("v1.2/users")).is_err());
+    }
+
+    #[rstest]
+    fn test_modules_from_dir() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("fixtures")).unwrap();
+        fs::write(dir.join("helpers.lua"), "return {}").unwrap();
+        fs::write(dir.join("fixtures/init.lua"), "return 1").unwrap();
+        fs::write(dir.join("fixtures/users.lua"), "return 2").unwrap();
+        fs::write(dir.join("README.md"), "not a module").unwrap();
+
+        let modules = LuaModule::from_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            modules,
+            vec![
+                LuaModule::new("fixtures", "return 1"),
+                LuaModule::new("fixtures.users", "return 2"),
+                LuaModule::new("helpers", "return {}"),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_install_modules_lua() {
+        let script =
+            install_modules_lua(&[LuaModule::new("fixtures.users", "local M = {}\nreturn M")]);
+        assert!(script.contains(r#"install("fixtures.users", "local M = {}\nreturn M")"#));
+        assert!(script.contains(r#""picotest_lua")"#));
+        assert!(script.contains("package.path = prefix .. package.path"));
+    }
+}
@@ -0,0 +1,531 @@
+//! Typed access to `manifest.yaml` of the built plugin.
+//!
+//! Pike ships the plugin into `target/{profile}/{plugin}/{version}` together with
+//! `manifest.yaml` generated from `manifest.yaml.template`. [`load`] reads it,
+//! so tests can assert on services, their default configuration and
+//! migrations the plugin is actually shipped with.
+//!
+//! [`apply_plugin_configuration`] pre-bakes config into `default_configuration`
+//! of the shipped manifest, as an alternative to [`Cluster::apply_config`]
+//! at runtime. Manifest must be rewritten after the plugin is built and
+//! before the cluster is started, as the plugin is installed from it.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_shipped_manifest() {
+//!     let manifest = cluster.manifest().unwrap();
+//!     assert_eq!(manifest.service("router").unwrap().default_configuration["timeout"], 1);
+//!     assert_eq!(manifest.migrations, ["migrations/0001_init.sql"]);
+//! }
+//! ```
+
+use crate::migration::is_plugin_shipping_dir;
+use crate::plugin_build::BuildProfile;
+use crate::plugin_package::{PluginSource, PACKAGE_TARGET_DIR};
+use crate::{Cluster, PluginConfigMap};
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_norway::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.yaml";
+
+/// What to do with config of the service, which isn't declared by the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownServicePolicy {
+    /// Fail without changing the manifest.
+    #[default]
+    Error,
+    /// Skip config of the service.
+    Ignore,
+}
+
+/// Manifest of the built plugin.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub services: Vec<ServiceManifest>,
+    /// Migration files relative to the shipping directory, in order of apply.
+    #[serde(default, rename = "migration")]
+    pub migrations: Vec<String>,
+    /// Directory the plugin is shipped to, i.e. where manifest is located.
+    #[serde(skip)]
+    pub shipping_dir: PathBuf,
+}
+
+/// Service declared by the plugin manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServiceManifest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub default_configuration: Value,
+}
+
+impl PluginManifest {
+    /// Reads manifest located in the shipping directory of the plugin,
+    /// e.g. `target/debug/my_plugin/0.1.0`.
+    pub fn from_shipping_dir(shipping_dir: &Path) -> anyhow::Result<Self> {
+        let path = shipping_dir.join(MANIFEST_FILE_NAME);
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read plugin manifest '{}'", path.display()))?;
+        let mut manifest = Self::parse(&text)
+            .with_context(|| format!("failed to parse plugin manifest '{}'", path.display()))?;
+        manifest.shipping_dir = shipping_dir.to_path_buf();
+        Ok(manifest)
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(serde_norway::from_str(text)?)
+    }
+
+    /// Returns service of the manifest by its name.
+    pub fn service(&self, name: &str) -> Option<&ServiceManifest> {
+        self.services.iter().find(|service| service.name == name)
+    }
+
+    /// Returns names of the services in order of the manifest.
+    pub fn service_names(&self) -> Vec<&str> {
+        self.services
+            .iter()
+            .map(|service| service.name.as_str())
+            .collect()
+    }
+
+    /// Returns default configuration of every service, which can be
+    /// passed to [`Cluster::apply_config`] to reset the plugin config.
+    pub fn default_config(&self) -> anyhow::Result<PluginConfigMap> {
+        self.services
+            .iter()
+            .map(|service| Ok((service.name.clone(), service.default_config()?)))
+            .collect()
+    }
+
+    /// Returns paths of the migration files in order of apply.
+    pub fn migration_paths(&self) -> Vec<PathBuf> {
+        self.migrations
+            .iter()
+            .map(|migration| self.shipping_dir.join(migration))
+            .collect()
+    }
+}
+
+impl ServiceManifest {
+    /// Returns default configuration of the service as map of its keys.
+    pub fn default_config<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        let config = match &self.default_configuration {
+            Value::Null => Value::Mapping(Mapping::new()),
+            config => config.clone(),
+        };
+        serde_norway::from_value(config).with_context(|| {
+            format!(
+                "failed to deserialize default configuration of service '{}'",
+                self.name
+            )
+        })
+    }
+}
+
+/// Loads manifest of the plugin built in `plugin_path` with the profile
+/// of [`PICOTEST_BUILD_PROFILE`](crate::plugin_build::PICOTEST_BUILD_PROFILE),
+/// see [module docs](self).
+///
+/// ### Returns
+/// - On success, manifest of the latest version of the plugin.
+/// - On failure, instance of [`anyhow::Error`], e.g. if plugin isn't built
+///   or workspace ships several plugins, see [`load_all`].
+///
+/// ### Examples:
+/// ```rust,ignore
+/// let manifest = picotest::manifest::load(Path::new("."))?;
+/// assert_eq!(manifest.service_names(), ["router", "storage"]);
+/// ```
+pub fn load(plugin_path: &Path) -> anyhow::Result<PluginManifest> {
+    let profile_path = BuildProfile::from_env()?.build_path(plugin_path);
+    single_manifest(load_all_from(&profile_path)?, &profile_path)
+}
+
+/// Loads manifests of every plugin built in `plugin_path`,
+/// e.g. of the workspace with several plugins.
+pub fn load_all(plugin_path: &Path) -> anyhow::Result<Vec<PluginManifest>> {
+    load_all_from(&BuildProfile::from_env()?.build_path(plugin_path))
+}
+
+/// Loads manifests of the latest version of every plugin shipped into
+/// the profile build directory, sorted by plugin name.
+fn load_all_from(profile_path: &Path) -> anyhow::Result<Vec<PluginManifest>> {
+    let entries = fs::read_dir(profile_path).with_context(|| {
+        format!(
+            "failed to read plugin build directory '{}'",
+            profile_path.display()
+        )
+    })?;
+    let mut plugin_dirs: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(is_plugin_shipping_dir)
+        .collect();
+    plugin_dirs.sort_by_key(|dir| dir.file_name());
+
+    let mut manifests = Vec::new();
+    for plugin_dir in plugin_dirs {
+        let mut versions: Vec<_> = fs::read_dir(plugin_dir.path())?
+            .filter_map(Result::ok)
+            .filter(|version| version.path().join(MANIFEST_FILE_NAME).is_file())
+            .collect();
+        versions.sort_by_cached_key(|dir| dir.file_name());
+        if let Some(latest_version) = versions.last() {
+            manifests.push(PluginManifest::from_shipping_dir(&latest_version.path())?);
+        }
+    }
+    Ok(manifests)
+}
+
+fn single_manifest(
+    mut manifests: Vec<PluginManifest>,
+    profile_path: &Path,
+) -> anyhow::Result<PluginManifest> {
+    match manifests.len() {
+        0 => bail!(
+            "plugin manifest is not found in '{}', is the plugin built?",
+            profile_path.display()
+        ),
+        1 => Ok(manifests.remove(0)),
+        _ => bail!(
+            "several plugins are shipped into '{}': {:?}, use load_all",
+            profile_path.display(),
+            manifests
+                .iter()
+                .map(|manifest| &manifest.name)
+                .collect::<Vec<_>>()
+        ),
+    }
+}
+
+/// Rewrites `default_configuration` of the services of the built plugin
+/// by `config` and saves the manifest, see [module docs](self).
+///
+/// ### Arguments:
+/// - `plugin_path` - path to the plugin root directory, plugin must be built.
+/// - `config` - config of the services, merged as by [`replace_services_configuration`].
+/// - `policy` - what to do with config of the services missing in the manifest.
+///
+/// ### Returns
+/// - On success, rewritten manifest.
+/// - On failure, instance of [`anyhow::Error`].
+///
+/// ### Examples:
+/// ```rust,ignore
+/// let config: PluginConfigMap = serde_norway::from_str("router: {timeout: 1}")?;
+/// apply_plugin_configuration(plugin_path, &config, UnknownServicePolicy::Error)?;
+/// let cluster = Cluster::new(plugin_path.to_path_buf(), ...)?.run()?;
+/// ```
+pub fn apply_plugin_configuration(
+    plugin_path: &Path,
+    config: &PluginConfigMap,
+    policy: UnknownServicePolicy,
+) -> anyhow::Result<PluginManifest> {
+    let shipping_dir = load(plugin_path)?.shipping_dir;
+    let path = shipping_dir.join(MANIFEST_FILE_NAME);
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read plugin manifest '{}'", path.display()))?;
+    // Manifest is rewritten as a whole document,
+    // so fields unknown to picotest are kept.
+    let mut manifest: Value = serde_norway::from_str(&text)
+        .with_context(|| format!("failed to parse plugin manifest '{}'", path.display()))?;
+    replace_services_configuration(&mut manifest, config, policy)?;
+    fs::write(&path, serde_norway::to_string(&manifest)?)
+        .with_context(|| format!("failed to write plugin manifest '{}'", path.display()))?;
+    PluginManifest::from_shipping_dir(&shipping_dir)
+}
+
+/// Rewrites `default_configuration` of the services of the `manifest` document.
+///
+/// Services are matched by name. Keys of the service config replace keys of
+/// its default configuration, while nested maps are merged key by key, so
+/// keys missing in `config` keep their default values. Manifest isn't
+/// changed if it fails.
+pub fn replace_services_configuration(
+    manifest: &mut Value,
+    config: &PluginConfigMap,
+    policy: UnknownServicePolicy,
+) -> anyhow::Result<()> {
+    let Some(services) = manifest.get_mut("services") else {
+        return handle_unknown_services(config.keys(), policy);
+    };
+    let Value::Sequence(services) = services else {
+        bail!("services of the manifest must be a sequence, got: {services:?}");
+    };
+
+    let unknown = config.keys().filter(|name| {
+        !services
+            .iter()
+            .any(|service| service.get("name").and_then(Value::as_str) == Some(name.as_str()))
+    });
+    handle_unknown_services(unknown, policy)?;
+
+    for service in services.iter_mut() {
+        let Some(service_config) = service
+            .get("name")
+            .and_then(Value::as_str)
+            .and_then(|name| config.get(name))
+        else {
+            continue;
+        };
+        let Value::Mapping(service) = service else {
+            continue;
+        };
+        let defaults = service
+            .entry(Value::from("default_configuration"))
+            .or_insert(Value::Null);
+        let patch = service_config
+            .iter()
+            .map(|(key, value)| (Value::from(key.as_str()), value.clone()))
+            .collect();
+        merge_value(defaults, Value::Mapping(patch));
+    }
+    Ok(())
+}
+
+fn handle_unknown_services<'a>(
+    mut unknown: impl Iterator<Item = &'a String>,
+    policy: UnknownServicePolicy,
+) -> anyhow::Result<()> {
+    match (unknown.next(), policy) {
+        (Some(service), UnknownServicePolicy::Error) => {
+            bail!("service '{service}' is not declared by the plugin manifest")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Merges `patch` into `target`: mappings are merged key by key,
+/// other values are replaced.
+fn merge_value(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Mapping(target), Value::Mapping(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(&key) {
+                    Some(current) => merge_value(current, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+impl Cluster {
+    /// Returns manifest of the plugin the cluster is started with,
+    /// either from the build or from the unpacked package.
+    pub fn manifest(&self) -> anyhow::Result<PluginManifest> {
+        let profile_path = self.profile_path();
+        single_manifest(load_all_from(&profile_path)?, &profile_path)
+    }
+
+    /// Returns manifests of every plugin the cluster is started with.
+    pub fn manifests(&self) -> anyhow::Result<Vec<PluginManifest>> {
+        load_all_from(&self.profile_path())
+    }
+
+    fn profile_path(&self) -> PathBuf {
+        let target_dir = match self.install_from {
+            PluginSource::Build => self.plugin_path.join("target"),
+            PluginSource::Package => self.plugin_path.join(PACKAGE_TARGET_DIR),
+        };
+        target_dir.join(self.build_profile.dir_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_plugin_configuration, load, load_all, replace_services_configuration, PluginManifest,
+        UnknownServicePolicy, MANIFEST_FILE_NAME,
+    };
+    use crate::PluginConfigMap;
+    use rstest::rstest;
+    use serde_norway::Value;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    const MANIFEST: &str = r#"
+description: Test plugin
+name: test_plugin
+version: 0.2.0
+services:
+  - name: router
+    description: Routes requests
+    default_configuration:
+      timeout: 1
+      endpoint: /api
+  - name: storage
+    description: Stores data
+    default_configuration:
+migration:
+  - migrations/0001_init.sql
+  - migrations/0002_index.sql
+"#;
+
+    fn ship(plugin_path: &Path, name: &str, version: &str, manifest: &str) {
+        let shipping_dir = plugin_path
+            .join("target")
+            .join("debug")
+            .join(name)
+            .join(version);
+        fs::create_dir_all(&shipping_dir).unwrap();
+        fs::write(shipping_dir.join(MANIFEST_FILE_NAME), manifest).unwrap();
+    }
+
+    #[rstest]
+    fn test_parse_manifest() {
+        let manifest = PluginManifest::parse(MANIFEST).unwrap();
+        assert_eq!(manifest.name, "test_plugin");
+        assert_eq!(manifest.version, "0.2.0");
+        assert_eq!(manifest.service_names(), ["router", "storage"]);
+        assert_eq!(
+            manifest.migrations,
+            ["migrations/0001_init.sql", "migrations/0002_index.sql"]
+        );
+
+        let router = manifest.service("router").unwrap();
+        assert_eq!(router.default_configuration["timeout"], 1);
+
+        let config = manifest.default_config().unwrap();
+        assert_eq!(config["router"]["endpoint"], Value::from("/api"));
+        assert!(config["storage"].is_empty());
+
+        let storage: HashMap<String, u32> = manifest
+            .service("storage")
+            .unwrap()
+            .default_config()
+            .unwrap();
+        assert!(storage.is_empty());
+    }
+
+    #[rstest]
+    fn test_load_latest_version() {
+        let plugin_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        ship(
+            &plugin_path,
+            "test_plugin",
+            "0.1.0",
+            "name: test_plugin\nversion: 0.1.0",
+        );
+        ship(&plugin_path, "test_plugin", "0.2.0", MANIFEST);
+        fs::create_dir_all(plugin_path.join("target").join("debug").join("deps")).unwrap();
+
+        let manifest = load(&plugin_path).unwrap();
+        assert_eq!(manifest.version, "0.2.0");
+        assert_eq!(
+            manifest.migration_paths()[0],
+            plugin_path.join("target/debug/test_plugin/0.2.0/migrations/0001_init.sql")
+        );
+
+        ship(
+            &plugin_path,
+            "other_plugin",
+            "0.1.0",
+            "name: other_plugin\nversion: 0.1.0",
+        );
+        assert!(load(&plugin_path).is_err());
+        let names: Vec<String> = load_all(&plugin_path)
+            .unwrap()
+            .into_iter()
+            .map(|manifest| manifest.name)
+            .collect();
+        assert_eq!(names, ["other_plugin", "test_plugin"]);
+
+        fs::remove_dir_all(plugin_path).unwrap();
+    }
+
+    fn config(yaml: &str) -> PluginConfigMap {
+        serde_norway::from_str(yaml).unwrap()
+    }
+
+    #[rstest]
+    fn test_replace_services_configuration() {
+        let mut manifest: Value = serde_norway::from_str(
+            r#"
+services:
+  - name: router
+    default_configuration:
+      timeout: 1
+      limits: {rps: 10, burst: 20}
+  - name: storage
+    default_configuration:
+extra: kept
+"#,
+        )
+        .unwrap();
+
+        replace_services_configuration(
+            &mut manifest,
+            &config("router: {timeout: 5, limits: {rps: 100}}\nstorage: {path: /data}"),
+            UnknownServicePolicy::Error,
+        )
+        .unwrap();
+
+        let router = &manifest["services"][0]["default_configuration"];
+        assert_eq!(router["timeout"], 5);
+        assert_eq!(router["limits"]["rps"], 100);
+        assert_eq!(router["limits"]["burst"], 20);
+        let storage = &manifest["services"][1]["default_configuration"];
+        assert_eq!(storage["path"], "/data");
+        assert_eq!(manifest["extra"], "kept");
+    }
+
+    #[rstest]
+    fn test_unknown_service_policy() {
+        let original: Value =
+            serde_norway::from_str("services: [{name: router, default_configuration: {a: 1}}]")
+                .unwrap();
+        let config = config("router: {a: 2}\ncache: {size: 1}");
+
+        let mut manifest = original.clone();
+        let err =
+            replace_services_configuration(&mut manifest, &config, Default::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "service 'cache' is not declared by the plugin manifest"
+        );
+        assert_eq!(manifest, original);
+
+        replace_services_configuration(&mut manifest, &config, UnknownServicePolicy::Ignore)
+            .unwrap();
+        assert_eq!(manifest["services"][0]["default_configuration"]["a"], 2);
+    }
+
+    #[rstest]
+    fn test_apply_plugin_configuration() {
+        let plugin_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        ship(&plugin_path, "test_plugin", "0.2.0", MANIFEST);
+
+        let manifest = apply_plugin_configuration(
+            &plugin_path,
+            &config("storage: {size: 2}"),
+            UnknownServicePolicy::Error,
+        )
+        .unwrap();
+        assert_eq!(manifest, load(&plugin_path).unwrap());
+        assert_eq!(manifest.migrations.len(), 2);
+        let storage: HashMap<String, u32> = manifest
+            .service("storage")
+            .unwrap()
+            .default_config()
+            .unwrap();
+        assert_eq!(storage, HashMap::from([("size".to_string(), 2)]));
+
+        fs::remove_dir_all(plugin_path).unwrap();
+    }
+}
@@ -1,15 +1,29 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display};
 use std::fs::{read_dir, read_to_string};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Error};
+use log::debug;
 use pike::cluster::MigrationContextVar;
 
+use crate::introspection::eval_lua_yaml;
+use crate::lua::lua_string_literal;
+use crate::{Cluster, PicotestInstance};
+
+/// Default timeout of waiting until plugin migrations are applied.
+pub const DEFAULT_MIGRATIONS_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MIGRATIONS_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 #[derive(Debug, Clone)]
 pub struct Migrations {
     sequence: Vec<Migration>,
+    warnings: Vec<MigrationWarning>,
 }
 
 impl Deref for Migrations {
@@ -21,13 +35,68 @@ impl Deref for Migrations {
 
 impl Migrations {
     pub fn from_unsorted(mut migrations: Vec<Migration>) -> Self {
-        migrations.sort_by(|a, b| a.version.cmp(&b.version));
+        migrations.sort_by_key(|migration| migration.version);
         Self {
             sequence: migrations,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Combines migrations of several plugins into a single sequence sorted by version.
+    /// Migrations of the same version keep their order, `self` goes first.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// let migrations = parse_migrations("plugin_a/migrations")?
+    ///     .merge(parse_migrations("plugin_b/migrations")?);
+    /// ```
+    pub fn merge(mut self, other: Migrations) -> Self {
+        self.sequence.extend(other.sequence);
+        self.sequence.sort_by_key(|migration| migration.version);
+        self.warnings.extend(other.warnings);
+        self
+    }
+
+    /// Returns recoverable issues found while parsing migrations, e.g. skipped files.
+    pub fn warnings(&self) -> &[MigrationWarning] {
+        &self.warnings
+    }
+}
+
+/// Recoverable issue found while parsing migrations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationWarning {
+    pub path: PathBuf,
+    /// Line number (counted from 1), if issue relates to a particular line.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Display for MigrationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.path.display(), self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
         }
     }
 }
 
+/// Options of [`parse_migrations_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Skip files without `.sql` extension (and directories) with
+    /// a warning instead of failing.
+    pub skip_non_sql: bool,
+}
+
+impl ParseOptions {
+    pub fn skip_non_sql(mut self, is_enabled: bool) -> Self {
+        self.skip_non_sql = is_enabled;
+        self
+    }
+}
+
 pub type MigrationVersion = u32;
 
 #[derive(Debug, Clone)]
@@ -40,6 +109,92 @@ pub struct Migration {
 }
 
 impl Migration {
+    /// Creates migration with empty UP and DOWN parts.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// let migration = Migration::new(1, "init")
+    ///     .up(["CREATE TABLE users (id INT PRIMARY KEY, name TEXT);"])
+    ///     .down(["DROP TABLE users;"]);
+    /// let migrations = Migrations::from_unsorted(vec![migration]);
+    /// ```
+    pub fn new(version: MigrationVersion, name: impl ToString) -> Self {
+        Self::from_parts(version, name.to_string(), Vec::new(), Vec::new())
+    }
+
+    /// Replaces statements of UP part of the migration.
+    pub fn up<I>(self, statements: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let up = statements
+            .into_iter()
+            .map(MigrationStatement::new)
+            .collect();
+        let down = self.down_part();
+        Self::from_parts(self.version, self.name, up, down)
+    }
+
+    /// Replaces statements of DOWN part of the migration.
+    pub fn down<I>(self, statements: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let up = self.up_part();
+        let down = statements
+            .into_iter()
+            .map(MigrationStatement::new)
+            .collect();
+        Self::from_parts(self.version, self.name, up, down)
+    }
+
+    /// Lays out statements like in migration file, i.e. each part follows its marker.
+    fn from_parts(
+        version: MigrationVersion,
+        name: String,
+        up: Vec<MigrationStatement>,
+        down: Vec<MigrationStatement>,
+    ) -> Self {
+        let down_start = up.len() + 1;
+        let mut statements = Vec::with_capacity(up.len() + down.len() + 2);
+        statements.push(MigrationStatement::new("-- pico.UP"));
+        statements.extend(up);
+        statements.push(MigrationStatement::new("-- pico.DOWN"));
+        statements.extend(down);
+        Self {
+            version,
+            name,
+            up_range: (0, down_start),
+            down_range: (down_start, statements.len()),
+            statements,
+        }
+    }
+
+    /// Returns statements of UP part without the marker.
+    fn up_part(&self) -> Vec<MigrationStatement> {
+        self.up_statements()
+            .iter()
+            .filter(|statement| !statement.is_pico_up())
+            .cloned()
+            .collect()
+    }
+
+    /// Returns statements of DOWN part without the marker.
+    fn down_part(&self) -> Vec<MigrationStatement> {
+        self.down_statements()
+            .iter()
+            .filter(|statement| !statement.is_pico_down())
+            .cloned()
+            .collect()
+    }
+
+    pub fn version(&self) -> MigrationVersion {
+        self.version
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -87,6 +242,31 @@ impl MigrationStatement {
         self.original_text.starts_with("-- pico.DOWN")
     }
 
+    /// Returns statement text with `@_plugin_config.<name>` references
+    /// replaced by values of migration context variables.
+    /// Unknown references are kept as is.
+    pub fn text_with_context(&self, context: &[MigrationContextVar]) -> String {
+        let pattern = "@_plugin_config.";
+        let mut output = String::with_capacity(self.original_text.len());
+        let mut rest = self.original_text.as_str();
+        while let Some(idx) = rest.find(pattern) {
+            let (before, reference) = rest.split_at(idx);
+            let name_len = reference[pattern.len()..]
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(reference.len() - pattern.len());
+            let (reference, after) = reference.split_at(pattern.len() + name_len);
+            let name = &reference[pattern.len()..];
+            output.push_str(before);
+            match context.iter().find(|var| var.name == name) {
+                Some(var) => output.push_str(&var.value),
+                None => output.push_str(reference),
+            }
+            rest = after;
+        }
+        output.push_str(rest);
+        output
+    }
+
     pub fn extract_tier_variables(&self) -> Vec<String> {
         // returns true, if character can not belong to identifier
         fn is_not_identifier_char(c: char) -> bool {
@@ -168,11 +348,25 @@ pub fn parse_migration_text<S>(sql_text: S) -> Result<Vec<MigrationStatement>, E
 where
     S: AsRef<str>,
 {
-    let sql_text = sql_text.as_ref();
+    parse_statements(sql_text.as_ref()).map(|(statements, _warnings)| statements)
+}
+
+/// Recoverable issue of migration text along with number of the line it relates to.
+type LineWarning = (Option<usize>, String);
+
+/// Splits migration text into statements.
+///
+/// Returns statements and recoverable issues along with numbers of lines they relate to.
+fn parse_statements(sql_text: &str) -> Result<(Vec<MigrationStatement>, Vec<LineWarning>), Error> {
     let mut output = Vec::with_capacity(sql_text.matches('\n').count());
-    let mut acc = None;
+    let mut warnings = Vec::new();
+    // Text of the statement being built and number of its first line.
+    let mut acc: Option<(String, usize)> = None;
+    let mut up_marker_line = None;
+    let mut down_marker_line = None;
 
-    for line in sql_text.lines() {
+    for (line_idx, line) in sql_text.lines().enumerate() {
+        let line_number = line_idx + 1;
         // skip empty lines
         let line = line.trim();
         if line.is_empty() {
@@ -182,24 +376,55 @@ where
         if line.starts_with("--") {
             // ignore if currently building a statement
             if acc.is_none() {
-                output.push(MigrationStatement::new(line));
+                let statement = MigrationStatement::new(line);
+                let marker_line = if statement.is_pico_up() {
+                    Some(&mut up_marker_line)
+                } else if statement.is_pico_down() {
+                    Some(&mut down_marker_line)
+                } else {
+                    None
+                };
+                if let Some(marker_line) = marker_line {
+                    if let Some(first_line) = marker_line.replace(line_number) {
+                        warnings.push((
+                            Some(line_number),
+                            format!("duplicate '{line}' marker, first one is at line {first_line}"),
+                        ));
+                    }
+                }
+                output.push(statement);
                 continue;
             }
         }
         // append and insert statement text
-        if let Some(acc_string) = acc.take() {
-            acc = Some(acc_string + " " + line)
-        } else {
-            acc = Some(String::from(line));
+        match acc.as_mut() {
+            Some((acc_string, _)) => {
+                acc_string.push(' ');
+                acc_string.push_str(line);
+            }
+            None => acc = Some((String::from(line), line_number)),
         }
         // statement was not finished, continue building
         if !line.ends_with(';') {
             continue;
         }
-        let acc_string = acc.take().unwrap();
+        let (acc_string, first_line) = acc.take().unwrap();
+        if up_marker_line.is_none() && down_marker_line.is_none() {
+            warnings.push((
+                Some(first_line),
+                format!("statement precedes '-- pico.UP' marker: {acc_string}"),
+            ));
+        }
         output.push(MigrationStatement::new(acc_string));
     }
-    Ok(output)
+
+    if let Some((acc_string, first_line)) = acc {
+        bail!("line {first_line}: statement is not terminated with ';': {acc_string}");
+    }
+    if up_marker_line.is_none() {
+        warnings.push((None, "'-- pico.UP' marker is missing".to_string()));
+    }
+    Ok((output, warnings))
 }
 
 /// Extract indexes [a,b), where starts and ends migrations by type.
@@ -223,36 +448,110 @@ fn extract_up_down_ranges(
     ))
 }
 
-fn parse_migration_file<P>(path: P) -> Result<Migration, Error>
-where
-    P: AsRef<Path>,
-{
-    let path = path.as_ref();
-    let file_content = read_to_string(path)?;
-    let (version, name) = parse_migration_file_name(path)?;
-    let statements = parse_migration_text(&file_content)?;
+fn parse_migration_file(path: &Path) -> Result<(Migration, Vec<MigrationWarning>), Error> {
+    let file_content = read_to_string(path)
+        .with_context(|| format!("failed to read migration file '{}'", path.display()))?;
+    let (version, name) = parse_migration_file_name(path)
+        .with_context(|| format!("invalid migration file name '{}'", path.display()))?;
+    let (statements, warnings) = parse_statements(&file_content)
+        .with_context(|| format!("failed to parse migration file '{}'", path.display()))?;
     let (up_range, down_range) = extract_up_down_ranges(&statements)?;
-    Ok(Migration {
+    let warnings = warnings
+        .into_iter()
+        .map(|(line, message)| MigrationWarning {
+            path: path.to_path_buf(),
+            line,
+            message,
+        })
+        .collect();
+    let migration = Migration {
         version,
         name,
         statements,
         up_range,
         down_range,
-    })
+    };
+    Ok((migration, warnings))
 }
 
+/// Returns `true` if `path` is a file with `.sql` extension.
+fn is_sql_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("sql"))
+}
+
+/// Parses migrations of the plugin located in `migrations_dir`.
+/// Every entry of the directory must be a migration file.
 pub fn parse_migrations<P>(migrations_dir: P) -> Result<Migrations, Error>
+where
+    P: AsRef<Path>,
+{
+    parse_migrations_with_options(migrations_dir, &ParseOptions::default())
+}
+
+/// Parses migrations of the plugin located in `migrations_dir`.
+///
+/// ### Returns
+///
+/// - On success, migrations sorted by version. Recoverable issues,
+///   e.g. skipped files or duplicate versions, are reported by [`Migrations::warnings`].
+/// - On failure, instance of [`anyhow::Error`] with path of the file
+///   and number of the line failed to parse.
+pub fn parse_migrations_with_options<P>(
+    migrations_dir: P,
+    options: &ParseOptions,
+) -> Result<Migrations, Error>
 where
     P: AsRef<Path>,
 {
     let path = migrations_dir.as_ref();
-    let dir = std::fs::read_dir(path).context("migration directory can not be read")?;
-    let entries = dir.map(Result::unwrap).collect::<Vec<_>>();
+    let mut entries = read_dir(path)
+        .and_then(|dir| dir.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("migration directory '{}' can not be read", path.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
     let mut migrations = Vec::with_capacity(entries.len());
+    let mut warnings = Vec::new();
     for entry in entries {
-        migrations.push(parse_migration_file(entry.path())?);
+        let entry_path = entry.path();
+        if options.skip_non_sql && !is_sql_file(&entry_path) {
+            warnings.push(MigrationWarning {
+                path: entry_path,
+                line: None,
+                message: "not a migration file, skipped".to_string(),
+            });
+            continue;
+        }
+        let (migration, file_warnings) = parse_migration_file(&entry_path)?;
+        migrations.push(migration);
+        warnings.extend(file_warnings);
     }
-    Ok(Migrations::from_unsorted(migrations))
+
+    let mut migrations = Migrations::from_unsorted(migrations);
+    for pair in migrations.windows(2) {
+        if pair[0].version == pair[1].version {
+            warnings.push(MigrationWarning {
+                path: path.to_path_buf(),
+                line: None,
+                message: format!(
+                    "migrations '{}' and '{}' have the same version {}",
+                    pair[0].name, pair[1].name, pair[0].version
+                ),
+            });
+        }
+    }
+    migrations.warnings = warnings;
+    Ok(migrations)
+}
+
+/// Returns `true` if entry of profile build directory is a directory
+/// where plugin is shipped to, i.e. not an auxiliary cargo directory.
+pub(crate) fn is_plugin_shipping_dir(dir: &std::fs::DirEntry) -> bool {
+    let blacklist = ["build", "deps", "examples", "incremental", ".fingerprint"];
+    !blacklist.contains(&dir.file_name().to_string_lossy().as_ref())
+        && dir.file_type().is_ok_and(|t| t.is_dir())
 }
 
 /// Tries to locate all directories with plugin migrations in given profile build
@@ -260,16 +559,13 @@ pub fn find_migrations_directories<P>(target_dir: P) -> Result<Vec<(String, Path
 where
     P: AsRef<Path>,
 {
-    fn allowlisted_dir_name(dir: &std::fs::DirEntry) -> bool {
-        let blacklist = ["build", "deps", "examples", "incremental", ".fingerprint"];
-        !blacklist.contains(&dir.file_name().to_string_lossy().as_ref())
-            && dir.file_type().is_ok_and(|t| t.is_dir())
-    }
-
     let mut output = Vec::new();
     let entries = read_dir(target_dir.as_ref())
         .context("reading plugin target directory for migrations search")?;
-    for plugin_entry in entries.filter_map(Result::ok).filter(allowlisted_dir_name) {
+    for plugin_entry in entries
+        .filter_map(Result::ok)
+        .filter(is_plugin_shipping_dir)
+    {
         let plugin_name = plugin_entry.file_name().to_string_lossy().into_owned();
         let plugin_shipping_path = plugin_entry.path();
         let plugin_dir = read_dir(&plugin_shipping_path).with_context(|| {
@@ -310,15 +606,240 @@ pub fn make_ddl_tier_overrides(
     output
 }
 
+/// Row of `_pico_plugin_migration` system table.
+type PluginMigrationRow = (String, String, String);
+
+/// Migration recorded as applied in `_pico_plugin_migration`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+    pub version: MigrationVersion,
+    pub name: String,
+    /// Migration file as recorded by picodata, relative to the plugin directory.
+    pub file: String,
+    /// Hash of migration file content.
+    pub hash: String,
+}
+
+impl TryFrom<PluginMigrationRow> for AppliedMigration {
+    type Error = Error;
+
+    fn try_from((_plugin, file, hash): PluginMigrationRow) -> Result<Self, Self::Error> {
+        let (version, name) = parse_migration_file_name(&file)
+            .with_context(|| format!("failed to parse applied migration '{file}'"))?;
+        Ok(Self {
+            version,
+            name,
+            file,
+            hash,
+        })
+    }
+}
+
+/// Returns `true` if migration of `min_version` or a later one is applied.
+/// Picodata applies migrations in order, so earlier ones are applied too.
+fn is_applied(migrations: &[AppliedMigration], min_version: MigrationVersion) -> bool {
+    migrations
+        .iter()
+        .any(|migration| migration.version >= min_version)
+}
+
+/// Lists tables and indexes of the cluster schema.
+const SCHEMA_OBJECTS_EXPRESSION: &str = "(function() \
+    local objects = {}; \
+    for _, t in box.space._pico_table:pairs() do table.insert(objects, 'table ' .. t.name) end; \
+    for _, i in box.space._pico_index:pairs() do table.insert(objects, 'index ' .. i.name) end; \
+    table.sort(objects); \
+    return objects; \
+end)()";
+
+/// Returns human-readable difference of schema objects.
+fn schema_diff(expected: &[String], actual: &[String]) -> String {
+    let missing: Vec<&String> = expected.iter().filter(|o| !actual.contains(o)).collect();
+    let extra: Vec<&String> = actual.iter().filter(|o| !expected.contains(o)).collect();
+    format!("missing {missing:?}, unexpected {extra:?}")
+}
+
+impl PicotestInstance {
+    /// Returns migrations of the plugin applied as seen by the instance, sorted by version.
+    pub fn applied_migrations(&self, plugin_name: &str) -> anyhow::Result<Vec<AppliedMigration>> {
+        let rows: Vec<PluginMigrationRow> = eval_lua_yaml(
+            self,
+            &format!(
+                "box.space._pico_plugin_migration:select({{{}}})",
+                lua_string_literal(plugin_name)
+            ),
+        )?;
+
+        let mut migrations = rows
+            .into_iter()
+            .map(AppliedMigration::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        migrations.sort_by_key(|migration| migration.version);
+        Ok(migrations)
+    }
+}
+
+impl Cluster {
+    /// Checks that DOWN part of every migration cleanly reverses its UP part.
+    ///
+    /// Migrations are applied by SQL on the main instance one by one: UP, then DOWN,
+    /// then UP again. Schema (tables and indexes) after DOWN must be the same as
+    /// before UP, and UP must succeed again and produce the same schema.
+    ///
+    /// Cluster must not have the plugin installed, so it's supposed to be a scratch one.
+    ///
+    /// ### Arguments:
+    ///
+    /// - `migrations` - parsed migrations of the plugin, see [`parse_migrations`].
+    /// - `context` - migration context variables substituted into statements.
+    ///
+    /// ### Returns
+    ///
+    /// - On success, unit type.
+    /// - On failure, instance of [`anyhow::Error`] with the failed statement
+    ///   or the schema difference left by migration.
+    pub fn verify_migrations(
+        &self,
+        migrations: &Migrations,
+        context: &[MigrationContextVar],
+    ) -> anyhow::Result<()> {
+        let apply = |migration: &Migration, part: &str, statements: &[MigrationStatement]| {
+            for statement in statements.iter().filter(|s| !s.is_line_comment()) {
+                let query = statement.text_with_context(context);
+                self.sql_query(&query, &[]).with_context(|| {
+                    format!(
+                        "statement of migration {:04}_{} {part} has failed: {query}",
+                        migration.version, migration.name
+                    )
+                })?;
+            }
+            anyhow::Ok(())
+        };
+        let schema = || -> anyhow::Result<Vec<String>> {
+            eval_lua_yaml(self.main(), SCHEMA_OBJECTS_EXPRESSION)
+        };
+
+        for migration in migrations.iter() {
+            debug!(
+                "Verifying migration {:04}_{}",
+                migration.version, migration.name
+            );
+            let before = schema()?;
+            apply(migration, "UP", migration.up_statements())?;
+            let after_up = schema()?;
+
+            apply(migration, "DOWN", migration.down_statements())?;
+            let after_down = schema()?;
+            if after_down != before {
+                bail!(
+                    "DOWN of migration {:04}_{} doesn't reverse UP: {}",
+                    migration.version,
+                    migration.name,
+                    schema_diff(&before, &after_down)
+                );
+            }
+
+            apply(migration, "UP (repeated)", migration.up_statements())?;
+            let after_repeated_up = schema()?;
+            if after_repeated_up != after_up {
+                bail!(
+                    "UP of migration {:04}_{} repeated after DOWN changes schema differently: {}",
+                    migration.version,
+                    migration.name,
+                    schema_diff(&after_up, &after_repeated_up)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns migrations of the plugin applied as seen by the main instance, sorted by version.
+    pub fn applied_migrations(&self, plugin_name: &str) -> anyhow::Result<Vec<AppliedMigration>> {
+        self.main().applied_migrations(plugin_name)
+    }
+
+    /// Waits until migrations of the plugin up to `min_version` are applied
+    /// as seen by every cluster instance.
+    ///
+    /// ### Arguments:
+    ///
+    /// - `plugin_name` - name of the plugin which migrations are awaited.
+    /// - `min_version` - version of the migration, i.e. numeric prefix of its file name.
+    /// - `timeout` - how long to wait for migrations to be applied.
+    ///   See [`DEFAULT_MIGRATIONS_TIMEOUT`].
+    ///
+    /// ### Returns
+    ///
+    /// - On success, applied migrations as seen by the main instance.
+    /// - On failure, instance of [`anyhow::Error`]. Timeout is a failure too.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    ///
+    /// #[picotest]
+    /// fn test_users_table() {
+    ///     cluster
+    ///         .wait_migrations_applied("plugin", 2, DEFAULT_MIGRATIONS_TIMEOUT)
+    ///         .expect("Migrations should be applied");
+    ///     cluster.run_sql("SELECT * FROM users").unwrap();
+    /// }
+    /// ```
+    pub fn wait_migrations_applied(
+        &self,
+        plugin_name: &str,
+        min_version: MigrationVersion,
+        timeout: Duration,
+    ) -> anyhow::Result<Vec<AppliedMigration>> {
+        let mut progress =
+            self.progress(format!("migration {min_version} of plugin '{plugin_name}'"));
+        let start_time = Instant::now();
+        let mut pending: Vec<&PicotestInstance> = self.instances().iter().collect();
+        loop {
+            let mut still_pending = Vec::with_capacity(pending.len());
+            for instance in pending {
+                let applied = instance.applied_migrations(plugin_name)?;
+                if !is_applied(&applied, min_version) {
+                    still_pending.push(instance);
+                }
+            }
+            pending = still_pending;
+            progress.report(
+                pending
+                    .iter()
+                    .map(|instance| instance.instance_name.clone())
+                    .collect(),
+            );
+
+            if pending.is_empty() {
+                return self.applied_migrations(plugin_name);
+            }
+
+            if start_time.elapsed() > timeout {
+                let names: Vec<&str> = pending.iter().map(|i| i.instance_name.as_str()).collect();
+                bail!(
+                    "migration {min_version} of plugin '{plugin_name}' is not applied on {names:?} within {}s",
+                    timeout.as_secs_f64()
+                );
+            }
+            thread::sleep(MIGRATIONS_POLL_INTERVAL);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::ffi::OsStr;
 
+    use pike::cluster::MigrationContextVar;
     use rstest::rstest;
 
     use crate::migration::make_ddl_tier_overrides;
 
     use super::{extract_up_down_ranges, parse_migration_file_name, parse_migration_text};
+    use super::{is_applied, schema_diff, AppliedMigration, PluginMigrationRow};
+    use super::{parse_migrations, parse_migrations_with_options, parse_statements, ParseOptions};
     use super::{Migration, MigrationStatement, Migrations};
 
     #[rstest]
@@ -505,4 +1026,192 @@ mod test {
         assert_eq!(ctx_vars[1].name, "router");
         assert_eq!(ctx_vars[1].value, "default");
     }
+
+    #[rstest]
+    fn migration_applied_from_rows() {
+        let output = r#"---
+- - [plugin, migrations/0002_users.sql, 5d41402abc4b2a76]
+  - [plugin, migrations/0001_init.sql, 7d793037a0760186]
+...
+"#;
+        let rows: Vec<Vec<PluginMigrationRow>> = serde_norway::from_str(output).unwrap();
+        let applied = rows[0]
+            .iter()
+            .cloned()
+            .map(AppliedMigration::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(applied[0].version, 2);
+        assert_eq!(applied[0].name, "users");
+        assert_eq!(applied[1].file, "migrations/0001_init.sql");
+        assert!(is_applied(&applied, 1));
+        assert!(is_applied(&applied, 2));
+        assert!(!is_applied(&applied, 3));
+        assert!(!is_applied(&[], 1));
+
+        let invalid: PluginMigrationRow = ("plugin".into(), "init.sql".into(), "".into());
+        assert!(AppliedMigration::try_from(invalid).is_err());
+    }
+
+    #[rstest]
+    fn migration_statement_text_with_context() {
+        let statement = MigrationStatement::new(
+            "CREATE TABLE t (id INT) IN TIER @_plugin_config.storage_tier; -- @_plugin_config.storage",
+        );
+        let context = vec![
+            MigrationContextVar {
+                name: "storage".into(),
+                value: "wrong".into(),
+            },
+            MigrationContextVar {
+                name: "storage_tier".into(),
+                value: "default".into(),
+            },
+        ];
+        assert_eq!(
+            statement.text_with_context(&context[1..]),
+            "CREATE TABLE t (id INT) IN TIER default; -- @_plugin_config.storage"
+        );
+        assert_eq!(
+            statement.text_with_context(&context),
+            "CREATE TABLE t (id INT) IN TIER default; -- wrong"
+        );
+    }
+
+    #[rstest]
+    fn migration_schema_diff() {
+        let before = vec!["table _pico_table".to_string()];
+        let after = vec!["table _pico_table".to_string(), "table users".to_string()];
+        assert_eq!(
+            schema_diff(&before, &after),
+            r#"missing [], unexpected ["table users"]"#
+        );
+        assert_eq!(
+            schema_diff(&after, &before),
+            r#"missing ["table users"], unexpected []"#
+        );
+    }
+
+    #[rstest]
+    fn migration_parse_warnings() {
+        let text = r#"
+        CREATE TABLE early (id INT);
+        -- pico.UP
+        CREATE TABLE t (id INT);
+        -- pico.DOWN
+        DROP TABLE t;
+        -- pico.DOWN
+        "#;
+        let (statements, warnings) = parse_statements(text).unwrap();
+        assert_eq!(statements.len(), 6);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].0, Some(2));
+        assert!(warnings[0].1.contains("CREATE TABLE early"));
+        assert_eq!(warnings[1].0, Some(7));
+        assert!(warnings[1].1.contains("first one is at line 5"));
+
+        let (_, warnings) = parse_statements("-- pico.DOWN\nDROP TABLE t;").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0], (None, "'-- pico.UP' marker is missing".into()));
+    }
+
+    #[rstest]
+    fn migration_parse_unterminated_statement() {
+        let text = "-- pico.UP\nCREATE TABLE t (\n    id INT\n)\n-- pico.DOWN\nDROP TABLE t;";
+        let (statements, _) = parse_statements(text).unwrap();
+        // Comment inside statement is a part of it.
+        assert_eq!(statements.len(), 2);
+
+        let text = "-- pico.UP\n\nCREATE TABLE t (\n    id INT\n)";
+        let err = parse_statements(text).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3: statement is not terminated with ';': CREATE TABLE t ( id INT )"
+        );
+    }
+
+    #[rstest]
+    fn migration_parse_directory() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("0002_users.sql"),
+            "-- pico.UP\nCREATE TABLE users (id INT);\n-- pico.DOWN\nDROP TABLE users;\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("0001_init.sql"), "-- pico.UP\n-- pico.DOWN\n").unwrap();
+        std::fs::write(dir.join("README.md"), "Migrations of the plugin").unwrap();
+
+        let err = parse_migrations(&dir).unwrap_err();
+        assert!(format!("{err:#}").contains("README.md"));
+
+        let migrations =
+            parse_migrations_with_options(&dir, &ParseOptions::default().skip_non_sql(true))
+                .unwrap();
+        assert_eq!(migrations.len(), 2);
+        assert_eq!(migrations[0].name(), "init");
+        assert_eq!(migrations[1].up_statements().len(), 2);
+        assert_eq!(migrations.warnings().len(), 1);
+        assert_eq!(migrations.warnings()[0].path, dir.join("README.md"));
+
+        std::fs::write(dir.join("0003_broken.sql"), "-- pico.UP\nCREATE TABLE t (").unwrap();
+        let err = parse_migrations(&dir).unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("0003_broken.sql"), "{message}");
+        assert!(message.contains("line 2"), "{message}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest]
+    fn migration_builder() {
+        let migration = Migration::new(3, "users")
+            .up([
+                "CREATE TABLE users (id INT);",
+                "CREATE INDEX users_id ON users (id);",
+            ])
+            .down(["DROP TABLE users;"]);
+
+        assert_eq!(migration.version(), 3);
+        assert_eq!(migration.name(), "users");
+        assert_eq!(migration.statements().len(), 5);
+        assert!(migration.up_statements()[0].is_pico_up());
+        assert_eq!(migration.up_statements().len(), 3);
+        assert!(migration.down_statements()[0].is_pico_down());
+        assert_eq!(migration.down_statements()[1].text(), "DROP TABLE users;");
+
+        // Parts are replaced independently.
+        let migration = migration.up(["CREATE TABLE accounts (id INT);"]);
+        assert_eq!(
+            migration.up_statements()[1].text(),
+            "CREATE TABLE accounts (id INT);"
+        );
+        assert_eq!(migration.down_statements().len(), 2);
+
+        // Layout is the same as of parsed migration file.
+        let parsed = parse_migration_text(
+            "-- pico.UP\nCREATE TABLE accounts (id INT);\n-- pico.DOWN\nDROP TABLE users;",
+        )
+        .unwrap();
+        assert_eq!(migration.statements(), parsed.as_slice());
+        let (up, down) = extract_up_down_ranges(&parsed).unwrap();
+        assert_eq!((up, down), (migration.up_range, migration.down_range));
+    }
+
+    #[rstest]
+    fn migration_merge() {
+        let plugin_a = Migrations::from_unsorted(vec![
+            Migration::new(1, "a_init"),
+            Migration::new(3, "a_users"),
+        ]);
+        let plugin_b = Migrations::from_unsorted(vec![
+            Migration::new(2, "b_init"),
+            Migration::new(3, "b_users"),
+        ]);
+
+        let merged = plugin_a.merge(plugin_b);
+        let names: Vec<&str> = merged.iter().map(Migration::name).collect();
+        assert_eq!(names, ["a_init", "b_init", "a_users", "b_users"]);
+    }
 }
@@ -0,0 +1,212 @@
+//! Mocks of service dependencies for `#[picotest_unit]` tests.
+//!
+//! Unit tests are executed by the plugin library loaded into the instance,
+//! so the registry lives in the memory of the library and is shared by the
+//! test and the service code it calls. Service code looks its collaborators
+//! up with [`get`] or [`call_rpc`] and falls back to the real ones if no mock
+//! is installed. All mocks are removed once no unit test is running.
+//! Module is compiled only with `unit_runner` feature.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! // Service code.
+//! fn fetch_rate(currency: &str) -> anyhow::Result<Vec<u8>> {
+//!     let path = "/rates";
+//!     if let Some(result) = picotest::mock::call_rpc(path, currency.as_bytes()) {
+//!         return result;
+//!     }
+//!     real_rpc_call(path, currency.as_bytes())
+//! }
+//!
+//! #[picotest_unit]
+//! fn test_fetch_rate() {
+//!     let _rates = picotest::mock::mock_rpc("/rates", |_| Ok(b"42".to_vec()));
+//!     assert_eq!(fetch_rate("USD").unwrap(), b"42");
+//!     assert_eq!(picotest::mock::rpc_calls("/rates"), [b"USD".to_vec()]);
+//! }
+//! ```
+
+use std::any::{type_name, Any};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+type Mock = Arc<dyn Any + Send + Sync>;
+
+/// Name prefix of RPC mocks in the registry.
+const RPC_MOCK_PREFIX: &str = "rpc:";
+
+fn registry() -> MutexGuard<'static, HashMap<String, Mock>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Mock>>> = OnceLock::new();
+
+    REGISTRY
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Installed mock. Mock is removed and the previous one is restored on drop.
+#[must_use = "mock is removed when the guard is dropped"]
+pub struct MockGuard {
+    name: String,
+    installed: Mock,
+    previous: Option<Mock>,
+}
+
+impl Drop for MockGuard {
+    fn drop(&mut self) {
+        let mut registry = registry();
+        // Mock might be already replaced or removed by `clear`.
+        if !registry
+            .get(&self.name)
+            .is_some_and(|mock| Arc::ptr_eq(mock, &self.installed))
+        {
+            return;
+        }
+        match self.previous.take() {
+            Some(previous) => registry.insert(self.name.clone(), previous),
+            None => registry.remove(&self.name),
+        };
+    }
+}
+
+/// Installs `mock` under the `name`.
+///
+/// ### Arguments:
+/// - `name` - name by which service code looks the mock up
+/// - `mock` - value of any type: fake client, config, closure, etc.
+///
+/// ### Returns
+/// Guard removing the mock when dropped.
+pub fn install<T: Any + Send + Sync>(name: impl Into<String>, mock: T) -> MockGuard {
+    let name = name.into();
+    let installed: Mock = Arc::new(mock);
+    let previous = registry().insert(name.clone(), installed.clone());
+    MockGuard {
+        name,
+        installed,
+        previous,
+    }
+}
+
+/// Returns mock installed under the `name`.
+///
+/// Panics if the mock has type other than `T`.
+pub fn get<T: Any + Send + Sync>(name: &str) -> Option<Arc<T>> {
+    let mock = registry().get(name)?.clone();
+    let mock = mock
+        .downcast()
+        .unwrap_or_else(|_| panic!("Mock '{name}' is not of type {}", type_name::<T>()));
+    Some(mock)
+}
+
+/// Removes all installed mocks.
+pub fn clear() {
+    registry().clear();
+}
+
+type RpcHandler = dyn Fn(&[u8]) -> anyhow::Result<Vec<u8>> + Send + Sync;
+
+/// Fake RPC endpoint recording its calls.
+struct RpcMock {
+    handler: Box<RpcHandler>,
+    calls: Mutex<Vec<Vec<u8>>>,
+}
+
+/// Installs fake RPC endpoint `path` answering with `handler`.
+///
+/// ### Arguments:
+/// - `path` - path of the endpoint
+/// - `handler` - function returning encoded response for encoded request
+///
+/// ### Returns
+/// Guard removing the mock when dropped.
+pub fn mock_rpc(
+    path: &str,
+    handler: impl Fn(&[u8]) -> anyhow::Result<Vec<u8>> + Send + Sync + 'static,
+) -> MockGuard {
+    install(
+        format!("{RPC_MOCK_PREFIX}{path}"),
+        RpcMock {
+            handler: Box::new(handler),
+            calls: Mutex::default(),
+        },
+    )
+}
+
+/// Calls fake RPC endpoint `path` with the `input`.
+///
+/// ### Returns
+/// Response of the mock or `None` if endpoint isn't mocked.
+pub fn call_rpc(path: &str, input: &[u8]) -> Option<anyhow::Result<Vec<u8>>> {
+    let mock = get::<RpcMock>(&format!("{RPC_MOCK_PREFIX}{path}"))?;
+    mock.calls
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(input.to_vec());
+    Some((mock.handler)(input))
+}
+
+/// Returns inputs, which fake RPC endpoint `path` has been called with.
+pub fn rpc_calls(path: &str) -> Vec<Vec<u8>> {
+    get::<RpcMock>(&format!("{RPC_MOCK_PREFIX}{path}"))
+        .map(|mock| {
+            mock.calls
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{call_rpc, get, install, mock_rpc, rpc_calls};
+    use rstest::rstest;
+
+    // Registry is shared by tests running in parallel, so every test uses its own names.
+
+    #[rstest]
+    fn test_install_mock() {
+        assert!(get::<u32>("test_install_mock").is_none());
+
+        let outer = install("test_install_mock", 1u32);
+        assert_eq!(get::<u32>("test_install_mock").as_deref(), Some(&1));
+        {
+            let _inner = install("test_install_mock", 2u32);
+            assert_eq!(get::<u32>("test_install_mock").as_deref(), Some(&2));
+        }
+        assert_eq!(get::<u32>("test_install_mock").as_deref(), Some(&1));
+
+        drop(outer);
+        assert!(get::<u32>("test_install_mock").is_none());
+    }
+
+    #[rstest]
+    #[should_panic(expected = "Mock 'test_mock_type_mismatch' is not of type")]
+    fn test_mock_type_mismatch() {
+        let _mock = install("test_mock_type_mismatch", "value");
+        get::<u32>("test_mock_type_mismatch");
+    }
+
+    #[rstest]
+    fn test_mock_rpc() {
+        assert!(call_rpc("/test_mock_rpc", b"ping").is_none());
+
+        let mock = mock_rpc("/test_mock_rpc", |input| {
+            anyhow::ensure!(input == b"ping", "unexpected request");
+            Ok(b"pong".to_vec())
+        });
+        assert_eq!(
+            call_rpc("/test_mock_rpc", b"ping").unwrap().unwrap(),
+            b"pong"
+        );
+        assert!(call_rpc("/test_mock_rpc", b"pong").unwrap().is_err());
+        assert_eq!(
+            rpc_calls("/test_mock_rpc"),
+            [b"ping".to_vec(), b"pong".to_vec()]
+        );
+
+        drop(mock);
+        assert!(rpc_calls("/test_mock_rpc").is_empty());
+    }
+}
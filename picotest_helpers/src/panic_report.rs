@@ -0,0 +1,179 @@
+//! Human-friendly rendering of panics raised by tests on the instance side.
+//!
+//! Payloads of failed `assert_eq!`/`assert_ne!` are pretty-printed and
+//! compared line by line, so that the difference is visible at a glance
+//! even for large structures.
+
+use crate::snapshot::lines_diff;
+use std::env;
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+const ASSERTION_PREFIX: &str = "assertion `left ";
+const LEFT_MARKER: &str = "\n  left: ";
+const RIGHT_MARKER: &str = "\n right: ";
+
+const INDENT: &str = "    ";
+
+const COLOR_RED: &str = "\x1b[31m";
+const COLOR_GREEN: &str = "\x1b[32m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// Renders panic message of the remote test. Colors are used
+/// if stderr is a terminal and `NO_COLOR` isn't set.
+pub fn format_remote_panic(message: &str) -> String {
+    let color = env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal();
+    render_panic(message, color)
+}
+
+/// Failed assertion split into parts of the standard panic message.
+#[derive(Debug, PartialEq)]
+struct AssertionFailure<'a> {
+    /// Text preceding operands, e.g. "assertion `left == right` failed: message".
+    header: &'a str,
+    left: &'a str,
+    right: &'a str,
+}
+
+fn parse_assertion(message: &str) -> Option<AssertionFailure<'_>> {
+    let start = message.find(ASSERTION_PREFIX)?;
+    let message = &message[start..];
+    let left_at = message.find(LEFT_MARKER)?;
+    let right_at = message.rfind(RIGHT_MARKER)?;
+    if right_at < left_at {
+        return None;
+    }
+    Some(AssertionFailure {
+        header: &message[..left_at],
+        left: &message[left_at + LEFT_MARKER.len()..right_at],
+        right: &message[right_at + RIGHT_MARKER.len()..],
+    })
+}
+
+fn render_panic(message: &str, color: bool) -> String {
+    let Some(assertion) = parse_assertion(message) else {
+        return message.to_string();
+    };
+
+    let mut rendered = message[..message.find(ASSERTION_PREFIX).unwrap()].to_string();
+    rendered.push_str(assertion.header);
+    rendered.push_str("\nDiff < left / right > :\n");
+    let diff = lines_diff(
+        &pretty_debug(assertion.left),
+        &pretty_debug(assertion.right),
+    );
+    for line in diff.lines() {
+        let line_color = match line.as_bytes().first() {
+            Some(b'-') if color => COLOR_RED,
+            Some(b'+') if color => COLOR_GREEN,
+            _ => "",
+        };
+        if line_color.is_empty() {
+            writeln!(rendered, "{line}").unwrap();
+        } else {
+            writeln!(rendered, "{line_color}{line}{COLOR_RESET}").unwrap();
+        }
+    }
+    rendered
+}
+
+/// Splits single-line `Debug` output into lines, one per field
+/// or element, similar to `{:#?}` formatting.
+fn pretty_debug(value: &str) -> String {
+    let mut pretty = String::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = value.chars().peekable();
+
+    let new_line = |pretty: &mut String, depth: usize| {
+        pretty.push('\n');
+        pretty.push_str(&INDENT.repeat(depth));
+    };
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            pretty.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                pretty.push(c);
+            }
+            '{' | '[' | '(' => {
+                pretty.push(c);
+                // Empty collections are kept inline.
+                if matches!(chars.peek(), Some('}' | ']' | ')')) {
+                    pretty.push(chars.next().unwrap());
+                    continue;
+                }
+                depth += 1;
+                new_line(&mut pretty, depth);
+                if c == '{' && chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+            }
+            '}' | ']' | ')' => {
+                depth = depth.saturating_sub(1);
+                let trimmed_len = pretty.trim_end().len();
+                pretty.truncate(trimmed_len);
+                new_line(&mut pretty, depth);
+                pretty.push(c);
+            }
+            ',' => {
+                pretty.push(c);
+                if chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+                new_line(&mut pretty, depth);
+            }
+            _ => pretty.push(c),
+        }
+    }
+    pretty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_assertion, pretty_debug, render_panic, AssertionFailure};
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_parse_assertion() {
+        let message = "prefix: assertion `left == right` failed: values\n  left: 1\n right: 2";
+        assert_eq!(
+            parse_assertion(message),
+            Some(AssertionFailure {
+                header: "assertion `left == right` failed: values",
+                left: "1",
+                right: "2",
+            })
+        );
+        assert_eq!(parse_assertion("test panicked"), None);
+    }
+
+    #[rstest]
+    fn test_pretty_debug() {
+        assert_eq!(
+            pretty_debug(r#"User { id: 1, tags: ["a, b"], empty: [] }"#),
+            "User {\n    id: 1,\n    tags: [\n        \"a, b\"\n    ],\n    empty: []\n}"
+        );
+    }
+
+    #[rstest]
+    fn test_render_panic() {
+        let message = "assertion `left == right` failed\n  left: [1, 2]\n right: [1, 3]";
+        assert_eq!(
+            render_panic(message, false),
+            "assertion `left == right` failed\nDiff < left / right > :\n  [\n      1,\n-     2\n+     3\n  ]\n"
+        );
+        assert_eq!(render_panic("boom", true), "boom");
+    }
+}
@@ -0,0 +1,369 @@
+//! Latency measurements and budgets for lightweight performance gates.
+//!
+//! Operation is executed several times to warm up caches and connections,
+//! then its latency is sampled sequentially. Budget violation is reported
+//! with a histogram of sampled latencies.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::perf::{self, LatencyBudget};
+//!
+//! let measurement = perf::measure(|| client.query("SELECT * FROM users", &[]).unwrap());
+//! measurement.assert_within(LatencyBudget::p95(Duration::from_millis(20)));
+//!
+//! let request = RpcRequest {
+//!     plugin_name: "plugin",
+//!     path: "/greetings_rpc",
+//!     service_name: "main",
+//!     plugin_version: "0.1.0",
+//!     input: User { name: "Dodo".into() },
+//! };
+//! cluster
+//!     .assert_rpc_latency(&request, LatencyBudget::p95(Duration::from_millis(50)), 200)
+//!     .await;
+//! ```
+
+use crate::rpc_batch::{percentile, LatencyStats, RpcRequest};
+use crate::{Cluster, PicotestInstance};
+use serde::de::IgnoredAny;
+use serde::Serialize;
+use std::fmt::{self, Display, Write};
+use std::future::Future;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Default number of sampled executions.
+pub const DEFAULT_SAMPLES: usize = 100;
+
+/// Default number of warm-up executions, which aren't sampled.
+pub const DEFAULT_WARMUP: usize = 10;
+
+/// Number of buckets in the histogram of latencies.
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Width of the longest bar of the histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// How many times the operation is executed.
+///
+/// Number of samples can be set from `usize`, e.g. `measure_with(200, ...)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampling {
+    pub samples: usize,
+    pub warmup: usize,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            samples: DEFAULT_SAMPLES,
+            warmup: DEFAULT_WARMUP,
+        }
+    }
+}
+
+impl Sampling {
+    /// Samples `samples` executions after default warm-up.
+    pub fn samples(samples: usize) -> Self {
+        Self {
+            samples,
+            ..Default::default()
+        }
+    }
+
+    /// Sets number of warm-up executions.
+    pub fn warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+}
+
+impl From<usize> for Sampling {
+    fn from(samples: usize) -> Self {
+        Self::samples(samples)
+    }
+}
+
+/// Upper bound of the latency percentile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyBudget {
+    /// Percentile in range `1..=100`, where 100 is the maximum.
+    pub percentile: usize,
+    pub limit: Duration,
+}
+
+impl LatencyBudget {
+    /// Requires `percentile` of latencies to be less than `limit`.
+    pub fn new(percentile: usize, limit: Duration) -> Self {
+        assert!(
+            (1..=100).contains(&percentile),
+            "percentile must be in range 1..=100"
+        );
+        Self { percentile, limit }
+    }
+
+    pub fn p50(limit: Duration) -> Self {
+        Self::new(50, limit)
+    }
+
+    pub fn p95(limit: Duration) -> Self {
+        Self::new(95, limit)
+    }
+
+    pub fn p99(limit: Duration) -> Self {
+        Self::new(99, limit)
+    }
+
+    pub fn max(limit: Duration) -> Self {
+        Self::new(100, limit)
+    }
+}
+
+impl Display for LatencyBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.percentile {
+            100 => write!(f, "max < {:?}", self.limit),
+            p => write!(f, "p{p} < {:?}", self.limit),
+        }
+    }
+}
+
+/// Sampled latencies of the operation.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Measurement {
+    /// Latencies in order of execution.
+    pub latencies: Vec<Duration>,
+    sorted: Vec<Duration>,
+}
+
+impl Measurement {
+    pub fn from_latencies(latencies: Vec<Duration>) -> Self {
+        let mut sorted = latencies.clone();
+        sorted.sort();
+        Self { latencies, sorted }
+    }
+
+    /// Returns nearest-rank `p`-th percentile of latencies.
+    pub fn percentile(&self, p: usize) -> Duration {
+        percentile(&self.sorted, p)
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        LatencyStats::from_latencies(&self.sorted)
+    }
+
+    /// Returns `true` if latencies fit into the `budget`.
+    pub fn is_within(&self, budget: LatencyBudget) -> bool {
+        self.percentile(budget.percentile) < budget.limit
+    }
+
+    /// Asserts that latencies fit into the `budget`.
+    /// Panic message contains histogram of latencies.
+    #[track_caller]
+    pub fn assert_within(&self, budget: LatencyBudget) {
+        assert!(
+            self.is_within(budget),
+            "latency budget {budget} is exceeded: p{} is {:?} over {} samples\n{}",
+            budget.percentile,
+            self.percentile(budget.percentile),
+            self.latencies.len(),
+            self.histogram()
+        );
+    }
+
+    /// Renders histogram of latencies with equal-width buckets
+    /// between the minimum and the maximum latency.
+    pub fn histogram(&self) -> String {
+        let (Some(min), Some(max)) = (self.sorted.first(), self.sorted.last()) else {
+            return "no samples".to_string();
+        };
+        let width = (*max - *min) / HISTOGRAM_BUCKETS as u32;
+        let mut counts = [0usize; HISTOGRAM_BUCKETS];
+        for latency in &self.sorted {
+            let bucket = if width.is_zero() {
+                0
+            } else {
+                ((*latency - *min).as_nanos() / width.as_nanos()) as usize
+            };
+            counts[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        }
+
+        let highest = counts.iter().copied().max().unwrap_or_default().max(1);
+        let mut histogram = format!("{}\n", self.stats_line());
+        for (bucket, count) in counts.iter().enumerate() {
+            let from = *min + width * bucket as u32;
+            let to = if bucket == HISTOGRAM_BUCKETS - 1 {
+                *max
+            } else {
+                from + width
+            };
+            let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(highest));
+            writeln!(
+                histogram,
+                "{:>12} .. {:<12} | {bar} {count}",
+                format!("{from:?}"),
+                format!("{to:?}")
+            )
+            .expect("writing into string can't fail");
+            if width.is_zero() {
+                break;
+            }
+        }
+        histogram
+    }
+
+    fn stats_line(&self) -> String {
+        let stats = self.stats();
+        format!(
+            "p50: {:?}, p95: {:?}, p99: {:?}, max: {:?}",
+            stats.p50,
+            stats.p95,
+            self.percentile(99),
+            stats.max
+        )
+    }
+}
+
+/// Measures latency of `operation` with default [`Sampling`].
+pub fn measure<T>(operation: impl FnMut() -> T) -> Measurement {
+    measure_with(Sampling::default(), operation)
+}
+
+/// Measures latency of `operation` executed according to `sampling`.
+pub fn measure_with<T>(
+    sampling: impl Into<Sampling>,
+    mut operation: impl FnMut() -> T,
+) -> Measurement {
+    let sampling = sampling.into();
+    for _ in 0..sampling.warmup {
+        black_box(operation());
+    }
+    let latencies = (0..sampling.samples)
+        .map(|_| {
+            let started_at = Instant::now();
+            black_box(operation());
+            started_at.elapsed()
+        })
+        .collect();
+    Measurement::from_latencies(latencies)
+}
+
+/// Measures latency of async `operation` executed according to `sampling`.
+pub async fn measure_async<T, F>(
+    sampling: impl Into<Sampling>,
+    mut operation: impl FnMut() -> F,
+) -> Measurement
+where
+    F: Future<Output = T>,
+{
+    let sampling = sampling.into();
+    for _ in 0..sampling.warmup {
+        black_box(operation().await);
+    }
+    let mut latencies = Vec::with_capacity(sampling.samples);
+    for _ in 0..sampling.samples {
+        let started_at = Instant::now();
+        black_box(operation().await);
+        latencies.push(started_at.elapsed());
+    }
+    Measurement::from_latencies(latencies)
+}
+
+impl PicotestInstance {
+    /// Asserts that latency of the RPC `request` fits into the `budget`.
+    ///
+    /// Calls are executed one by one, failed call fails the assertion.
+    ///
+    /// ### Returns
+    /// Measurement for further checks.
+    pub async fn assert_rpc_latency<S: Serialize>(
+        &self,
+        request: &RpcRequest<'_, S>,
+        budget: LatencyBudget,
+        sampling: impl Into<Sampling>,
+    ) -> Measurement {
+        let measurement = measure_async(sampling, || async {
+            self.execute_rpc::<S, IgnoredAny>(
+                request.plugin_name,
+                request.path,
+                request.service_name,
+                request.plugin_version,
+                &request.input,
+            )
+            .await
+            .unwrap_or_else(|err| panic!("RPC '{}' has failed: {err:#}", request.path))
+        })
+        .await;
+        measurement.assert_within(budget);
+        measurement
+    }
+}
+
+impl Cluster {
+    /// Asserts that latency of the RPC `request` to the main instance fits into the `budget`.
+    /// See [`PicotestInstance::assert_rpc_latency`].
+    pub async fn assert_rpc_latency<S: Serialize>(
+        &self,
+        request: &RpcRequest<'_, S>,
+        budget: LatencyBudget,
+        sampling: impl Into<Sampling>,
+    ) -> Measurement {
+        self.main()
+            .assert_rpc_latency(request, budget, sampling)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{measure_with, LatencyBudget, Measurement, Sampling};
+    use rstest::rstest;
+    use std::panic::catch_unwind;
+    use std::time::Duration;
+
+    fn millis(latencies: impl IntoIterator<Item = u64>) -> Measurement {
+        Measurement::from_latencies(latencies.into_iter().map(Duration::from_millis).collect())
+    }
+
+    #[rstest]
+    fn test_latency_budget() {
+        let measurement = millis((1..=100).rev());
+        assert_eq!(measurement.percentile(95), Duration::from_millis(95));
+        assert!(measurement.is_within(LatencyBudget::p95(Duration::from_millis(96))));
+        assert!(!measurement.is_within(LatencyBudget::p95(Duration::from_millis(95))));
+        assert!(!measurement.is_within(LatencyBudget::max(Duration::from_millis(100))));
+        assert_eq!(
+            LatencyBudget::p99(Duration::from_millis(50)).to_string(),
+            "p99 < 50ms"
+        );
+
+        let err = catch_unwind(|| {
+            measurement.assert_within(LatencyBudget::p50(Duration::from_millis(10)))
+        })
+        .unwrap_err();
+        let message = err.downcast_ref::<String>().unwrap();
+        assert!(message.contains("latency budget p50 < 10ms is exceeded: p50 is 50ms"));
+        assert!(message.contains("#"));
+    }
+
+    #[rstest]
+    fn test_histogram() {
+        let histogram = millis([1, 1, 1, 11]).histogram();
+        let lines: Vec<_> = histogram.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert!(lines[1].ends_with(&format!("| {} 3", "#".repeat(40))));
+        assert!(lines[10].ends_with("| ############## 1"));
+
+        assert_eq!(millis([5, 5]).histogram().lines().count(), 2);
+        assert_eq!(Measurement::default().histogram(), "no samples");
+    }
+
+    #[rstest]
+    fn test_measure() {
+        let mut executions = 0;
+        let measurement = measure_with(Sampling::samples(20).warmup(5), || executions += 1);
+        assert_eq!(executions, 25);
+        assert_eq!(measurement.latencies.len(), 20);
+        assert_eq!(Sampling::from(7), Sampling::samples(7));
+    }
+}
@@ -0,0 +1,191 @@
+//! Execution of pike commands with captured output.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! use picotest_helpers::pike_command::run_pike_captured;
+//!
+//! let output = run_pike_captured(
+//!     vec!["plugin", "new", "my_plugin", "--without-git"],
+//!     "tmp",
+//!     Some(Duration::from_secs(60)),
+//! )?
+//! .check()?;
+//! println!("{}", output.stdout);
+//! ```
+
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Interval between checks whether pike command has finished.
+const PIKE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Output of the finished pike command.
+#[derive(Debug, Clone)]
+pub struct PikeOutput {
+    /// Command line, e.g. `cargo pike plugin pack`.
+    pub command: String,
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl PikeOutput {
+    /// Returns `true` if the command has finished successfully.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// Returns the output if the command has finished successfully.
+    ///
+    /// ### Returns
+    /// Error with the exit status and stderr of the command otherwise.
+    pub fn check(self) -> anyhow::Result<Self> {
+        if !self.success() {
+            bail!("{self}");
+        }
+        Ok(self)
+    }
+}
+
+impl Display for PikeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\" has finished with {}", self.command, self.status)?;
+        for (name, output) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !output.trim().is_empty() {
+                write!(f, "\n{name}:\n{}", output.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `cargo pike` with `args` in `current_dir` and captures its output.
+///
+/// ### Arguments:
+/// - `args` - arguments of pike, e.g. `["plugin", "pack"]`.
+/// - `current_dir` - working directory of the command.
+/// - `timeout` - the command is killed if it hasn't finished in time.
+///   Command may run indefinitely if it's `None`.
+///
+/// ### Returns
+/// - On completion, output of the command, even if it has failed.
+///   See [`PikeOutput::check`].
+/// - On failure to run the command or on timeout, instance of [`anyhow::Error`].
+pub fn run_pike_captured<A, P>(
+    args: Vec<A>,
+    current_dir: P,
+    timeout: Option<Duration>,
+) -> anyhow::Result<PikeOutput>
+where
+    A: AsRef<OsStr>,
+    P: AsRef<Path>,
+{
+    let command = std::iter::once(OsStr::new("cargo pike"))
+        .chain(args.iter().map(AsRef::as_ref))
+        .map(OsStr::to_string_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+    debug!(
+        "Running \"{command}\" in '{}'",
+        current_dir.as_ref().display()
+    );
+
+    let mut child = Command::new("cargo")
+        .arg("pike")
+        .args(&args)
+        .current_dir(current_dir.as_ref())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run \"{command}\""))?;
+
+    // Both pipes are drained concurrently, so the command
+    // isn't blocked on writing to the filled one.
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let start_time = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(timeout) = timeout.filter(|timeout| start_time.elapsed() > *timeout) {
+            child.kill()?;
+            let status = child.wait()?;
+            let output = PikeOutput {
+                command,
+                status,
+                stdout: join_pipe(stdout),
+                stderr: join_pipe(stderr),
+            };
+            bail!("{output}\nCommand has been killed after {timeout:?} timeout");
+        }
+        thread::sleep(PIKE_POLL_INTERVAL);
+    };
+
+    let output = PikeOutput {
+        command,
+        status,
+        stdout: join_pipe(stdout),
+        stderr: join_pipe(stderr),
+    };
+    if output.success() {
+        debug!("{output}");
+    } else {
+        warn!("{output}");
+    }
+    Ok(output)
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    })
+}
+
+fn join_pipe(reader: JoinHandle<String>) -> String {
+    reader.join().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PikeOutput;
+    use rstest::rstest;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    #[rstest]
+    fn test_pike_output_check() {
+        let output = PikeOutput {
+            command: "cargo pike plugin pack".to_string(),
+            status: ExitStatus::from_raw(0),
+            stdout: "packed\n".to_string(),
+            stderr: String::new(),
+        };
+        assert!(output.clone().check().is_ok());
+
+        let failed = PikeOutput {
+            status: ExitStatus::from_raw(1 << 8),
+            stderr: "error: plugin is not found\n".to_string(),
+            ..output
+        };
+        assert_eq!(
+            failed.check().unwrap_err().to_string(),
+            "\"cargo pike plugin pack\" has finished with exit status: 1\n\
+             stdout:\npacked\n\
+             stderr:\nerror: plugin is not found"
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! Plugin build artifacts.
+//!
+//! Cluster loads plugin shared library from the profile build directory.
+//! If sources of the plugin were changed after it has been built, tests
+//! would run against outdated code. Build is considered stale if any of
+//! plugin sources is newer than the most recently built shared library.
+//!
+//! Name of the shared library is taken from `cargo metadata`, as it may
+//! differ from the package name (e.g. `[lib] name = ...`).
+
+use anyhow::{bail, Context};
+use log::warn;
+use serde::Deserialize;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Directories of plugin workspace, which never contain plugin sources.
+const IGNORED_DIRS: [&str; 3] = ["target", "tmp", ".git"];
+
+/// Plugin topology file marking the root directory of the plugin.
+const PLUGIN_TOPOLOGY_FILENAME: &str = "topology.toml";
+
+/// Dependency of the package implementing the plugin.
+const PLUGIN_SDK_PACKAGE: &str = "picodata-plugin";
+
+/// What to do if plugin build is stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StaleBuildPolicy {
+    /// Don't check plugin build.
+    Ignore,
+    /// Print warning and proceed.
+    #[default]
+    Warn,
+    /// Build the plugin before cluster start.
+    Rebuild,
+    /// Fail, e.g. in CI where plugin must be built beforehand.
+    Error,
+}
+
+impl FromStr for StaleBuildPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "warn" => Ok(Self::Warn),
+            "rebuild" => Ok(Self::Rebuild),
+            "error" => Ok(Self::Error),
+            _ => bail!(
+                "unknown stale build policy '{s}', expected one of: ignore, warn, rebuild, error"
+            ),
+        }
+    }
+}
+
+/// Environment variable selecting cargo profile the plugin is built with,
+/// see [`BuildProfile`].
+pub const PICOTEST_BUILD_PROFILE: &str = "PICOTEST_BUILD_PROFILE";
+
+/// Cargo profile the plugin is built and shipped with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BuildProfile {
+    #[default]
+    Debug,
+    Release,
+}
+
+impl FromStr for BuildProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "debug" | "dev" => Ok(Self::Debug),
+            "release" => Ok(Self::Release),
+            _ => bail!("unknown build profile '{s}', expected one of: debug, release"),
+        }
+    }
+}
+
+impl BuildProfile {
+    /// Reads profile from [`PICOTEST_BUILD_PROFILE`], [`BuildProfile::Debug`] if it's not set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var(PICOTEST_BUILD_PROFILE) {
+            Ok(value) if !value.is_empty() => value.parse(),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Name of the profile directory inside of the target directory.
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Release => "release",
+        }
+    }
+
+    pub fn is_release(self) -> bool {
+        self == Self::Release
+    }
+
+    /// Returns profile build directory of the plugin
+    /// built by pike, e.g. `target/debug`.
+    pub fn build_path(self, plugin_path: &Path) -> PathBuf {
+        plugin_path.join("target").join(self.dir_name())
+    }
+}
+
+/// Checks plugin build of the `profile` against the plugin sources
+/// and acts according to the `policy`.
+pub fn ensure_fresh_build(
+    plugin_path: &Path,
+    profile: BuildProfile,
+    policy: StaleBuildPolicy,
+) -> anyhow::Result<()> {
+    let profile_path = profile.build_path(plugin_path);
+    if policy == StaleBuildPolicy::Ignore || !is_build_stale(plugin_path, &profile_path) {
+        return Ok(());
+    }
+
+    match policy {
+        StaleBuildPolicy::Ignore => Ok(()),
+        StaleBuildPolicy::Warn => {
+            println!(
+                "Plugin build in '{}' is older than plugin sources, \
+                tests may run against outdated code",
+                profile_path.display()
+            );
+            Ok(())
+        }
+        StaleBuildPolicy::Rebuild => {
+            println!("Plugin build is stale, rebuilding");
+            build_plugin(plugin_path, profile)
+        }
+        StaleBuildPolicy::Error => bail!(
+            "plugin build in '{}' is older than plugin sources, run \"cargo build\" first",
+            profile_path.display()
+        ),
+    }
+}
+
+/// Returns `true` if plugin sources were modified after the most
+/// recently built shared library or if there's no library at all.
+pub fn is_build_stale(plugin_path: &Path, profile_path: &Path) -> bool {
+    let Some(built_at) = newest_library_mtime(profile_path) else {
+        return true;
+    };
+    newest_source_mtime(plugin_path).is_some_and(|modified_at| modified_at > built_at)
+}
+
+/// Builds the plugin located by `plugin_path` with cargo `profile`.
+pub fn build_plugin(plugin_path: &Path, profile: BuildProfile) -> anyhow::Result<()> {
+    let mut command = Command::new("cargo");
+    command.arg("build").current_dir(plugin_path);
+    if profile.is_release() {
+        command.arg("--release");
+    }
+    let status = command.status()?;
+    if !status.success() {
+        bail!("\"cargo build\" has finished with failure: {status}");
+    }
+    Ok(())
+}
+
+/// Subset of `cargo metadata` output.
+#[derive(Debug, Deserialize)]
+struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+    target_directory: PathBuf,
+    #[serde(default)]
+    workspace_root: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoPackage {
+    name: String,
+    targets: Vec<CargoTarget>,
+    #[serde(default)]
+    manifest_path: PathBuf,
+    #[serde(default)]
+    dependencies: Vec<CargoDependency>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoDependency {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl CargoMetadata {
+    /// Returns path to the shared library built with `profile`
+    /// from the `cdylib` target of the package.
+    fn library_path(&self, package_name: &str, profile: BuildProfile) -> anyhow::Result<PathBuf> {
+        let package = self
+            .packages
+            .iter()
+            .find(|package| package.name == package_name)
+            .with_context(|| format!("package '{package_name}' is not found"))?;
+        let target = package
+            .targets
+            .iter()
+            .find(|target| target.kind.iter().any(|kind| kind == "cdylib"))
+            .with_context(|| format!("package '{package_name}' has no cdylib target"))?;
+
+        Ok(self
+            .target_directory
+            .join(profile.dir_name())
+            .join(library_file_name(&target.name)))
+    }
+
+    /// Returns directories, which may contain plugin topology: the workspace
+    /// root and directories of packages depending on `picodata-plugin`.
+    fn plugin_dirs(&self) -> Vec<PathBuf> {
+        let packages = self
+            .packages
+            .iter()
+            .filter(|package| {
+                package
+                    .dependencies
+                    .iter()
+                    .any(|dependency| dependency.name.replace('_', "-") == PLUGIN_SDK_PACKAGE)
+            })
+            .filter_map(|package| package.manifest_path.parent());
+
+        let mut dirs = vec![self.workspace_root.clone()];
+        for dir in packages {
+            if !dirs.iter().any(|known| known == dir) {
+                dirs.push(dir.to_path_buf());
+            }
+        }
+        dirs
+    }
+}
+
+/// Locates shared library of the package.
+///
+/// Library name and target directory are taken from `cargo metadata` of the
+/// workspace `package_dir` belongs to, so package may be either the plugin
+/// itself or a sub-crate of the plugin workspace with its own cdylib.
+/// If it fails, library named after the package is looked up in the `profile`
+/// build directory of the plugin and, if it's missing, the only library
+/// found there is used.
+pub fn find_plugin_library(
+    package_dir: &Path,
+    plugin_path: &Path,
+    profile: BuildProfile,
+    package_name: &str,
+) -> PathBuf {
+    let profile_path = profile.build_path(plugin_path);
+    match cargo_metadata(package_dir)
+        .and_then(|metadata| metadata.library_path(package_name, profile))
+    {
+        Ok(path) => return path,
+        Err(err) => warn!(
+            "Failed to get plugin library name from cargo metadata, \
+            looking it up in '{}': {err:#}",
+            profile_path.display()
+        ),
+    }
+
+    let expected = profile_path.join(library_file_name(package_name));
+    if expected.exists() {
+        return expected;
+    }
+    match libraries(&profile_path).as_slice() {
+        [single] => single.clone(),
+        _ => expected,
+    }
+}
+
+/// Locates root directory of the plugin, the package `package_dir` belongs to.
+///
+/// Plugin root is the directory with plugin topology and `Cargo.toml`. It is
+/// looked up in the root of the cargo workspace of the package, in
+/// directories of workspace packages depending on `picodata-plugin` and,
+/// finally, in `package_dir` and its parents. So tests may reside either in
+/// the plugin crate itself or in any sub-crate of the plugin workspace.
+///
+/// ### Returns
+/// - On success, path to the plugin root directory.
+/// - On failure, instance of [`anyhow::Error`] listing searched directories.
+pub fn find_plugin_root(package_dir: &Path) -> anyhow::Result<PathBuf> {
+    let metadata = cargo_metadata(package_dir);
+    let candidates = metadata
+        .as_ref()
+        .map(CargoMetadata::plugin_dirs)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(package_dir.ancestors().map(Path::to_path_buf));
+
+    let mut searched: Vec<PathBuf> = Vec::new();
+    for dir in candidates {
+        if searched.contains(&dir) {
+            continue;
+        }
+        if dir.join(PLUGIN_TOPOLOGY_FILENAME).exists() {
+            if !dir.join("Cargo.toml").exists() {
+                bail!(
+                    "plugin directory '{}' has {PLUGIN_TOPOLOGY_FILENAME}, but no Cargo.toml",
+                    dir.display()
+                );
+            }
+            return Ok(dir);
+        }
+        searched.push(dir);
+    }
+
+    let mut message = format!(
+        "plugin topology file {PLUGIN_TOPOLOGY_FILENAME} is not found in:\n{}",
+        searched
+            .iter()
+            .map(|dir| format!("  {}", dir.display()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    match &metadata {
+        Ok(metadata) if metadata.plugin_dirs().len() == 1 => message.push_str(&format!(
+            "\nno package of the workspace '{}' depends on {PLUGIN_SDK_PACKAGE}",
+            metadata.workspace_root.display()
+        )),
+        Ok(_) => {}
+        Err(err) => message.push_str(&format!("\nfailed to get cargo metadata: {err:#}")),
+    }
+    message.push_str(
+        "\nrun tests from the plugin workspace or specify the plugin \
+        with #[picotest(path = \"...\")]",
+    );
+    bail!(message)
+}
+
+fn cargo_metadata(package_dir: &Path) -> anyhow::Result<CargoMetadata> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(package_dir)
+        .output()
+        .context("failed to run \"cargo metadata\"")?;
+    if !output.status.success() {
+        bail!(
+            "\"cargo metadata\" has finished with failure: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout).context("failed to parse output of \"cargo metadata\"")
+}
+
+/// Returns platform-specific file name of the shared library, e.g.
+/// `libplugin.so` on Linux or `libplugin.dylib` on macOS.
+fn library_file_name(name: &str) -> String {
+    format!("{DLL_PREFIX}{}{DLL_SUFFIX}", name.replace('-', "_"))
+}
+
+pub(crate) fn libraries(profile_path: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(profile_path) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "so" || ext == "dylib")
+        })
+        .collect()
+}
+
+fn newest_library_mtime(profile_path: &Path) -> Option<SystemTime> {
+    libraries(profile_path)
+        .into_iter()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+fn newest_source_mtime(dir: &Path) -> Option<SystemTime> {
+    let mut newest = None;
+    for entry in fs::read_dir(dir).ok()?.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name();
+        let modified = if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            if IGNORED_DIRS.contains(&name.to_string_lossy().as_ref()) {
+                continue;
+            }
+            newest_source_mtime(&path)
+        } else if is_source_file(&path) {
+            entry.metadata().and_then(|m| m.modified()).ok()
+        } else {
+            None
+        };
+        newest = newest.max(modified);
+    }
+    newest
+}
+
+fn is_source_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "rs" || ext == "sql")
+        || path
+            .file_name()
+            .is_some_and(|name| name == "Cargo.toml" || name == "manifest.yaml.template")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_plugin_root, is_build_stale, library_file_name, BuildProfile, CargoMetadata,
+        StaleBuildPolicy,
+    };
+    use rstest::rstest;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    fn touch(path: &Path, modified: SystemTime) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[rstest]
+    fn test_is_build_stale() {
+        let plugin_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let profile_path = plugin_path.join("target").join("debug");
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+
+        touch(&plugin_path.join("src").join("lib.rs"), now - hour);
+        assert!(
+            is_build_stale(&plugin_path, &profile_path),
+            "no library is built"
+        );
+
+        touch(&profile_path.join("libplugin.so"), now);
+        assert!(!is_build_stale(&plugin_path, &profile_path));
+
+        // Files in target and unrelated files are not sources.
+        touch(&profile_path.join("build").join("out.rs"), now + hour);
+        touch(&plugin_path.join("README.md"), now + hour);
+        assert!(!is_build_stale(&plugin_path, &profile_path));
+
+        touch(
+            &plugin_path.join("migrations").join("0001_init.sql"),
+            now + hour,
+        );
+        assert!(is_build_stale(&plugin_path, &profile_path));
+
+        fs::remove_dir_all(plugin_path).unwrap();
+    }
+
+    #[rstest]
+    #[case("warn", StaleBuildPolicy::Warn)]
+    #[case("Rebuild", StaleBuildPolicy::Rebuild)]
+    #[case("ERROR", StaleBuildPolicy::Error)]
+    #[case("ignore", StaleBuildPolicy::Ignore)]
+    fn test_stale_build_policy_from_str(#[case] value: &str, #[case] expected: StaleBuildPolicy) {
+        assert_eq!(value.parse::<StaleBuildPolicy>().unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_stale_build_policy_from_invalid_str() {
+        assert!("sometimes".parse::<StaleBuildPolicy>().is_err());
+    }
+
+    #[rstest]
+    #[case("debug", BuildProfile::Debug)]
+    #[case("dev", BuildProfile::Debug)]
+    #[case("Release", BuildProfile::Release)]
+    fn test_build_profile_from_str(#[case] value: &str, #[case] expected: BuildProfile) {
+        assert_eq!(value.parse::<BuildProfile>().unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_build_profile_from_invalid_str() {
+        assert!("bench".parse::<BuildProfile>().is_err());
+    }
+
+    #[rstest]
+    fn test_library_path() {
+        let metadata: CargoMetadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    {"name": "other", "targets": [{"name": "other", "kind": ["cdylib"]}]},
+                    {"name": "my-plugin", "targets": [
+                        {"name": "build-script-build", "kind": ["custom-build"]},
+                        {"name": "custom_lib", "kind": ["cdylib", "rlib"]}
+                    ]}
+                ],
+                "target_directory": "/work/target"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            metadata
+                .library_path("my-plugin", BuildProfile::Debug)
+                .unwrap(),
+            Path::new("/work/target/debug").join(library_file_name("custom_lib"))
+        );
+        assert_eq!(
+            metadata
+                .library_path("my-plugin", BuildProfile::Release)
+                .unwrap(),
+            Path::new("/work/target/release").join(library_file_name("custom_lib"))
+        );
+        assert!(metadata
+            .library_path("missing", BuildProfile::Debug)
+            .is_err());
+    }
+
+    #[rstest]
+    fn test_plugin_dirs() {
+        let metadata: CargoMetadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    {"name": "tests", "targets": [], "manifest_path": "/work/tests/Cargo.toml",
+                     "dependencies": [{"name": "picotest"}]},
+                    {"name": "my-plugin", "targets": [], "manifest_path": "/work/plugin/Cargo.toml",
+                     "dependencies": [{"name": "picodata-plugin"}]},
+                    {"name": "root", "targets": [], "manifest_path": "/work/Cargo.toml",
+                     "dependencies": [{"name": "picodata_plugin"}]}
+                ],
+                "target_directory": "/work/target",
+                "workspace_root": "/work"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            metadata.plugin_dirs(),
+            [Path::new("/work"), Path::new("/work/plugin")]
+        );
+    }
+
+    #[rstest]
+    fn test_find_plugin_root_in_parents() {
+        let plugin_path = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let package_dir = plugin_path.join("tests").join("integration");
+        fs::create_dir_all(&package_dir).unwrap();
+
+        let err = find_plugin_root(&package_dir).unwrap_err().to_string();
+        assert!(err.contains("topology.toml is not found"), "{err}");
+        assert!(err.contains(&package_dir.display().to_string()), "{err}");
+
+        File::create(plugin_path.join("topology.toml")).unwrap();
+        let err = find_plugin_root(&package_dir).unwrap_err().to_string();
+        assert!(err.contains("but no Cargo.toml"), "{err}");
+
+        File::create(plugin_path.join("Cargo.toml")).unwrap();
+        assert_eq!(find_plugin_root(&package_dir).unwrap(), plugin_path);
+
+        fs::remove_dir_all(plugin_path).unwrap();
+    }
+
+    #[rstest]
+    fn test_library_file_name() {
+        let name = library_file_name("my-plugin");
+        assert!(name.contains("my_plugin"));
+        #[cfg(target_os = "linux")]
+        assert_eq!(name, "libmy_plugin.so");
+        #[cfg(target_os = "macos")]
+        assert_eq!(name, "libmy_plugin.dylib");
+    }
+}
@@ -0,0 +1,464 @@
+//! Access to plugin configuration stored in `_pico_plugin_config`.
+
+use crate::{Cluster, PicotestInstance, PluginConfigMap};
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_norway::{Mapping, Value};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default timeout of waiting until applied configuration is visible.
+pub const DEFAULT_APPLY_CONFIG_TIMEOUT: Duration = Duration::from_secs(30);
+
+const APPLY_CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Part of the plugin config applied by [`Cluster::apply_config_scoped`].
+///
+/// Plugin config is stored per service, so config of the tier is config
+/// of the services deployed to it. Service deployed to several tiers has
+/// the same config on all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigScope {
+    /// All services of the config.
+    Cluster,
+    /// Services deployed to the tier, according to `_pico_service`.
+    Tier(String),
+    /// Listed services, each of them must be present in the config.
+    Services(Vec<String>),
+}
+
+impl ConfigScope {
+    /// Returns services of the scope or `None` for the whole cluster.
+    fn services(&self, cluster: &Cluster) -> anyhow::Result<Option<Vec<String>>> {
+        match self {
+            Self::Cluster => Ok(None),
+            Self::Tier(tier) => {
+                let services = cluster
+                    .catalog()
+                    .services()?
+                    .into_iter()
+                    .filter(|service| service.tiers.contains(tier))
+                    .map(|service| service.name)
+                    .collect();
+                Ok(Some(services))
+            }
+            Self::Services(services) => Ok(Some(services.clone())),
+        }
+    }
+}
+
+impl Display for ConfigScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cluster => write!(f, "cluster"),
+            Self::Tier(tier) => write!(f, "tier '{tier}'"),
+            Self::Services(services) => write!(f, "services {services:?}"),
+        }
+    }
+}
+
+impl PicotestInstance {
+    /// Reads configuration of all services of the plugin as seen by the instance.
+    pub fn plugin_config(&self, plugin_name: &str) -> anyhow::Result<PluginConfigMap> {
+        let mut config = PluginConfigMap::new();
+        for entry in self.catalog().plugin_config(plugin_name)? {
+            config
+                .entry(entry.entity)
+                .or_default()
+                .insert(entry.key, entry.value);
+        }
+        Ok(config)
+    }
+
+    /// Reads configuration of the service as seen by the instance
+    /// and deserializes it into `T`.
+    pub fn get_service_config<T: DeserializeOwned>(&self, service_name: &str) -> anyhow::Result<T> {
+        let entries = self.catalog().service_config(service_name)?;
+        if entries.is_empty() {
+            bail!("service '{service_name}' has no configuration");
+        }
+
+        let config: Mapping = entries
+            .into_iter()
+            .map(|entry| (Value::String(entry.key), entry.value))
+            .collect();
+        serde_norway::from_value(Value::Mapping(config))
+            .with_context(|| format!("failed to deserialize config of service '{service_name}'"))
+    }
+}
+
+impl Cluster {
+    /// Reads configuration of all services of the plugin from the main instance.
+    pub fn plugin_config(&self, plugin_name: &str) -> anyhow::Result<PluginConfigMap> {
+        self.main().plugin_config(plugin_name)
+    }
+
+    /// Reads configuration of the service from the main instance
+    /// and deserializes it into `T`.
+    ///
+    /// **Note:** service is looked up by its name only, so service names
+    /// must be unique among plugins installed into the cluster.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct RouterConfig {
+    ///     rpc_endpoint: String,
+    ///     max_rpc_message_size_bytes: u64,
+    /// }
+    ///
+    /// #[picotest]
+    /// fn test_get_service_config() {
+    ///     let config: RouterConfig = cluster.get_service_config("router").unwrap();
+    ///     assert_eq!(config.rpc_endpoint, "/hello");
+    /// }
+    /// ```
+    pub fn get_service_config<T: DeserializeOwned>(&self, service_name: &str) -> anyhow::Result<T> {
+        self.main().get_service_config(service_name)
+    }
+
+    /// Applies configuration of a single service represented by
+    /// any serializable type through [`Cluster::apply_config`].
+    ///
+    /// `config` must serialize into a mapping, i.e. be a struct or a map.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct RouterConfig {
+    ///     rpc_endpoint: String,
+    ///     max_rpc_message_size_bytes: u64,
+    /// }
+    ///
+    /// #[picotest]
+    /// fn test_apply_service_config() {
+    ///     let config = RouterConfig {
+    ///         rpc_endpoint: "/test".into(),
+    ///         max_rpc_message_size_bytes: 128,
+    ///     };
+    ///     cluster
+    ///         .apply_service_config("router", &config)
+    ///         .expect("Failed to apply config");
+    /// }
+    /// ```
+    pub fn apply_service_config<T: Serialize>(
+        &self,
+        service_name: &str,
+        config: &T,
+    ) -> anyhow::Result<()> {
+        let service_config = service_config_map(service_name, config)?;
+        self.apply_config(HashMap::from([(service_name.to_string(), service_config)]))
+    }
+
+    /// Applies part of the plugin config selected by `scope` through
+    /// [`Cluster::apply_config`], so services out of the scope keep
+    /// their config.
+    ///
+    /// ### Returns
+    /// - On success, applied part of the config.
+    /// - On failure, instance of [`anyhow::Error`], e.g. if config has no
+    ///   services of the scope.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use picotest::ConfigScope;
+    ///
+    /// #[picotest]
+    /// fn test_storage_config() {
+    ///     let plugin_config: PluginConfigMap = serde_norway::from_str(
+    ///         r#"
+    ///         router:
+    ///             timeout: 1
+    ///         storage:
+    ///             timeout: 10
+    ///         "#,
+    ///     )
+    ///     .unwrap();
+    ///
+    ///     let applied = cluster
+    ///         .apply_config_scoped(plugin_config, &ConfigScope::Services(vec!["storage".into()]))
+    ///         .unwrap();
+    ///     assert!(!applied.contains_key("router"));
+    /// }
+    /// ```
+    pub fn apply_config_scoped<T>(
+        &self,
+        config: T,
+        scope: &ConfigScope,
+    ) -> anyhow::Result<PluginConfigMap>
+    where
+        T: Into<PluginConfigMap>,
+    {
+        let config = config.into();
+        let config = match scope.services(self)? {
+            Some(services) => select_services(config, &services, scope)?,
+            None => config,
+        };
+        self.apply_config(config.clone())?;
+        Ok(config)
+    }
+
+    /// Applies config of the services deployed to the `tier`,
+    /// see [`Cluster::apply_config_scoped`].
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// #[picotest]
+    /// fn test_router_tier_config() {
+    ///     let config: PluginConfigMap =
+    ///         serde_norway::from_str("router: {timeout: 1}").unwrap();
+    ///     cluster.apply_config_on_tier("router", config).unwrap();
+    /// }
+    /// ```
+    pub fn apply_config_on_tier<T>(&self, tier: &str, config: T) -> anyhow::Result<PluginConfigMap>
+    where
+        T: Into<PluginConfigMap>,
+    {
+        self.apply_config_scoped(config, &ConfigScope::Tier(tier.to_string()))
+    }
+
+    /// Applies plugin config like [`Cluster::apply_config`] and waits until
+    /// all applied values become visible in `_pico_plugin_config` on every
+    /// cluster instance.
+    ///
+    /// ### Arguments:
+    ///
+    /// - `plugin_name` - name of the plugin which config is applied.
+    /// - `config` - mapping of plugin services to their values.
+    /// - `timeout` - how long to wait for the config to be applied.
+    ///   See [`DEFAULT_APPLY_CONFIG_TIMEOUT`].
+    ///
+    /// ### Returns
+    ///
+    /// - On success, effective configuration of all plugin services
+    ///   as seen by the main instance.
+    /// - On failure, instance of [`anyhow::Error`]. Timeout is a failure too.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    ///
+    /// #[picotest]
+    /// fn test_apply_config_and_wait() {
+    ///     let plugin_config: PluginConfigMap = serde_norway::from_str(
+    ///         r#"
+    ///         router:
+    ///             rpc_endpoint: "/test"
+    ///         "#,
+    ///     )
+    ///     .unwrap();
+    ///
+    ///     let effective = cluster
+    ///         .apply_config_and_wait("plugin", plugin_config, DEFAULT_APPLY_CONFIG_TIMEOUT)
+    ///         .expect("Failed to apply config");
+    ///
+    ///     assert_eq!(effective["router"]["rpc_endpoint"], "/test");
+    /// }
+    /// ```
+    pub fn apply_config_and_wait<T>(
+        &self,
+        plugin_name: &str,
+        config: T,
+        timeout: Duration,
+    ) -> anyhow::Result<PluginConfigMap>
+    where
+        T: Into<PluginConfigMap>,
+    {
+        let expected: PluginConfigMap = config.into();
+        self.apply_config(expected.clone())?;
+
+        let mut progress = self.progress(format!("config apply of plugin '{plugin_name}'"));
+        let start_time = Instant::now();
+        let mut pending: Vec<&PicotestInstance> = self.instances().iter().collect();
+        loop {
+            let mut still_pending = Vec::with_capacity(pending.len());
+            let mut findings = Vec::with_capacity(pending.len());
+            for instance in pending {
+                let actual = instance.plugin_config(plugin_name)?;
+                if let Some((service, key)) = first_mismatch(&expected, &actual) {
+                    findings.push(format!(
+                        "{} (key '{service}.{key}')",
+                        instance.instance_name
+                    ));
+                    still_pending.push(instance);
+                }
+            }
+            pending = still_pending;
+            progress.report(findings);
+
+            if pending.is_empty() {
+                return self.plugin_config(plugin_name);
+            }
+
+            if start_time.elapsed() > timeout {
+                let names: Vec<&str> = pending.iter().map(|i| i.instance_name.as_str()).collect();
+                bail!(
+                    "config of plugin '{plugin_name}' is not applied on {names:?} within {}s",
+                    timeout.as_secs_f64()
+                );
+            }
+            thread::sleep(APPLY_CONFIG_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Converts serializable service config into mapping of its keys to values.
+fn service_config_map<T: Serialize>(
+    service_name: &str,
+    config: &T,
+) -> anyhow::Result<HashMap<String, Value>> {
+    let value = serde_norway::to_value(config)
+        .with_context(|| format!("failed to serialize config of service '{service_name}'"))?;
+    let Value::Mapping(mapping) = value else {
+        bail!("config of service '{service_name}' must be a mapping, got: {value:?}");
+    };
+
+    mapping
+        .into_iter()
+        .map(|(key, value)| match key {
+            Value::String(key) => Ok((key, value)),
+            key => bail!("config of service '{service_name}' has non-string key {key:?}"),
+        })
+        .collect()
+}
+
+/// Keeps config of the `services` of the `scope` only.
+fn select_services(
+    mut config: PluginConfigMap,
+    services: &[String],
+    scope: &ConfigScope,
+) -> anyhow::Result<PluginConfigMap> {
+    if let ConfigScope::Services(_) = scope {
+        if let Some(missing) = services
+            .iter()
+            .find(|service| !config.contains_key(*service))
+        {
+            bail!("config has no service '{missing}'");
+        }
+    }
+    config.retain(|service, _| services.contains(service));
+    if config.is_empty() {
+        bail!("config has no services of {scope}, which has {services:?}");
+    }
+    Ok(config)
+}
+
+/// Returns service and key of the first expected value
+/// which is missing or differs in actual config.
+fn first_mismatch<'a>(
+    expected: &'a PluginConfigMap,
+    actual: &PluginConfigMap,
+) -> Option<(&'a str, &'a str)> {
+    expected.iter().find_map(|(service, values)| {
+        values.iter().find_map(|(key, value)| {
+            let actual_value = actual.get(service).and_then(|values| values.get(key));
+            (actual_value != Some(value)).then_some((service.as_str(), key.as_str()))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_mismatch, select_services, service_config_map, ConfigScope};
+    use crate::PluginConfigMap;
+    use rstest::rstest;
+    use serde::Serialize;
+    use serde_norway::Value;
+
+    fn config(yaml: &str) -> PluginConfigMap {
+        serde_norway::from_str(yaml).unwrap()
+    }
+
+    #[rstest]
+    fn test_first_mismatch() {
+        let expected = config("router: {endpoint: /test, size: 128}");
+
+        assert!(matches!(
+            first_mismatch(&expected, &config("storage: {size: 1}")),
+            Some(("router", _))
+        ));
+        assert_eq!(
+            first_mismatch(&expected, &config("router: {endpoint: /test, size: 64}")),
+            Some(("router", "size"))
+        );
+        assert_eq!(
+            first_mismatch(
+                &expected,
+                &config("router: {endpoint: /test, size: 128, extra: true}")
+            ),
+            None,
+            "extra keys are allowed"
+        );
+    }
+
+    #[derive(Serialize)]
+    struct RouterConfig {
+        rpc_endpoint: String,
+        max_rpc_message_size_bytes: u64,
+        nested: Vec<u8>,
+    }
+
+    #[rstest]
+    fn test_service_config_map() {
+        let config = RouterConfig {
+            rpc_endpoint: "/test".into(),
+            max_rpc_message_size_bytes: 128,
+            nested: vec![1, 2],
+        };
+        let map = service_config_map("router", &config).unwrap();
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["rpc_endpoint"], Value::String("/test".into()));
+        assert_eq!(map["max_rpc_message_size_bytes"], Value::Number(128.into()));
+    }
+
+    #[rstest]
+    fn test_service_config_map_not_a_mapping() {
+        assert!(service_config_map("router", &42).is_err());
+        assert!(service_config_map("router", &vec!["a"]).is_err());
+    }
+
+    #[rstest]
+    fn test_select_tier_services() {
+        let scope = ConfigScope::Tier("storage".into());
+        let services = ["storage".to_string(), "cache".to_string()];
+        let config = config("router: {size: 1}\nstorage: {size: 2}");
+
+        let selected = select_services(config.clone(), &services, &scope).unwrap();
+        assert_eq!(selected, self::config("storage: {size: 2}"));
+
+        let err = select_services(config, &services[1..], &scope).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config has no services of tier 'storage', which has [\"cache\"]"
+        );
+    }
+
+    #[rstest]
+    fn test_select_listed_services() {
+        let services = ["router".to_string()];
+        let scope = ConfigScope::Services(services.to_vec());
+        let config = config("router: {size: 1}\nstorage: {size: 2}");
+
+        let selected = select_services(config, &services, &scope).unwrap();
+        assert_eq!(selected, self::config("router: {size: 1}"));
+
+        let err = select_services(self::config("storage: {size: 2}"), &services, &scope);
+        assert!(err.is_err_and(|err| err.to_string() == "config has no service 'router'"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Installation of the plugin from its packaged artifact.
+//!
+//! By default, cluster loads the plugin from the build directory of the
+//! plugin (e.g. `target/debug`). To verify the shipping path, plugin can be
+//! packed by `pike plugin pack` instead, and the resulting archive is
+//! unpacked into a separate target directory the cluster is started from.
+
+use crate::freeze::run_tar;
+use crate::pike_command::run_pike_captured;
+use crate::plugin_build::BuildProfile;
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Target directory (relative to the plugin path) the packaged plugin is unpacked into.
+pub const PACKAGE_TARGET_DIR: &str = "tmp/package";
+
+/// Where cluster installs the plugin from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PluginSource {
+    /// Build directory of the plugin.
+    #[default]
+    Build,
+    /// Archive produced by `pike plugin pack`.
+    Package,
+}
+
+impl FromStr for PluginSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "build" => Ok(Self::Build),
+            "package" => Ok(Self::Package),
+            _ => bail!("unknown plugin source '{s}', expected one of: build, package"),
+        }
+    }
+}
+
+/// Packs the plugin located by `plugin_path` built with `profile`
+/// by `pike plugin pack`.
+///
+/// ### Returns
+///
+/// - On success, path to the produced archive.
+/// - On failure, instance of [`anyhow::Error`].
+pub fn pack_plugin(plugin_path: &Path, profile: BuildProfile) -> anyhow::Result<PathBuf> {
+    let started_at = SystemTime::now();
+    // Pike packs release build unless asked otherwise.
+    let mut args = vec!["plugin", "pack"];
+    if !profile.is_release() {
+        args.push("--debug");
+    }
+    run_pike_captured(args, plugin_path, None)?.check()?;
+
+    let profile_path = profile.build_path(plugin_path);
+    newest_archive(&profile_path)
+        .filter(|(_, modified)| *modified >= started_at)
+        .map(|(path, _)| path)
+        .with_context(|| format!("packed plugin is not found in '{}'", profile_path.display()))
+}
+
+/// Unpacks plugin `archive` into `profile` directory
+/// of [`PACKAGE_TARGET_DIR`] of the plugin.
+///
+/// ### Returns
+///
+/// - On success, target directory to start the cluster from.
+/// - On failure, instance of [`anyhow::Error`].
+pub fn unpack_plugin(
+    plugin_path: &Path,
+    archive: &Path,
+    profile: BuildProfile,
+) -> anyhow::Result<PathBuf> {
+    let target_dir = plugin_path.join(PACKAGE_TARGET_DIR);
+    let profile_path = target_dir.join(profile.dir_name());
+    if target_dir.exists() {
+        fs::remove_dir_all(&target_dir)
+            .with_context(|| format!("failed to remove '{}'", target_dir.display()))?;
+    }
+    fs::create_dir_all(&profile_path)
+        .with_context(|| format!("failed to create '{}'", profile_path.display()))?;
+
+    run_tar(
+        Command::new("tar")
+            .arg("-xzf")
+            .arg(archive)
+            .arg("-C")
+            .arg(&profile_path),
+    )?;
+    Ok(target_dir)
+}
+
+fn newest_archive(dir: &Path) -> Option<(PathBuf, SystemTime)> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(".tar.gz"))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .max_by_key(|(_, modified)| *modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PluginSource;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("build", PluginSource::Build)]
+    #[case("Package", PluginSource::Package)]
+    fn test_plugin_source_from_str(#[case] value: &str, #[case] expected: PluginSource) {
+        assert_eq!(value.parse::<PluginSource>().unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_plugin_source_from_invalid_str() {
+        assert!("registry".parse::<PluginSource>().is_err());
+    }
+}
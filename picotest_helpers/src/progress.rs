@@ -0,0 +1,196 @@
+//! Progress of waiting for the cluster.
+//!
+//! Polling loops of picotest (cluster start, [`Cluster::wait_ready`], config
+//! and migrations apply) report findings of every poll as [`ProgressEvent`]
+//! to the callback set by [`Cluster::on_progress`]. Events are logged at debug
+//! level too and, if [`PICOTEST_PROGRESS`] is set, rendered as a progress line
+//! on stderr, so the output of tests isn't polluted.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let cluster = Cluster::new(plugin_path, topology, picodata_path)?
+//!     .on_progress(|event| println!("{event}"))
+//!     .run()?;
+//! ```
+
+use crate::Cluster;
+use log::debug;
+use std::env;
+use std::fmt::{self, Display};
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Environment variable enabling progress line on stderr, e.g. `PICOTEST_PROGRESS=1`.
+pub const PICOTEST_PROGRESS: &str = "PICOTEST_PROGRESS";
+
+/// Callback receiving progress of waiting, see [`Cluster::on_progress`].
+pub type ProgressCallback = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+/// Findings of a single poll of the waiting loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressEvent {
+    /// What is being waited for, e.g. `cluster readiness`.
+    pub operation: String,
+    /// Number of the poll starting from 1.
+    pub poll: u32,
+    /// Time passed since waiting has started.
+    pub elapsed: Duration,
+    /// What the operation is still waiting on, e.g. instances which
+    /// aren't online yet. Empty if waiting has finished successfully.
+    pub pending: Vec<String>,
+}
+
+impl ProgressEvent {
+    /// Returns `true` if nothing is pending anymore.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Display for ProgressEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5.1}s] {}: ",
+            self.elapsed.as_secs_f64(),
+            self.operation
+        )?;
+        if self.is_done() {
+            write!(f, "done")
+        } else {
+            write!(f, "waiting on {}", self.pending.join(", "))
+        }
+    }
+}
+
+/// Reports progress of a single waiting loop.
+pub(crate) struct ProgressReporter {
+    callback: Option<ProgressCallback>,
+    terminal: Option<bool>,
+    operation: String,
+    poll: u32,
+    start_time: Instant,
+}
+
+impl ProgressReporter {
+    /// Reports findings of the next poll. Empty `pending` means
+    /// that waiting has finished successfully.
+    pub(crate) fn report(&mut self, pending: Vec<String>) {
+        self.poll += 1;
+        let event = ProgressEvent {
+            operation: self.operation.clone(),
+            poll: self.poll,
+            elapsed: self.start_time.elapsed(),
+            pending,
+        };
+        debug!("{event}");
+        if let Some(callback) = &self.callback {
+            callback(&event);
+        }
+        if let Some(is_terminal) = self.terminal {
+            print_progress_line(&event, is_terminal);
+        }
+    }
+}
+
+/// Prints event on stderr. Terminal line is rewritten by the next event of
+/// the operation, otherwise every event is printed on a separate line.
+fn print_progress_line(event: &ProgressEvent, is_terminal: bool) {
+    let mut stderr = std::io::stderr().lock();
+    let _ = if !is_terminal {
+        writeln!(stderr, "{event}")
+    } else if event.is_done() {
+        writeln!(stderr, "\r\x1b[2K{event}")
+    } else {
+        write!(stderr, "\r\x1b[2K{event}").and_then(|_| stderr.flush())
+    };
+}
+
+impl Cluster {
+    /// Sets callback receiving findings of every poll, while picotest waits
+    /// for the cluster: its start, readiness, config or migrations apply.
+    ///
+    /// ### Examples:
+    /// ```rust,ignore
+    /// let cluster = Cluster::new(plugin_path, topology, picodata_path)?
+    ///     .on_progress(|event| {
+    ///         if !event.is_done() {
+    ///             println!("{}: {:?}", event.operation, event.pending);
+    ///         }
+    ///     })
+    ///     .run()?;
+    /// ```
+    pub fn on_progress(
+        mut self,
+        callback: impl Fn(&ProgressEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Starts reporting progress of the waiting `operation`.
+    pub(crate) fn progress(&self, operation: impl Into<String>) -> ProgressReporter {
+        let terminal = env::var_os(PICOTEST_PROGRESS)
+            .filter(|value| !value.is_empty() && value != "0" && value != "false")
+            .map(|_| std::io::stderr().is_terminal());
+        ProgressReporter {
+            callback: self.progress.clone(),
+            terminal,
+            operation: operation.into(),
+            poll: 0,
+            start_time: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProgressEvent, ProgressReporter};
+    use rstest::rstest;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[rstest]
+    fn test_progress_event_display() {
+        let mut event = ProgressEvent {
+            operation: "cluster readiness".to_string(),
+            poll: 3,
+            elapsed: Duration::from_millis(2500),
+            pending: vec![
+                "default_1_1 (Offline)".to_string(),
+                "plugin 'weather' is disabled".to_string(),
+            ],
+        };
+        assert_eq!(
+            event.to_string(),
+            "[  2.5s] cluster readiness: waiting on default_1_1 (Offline), plugin 'weather' is disabled"
+        );
+
+        event.pending.clear();
+        assert_eq!(event.to_string(), "[  2.5s] cluster readiness: done");
+    }
+
+    #[rstest]
+    fn test_progress_reporter() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collected = events.clone();
+        let mut reporter = ProgressReporter {
+            callback: Some(Arc::new(move |event: &ProgressEvent| {
+                collected.lock().unwrap().push(event.clone())
+            })),
+            terminal: None,
+            operation: "config apply".to_string(),
+            poll: 0,
+            start_time: Instant::now(),
+        };
+
+        reporter.report(vec!["default_1_1".to_string()]);
+        reporter.report(Vec::new());
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].poll, events[0].is_done()), (1, false));
+        assert_eq!((events[1].poll, events[1].is_done()), (2, true));
+    }
+}
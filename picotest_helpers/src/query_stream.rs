@@ -0,0 +1,211 @@
+//! Streaming output of admin console queries.
+//!
+//! Queries executed by `run_sql` and `run_lua` buffer the whole output, which
+//! is limited by [`Cluster::max_query_output`]. [`PicotestInstance::run_query_stream`]
+//! returns output by lines instead, as admin console prints it, so queries
+//! selecting huge tables don't exhaust memory of the test process.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_large_table() {
+//!     let rows = cluster
+//!         .main()
+//!         .run_query_stream("SELECT * FROM events;")
+//!         .unwrap()
+//!         .filter(|line| line.as_ref().is_ok_and(|line| line.starts_with("  - [")))
+//!         .count();
+//!     assert_eq!(rows, 1_000_000);
+//! }
+//! ```
+
+use crate::cassette;
+use crate::{Cluster, PicotestInstance, OUTPUT_FOOTER};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Lines, Read, Write};
+use std::process::{Child, ChildStdout};
+use std::vec;
+
+/// Number of header lines printed by admin console before the output.
+const HEADER_LINES: usize = 2;
+
+/// Lines of the query output, see [module docs](self).
+///
+/// Admin console is killed when the stream is dropped,
+/// so the query output may be left unread.
+pub struct QueryStream {
+    source: Source,
+    instance_name: String,
+    finished: bool,
+}
+
+enum Source {
+    Console {
+        admin: Child,
+        lines: Lines<BufReader<ChildStdout>>,
+    },
+    /// Output of the query recorded by the active cassette.
+    Recorded(vec::IntoIter<String>),
+}
+
+impl Iterator for QueryStream {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let lines = match &mut self.source {
+            Source::Console { lines, .. } => lines,
+            Source::Recorded(lines) => return lines.next().map(Ok),
+        };
+        match lines.next() {
+            Some(Ok(line)) if line == OUTPUT_FOOTER => {
+                self.finished = true;
+                None
+            }
+            Some(Ok(line)) => Some(Ok(line)),
+            Some(Err(err)) => {
+                self.finished = true;
+                Some(Err(err))
+            }
+            None => {
+                self.finished = true;
+                Some(Err(self.disconnect_error()))
+            }
+        }
+    }
+}
+
+impl QueryStream {
+    /// Describes exit of admin console before the end of the output.
+    fn disconnect_error(&mut self) -> Error {
+        let mut message = format!(
+            "admin console of instance '{}' has exited before the end of output",
+            self.instance_name
+        );
+        if let Source::Console { admin, .. } = &mut self.source {
+            let mut stderr = String::new();
+            if let Some(mut admin_stderr) = admin.stderr.take() {
+                let _ = admin_stderr.read_to_string(&mut stderr);
+            }
+            if !stderr.trim().is_empty() {
+                message = format!("{message}: {}", stderr.trim());
+            }
+        }
+        Error::new(ErrorKind::UnexpectedEof, message)
+    }
+}
+
+impl Drop for QueryStream {
+    fn drop(&mut self) {
+        if let Source::Console { admin, .. } = &mut self.source {
+            let _ = admin.kill();
+            let _ = admin.wait();
+        }
+    }
+}
+
+impl PicotestInstance {
+    /// Executes raw query through admin console like `run_sql` and returns
+    /// its output by lines, see [module docs](self).
+    ///
+    /// Stream isn't limited by `query_timeout` and admin console isn't
+    /// restarted if it has disconnected: error is returned as the last line.
+    /// If cassette is active, query is recorded with the whole output.
+    pub fn run_query_stream<T: AsRef<[u8]>>(&self, query: T) -> Result<QueryStream, Error> {
+        if cassette::active().is_some() {
+            let output = self.run_query(query)?;
+            let lines: Vec<String> = output.lines().map(str::to_string).collect();
+            return Ok(QueryStream {
+                source: Source::Recorded(lines.into_iter()),
+                instance_name: self.instance_name.clone(),
+                finished: false,
+            });
+        }
+
+        let mut admin = self.await_picodata_admin()?;
+        let stdout = admin.stdout.take().expect("Failed to capture stdout");
+        // Closed stdin makes admin console exit once the query is done.
+        let written = admin.stdin.take().unwrap().write_all(query.as_ref());
+        let mut stream = QueryStream {
+            source: Source::Console {
+                admin,
+                lines: BufReader::new(stdout).lines(),
+            },
+            instance_name: self.instance_name.clone(),
+            finished: false,
+        };
+        written?;
+        for _ in 0..HEADER_LINES {
+            if let Some(Err(err)) = stream.next() {
+                return Err(err);
+            }
+        }
+        Ok(stream)
+    }
+}
+
+impl Cluster {
+    /// Executes raw query on the main instance and returns its output by lines.
+    /// See [`PicotestInstance::run_query_stream`].
+    pub fn run_query_stream<T: AsRef<[u8]>>(&self, query: T) -> Result<QueryStream, Error> {
+        self.main().run_query_stream(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryStream, Source};
+    use crate::read_output;
+    use rstest::rstest;
+    use std::io::{BufRead, BufReader, ErrorKind};
+    use std::process::{Command, Stdio};
+
+    fn console_stream(output: &str) -> QueryStream {
+        let mut admin = Command::new("printf")
+            .arg(output)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = admin.stdout.take().unwrap();
+        QueryStream {
+            source: Source::Console {
+                admin,
+                lines: BufReader::new(stdout).lines(),
+            },
+            instance_name: "default_1_1".into(),
+            finished: false,
+        }
+    }
+
+    #[rstest]
+    fn test_stream_until_footer() {
+        let lines: Vec<String> = console_stream("---\\n- [1]\\n...\\nBye\\nignored\\n")
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, ["---", "- [1]", "..."]);
+    }
+
+    #[rstest]
+    fn test_stream_without_footer() {
+        let mut stream = console_stream("---\\n- [1]\\n");
+        assert_eq!(stream.next().unwrap().unwrap(), "---");
+        assert_eq!(stream.next().unwrap().unwrap(), "- [1]");
+        let err = stream.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(stream.next().is_none());
+    }
+
+    #[rstest]
+    fn test_read_output_limit() {
+        let console = "Connected to admin console\nType '\\help'\n- [1]\n- [2]\nBye\n";
+
+        let output = read_output(console.as_bytes(), console.len()).unwrap();
+        assert!(output.complete && !output.truncated);
+        assert_eq!(output.text, "- [1]\n- [2]");
+
+        let output = read_output(console.as_bytes(), console.len() - 10).unwrap();
+        assert!(!output.complete && output.truncated);
+    }
+}
@@ -0,0 +1,245 @@
+//! Running tests against remote cluster, e.g. pre-production one.
+//!
+//! Instances of the remote cluster are described by a TOML file:
+//!
+//! ```toml
+//! [[instance]]
+//! ssh = "qa@staging-1"
+//! socket_path = "/var/lib/picodata/i1/admin.sock"
+//!
+//! [[instance]]
+//! ssh = "qa@staging-2"
+//! socket_path = "/var/lib/picodata/i2/admin.sock"
+//! host = "10.0.0.2"
+//! pg_port = 5432
+//! ```
+//!
+//! Queries are executed by admin console of the instance started over SSH,
+//! so `run_query`, `run_lua` and introspection work unchanged. Ports of
+//! iproto and pgproto are reached at `host` (host of SSH destination by
+//! default). Cluster isn't managed by picotest: pike isn't involved, and
+//! instance lifecycle operations (stop, shutdown, kill) return an error.
+
+use crate::launcher::shell_quote;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environment variable with path to the remote cluster config.
+pub const PICOTEST_REMOTE_CONFIG: &str = "PICOTEST_REMOTE_CONFIG";
+
+/// Description of the remote cluster.
+#[derive(Debug, Deserialize)]
+pub struct RemoteConfig {
+    #[serde(rename = "instance")]
+    pub instances: Vec<RemoteInstanceConfig>,
+}
+
+/// Description of the remote cluster instance.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteInstanceConfig {
+    /// Destination passed to `ssh`, e.g. `user@host`.
+    pub ssh: String,
+    /// Additional options of `ssh`, e.g. `["-p", "2222"]`.
+    #[serde(default)]
+    pub ssh_options: Vec<String>,
+    /// Path to the admin socket on the remote host.
+    pub socket_path: PathBuf,
+    /// Address iproto and pgproto ports are reachable at.
+    /// Host of `ssh` destination is used if it's omitted.
+    pub host: Option<String>,
+    /// Overrides pgproto port, which is derived from raft id like pike does.
+    pub pg_port: Option<u16>,
+    /// Overrides HTTP port, which is derived from raft id like pike does.
+    pub http_port: Option<u16>,
+}
+
+impl RemoteConfig {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("failed to parse remote cluster config '{}'", path.display()))
+    }
+}
+
+/// How admin console of the remote instance is reached.
+#[derive(Debug, Clone)]
+pub(crate) struct RemoteTarget {
+    ssh: String,
+    ssh_options: Vec<String>,
+    pub(crate) host: String,
+}
+
+impl RemoteTarget {
+    /// Returns command running admin console of the instance over SSH.
+    pub(crate) fn admin_command(&self, socket_path: &Path) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .args(["-T", "-o", "BatchMode=yes"])
+            .args(&self.ssh_options)
+            .arg(&self.ssh)
+            .arg("picodata")
+            .arg("admin")
+            .arg(remote_path(socket_path));
+        command
+    }
+
+    /// Returns command attaching interactive admin console of the instance over SSH.
+    pub(crate) fn attach_command(&self, socket_path: &Path) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-t")
+            .args(&self.ssh_options)
+            .arg(&self.ssh)
+            .arg("picodata")
+            .arg("admin")
+            .arg(remote_path(socket_path));
+        command
+    }
+}
+
+/// Quotes `path` for the remote shell, which SSH passes the command to
+/// as a single string.
+fn remote_path(path: &Path) -> String {
+    shell_quote(&path.to_string_lossy())
+}
+
+/// Extracts host from SSH destination, i.e. `[ssh://][user@]host[:port]`.
+fn ssh_host(destination: &str) -> &str {
+    let (destination, has_scheme) = match destination.strip_prefix("ssh://") {
+        Some(destination) => (destination, true),
+        None => (destination, false),
+    };
+    let host = destination.rsplit('@').next().unwrap_or(destination);
+    if has_scheme {
+        host.split(':').next().unwrap_or(host)
+    } else {
+        host
+    }
+}
+
+impl PicotestInstance {
+    /// Connects to the remote instance over SSH and discovers its properties.
+    pub fn attach_remote(config: &RemoteInstanceConfig) -> anyhow::Result<Self> {
+        let remote = RemoteTarget {
+            ssh: config.ssh.clone(),
+            ssh_options: config.ssh_options.clone(),
+            host: config
+                .host
+                .clone()
+                .unwrap_or_else(|| ssh_host(&config.ssh).to_string()),
+        };
+        let mut instance = Self::discover(config.socket_path.clone(), Some(remote))
+            .with_context(|| format!("failed to attach to remote instance '{}'", config.ssh))?;
+        if let Some(pg_port) = config.pg_port {
+            instance.pg_port = pg_port;
+        }
+        if let Some(http_port) = config.http_port {
+            instance.http_port = http_port;
+        }
+        Ok(instance)
+    }
+
+    /// Returns `true` if instance belongs to a remote cluster.
+    pub fn is_remote(&self) -> bool {
+        self.remote.is_some()
+    }
+}
+
+impl Cluster {
+    /// Connects to the remote cluster described by config file.
+    /// See [module documentation](crate::remote) for the config format.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// let cluster = Cluster::remote(plugin_path, Path::new("staging.toml"))?;
+    /// cluster.run_sql("SELECT * FROM users")?;
+    /// ```
+    pub fn remote(plugin_path: PathBuf, config_path: &Path) -> anyhow::Result<Self> {
+        let config = RemoteConfig::from_file(config_path)?;
+        let instances = config
+            .instances
+            .iter()
+            .map(PicotestInstance::attach_remote)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Self::attached(plugin_path, PathBuf::new(), instances).with_context(|| {
+            format!(
+                "failed to attach to cluster from '{}'",
+                config_path.display()
+            )
+        })
+    }
+
+    /// Returns `true` if cluster was attached by [`Cluster::remote`].
+    pub fn is_remote(&self) -> bool {
+        self.instances().iter().any(PicotestInstance::is_remote)
+    }
+
+    /// Returns an error if lifecycle `operation` can't be
+    /// performed, because cluster is remote.
+    pub(crate) fn ensure_not_remote(&self, operation: &str) -> anyhow::Result<()> {
+        if self.is_remote() {
+            bail!("{operation} is not supported by remote cluster");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ssh_host, RemoteConfig, RemoteTarget};
+    use rstest::rstest;
+    use std::path::Path;
+
+    #[rstest]
+    #[case("host", "host")]
+    #[case("qa@staging-1", "staging-1")]
+    #[case("ssh://qa@10.0.0.1:2222", "10.0.0.1")]
+    fn test_ssh_host(#[case] destination: &str, #[case] expected: &str) {
+        assert_eq!(ssh_host(destination), expected);
+    }
+
+    #[rstest]
+    fn test_admin_command_quotes_socket_path() {
+        let target = RemoteTarget {
+            ssh: "qa@staging-1".into(),
+            ssh_options: Vec::new(),
+            host: "staging-1".into(),
+        };
+        let command = target.admin_command(Path::new("/var/lib/it's here/admin.sock"));
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args.last().unwrap().to_string_lossy(),
+            r"'/var/lib/it'\''s here/admin.sock'"
+        );
+    }
+
+    #[rstest]
+    fn test_remote_config() {
+        let config: RemoteConfig = toml::from_str(
+            r#"
+            [[instance]]
+            ssh = "qa@staging-1"
+            socket_path = "/var/lib/picodata/i1/admin.sock"
+
+            [[instance]]
+            ssh = "qa@staging-2"
+            ssh_options = ["-p", "2222"]
+            socket_path = "/var/lib/picodata/i2/admin.sock"
+            host = "10.0.0.2"
+            pg_port = 5433
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.instances.len(), 2);
+        assert_eq!(config.instances[0].host, None);
+        assert_eq!(config.instances[1].ssh_options, ["-p", "2222"]);
+        assert_eq!(config.instances[1].pg_port, Some(5433));
+    }
+}
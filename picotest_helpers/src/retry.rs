@@ -0,0 +1,167 @@
+//! Retrying of cluster operations prone to transient failures.
+//!
+//! Cluster start, creation of picotest users and applying of plugin config
+//! may fail for reasons unrelated to the plugin, e.g. because of port
+//! binding races or slow disks on CI. Such operations are retried according
+//! to [`RetryPolicy`] of the cluster, see [`Cluster::retry_policy`](crate::Cluster::retry_policy).
+
+use anyhow::Context;
+use log::warn;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Default delay before the first retry.
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound of the delay between retries.
+pub const DEFAULT_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How many times and how often failed operation is retried.
+///
+/// Delay between attempts starts from `backoff` and doubles
+/// after every failed attempt, but doesn't exceed `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Operations are performed once, without retries.
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl RetryPolicy {
+    /// Creates policy performing up to `attempts` attempts with default backoff.
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            backoff: DEFAULT_RETRY_BACKOFF,
+            max_backoff: DEFAULT_RETRY_MAX_BACKOFF,
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Returns delay before attempt following the failed `attempt` (counted from 1).
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// Runs `operation` until it succeeds or attempts are exhausted.
+    /// Returns result of the last attempt.
+    ///
+    /// ### Arguments:
+    /// - `name` - name of the operation used in logs.
+    /// - `operation` - operation to perform, receives number of the attempt (counted from 1).
+    ///
+    pub fn run<T, E, F>(&self, name: &str, mut operation: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut(u32) -> Result<T, E>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation(attempt) {
+                Err(err) if attempt < self.attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{name} failed (attempt {attempt}/{}), retrying in {}s: {err:#}",
+                        self.attempts,
+                        delay.as_secs_f64()
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Removes whatever failed attempt has left in `dir` and creates it anew,
+/// so the next attempt starts from scratch rather than from partial state
+/// (e.g. instance bootstrapped halfway).
+pub(crate) fn reset_dir(dir: &Path) -> anyhow::Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("failed to remove '{}'", dir.display()))?;
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed to create '{}'", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reset_dir, RetryPolicy};
+    use rstest::rstest;
+    use std::fs;
+    use std::time::Duration;
+
+    #[rstest]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy::new(5)
+            .backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(3));
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(3));
+        assert_eq!(policy.delay(40), Duration::from_secs(3));
+    }
+
+    #[rstest]
+    #[case(3, 2, Ok(2))]
+    #[case(3, 5, Err(3))]
+    #[case(0, 5, Err(1))]
+    fn test_retry_policy_run(
+        #[case] attempts: u32,
+        #[case] failures: u32,
+        #[case] expected: Result<u32, u32>,
+    ) {
+        let policy = RetryPolicy::new(attempts).backoff(Duration::ZERO);
+
+        let result = policy.run("operation", |attempt| {
+            if attempt <= failures {
+                Err(attempt)
+            } else {
+                Ok(attempt - 1)
+            }
+        });
+        assert_eq!(result, expected);
+    }
+
+    #[rstest]
+    fn test_reset_dir() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("default_1_1")).unwrap();
+        fs::write(
+            dir.join("default_1_1").join("00000000000000000000.snap"),
+            "",
+        )
+        .unwrap();
+
+        reset_dir(&dir).unwrap();
+        let entries = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(entries, 0);
+
+        reset_dir(&dir).unwrap();
+        assert!(dir.is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
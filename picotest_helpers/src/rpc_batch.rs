@@ -0,0 +1,162 @@
+//! Concurrent RPC calls for load-style tests.
+//!
+//! Requests are multiplexed over the iproto connection pool of the instance,
+//! see [`Cluster::iproto_pool_size`].
+
+use crate::{Cluster, PicotestInstance};
+use futures_util::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// RPC request of the batch, see [`PicotestInstance::execute_rpc`] for the meaning of fields.
+#[derive(Debug, Clone)]
+pub struct RpcRequest<'a, S> {
+    pub plugin_name: &'a str,
+    pub path: &'a str,
+    pub service_name: &'a str,
+    pub plugin_version: &'a str,
+    pub input: S,
+}
+
+/// Latency percentiles of the batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyStats {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Computes nearest-rank percentiles of `latencies`.
+    pub fn from_latencies(latencies: &[Duration]) -> Self {
+        let mut sorted = latencies.to_vec();
+        sorted.sort();
+        Self {
+            p50: percentile(&sorted, 50),
+            p95: percentile(&sorted, 95),
+            max: sorted.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns nearest-rank `p`-th percentile of `sorted` latencies.
+pub(crate) fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied().unwrap_or_default()
+}
+
+/// Outcome of [`PicotestInstance::rpc_batch`].
+#[derive(Debug)]
+pub struct RpcBatchReport<G> {
+    /// Results in order of requests.
+    pub results: Vec<anyhow::Result<G>>,
+    /// Latencies in order of requests.
+    pub latencies: Vec<Duration>,
+    pub stats: LatencyStats,
+}
+
+impl<G> RpcBatchReport<G> {
+    /// Returns number of failed requests.
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|result| result.is_err()).count()
+    }
+}
+
+impl PicotestInstance {
+    /// Executes RPC `requests` on the instance, running up to `concurrency`
+    /// of them at once over the iproto connection pool of the instance.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// #[tokio::test]
+    /// #[picotest]
+    /// async fn test_rpc_throughput() {
+    ///     let requests = (0..1000).map(|i| RpcRequest {
+    ///         plugin_name: "plugin",
+    ///         path: "/greetings_rpc",
+    ///         service_name: "main",
+    ///         plugin_version: "0.1.0",
+    ///         input: User { name: format!("user_{i}") },
+    ///     });
+    ///     let report = cluster
+    ///         .main()
+    ///         .rpc_batch::<_, ExampleResponse, _>(requests, 32)
+    ///         .await;
+    ///     assert_eq!(report.failures(), 0);
+    ///     assert!(report.stats.p95 < Duration::from_millis(50));
+    /// }
+    /// ```
+    pub async fn rpc_batch<'a, S, G, I>(&self, requests: I, concurrency: usize) -> RpcBatchReport<G>
+    where
+        S: Serialize + 'a,
+        G: DeserializeOwned,
+        I: IntoIterator<Item = RpcRequest<'a, S>>,
+    {
+        let outcomes: Vec<(anyhow::Result<G>, Duration)> = stream::iter(requests)
+            .map(|request| async move {
+                let started_at = Instant::now();
+                let result = self
+                    .execute_rpc(
+                        request.plugin_name,
+                        request.path,
+                        request.service_name,
+                        request.plugin_version,
+                        &request.input,
+                    )
+                    .await;
+                (result, started_at.elapsed())
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await;
+
+        let (results, latencies): (Vec<_>, Vec<_>) = outcomes.into_iter().unzip();
+        RpcBatchReport {
+            stats: LatencyStats::from_latencies(&latencies),
+            results,
+            latencies,
+        }
+    }
+}
+
+impl Cluster {
+    /// Executes RPC `requests` on the main instance.
+    /// See [`PicotestInstance::rpc_batch`].
+    pub async fn rpc_batch<'a, S, G, I>(&self, requests: I, concurrency: usize) -> RpcBatchReport<G>
+    where
+        S: Serialize + 'a,
+        G: DeserializeOwned,
+        I: IntoIterator<Item = RpcRequest<'a, S>>,
+    {
+        self.main().rpc_batch(requests, concurrency).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LatencyStats;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest]
+    fn test_latency_stats() {
+        let latencies: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        assert_eq!(
+            LatencyStats::from_latencies(&latencies),
+            LatencyStats {
+                p50: Duration::from_millis(50),
+                p95: Duration::from_millis(95),
+                max: Duration::from_millis(100),
+            }
+        );
+
+        let single = [Duration::from_millis(7)];
+        assert_eq!(
+            LatencyStats::from_latencies(&single).p50,
+            Duration::from_millis(7)
+        );
+        assert_eq!(LatencyStats::from_latencies(&[]), LatencyStats::default());
+    }
+}
@@ -0,0 +1,371 @@
+//! Sanitized builds of the plugin (ASAN, TSAN, MSAN).
+//!
+//! Library built with `-Z sanitizer=...` can be loaded only by picodata
+//! built with the same sanitizer: otherwise instance crashes on start of
+//! the plugin or on the call of unit test with an obscure message of the
+//! sanitizer runtime. So picotest refuses to run such combination early
+//! and supports sanitized tests in an opt-in mode enabled by
+//! [`PICOTEST_SANITIZER`]:
+//! - picodata located by `PICODATA_PATH` must be built with the sanitizer,
+//! - `RUSTFLAGS` of the test process must enable it, as the plugin is
+//!   built by pike with the environment of the test process,
+//! - instances are started by a generated launcher script, which forwards
+//!   sanitizer options (e.g. `ASAN_OPTIONS`) of the test process and writes
+//!   reports into [`REPORTS_DIR`] of the instance directory, see
+//!   [`Cluster::sanitizer_reports`].
+//!
+//! ### Examples:
+//! ```text
+//! RUSTFLAGS="-Zsanitizer=address" \
+//! PICOTEST_SANITIZER=address \
+//! PICODATA_PATH=/opt/picodata-asan/bin/picodata \
+//! ASAN_OPTIONS=detect_leaks=0 \
+//!     cargo +nightly test --target x86_64-unknown-linux-gnu
+//! ```
+
+use crate::container::Backend;
+use crate::launcher::{self, INSTANCE_DIR_SCRIPT};
+use crate::Cluster;
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Environment variable, which enables sanitizer mode, e.g. `address`.
+pub const PICOTEST_SANITIZER: &str = "PICOTEST_SANITIZER";
+
+/// Directory of sanitizer reports in the instance directory.
+pub const REPORTS_DIR: &str = "sanitizer";
+
+/// Directory (relative to the plugin path) of generated launcher scripts.
+pub const SANITIZER_LAUNCHERS_DIR: &str = "tmp/sanitizer";
+
+/// Sanitizer supported by rustc, which instruments the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Sanitizer {
+    Address,
+    Thread,
+    Memory,
+}
+
+impl Sanitizer {
+    pub const ALL: [Sanitizer; 3] = [Self::Address, Self::Thread, Self::Memory];
+
+    /// Reads [`PICOTEST_SANITIZER`] environment variable.
+    ///
+    /// ### Returns
+    /// `None` if sanitizer mode isn't enabled.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match env::var(PICOTEST_SANITIZER) {
+            Ok(value) if !value.is_empty() => Ok(Some(value.parse()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Name of the sanitizer as accepted by `-Z sanitizer=`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Address => "address",
+            Self::Thread => "thread",
+            Self::Memory => "memory",
+        }
+    }
+
+    /// Environment variable with runtime options of the sanitizer.
+    pub fn options_variable(self) -> &'static str {
+        match self {
+            Self::Address => "ASAN_OPTIONS",
+            Self::Thread => "TSAN_OPTIONS",
+            Self::Memory => "MSAN_OPTIONS",
+        }
+    }
+
+    /// Symbol called by every binary instrumented by the sanitizer.
+    fn init_symbol(self) -> &'static [u8] {
+        match self {
+            Self::Address => b"__asan_init",
+            Self::Thread => b"__tsan_init",
+            Self::Memory => b"__msan_init",
+        }
+    }
+}
+
+impl Display for Sanitizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Sanitizer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "address" | "asan" => Ok(Self::Address),
+            "thread" | "tsan" => Ok(Self::Thread),
+            "memory" | "msan" => Ok(Self::Memory),
+            _ => bail!("unknown sanitizer '{s}', expected one of: address, thread, memory"),
+        }
+    }
+}
+
+impl Cluster {
+    /// Enables sanitizer mode, see [module docs](self).
+    pub fn sanitizer(mut self, sanitizer: Option<Sanitizer>) -> Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    /// Checks that `library` (e.g. of unit tests) can be loaded by the
+    /// instances, see [`check_library`]. Picodata of the remote cluster
+    /// or of the container isn't accessible, so it isn't checked.
+    pub fn check_sanitized_library(&self, library: &Path) -> anyhow::Result<()> {
+        if self.is_remote() || self.backend == Backend::Container {
+            return Ok(());
+        }
+        check_library(library, &self.picodata_path)
+    }
+
+    /// Returns reports written by sanitizer runtime of the instances.
+    pub fn sanitizer_reports(&self) -> Vec<PathBuf> {
+        let Ok(instance_dirs) = fs::read_dir(self.data_dir_path().join("cluster")) else {
+            return Vec::new();
+        };
+        let mut reports: Vec<PathBuf> = instance_dirs
+            .filter_map(Result::ok)
+            .filter_map(|instance_dir| fs::read_dir(instance_dir.path().join(REPORTS_DIR)).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+        reports.sort();
+        reports
+    }
+}
+
+/// Returns sanitizers enabled by `-Z sanitizer=` flags of `rustflags`.
+pub fn rustflags_sanitizers(rustflags: &str) -> Vec<Sanitizer> {
+    let mut sanitizers: Vec<Sanitizer> = rustflags
+        .match_indices("sanitizer=")
+        .flat_map(|(index, pattern)| {
+            let values = &rustflags[index + pattern.len()..];
+            values
+                .split(char::is_whitespace)
+                .next()
+                .unwrap_or_default()
+                .split(',')
+        })
+        .filter_map(|name| name.parse().ok())
+        .collect();
+    sanitizers.sort();
+    sanitizers.dedup();
+    sanitizers
+}
+
+/// Returns sanitizers the binary or shared library is instrumented by.
+pub fn detect_sanitizers(binary_path: &Path) -> anyhow::Result<Vec<Sanitizer>> {
+    let content = fs::read(binary_path)
+        .with_context(|| format!("failed to read '{}'", binary_path.display()))?;
+    Ok(Sanitizer::ALL
+        .into_iter()
+        .filter(|sanitizer| {
+            let symbol = sanitizer.init_symbol();
+            content.windows(symbol.len()).any(|window| window == symbol)
+        })
+        .collect())
+}
+
+/// Checks that `RUSTFLAGS` of the test process build the plugin the way
+/// picodata built with `sanitizer` can load it.
+///
+/// ### Returns
+/// Error if `RUSTFLAGS` enable a sanitizer, but sanitizer mode isn't enabled.
+pub fn check_build_flags(sanitizer: Option<Sanitizer>) -> anyhow::Result<()> {
+    let enabled = rustflags_sanitizers(&env::var("RUSTFLAGS").unwrap_or_default());
+    match sanitizer {
+        None if !enabled.is_empty() => bail!(
+            "RUSTFLAGS enable {}, so the plugin can't be loaded by regular picodata. \
+            Set {PICOTEST_SANITIZER} and point PICODATA_PATH to picodata built \
+            with the same sanitizer",
+            describe(&enabled)
+        ),
+        Some(sanitizer) if !enabled.contains(&sanitizer) => warn!(
+            "RUSTFLAGS of the test process don't enable {sanitizer} sanitizer, \
+            plugin may be built without it"
+        ),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Checks that picodata located by `picodata_path` is built with `sanitizer`.
+pub fn check_picodata(picodata_path: &Path, sanitizer: Sanitizer) -> anyhow::Result<()> {
+    let picodata_path = find_executable(picodata_path);
+    if !detect_sanitizers(&picodata_path)?.contains(&sanitizer) {
+        bail!(
+            "{PICOTEST_SANITIZER} is '{sanitizer}', but picodata '{}' isn't built with \
+            {sanitizer} sanitizer. Point PICODATA_PATH to the instrumented picodata",
+            picodata_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Checks that `library` can be loaded by picodata located by `picodata_path`.
+///
+/// ### Returns
+/// Error if the library is built with sanitizers picodata isn't built with.
+pub fn check_library(library: &Path, picodata_path: &Path) -> anyhow::Result<()> {
+    let library_sanitizers = detect_sanitizers(library)?;
+    if library_sanitizers.is_empty() {
+        return Ok(());
+    }
+    let picodata_path = find_executable(picodata_path);
+    let picodata_sanitizers = detect_sanitizers(&picodata_path)?;
+    if !library_sanitizers
+        .iter()
+        .all(|sanitizer| picodata_sanitizers.contains(sanitizer))
+    {
+        bail!(
+            "library '{}' is built with {}, but picodata '{}' is built with {}, so it \
+            would crash loading the library. Point PICODATA_PATH to picodata built \
+            with the same sanitizer and set {PICOTEST_SANITIZER}",
+            library.display(),
+            describe(&library_sanitizers),
+            picodata_path.display(),
+            describe(&picodata_sanitizers)
+        );
+    }
+    Ok(())
+}
+
+fn describe(sanitizers: &[Sanitizer]) -> String {
+    if sanitizers.is_empty() {
+        return "no sanitizer".to_string();
+    }
+    let names: Vec<&str> = sanitizers
+        .iter()
+        .map(|sanitizer| sanitizer.name())
+        .collect();
+    format!("{} sanitizer", names.join(", "))
+}
+
+/// Locates `path` without directory components in `PATH`, like shell does.
+fn find_executable(path: &Path) -> PathBuf {
+    if path.components().count() > 1 {
+        return path.to_path_buf();
+    }
+    env::var_os("PATH")
+        .and_then(|dirs| {
+            env::split_paths(&dirs)
+                .map(|dir| dir.join(path))
+                .find(|candidate| candidate.is_file())
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Writes launcher script running `picodata_path` with options
+/// of `sanitizer` writing reports into the instance directory.
+///
+/// ### Returns
+/// Path to the script to be used as picodata binary.
+pub(crate) fn write_launcher(
+    plugin_path: &Path,
+    cluster_uuid: &Uuid,
+    picodata_path: &Path,
+    sanitizer: Sanitizer,
+) -> anyhow::Result<PathBuf> {
+    let launcher_path = launcher::write_launcher(
+        &plugin_path
+            .join(SANITIZER_LAUNCHERS_DIR)
+            .join(cluster_uuid.to_string()),
+        "picodata",
+        &launcher_script(picodata_path, sanitizer),
+    )?;
+
+    debug!(
+        "Instances will be started with {sanitizer} sanitizer options by '{}'",
+        launcher_path.display()
+    );
+    Ok(launcher_path)
+}
+
+/// Options of the test process go last, so they override the defaults.
+fn launcher_script(picodata_path: &Path, sanitizer: Sanitizer) -> String {
+    let exec = launcher::exec_line(picodata_path);
+    let options = sanitizer.options_variable();
+    format!(
+        r#"#!/bin/sh
+# Generated by picotest: runs picodata writing {sanitizer} sanitizer reports into the instance directory.
+set -e
+{INSTANCE_DIR_SCRIPT}
+if [ -n "$instance_dir" ]; then
+    mkdir -p "$instance_dir/{REPORTS_DIR}"
+    {options}="log_path=$instance_dir/{REPORTS_DIR}/{sanitizer}${{{options}:+:${options}}}"
+    export {options}
+fi
+{exec}"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_library, detect_sanitizers, launcher_script, rustflags_sanitizers, Sanitizer,
+    };
+    use rstest::rstest;
+    use std::fs;
+    use std::path::Path;
+
+    #[rstest]
+    #[case::none("-C opt-level=1", &[])]
+    #[case::separate("-Z sanitizer=address -C debuginfo=2", &[Sanitizer::Address])]
+    #[case::joined("-Zsanitizer=thread", &[Sanitizer::Thread])]
+    #[case::list("-Zsanitizer=memory,address,leak", &[Sanitizer::Address, Sanitizer::Memory])]
+    fn test_rustflags_sanitizers(#[case] rustflags: &str, #[case] expected: &[Sanitizer]) {
+        assert_eq!(rustflags_sanitizers(rustflags), expected);
+    }
+
+    #[rstest]
+    fn test_parse_sanitizer() {
+        assert_eq!("ASan".parse::<Sanitizer>().unwrap(), Sanitizer::Address);
+        assert_eq!("thread".parse::<Sanitizer>().unwrap(), Sanitizer::Thread);
+        assert!("leak".parse::<Sanitizer>().is_err());
+    }
+
+    #[rstest]
+    fn test_launcher_script() {
+        let script = launcher_script(Path::new("/opt/asan/picodata"), Sanitizer::Address);
+        assert!(script.contains(
+            r#"ASAN_OPTIONS="log_path=$instance_dir/sanitizer/address${ASAN_OPTIONS:+:$ASAN_OPTIONS}""#
+        ));
+        assert!(script.contains(r#"exec '/opt/asan/picodata' "$@""#));
+    }
+
+    #[rstest]
+    fn test_check_library() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("libasan.so"), b"\x7fELF...__asan_init...").unwrap();
+        fs::write(dir.join("libplain.so"), b"\x7fELF...").unwrap();
+        fs::write(dir.join("picodata"), b"\x7fELF...").unwrap();
+        fs::write(dir.join("picodata-asan"), b"\x7fELF..__asan_init..").unwrap();
+
+        let detected = detect_sanitizers(&dir.join("libasan.so")).unwrap();
+        let plain_ok = check_library(&dir.join("libplain.so"), &dir.join("picodata")).is_ok();
+        let asan_ok = check_library(&dir.join("libasan.so"), &dir.join("picodata-asan")).is_ok();
+        let mismatch = check_library(&dir.join("libasan.so"), &dir.join("picodata"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(detected, [Sanitizer::Address]);
+        assert!(plain_ok);
+        assert!(asan_ok);
+        let err = mismatch.unwrap_err().to_string();
+        assert!(err.contains("is built with address sanitizer"), "{err}");
+        assert!(err.contains("is built with no sanitizer"), "{err}");
+    }
+}
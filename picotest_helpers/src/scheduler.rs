@@ -0,0 +1,500 @@
+//! Grouping of tests by the cluster they require.
+//!
+//! Session cluster of the test binary is started with options of the first
+//! test using it, so tests requiring different topology profiles, plugin
+//! sources or topology transforms can't share the binary. Custom test harness
+//! of picotest groups [`ScheduledTest`]s by [`GroupKey`] of their cluster and
+//! runs the groups one after another, while tests of the group are run in
+//! parallel by [`run_group`].
+//!
+//! Harness accepts the common arguments of the standard one, see [`USAGE`],
+//! and ignores the rest of them, so it's run by `cargo test` and IDEs as is.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let args = HarnessArgs::from_env()?;
+//! let config = PicotestConfig::load(plugin_path)?;
+//! for group in plan(&tests, &args, &config) {
+//!     println!("{}", group.key);
+//!     run_group(&group.tests, &args, |test, outcome| println!("test {} ... {outcome}", test.name));
+//! }
+//! ```
+
+use crate::tags::{PicotestConfig, TopologyProfile};
+use crate::topology::PluginTopology;
+use anyhow::{bail, Context};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::num::NonZeroUsize;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::{env, thread};
+
+/// Environment variable with number of threads running tests of the group,
+/// same as of the standard test harness.
+pub const RUST_TEST_THREADS: &str = "RUST_TEST_THREADS";
+
+/// Usage of the test binary run by the custom harness.
+pub const USAGE: &str = "\
+Usage: <test binary> [OPTIONS] [FILTERS...]
+
+Options:
+        --exact             Filters match test names exactly
+        --skip FILTER       Skip tests with names containing FILTER
+        --list              List tests instead of running them
+        --ignored           Run only ignored tests
+        --include-ignored   Run ignored tests too
+        --test-threads N    Number of threads running tests of the cluster group
+    -q, --quiet             Print a character per test instead of a line
+        --format terse      Same as --quiet
+    -h, --help              Print this message
+";
+
+/// Function transforming plugin topology, see `topology_transform` of `#[picotest]`.
+pub type TopologyTransformFn = fn(&PluginTopology) -> PluginTopology;
+
+/// Options of the cluster requested by the test.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClusterProfile {
+    pub plugin_path: Option<&'static str>,
+    pub install_from: Option<&'static str>,
+    pub keepalive: bool,
+    /// Name of the function transforming plugin topology and the function itself.
+    pub topology_transform: Option<(&'static str, TopologyTransformFn)>,
+    /// Tag of the test mapped to topology profile by `picotest.toml`.
+    pub tag: Option<&'static str>,
+}
+
+impl ClusterProfile {
+    /// Returns key of the cluster, tests with equal keys share it.
+    pub fn key(&self, config: &PicotestConfig) -> GroupKey {
+        GroupKey {
+            keepalive: self.keepalive,
+            plugin_path: self.plugin_path,
+            install_from: self.install_from,
+            topology: self
+                .tag
+                .map(|tag| config.profile_of(tag))
+                .unwrap_or_default(),
+            topology_transform: self.topology_transform.map(|(name, _)| name),
+        }
+    }
+}
+
+/// Identity of the cluster shared by tests of the group.
+///
+/// Groups are ordered by the key, so ones with cluster kept alive
+/// are run last and their cluster can outlive the tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GroupKey {
+    pub keepalive: bool,
+    pub plugin_path: Option<&'static str>,
+    pub install_from: Option<&'static str>,
+    pub topology: TopologyProfile,
+    pub topology_transform: Option<&'static str>,
+}
+
+impl Display for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "topology: {:?}", self.topology)?;
+        if let Some(transform) = self.topology_transform {
+            write!(f, ", transform: {transform}")?;
+        }
+        if let Some(plugin_path) = self.plugin_path {
+            write!(f, ", plugin: {plugin_path}")?;
+        }
+        if let Some(install_from) = self.install_from {
+            write!(f, ", install from: {install_from}")?;
+        }
+        if self.keepalive {
+            f.write_str(", keepalive")?;
+        }
+        Ok(())
+    }
+}
+
+/// Test registered in the custom harness.
+#[derive(Debug, Clone, Copy)]
+pub struct ScheduledTest {
+    /// Test name including module path.
+    pub name: &'static str,
+    pub profile: ClusterProfile,
+    /// Test is marked by `#[ignore]`.
+    pub ignored: bool,
+    /// Test is marked by `#[should_panic]`.
+    pub should_panic: bool,
+    pub run: fn(),
+}
+
+/// Arguments of the test binary, see [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HarnessArgs {
+    /// Tests with names containing any of the filters are run, all if empty.
+    pub filters: Vec<String>,
+    /// Tests with names containing any of these are skipped.
+    pub skip: Vec<String>,
+    /// Filters must match names exactly.
+    pub exact: bool,
+    /// Tests are listed instead of being run.
+    pub list: bool,
+    /// Only ignored tests are run.
+    pub ignored: bool,
+    pub include_ignored: bool,
+    pub test_threads: usize,
+    /// Outcomes are printed tersely.
+    pub quiet: bool,
+    /// Usage is printed instead of running tests.
+    pub help: bool,
+}
+
+impl Default for HarnessArgs {
+    fn default() -> Self {
+        Self {
+            filters: Vec::new(),
+            skip: Vec::new(),
+            exact: false,
+            list: false,
+            ignored: false,
+            include_ignored: false,
+            test_threads: default_test_threads(),
+            quiet: false,
+            help: false,
+        }
+    }
+}
+
+fn default_test_threads() -> usize {
+    env::var(RUST_TEST_THREADS)
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .or_else(|| thread::available_parallelism().ok().map(NonZeroUsize::get))
+        .unwrap_or(1)
+}
+
+/// Options of the standard harness taking a value, which are accepted and ignored.
+const IGNORED_VALUE_OPTIONS: [&str; 3] = ["--color", "--logfile", "-Z"];
+
+impl HarnessArgs {
+    /// Parses arguments following the program name.
+    /// Unsupported options of the standard harness are ignored.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) if arg.starts_with("--") => (option, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .with_context(|| format!("option '{option}' requires a value"))
+            };
+            match option {
+                "--exact" => parsed.exact = true,
+                "--list" => parsed.list = true,
+                "--ignored" => parsed.ignored = true,
+                "--include-ignored" => parsed.include_ignored = true,
+                "-q" | "--quiet" => parsed.quiet = true,
+                "-h" | "--help" => parsed.help = true,
+                "--format" => parsed.quiet |= value()? == "terse",
+                "--skip" => parsed.skip.push(value()?),
+                "--test-threads" => {
+                    let threads = value()?;
+                    parsed.test_threads = match threads.parse() {
+                        Ok(threads) if threads > 0 => threads,
+                        _ => bail!("invalid number of test threads '{threads}'"),
+                    };
+                }
+                option if IGNORED_VALUE_OPTIONS.contains(&option) => {
+                    value()?;
+                }
+                option if option.starts_with('-') => {}
+                _ => parsed.filters.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Arguments the test binary is run with.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::parse(env::args().skip(1))
+    }
+
+    fn matches(&self, name: &str, pattern: &str) -> bool {
+        if self.exact {
+            name == pattern
+        } else {
+            name.contains(pattern)
+        }
+    }
+
+    /// Returns `true` if the test `name` isn't filtered out.
+    pub fn is_selected(&self, name: &str) -> bool {
+        (self.filters.is_empty() || self.filters.iter().any(|filter| self.matches(name, filter)))
+            && !self.skip.iter().any(|skip| self.matches(name, skip))
+    }
+
+    /// Returns `true` if the test is run rather than reported as ignored.
+    fn is_run(&self, test: &ScheduledTest) -> bool {
+        self.include_ignored || test.ignored == self.ignored
+    }
+}
+
+/// Tests sharing the cluster.
+#[derive(Debug, Clone)]
+pub struct TestGroup<'a> {
+    pub key: GroupKey,
+    /// Tests sorted by name.
+    pub tests: Vec<&'a ScheduledTest>,
+}
+
+/// Groups `tests` selected by `args` by clusters they require.
+///
+/// ### Returns
+/// Groups in order they should be run, see [`GroupKey`].
+pub fn plan<'a>(
+    tests: &'a [ScheduledTest],
+    args: &HarnessArgs,
+    config: &PicotestConfig,
+) -> Vec<TestGroup<'a>> {
+    let mut groups: BTreeMap<GroupKey, Vec<&ScheduledTest>> = BTreeMap::new();
+    for test in tests.iter().filter(|test| args.is_selected(test.name)) {
+        groups
+            .entry(test.profile.key(config))
+            .or_default()
+            .push(test);
+    }
+    groups
+        .into_iter()
+        .map(|(key, mut tests)| {
+            tests.sort_by_key(|test| test.name);
+            TestGroup { key, tests }
+        })
+        .collect()
+}
+
+/// Result of the test run by the harness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+impl Outcome {
+    /// Returns character the outcome is printed by with `--quiet`.
+    pub fn as_char(&self) -> char {
+        match self {
+            Self::Passed => '.',
+            Self::Failed => 'F',
+            Self::Ignored => 'i',
+        }
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Passed => "ok",
+            Self::Failed => "FAILED",
+            Self::Ignored => "ignored",
+        })
+    }
+}
+
+/// Runs `tests` in `args.test_threads` threads and passes
+/// outcome of every test to `report` once it's finished.
+///
+/// ### Returns
+/// Outcomes of the tests in order of `tests`.
+pub fn run_group(
+    tests: &[&ScheduledTest],
+    args: &HarnessArgs,
+    report: impl Fn(&ScheduledTest, Outcome) + Sync,
+) -> Vec<Outcome> {
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(vec![Outcome::Ignored; tests.len()]);
+    let worker = || loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let Some(test) = tests.get(index) else {
+            return;
+        };
+        let outcome = if args.is_run(test) {
+            let panicked = panic::catch_unwind(test.run).is_err();
+            if panicked == test.should_panic {
+                Outcome::Passed
+            } else {
+                Outcome::Failed
+            }
+        } else {
+            Outcome::Ignored
+        };
+        report(test, outcome);
+        outcomes.lock().unwrap_or_else(PoisonError::into_inner)[index] = outcome;
+    };
+    thread::scope(|scope| {
+        for _ in 0..args.test_threads.min(tests.len()) {
+            scope.spawn(worker);
+        }
+    });
+    outcomes
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan, run_group, ClusterProfile, HarnessArgs, Outcome, ScheduledTest};
+    use crate::tags::{PicotestConfig, TopologyProfile};
+    use crate::topology::PluginTopology;
+    use rstest::rstest;
+    use std::sync::Mutex;
+
+    fn args(args: &[&str]) -> HarnessArgs {
+        HarnessArgs::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[rstest]
+    fn test_parse_args() {
+        let parsed = args(&[
+            "api",
+            "--exact",
+            "--test-threads=3",
+            "--skip",
+            "slow",
+            "--format",
+            "terse",
+            "--nocapture",
+            "--color=never",
+            "smoke",
+        ]);
+        assert_eq!(parsed.filters, ["api", "smoke"]);
+        assert_eq!(parsed.skip, ["slow"]);
+        assert!(parsed.exact && parsed.quiet && !parsed.list);
+        assert_eq!(parsed.test_threads, 3);
+        assert!(args(&["-h"]).help && args(&["-q"]).quiet);
+        assert!(!args(&["--format", "pretty"]).quiet);
+
+        assert!(HarnessArgs::parse(["--test-threads".to_string()]).is_err());
+        assert!(HarnessArgs::parse(["--test-threads=0".to_string()]).is_err());
+    }
+
+    #[rstest]
+    #[case(&[], "tests::test_api", true)]
+    #[case(&["api"], "tests::test_api", true)]
+    #[case(&["api", "--skip", "test_api"], "tests::test_api", false)]
+    #[case(&["test_api", "--exact"], "tests::test_api", false)]
+    #[case(&["tests::test_api", "--exact"], "tests::test_api", true)]
+    fn test_is_selected(#[case] argv: &[&str], #[case] name: &str, #[case] expected: bool) {
+        assert_eq!(args(argv).is_selected(name), expected);
+    }
+
+    fn single_node(topology: &PluginTopology) -> PluginTopology {
+        topology.clone()
+    }
+
+    fn test(name: &'static str, profile: ClusterProfile) -> ScheduledTest {
+        ScheduledTest {
+            name,
+            profile,
+            ignored: false,
+            should_panic: false,
+            run: || {},
+        }
+    }
+
+    #[rstest]
+    fn test_plan_groups() {
+        let config: PicotestConfig =
+            toml::from_str("[tags.smoke]\ntopology = \"single_node\"").unwrap();
+        let smoke = ClusterProfile {
+            tag: Some("smoke"),
+            ..Default::default()
+        };
+        let kept = ClusterProfile {
+            keepalive: true,
+            ..Default::default()
+        };
+        let transformed = ClusterProfile {
+            topology_transform: Some(("single_node", single_node)),
+            ..Default::default()
+        };
+        let tests = [
+            test("b::test_kept", kept),
+            test("b::test_full", Default::default()),
+            test("a::test_smoke", smoke),
+            test(
+                "a::test_full",
+                ClusterProfile {
+                    tag: Some("heavy"),
+                    ..Default::default()
+                },
+            ),
+            test("a::test_transformed", transformed),
+            test("a::test_filtered", smoke),
+        ];
+
+        let groups = plan(&tests, &args(&["--skip", "filtered"]), &config);
+        let names: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|group| group.tests.iter().map(|test| test.name).collect())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                vec!["a::test_smoke"],
+                vec!["a::test_full", "b::test_full"],
+                vec!["a::test_transformed"],
+                vec!["b::test_kept"],
+            ]
+        );
+        assert_eq!(groups[0].key.topology, TopologyProfile::SingleNode);
+        assert_eq!(
+            groups[2].key.to_string(),
+            "topology: Full, transform: single_node"
+        );
+    }
+
+    #[rstest]
+    fn test_run_group() {
+        let tests = [
+            test("test_passed", Default::default()),
+            ScheduledTest {
+                run: || panic!("expected failure"),
+                ..test("test_failed", Default::default())
+            },
+            ScheduledTest {
+                run: || panic!("expected panic"),
+                should_panic: true,
+                ..test("test_should_panic", Default::default())
+            },
+            ScheduledTest {
+                ignored: true,
+                ..test("test_ignored", Default::default())
+            },
+        ];
+        let tests: Vec<&ScheduledTest> = tests.iter().collect();
+        let reported = Mutex::new(Vec::new());
+
+        let outcomes = run_group(&tests, &args(&["--test-threads", "2"]), |test, outcome| {
+            reported.lock().unwrap().push((test.name, outcome));
+        });
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Passed,
+                Outcome::Failed,
+                Outcome::Passed,
+                Outcome::Ignored
+            ]
+        );
+        assert_eq!(reported.into_inner().unwrap().len(), 4);
+
+        let outcomes = run_group(&tests, &args(&["--ignored"]), |_, _| {});
+        assert_eq!(outcomes[0], Outcome::Ignored);
+        assert_eq!(outcomes[3], Outcome::Passed);
+    }
+}
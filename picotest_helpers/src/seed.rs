@@ -0,0 +1,90 @@
+//! Reproducible randomness.
+//!
+//! Random generators handed to tests and generated data are derived from
+//! a single session seed. Seed is taken from [`PICOTEST_SEED`] environment
+//! variable or generated and printed, so failed run can be reproduced by
+//! setting the variable. Names of temporary directories are random regardless
+//! of the seed, so a reproduced run doesn't collide with the original one.
+//! Seeds of tests are derived by a hash, which is stable across toolchains.
+
+use crate::stable_hash::StableHasher;
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use std::env;
+use std::hash::Hasher;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+
+/// Environment variable with the seed of the test session.
+pub const PICOTEST_SEED: &str = "PICOTEST_SEED";
+
+/// Random generator handed to tests.
+pub type PicotestRng = StdRng;
+
+/// Returns seed of the test session.
+///
+/// Seed is printed when it's requested for the first time.
+pub fn session_seed() -> u64 {
+    static SEED: OnceLock<u64> = OnceLock::new();
+
+    *SEED.get_or_init(|| {
+        let seed = match env::var(PICOTEST_SEED) {
+            Ok(value) => value
+                .parse()
+                .unwrap_or_else(|_| panic!("{PICOTEST_SEED} must be an unsigned integer")),
+            Err(_) => rand::rng().random(),
+        };
+        println!("Picotest random seed: {PICOTEST_SEED}={seed}");
+        seed
+    })
+}
+
+/// Runs `f` with the session-wide random generator used for internal needs.
+pub fn with_session_rng<R>(f: impl FnOnce(&mut PicotestRng) -> R) -> R {
+    static RNG: OnceLock<Mutex<PicotestRng>> = OnceLock::new();
+
+    let rng = RNG.get_or_init(|| Mutex::new(PicotestRng::seed_from_u64(session_seed())));
+    f(&mut rng.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Creates random generator for the current test.
+///
+/// Generator is seeded by the session seed combined with the name of the
+/// current thread, which is the name of the test when run by libtest. Thus
+/// the generated sequence doesn't depend on order of tests execution.
+pub fn test_rng() -> PicotestRng {
+    PicotestRng::seed_from_u64(test_seed(session_seed(), thread::current().name()))
+}
+
+fn test_seed(session_seed: u64, test_name: Option<&str>) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write_u64(session_seed);
+    match test_name {
+        Some(test_name) => {
+            hasher.write_u8(1);
+            hasher.write_bytes(test_name.as_bytes());
+        }
+        None => hasher.write_u8(0),
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_seed;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_test_seed() {
+        assert_eq!(test_seed(42, Some("a")), test_seed(42, Some("a")));
+        assert_ne!(test_seed(42, Some("a")), test_seed(42, Some("b")));
+        assert_ne!(test_seed(42, Some("a")), test_seed(43, Some("a")));
+        assert_ne!(test_seed(42, Some("")), test_seed(42, None));
+    }
+
+    #[rstest]
+    fn test_test_seed_is_stable() {
+        // Seeds printed by one toolchain must reproduce the run on another one.
+        assert_eq!(test_seed(42, Some("tests::test_a")), 2437067693307148300);
+    }
+}
@@ -0,0 +1,150 @@
+//! Graceful and forced shutdown of cluster instances.
+//!
+//! Pike stops instances by signal and returns immediately, so processes
+//! of instances are tracked by their PIDs obtained from `box.info`.
+//! Instances in containers are tracked by processes of container runtime
+//! clients running them, see [`container::client_pids`].
+
+use crate::container::{self, Backend};
+use crate::Cluster;
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default time given to instances to exit before they're killed.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl Cluster {
+    /// Stops all instances and waits until they flush data and exit.
+    ///
+    /// Returns an error if some instances are still running after `timeout`.
+    /// These can be stopped with [`Cluster::stop_force`].
+    pub fn stop_graceful(&self, timeout: Duration) -> anyhow::Result<()> {
+        self.ensure_not_remote("Graceful stop of the cluster")?;
+        let pids = self.instance_pids();
+        self.stop()?;
+
+        let running = wait_processes_exit(&pids, timeout);
+        if !running.is_empty() {
+            bail!(
+                "instances with PIDs {running:?} haven't exited within {}s",
+                timeout.as_secs_f64()
+            );
+        }
+        Ok(())
+    }
+
+    /// Kills all instances with `SIGKILL` without letting them flush data,
+    /// e.g. to test crash recovery.
+    pub fn stop_force(&self) -> anyhow::Result<()> {
+        self.ensure_not_remote("Killing the cluster")?;
+        if self.backend == Backend::Container {
+            return container::kill_containers(&self.uuid, &self.container_config);
+        }
+        for pid in self.instance_pids() {
+            kill(pid)?;
+        }
+        Ok(())
+    }
+
+    /// Stops cluster gracefully and kills instances,
+    /// which haven't exited within the stop timeout.
+    ///
+    /// This is how cluster is stopped when it's dropped.
+    pub fn shutdown(&self) -> anyhow::Result<()> {
+        self.ensure_not_remote("Shutdown of the cluster")?;
+        let pids = self.instance_pids();
+        if let Err(err) = self.stop() {
+            warn!("Failed to stop picodata cluster gracefully: {err}");
+        }
+
+        for pid in wait_processes_exit(&pids, self.stop_timeout) {
+            warn!("Instance with PID {pid} hasn't exited in time, killing it");
+            kill(pid)?;
+        }
+
+        if self.backend == Backend::Container {
+            container::remove_containers(&self.uuid, &self.container_config)?;
+            container::remove_network(&self.uuid, &self.container_config)?;
+        }
+        // Profiles are written by instances on exit.
+        if let Some(coverage) = &self.coverage {
+            if let Err(err) = coverage.collect(&self.data_dir_path()) {
+                warn!("Failed to collect coverage profiles: {err:#}");
+            }
+        }
+        let reports = self.sanitizer_reports();
+        if !reports.is_empty() {
+            let reports: Vec<String> = reports
+                .iter()
+                .map(|report| format!("  - {}", report.display()))
+                .collect();
+            println!(
+                "Sanitizer has reported errors of the instances:\n{}",
+                reports.join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns PIDs of the running instances. Unreachable instances are skipped.
+    fn instance_pids(&self) -> Vec<u32> {
+        if self.backend == Backend::Container {
+            return container::client_pids(&self.data_dir_path());
+        }
+        self.instances()
+            .iter()
+            .filter_map(|instance| match instance.box_info() {
+                Ok(info) => Some(info.pid),
+                Err(err) => {
+                    debug!("Failed to get PID of '{}': {err:#}", instance.instance_name);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Waits for processes to exit and returns PIDs of still running ones.
+fn wait_processes_exit(pids: &[u32], timeout: Duration) -> Vec<u32> {
+    let start_time = Instant::now();
+    loop {
+        let running: Vec<u32> = pids
+            .iter()
+            .copied()
+            .filter(|pid| process_exists(*pid))
+            .collect();
+        if running.is_empty() || start_time.elapsed() > timeout {
+            return running;
+        }
+        thread::sleep(EXIT_POLL_INTERVAL);
+    }
+}
+
+fn process_exists(pid: u32) -> bool {
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn kill(pid: u32) -> anyhow::Result<()> {
+    if !process_exists(pid) {
+        return Ok(());
+    }
+    let status = Command::new("kill")
+        .arg("-9")
+        .arg(pid.to_string())
+        .status()
+        .context("failed to run kill")?;
+    if !status.success() && process_exists(pid) {
+        bail!("failed to kill process with PID {pid}");
+    }
+    Ok(())
+}
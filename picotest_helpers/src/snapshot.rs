@@ -0,0 +1,243 @@
+//! Golden (snapshot) testing of query results.
+//!
+//! Result of the query is normalized (rows are sorted, so that output
+//! doesn't depend on data distribution among replicasets) and stored as YAML
+//! under `tests/snapshots` of the tested package. Subsequent runs compare
+//! actual result with the stored one.
+
+use crate::Cluster;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_norway::Value;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Environment variable, which enables overwriting of mismatched snapshots.
+pub const PICOTEST_UPDATE_SNAPSHOTS: &str = "PICOTEST_UPDATE_SNAPSHOTS";
+
+/// Directory of snapshots, relative to the manifest directory of the tested package.
+pub const SNAPSHOTS_DIR: &str = "tests/snapshots";
+
+/// Normalized query result stored in a snapshot file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct QuerySnapshot {
+    query: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl QuerySnapshot {
+    fn new(query: &str, columns: Vec<String>, mut rows: Vec<Vec<Value>>) -> Self {
+        rows.sort_by_cached_key(|row| serde_norway::to_string(row).unwrap_or_default());
+        Self {
+            query: query.trim().to_string(),
+            columns,
+            rows,
+        }
+    }
+}
+
+impl Cluster {
+    /// Asserts that result of `query` matches snapshot called `name`.
+    ///
+    /// Snapshot is stored in `tests/snapshots/<name>.yaml`. Set [`PICOTEST_UPDATE_SNAPSHOTS`]
+    /// environment variable to `true` to create missing snapshots and overwrite snapshots,
+    /// which don't match. Otherwise, missing snapshot fails the assertion.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    ///
+    /// #[picotest]
+    /// fn test_report() {
+    ///     cluster.assert_query_snapshot("daily_report", "SELECT * FROM daily_report");
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn assert_query_snapshot(&self, name: &str, query: &str) {
+        let result = self
+            .sql_query(query, &[])
+            .unwrap_or_else(|err| panic!("Failed to run snapshot query '{name}': {err:#}"));
+        let actual = QuerySnapshot::new(
+            query,
+            result
+                .column_names()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            result.rows,
+        );
+
+        if let Err(message) =
+            check_snapshot(name, &snapshot_path(name), &actual, is_update_enabled())
+        {
+            panic!("{message}");
+        }
+    }
+}
+
+/// Compares `actual` result with snapshot stored in `path`.
+/// If `update` is set, missing or mismatched snapshot is (over)written.
+///
+/// ### Returns
+/// Message of the failed assertion.
+fn check_snapshot(
+    name: &str,
+    path: &Path,
+    actual: &QuerySnapshot,
+    update: bool,
+) -> Result<(), String> {
+    let expected = match fs::read_to_string(path) {
+        Ok(content) => Some(content),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(format!(
+                "Failed to read snapshot '{name}' ({}): {err}",
+                path.display()
+            ))
+        }
+    };
+
+    let actual_content = serde_norway::to_string(actual).expect("Failed to serialize snapshot");
+    let expected_snapshot: Option<QuerySnapshot> = expected
+        .as_deref()
+        .and_then(|expected| serde_norway::from_str(expected).ok());
+    if expected_snapshot.as_ref() == Some(actual) {
+        return Ok(());
+    }
+
+    if update {
+        write_snapshot(path, actual).map_err(|err| format!("{err:#}"))?;
+        let action = if expected.is_some() {
+            "updated"
+        } else {
+            "created"
+        };
+        println!("Snapshot '{name}' is {action} in '{}'", path.display());
+        return Ok(());
+    }
+
+    match expected {
+        Some(expected) => Err(format!(
+            "Snapshot '{name}' ({}) doesn't match query result, \
+            set {PICOTEST_UPDATE_SNAPSHOTS}=true to update it:\n{}",
+            path.display(),
+            lines_diff(&expected, &actual_content)
+        )),
+        None => Err(format!(
+            "Snapshot '{name}' ({}) doesn't exist, \
+            set {PICOTEST_UPDATE_SNAPSHOTS}=true to create it from query result:\n{actual_content}",
+            path.display(),
+        )),
+    }
+}
+
+fn snapshot_path(name: &str) -> PathBuf {
+    let manifest_dir = env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    manifest_dir
+        .join(SNAPSHOTS_DIR)
+        .join(format!("{name}.yaml"))
+}
+
+fn write_snapshot(path: &Path, snapshot: &QuerySnapshot) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_norway::to_string(snapshot)?)
+        .with_context(|| format!("failed to write snapshot '{}'", path.display()))
+}
+
+fn is_update_enabled() -> bool {
+    env::var(PICOTEST_UPDATE_SNAPSHOTS).is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// Line based diff of two texts, where removed lines are prefixed
+/// by `-`, added lines by `+` and common lines by space.
+pub(crate) fn lines_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Lengths of longest common subsequences of suffixes.
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            writeln!(diff, "  {}", expected[i]).unwrap();
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            writeln!(diff, "- {}", expected[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(diff, "+ {}", actual[j]).unwrap();
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_snapshot, lines_diff, QuerySnapshot};
+    use rstest::rstest;
+    use serde_norway::Value;
+    use std::fs;
+
+    #[rstest]
+    fn test_snapshot_rows_are_sorted() {
+        let snapshot = QuerySnapshot::new(
+            " SELECT id FROM t ",
+            vec!["id".into()],
+            vec![vec![Value::from(2)], vec![Value::from(1)]],
+        );
+
+        assert_eq!(snapshot.query, "SELECT id FROM t");
+        assert_eq!(
+            snapshot.rows,
+            vec![vec![Value::from(1)], vec![Value::from(2)]]
+        );
+    }
+
+    #[rstest]
+    fn test_lines_diff() {
+        let diff = lines_diff("a\nb\nc\n", "a\nx\nc\n");
+        assert_eq!(diff, "  a\n- b\n+ x\n  c\n");
+    }
+
+    #[rstest]
+    fn test_missing_snapshot() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        let path = dir.join("report.yaml");
+        let actual = QuerySnapshot::new("SELECT 1", vec!["col_1".into()], vec![vec![1.into()]]);
+
+        let err = check_snapshot("report", &path, &actual, false).unwrap_err();
+        assert!(err.contains("doesn't exist"), "{err}");
+        assert!(!path.exists());
+
+        check_snapshot("report", &path, &actual, true).unwrap();
+        check_snapshot("report", &path, &actual, false).unwrap();
+
+        let changed = QuerySnapshot::new("SELECT 2", vec!["col_1".into()], vec![vec![2.into()]]);
+        let err = check_snapshot("report", &path, &changed, false).unwrap_err();
+        assert!(err.contains("doesn't match"), "{err}");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+//! Typed execution of SQL queries.
+//!
+//! Unlike [`PicotestInstance::run_sql`], which returns raw admin console
+//! output, queries are executed through `pico.sql` and their results
+//! are deserialized into [`SqlRows`].
+
+use crate::impact;
+use crate::introspection::eval_lua_yaml;
+use crate::lua::lua_string_literal;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_norway::{Mapping, Value};
+
+/// Column of the query result.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SqlColumn {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: String,
+}
+
+/// Result of DQL query.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SqlRows {
+    #[serde(default)]
+    pub metadata: Vec<SqlColumn>,
+    #[serde(default)]
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl SqlRows {
+    /// Names of the result columns.
+    pub fn column_names(&self) -> Vec<&str> {
+        self.metadata.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// Returns rows as mappings of column names to values.
+    pub fn to_maps(&self) -> Vec<Mapping> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.metadata
+                    .iter()
+                    .zip(row)
+                    .map(|(column, value)| (Value::String(column.name.clone()), value.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Deserializes rows into `T`, matching struct fields by column names.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> anyhow::Result<Vec<T>> {
+        self.to_maps()
+            .into_iter()
+            .map(|row| {
+                serde_norway::from_value(Value::Mapping(row))
+                    .context("failed to deserialize SQL row")
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+/// Serializes `value` into a mapping of column names to values.
+pub(crate) fn to_row_map<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Mapping> {
+    match serde_norway::to_value(value).context("failed to serialize row")? {
+        Value::Mapping(mapping) => Ok(mapping),
+        value => bail!("row must serialize into a mapping, got: {value:?}"),
+    }
+}
+
+/// Quotes SQL identifier, e.g. table or column name.
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl PicotestInstance {
+    /// Executes DQL query with positional parameters (`?` or `$1`)
+    /// and returns typed result.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    ///
+    /// #[picotest]
+    /// fn test_sql_query() {
+    ///     let result = cluster
+    ///         .main()
+    ///         .sql_query("SELECT name FROM users WHERE id = ?", &[1.into()])
+    ///         .unwrap();
+    ///     assert_eq!(result.rows, vec![vec!["Alice".into()]]);
+    /// }
+    /// ```
+    pub fn sql_query(&self, query: &str, params: &[Value]) -> anyhow::Result<SqlRows> {
+        impact::record_query(query);
+        let params = serde_norway::to_string(params).context("failed to serialize parameters")?;
+        eval_lua_yaml(
+            self,
+            &format!(
+                "(function() \
+                    local res, err = pico.sql({}, require('yaml').decode({})); \
+                    if err ~= nil then error(err) end; \
+                    return res; \
+                end)()",
+                lua_string_literal(query),
+                lua_string_literal(&params)
+            ),
+        )
+        .with_context(|| format!("failed to execute query '{query}'"))
+    }
+}
+
+impl Cluster {
+    /// Executes DQL query on the main instance.
+    /// See [`PicotestInstance::sql_query`].
+    pub fn sql_query(&self, query: &str, params: &[Value]) -> anyhow::Result<SqlRows> {
+        self.main().sql_query(query, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_identifier, to_row_map, SqlRows};
+    use rstest::rstest;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct User {
+        id: u64,
+        name: String,
+    }
+
+    #[rstest]
+    fn test_deserialize_sql_rows() {
+        let output = r#"---
+- metadata:
+  - {name: id, type: integer}
+  - {name: name, type: string}
+  rows:
+  - [1, Alice]
+  - [2, Bob]
+...
+"#;
+        let result: Vec<SqlRows> = serde_norway::from_str(output).unwrap();
+        let result = &result[0];
+
+        assert_eq!(result.column_names(), vec!["id", "name"]);
+        assert_eq!(
+            result.deserialize::<User>().unwrap(),
+            vec![
+                User {
+                    id: 1,
+                    name: "Alice".into()
+                },
+                User {
+                    id: 2,
+                    name: "Bob".into()
+                },
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_to_row_map() {
+        let row = to_row_map(&std::collections::BTreeMap::from([("id", 1)])).unwrap();
+        assert_eq!(row.len(), 1);
+        assert!(to_row_map(&1).is_err());
+    }
+
+    #[rstest]
+    #[case("users", "\"users\"")]
+    #[case("we\"ird", "\"we\"\"ird\"")]
+    fn test_quote_identifier(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(quote_identifier(name), expected);
+    }
+}
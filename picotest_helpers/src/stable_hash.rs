@@ -0,0 +1,81 @@
+//! Hash stable across runs, platforms and Rust toolchains.
+//!
+//! Hashes stored between test runs (keep-alive state) or used to reproduce
+//! them (seeds of tests) must not depend on the toolchain, which
+//! [`std::collections::hash_map::DefaultHasher`] doesn't guarantee.
+//! Values are fed by explicit bytes rather than through [`std::hash::Hash`],
+//! whose implementations aren't guaranteed to be stable as well.
+
+use std::hash::Hasher;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hasher.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl StableHasher {
+    /// Feeds length-prefixed `bytes`, so that adjacent
+    /// values like `("ab", "c")` and `("a", "bc")` differ.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.write(bytes);
+    }
+
+    /// Returns hash formatted as 16 hex digits.
+    pub(crate) fn finish_hex(&self) -> String {
+        format!("{:016x}", self.finish())
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StableHasher;
+    use rstest::rstest;
+    use std::hash::Hasher;
+
+    #[rstest]
+    #[case(b"", 0xcbf2_9ce4_8422_2325)]
+    #[case(b"a", 0xaf63_dc4c_8601_ec8c)]
+    #[case(b"foobar", 0x8594_4171_f739_67e8)]
+    fn test_fnv1a(#[case] bytes: &[u8], #[case] expected: u64) {
+        let mut hasher = StableHasher::default();
+        hasher.write(bytes);
+        assert_eq!(hasher.finish(), expected);
+    }
+
+    #[rstest]
+    fn test_write_bytes_prefixes_length() {
+        let mut first = StableHasher::default();
+        first.write_bytes(b"ab");
+        first.write_bytes(b"c");
+        let mut second = StableHasher::default();
+        second.write_bytes(b"a");
+        second.write_bytes(b"bc");
+        assert_ne!(first.finish(), second.finish());
+    }
+}
@@ -0,0 +1,179 @@
+//! Dump of the cluster state for investigation of failed tests.
+//!
+//! Dump is a directory of plain text files, which are collected
+//! independently: failure to collect one of them is written into
+//! the file instead of its content, so the rest is still dumped.
+//!
+//! ```text
+//! <dump dir>/
+//!     health.txt                 state of every instance
+//!     governor.txt               unfinished work of the governor
+//!     topology.txt               declared and live topology
+//!     system_tables/<table>.yaml rows of picodata system tables
+//!     instances/<instance>/
+//!         instance_info.yaml     `pico.instance_info()`
+//!         box_info.yaml          `box.info()`
+//!         fibers.yaml            `fiber.info()`
+//!         picodata.log           last lines of the instance log
+//! ```
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! cluster.dump_state(Path::new("tmp/artifacts/my_test"))?;
+//! ```
+
+use crate::log_stream::instance_log_path;
+use crate::Cluster;
+use anyhow::Context;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable, which disables dumping state of the cluster
+/// when the test fails.
+pub const PICOTEST_DUMP_STATE: &str = "PICOTEST_DUMP_STATE";
+
+/// Directory of state dumps of failed tests, relative to the plugin root.
+pub const ARTIFACTS_DIR: &str = "tmp/artifacts";
+
+/// Number of the last lines of the instance log kept in the dump.
+pub const LOG_TAIL_LINES: usize = 500;
+
+/// System tables dumped with all their rows.
+const SYSTEM_TABLES: [&str; 10] = [
+    "_pico_instance",
+    "_pico_replicaset",
+    "_pico_tier",
+    "_pico_table",
+    "_pico_plugin",
+    "_pico_service",
+    "_pico_service_route",
+    "_pico_plugin_config",
+    "_pico_property",
+    "_pico_governor_queue",
+];
+
+const INSTANCE_INFO_LUA: &str = "return pico.instance_info()";
+const BOX_INFO_LUA: &str = "return box.info()";
+const FIBERS_LUA: &str = "return require('fiber').info({ backtrace = false })";
+
+impl Cluster {
+    /// Dumps state of the cluster into `dest_dir`, see [module docs](self).
+    ///
+    /// ### Returns
+    /// - On failure, instance of [`anyhow::Error`] if `dest_dir` can't be
+    ///   created. Failures to collect parts of the state are written into
+    ///   the dump.
+    pub fn dump_state(&self, dest_dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(dest_dir.join("system_tables"))
+            .with_context(|| format!("failed to create '{}'", dest_dir.display()))?;
+
+        write_part(dest_dir, "health.txt", || Ok(self.health().to_string()));
+        write_part(dest_dir, "governor.txt", || {
+            Ok(self.governor_queue()?.to_string())
+        });
+        write_part(dest_dir, "topology.txt", || {
+            Ok(format!(
+                "Declared:\n{:#?}\n\nLive:\n{:#?}\n",
+                self.topology,
+                self.topology()?
+            ))
+        });
+        for table in SYSTEM_TABLES {
+            write_part(dest_dir, &format!("system_tables/{table}.yaml"), || {
+                let rows = self.sql_query(&format!("SELECT * FROM {table}"), &[])?;
+                Ok(serde_norway::to_string(&rows.to_maps())?)
+            });
+        }
+
+        let data_dir = self.data_dir_path();
+        for instance in self.instances() {
+            let instance_dir = dest_dir.join("instances").join(&instance.instance_name);
+            fs::create_dir_all(&instance_dir)?;
+            for (file, script) in [
+                ("instance_info.yaml", INSTANCE_INFO_LUA),
+                ("box_info.yaml", BOX_INFO_LUA),
+                ("fibers.yaml", FIBERS_LUA),
+            ] {
+                write_part(&instance_dir, file, || Ok(instance.run_lua(script)?));
+            }
+            if !self.is_remote() {
+                write_part(&instance_dir, "picodata.log", || {
+                    let log_path = instance_log_path(&data_dir, &instance.instance_name);
+                    let log = fs::read_to_string(&log_path)
+                        .with_context(|| format!("failed to read '{}'", log_path.display()))?;
+                    Ok(log_tail(&log, LOG_TAIL_LINES).to_string())
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns directory for the state dump of the failed test `test_name`
+/// made now. Every failure is dumped into its own directory, so dumps of
+/// the test cases run concurrently don't overwrite each other.
+pub fn test_artifacts_dir(plugin_path: &Path, test_name: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    plugin_path
+        .join(ARTIFACTS_DIR)
+        .join(test_name.replace("::", "."))
+        .join(format!("{timestamp}_{}", std::process::id()))
+}
+
+/// Writes part of the dump collected by `collect` or its error into `file`.
+fn write_part(dir: &Path, file: &str, collect: impl FnOnce() -> anyhow::Result<String>) {
+    let content = collect().unwrap_or_else(|err| format!("# failed to collect: {err:#}\n"));
+    let _ = fs::write(dir.join(file), content);
+}
+
+/// Returns the last `lines` lines of the `log`.
+fn log_tail(log: &str, lines: usize) -> &str {
+    let trimmed = log.trim_end_matches('\n');
+    match trimmed.rmatch_indices('\n').nth(lines.saturating_sub(1)) {
+        Some((index, _)) if lines > 0 => &log[index + 1..],
+        Some(_) => "",
+        None => log,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{log_tail, test_artifacts_dir, write_part};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::Path;
+
+    #[rstest]
+    #[case::shorter("a\nb\n", 3, "a\nb\n")]
+    #[case::exact("a\nb\nc\n", 3, "a\nb\nc\n")]
+    #[case::longer("a\nb\nc\nd\n", 2, "c\nd\n")]
+    #[case::without_newline("a\nb\nc", 2, "b\nc")]
+    #[case::empty("", 2, "")]
+    fn test_log_tail(#[case] log: &str, #[case] lines: usize, #[case] expected: &str) {
+        assert_eq!(log_tail(log, lines), expected);
+    }
+
+    #[rstest]
+    fn test_artifacts_dir_of_test() {
+        let dir = test_artifacts_dir(Path::new("/plugin"), "tests::test_users");
+        assert!(dir.starts_with("/plugin/tmp/artifacts/tests.test_users"));
+        assert_eq!(dir.components().count(), 6);
+    }
+
+    #[rstest]
+    fn test_write_part() {
+        let dir = std::env::temp_dir().join(format!("picotest_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        write_part(&dir, "ok.txt", || Ok("state".to_string()));
+        write_part(&dir, "failed.txt", || anyhow::bail!("instance is down"));
+        let ok = fs::read_to_string(dir.join("ok.txt")).unwrap();
+        let failed = fs::read_to_string(dir.join("failed.txt")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ok, "state");
+        assert_eq!(failed, "# failed to collect: instance is down\n");
+    }
+}
@@ -0,0 +1,198 @@
+//! Tags of tests and topology profiles of the session cluster.
+//!
+//! Tests are tagged by `#[picotest(tag = "smoke")]`. Tags listed in
+//! [`PICOTEST_TAGS`] select tests to be run, the rest are skipped. Tags are
+//! mapped to topology profiles in `picotest.toml` located in the plugin root:
+//!
+//! ```toml
+//! [tags.smoke]
+//! topology = "single_node"
+//!
+//! [tags.heavy]
+//! topology = "full"
+//! ```
+//!
+//! Session cluster is started with the profile of selected tags, so the same
+//! suite runs quick single-node smoke pass locally and full topology in CI.
+
+use crate::impact::ImpactConfig;
+use crate::topology::{PluginTopology, SingleNodeTopologyTransformer, TopologyTransformer};
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Environment variable with comma-separated tags of tests to be run.
+pub const PICOTEST_TAGS: &str = "PICOTEST_TAGS";
+
+/// Name of the picotest configuration file in the plugin root.
+pub const PICOTEST_CONFIG_FILENAME: &str = "picotest.toml";
+
+/// Topology the session cluster is started with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyProfile {
+    /// Single instance of default tier, see [`SingleNodeTopologyTransformer`].
+    SingleNode,
+    /// Topology of the plugin as is.
+    #[default]
+    Full,
+}
+
+impl TopologyProfile {
+    /// Returns `topology` reduced according to the profile.
+    pub fn apply(self, topology: &PluginTopology) -> PluginTopology {
+        match self {
+            Self::SingleNode => SingleNodeTopologyTransformer::default().transform(topology),
+            Self::Full => topology.clone(),
+        }
+    }
+}
+
+/// Settings of the tag in `picotest.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TagConfig {
+    #[serde(default)]
+    pub topology: TopologyProfile,
+}
+
+/// Contents of `picotest.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct PicotestConfig {
+    #[serde(default)]
+    pub tags: BTreeMap<String, TagConfig>,
+    #[serde(default)]
+    pub impact: ImpactConfig,
+}
+
+impl PicotestConfig {
+    /// Loads `picotest.toml` located in `plugin_path`.
+    /// Missing file is treated as empty one.
+    pub fn load(plugin_path: &Path) -> anyhow::Result<Self> {
+        let path = plugin_path.join(PICOTEST_CONFIG_FILENAME);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(Self::default());
+        };
+        toml::from_str(&content).with_context(|| format!("failed to parse '{}'", path.display()))
+    }
+
+    /// Returns topology profile of the `tag`, [`TopologyProfile::Full`] if it's not configured.
+    pub fn profile_of(&self, tag: &str) -> TopologyProfile {
+        self.tags
+            .get(tag)
+            .map(|config| config.topology)
+            .unwrap_or_default()
+    }
+}
+
+/// Tags of tests selected to be run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    /// `None` if all tests are run.
+    tags: Option<BTreeSet<String>>,
+}
+
+impl TagFilter {
+    /// Parses comma-separated list of tags, e.g. `smoke,api`.
+    pub fn parse(value: &str) -> Self {
+        let tags: BTreeSet<_> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect();
+        Self {
+            tags: (!tags.is_empty()).then_some(tags),
+        }
+    }
+
+    /// Filter set by [`PICOTEST_TAGS`], if any.
+    pub fn from_env() -> Self {
+        std::env::var(PICOTEST_TAGS)
+            .map(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if test tagged by `tag` is run.
+    /// Untagged tests are run only if no tags are selected.
+    pub fn is_selected(&self, tag: Option<&str>) -> bool {
+        match (&self.tags, tag) {
+            (None, _) => true,
+            (Some(tags), Some(tag)) => tags.contains(tag),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Returns topology profile of the session cluster, i.e. the profile
+    /// shared by all selected tags, [`TopologyProfile::Full`] if no tags are selected.
+    pub fn session_profile(&self, config: &PicotestConfig) -> anyhow::Result<TopologyProfile> {
+        let Some(tags) = &self.tags else {
+            return Ok(TopologyProfile::Full);
+        };
+        let profiles: BTreeMap<_, _> = tags
+            .iter()
+            .map(|tag| (tag.as_str(), config.profile_of(tag)))
+            .collect();
+        let profile = profiles.values().next().copied().unwrap_or_default();
+        if profiles.values().any(|other| *other != profile) {
+            bail!(
+                "selected tags map to different topology profiles {profiles:?}, \
+                run them separately"
+            );
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PicotestConfig, TagFilter, TopologyProfile};
+    use rstest::rstest;
+
+    const CONFIG: &str = r#"
+[tags.smoke]
+topology = "single_node"
+
+[tags.api]
+topology = "single_node"
+
+[tags.heavy]
+topology = "full"
+"#;
+
+    #[rstest]
+    fn test_tag_filter() {
+        let filter = TagFilter::parse("smoke, api");
+        assert!(filter.is_selected(Some("smoke")));
+        assert!(filter.is_selected(Some("api")));
+        assert!(!filter.is_selected(Some("heavy")));
+        assert!(!filter.is_selected(None));
+
+        let filter = TagFilter::parse(" ");
+        assert_eq!(filter, TagFilter::default());
+        assert!(filter.is_selected(Some("heavy")));
+        assert!(filter.is_selected(None));
+    }
+
+    #[rstest]
+    #[case("", TopologyProfile::Full)]
+    #[case("smoke", TopologyProfile::SingleNode)]
+    #[case("smoke,api", TopologyProfile::SingleNode)]
+    #[case("heavy", TopologyProfile::Full)]
+    #[case("unknown", TopologyProfile::Full)]
+    fn test_session_profile(#[case] tags: &str, #[case] expected: TopologyProfile) {
+        let config: PicotestConfig = toml::from_str(CONFIG).unwrap();
+        let profile = TagFilter::parse(tags).session_profile(&config).unwrap();
+        assert_eq!(profile, expected);
+    }
+
+    #[rstest]
+    fn test_conflicting_profiles() {
+        let config: PicotestConfig = toml::from_str(CONFIG).unwrap();
+        let err = TagFilter::parse("smoke,heavy")
+            .session_profile(&config)
+            .unwrap_err();
+        assert!(err.to_string().contains("different topology profiles"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Test-scoped tables with automatic cleanup.
+//!
+//! Picodata has no SQL schemas, so [`TempSchema`] is a unique prefix of
+//! table names. Tables named by [`TempSchema::table`] belong to the schema
+//! and are dropped when the guard is dropped, so tests sharing the session
+//! cluster don't collide on table names.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_users() {
+//!     let schema = cluster.temp_schema();
+//!     let users = schema.table("users");
+//!     cluster.run_sql(format!(
+//!         "CREATE TABLE {users} (id INT PRIMARY KEY, name TEXT) DISTRIBUTED BY (id)"
+//!     ))?;
+//!     cluster.run_sql(format!("INSERT INTO {users} VALUES (1, 'Alice')"))?;
+//! } // `users` table is dropped here
+//! ```
+
+use crate::introspection::eval_lua_yaml;
+use crate::sql::quote_identifier;
+use crate::Cluster;
+use anyhow::Context;
+use log::{debug, warn};
+use uuid::Uuid;
+
+const TABLE_NAMES_EXPRESSION: &str = "(function() \
+    local names = {}; \
+    for _, t in box.space._pico_table:pairs() do table.insert(names, t.name) end; \
+    return names; \
+end)()";
+
+/// Guard of tables created by the test, see [`Cluster::temp_schema`].
+pub struct TempSchema<'a> {
+    cluster: &'a Cluster,
+    prefix: String,
+}
+
+impl<'a> TempSchema<'a> {
+    fn new(cluster: &'a Cluster) -> Self {
+        let id = Uuid::new_v4().simple().to_string();
+        Self {
+            cluster,
+            prefix: format!("t_{}_", &id[..8]),
+        }
+    }
+
+    /// Returns prefix of table names, e.g. `t_1a2b3c4d_`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns name of the table `name` owned by the schema,
+    /// e.g. `t_1a2b3c4d_users`.
+    pub fn table(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    /// Returns names of existing tables owned by the schema.
+    pub fn tables(&self) -> anyhow::Result<Vec<String>> {
+        let names: Vec<String> = eval_lua_yaml(self.cluster.main(), TABLE_NAMES_EXPRESSION)
+            .context("failed to list tables")?;
+        Ok(owned_tables(&self.prefix, names))
+    }
+
+    /// Drops all tables owned by the schema.
+    pub fn drop_tables(&self) -> anyhow::Result<()> {
+        for table in self.tables()? {
+            debug!("Dropping temporary table '{table}'");
+            self.cluster
+                .sql_query(&format!("DROP TABLE {}", quote_identifier(&table)), &[])
+                .with_context(|| format!("failed to drop temporary table '{table}'"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempSchema<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.drop_tables() {
+            warn!(
+                "Failed to clean up temporary schema '{}': {err:#}",
+                self.prefix
+            );
+        }
+    }
+}
+
+fn owned_tables(prefix: &str, names: Vec<String>) -> Vec<String> {
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .collect()
+}
+
+impl Cluster {
+    /// Creates uniquely named temporary schema. Tables named by
+    /// [`TempSchema::table`] are dropped when the schema is dropped.
+    pub fn temp_schema(&self) -> TempSchema<'_> {
+        TempSchema::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::owned_tables;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_owned_tables() {
+        let names = [
+            "_pico_table",
+            "users",
+            "t_1a2b3c4d_users",
+            "t_1a2b3c4d_orders",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            owned_tables("t_1a2b3c4d_", names),
+            ["t_1a2b3c4d_users", "t_1a2b3c4d_orders"]
+        );
+    }
+}
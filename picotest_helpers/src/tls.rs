@@ -0,0 +1,335 @@
+//! TLS connections to pgproto and iproto of the instances.
+//!
+//! Clients of picotest connect to the instances without encryption. If the
+//! cluster enforces encrypted connections, TLS is enabled by [`Cluster::pg_tls`]
+//! and [`Cluster::iproto_tls`]. Certificates of the test CA are trusted by
+//! [`TlsConfig::ca_cert`] or not verified at all by [`TlsConfig::accept_invalid`].
+//!
+//! iproto client doesn't support TLS, so it connects to a local tunnel,
+//! which forwards every connection to the instance over TLS.
+//!
+//! **Note:** TLS is implemented by `native-tls`, so picotest must be built
+//! with `tls` feature. Otherwise connections configured with TLS fail.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let tls = TlsConfig::new()
+//!     .ca_cert("certs/ca.pem")
+//!     .client_identity("certs/client.pem", "certs/client.key");
+//! let cluster = Cluster::new(plugin_path, topology, picodata_path)?
+//!     .pg_tls(Some(tls.clone()))
+//!     .iproto_tls(Some(tls))
+//!     .run()?;
+//! ```
+
+use crate::Cluster;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// TLS settings of the client connections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// CA certificate (PEM) verifying certificates of the instances.
+    /// System CA certificates are trusted anyway.
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate (PEM) presented to the instances for mTLS.
+    pub client_cert: Option<PathBuf>,
+    /// Private key (PEM, PKCS #8) of the client certificate.
+    pub client_key: Option<PathBuf>,
+    /// Certificates and host names of the instances aren't verified,
+    /// e.g. if they're issued by the self-signed test CA.
+    pub accept_invalid: bool,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts certificates issued by CA of the `path`.
+    pub fn ca_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_cert = Some(path.into());
+        self
+    }
+
+    /// Presents client certificate for mTLS.
+    pub fn client_identity(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.client_cert = Some(cert.into());
+        self.client_key = Some(key.into());
+        self
+    }
+
+    /// Disables verification of the instance certificates.
+    pub fn accept_invalid(mut self, is_enabled: bool) -> Self {
+        self.accept_invalid = is_enabled;
+        self
+    }
+}
+
+impl Cluster {
+    /// Connects to pgproto of the instances over TLS, see [module docs](self).
+    pub fn pg_tls(mut self, tls: Option<TlsConfig>) -> Self {
+        self.pg_tls = tls;
+        self
+    }
+
+    /// Connects to iproto of the instances over TLS, see [module docs](self).
+    pub fn iproto_tls(mut self, tls: Option<TlsConfig>) -> Self {
+        for instance in &mut self.instances {
+            instance.iproto_tls = tls.clone();
+            instance.iproto_pool = OnceLock::new();
+        }
+        self.iproto_tls = tls;
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+mod native {
+    use super::TlsConfig;
+    use anyhow::{anyhow, bail, Context};
+    use log::debug;
+    use native_tls::{Certificate, Identity, TlsConnector};
+    use postgres::{Client, Config};
+    use postgres_native_tls::MakeTlsConnector;
+    use std::fs;
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Interval the tunnel switches between directions of the connection.
+    const TUNNEL_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+    impl TlsConfig {
+        fn connector(&self) -> anyhow::Result<TlsConnector> {
+            let mut builder = TlsConnector::builder();
+            if let Some(path) = &self.ca_cert {
+                let cert = Certificate::from_pem(&read_pem(path)?)
+                    .with_context(|| format!("invalid CA certificate '{}'", path.display()))?;
+                builder.add_root_certificate(cert);
+            }
+            match (&self.client_cert, &self.client_key) {
+                (Some(cert), Some(key)) => {
+                    let identity = Identity::from_pkcs8(&read_pem(cert)?, &read_pem(key)?)
+                        .with_context(|| {
+                            format!("invalid client certificate '{}'", cert.display())
+                        })?;
+                    builder.identity(identity);
+                }
+                (None, None) => {}
+                _ => bail!("both client certificate and its key must be set"),
+            }
+            builder
+                .danger_accept_invalid_certs(self.accept_invalid)
+                .danger_accept_invalid_hostnames(self.accept_invalid);
+            builder.build().context("failed to build TLS connector")
+        }
+    }
+
+    fn read_pem(path: &Path) -> anyhow::Result<Vec<u8>> {
+        fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))
+    }
+
+    /// Connects postgres client configured by `config` over TLS.
+    pub(crate) fn connect_pg(config: &Config, tls: &TlsConfig) -> anyhow::Result<Client> {
+        Ok(config.connect(MakeTlsConnector::new(tls.connector()?))?)
+    }
+
+    /// Local listener forwarding connections to the `target` over TLS.
+    pub(crate) struct TlsTunnel {
+        local_address: SocketAddr,
+        target: String,
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl TlsTunnel {
+        pub(crate) fn start(tls: &TlsConfig, host: &str, target: String) -> anyhow::Result<Self> {
+            let connector = tls.connector()?;
+            let listener =
+                TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).context("failed to bind TLS tunnel")?;
+            let local_address = listener.local_addr()?;
+            let stopped = Arc::new(AtomicBool::new(false));
+
+            let domain = host.to_string();
+            let tunnel_target = target.clone();
+            let tunnel_stopped = stopped.clone();
+            thread::Builder::new()
+                .name("picotest-tls-tunnel".into())
+                .spawn(move || {
+                    for client in listener.incoming() {
+                        if tunnel_stopped.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        let Ok(client) = client else {
+                            continue;
+                        };
+                        let (connector, domain, target) =
+                            (connector.clone(), domain.clone(), tunnel_target.clone());
+                        thread::spawn(move || {
+                            if let Err(err) = forward(client, &connector, &domain, &target) {
+                                debug!("TLS tunnel connection to '{target}' is closed: {err:#}");
+                            }
+                        });
+                    }
+                })
+                .context("failed to spawn TLS tunnel thread")?;
+
+            Ok(Self {
+                local_address,
+                target,
+                stopped,
+            })
+        }
+
+        pub(crate) fn target(&self) -> &str {
+            &self.target
+        }
+
+        pub(crate) fn local_address(&self) -> String {
+            self.local_address.to_string()
+        }
+    }
+
+    impl Drop for TlsTunnel {
+        fn drop(&mut self) {
+            // Listener is woken up by connection to see the flag.
+            self.stopped.store(true, Ordering::Relaxed);
+            let _ = TcpStream::connect(self.local_address);
+        }
+    }
+
+    /// Copies data between `client` and the `target` until either side closes.
+    ///
+    /// TLS stream can't be read and written from different threads, so both
+    /// directions are polled in turn by reads with timeout.
+    fn forward(
+        mut client: TcpStream,
+        connector: &TlsConnector,
+        domain: &str,
+        target: &str,
+    ) -> anyhow::Result<()> {
+        let server = TcpStream::connect(target)?;
+        let mut server = connector
+            .connect(domain, server)
+            .map_err(|err| anyhow!("TLS handshake with '{target}' has failed: {err}"))?;
+        server
+            .get_ref()
+            .set_read_timeout(Some(TUNNEL_POLL_INTERVAL))?;
+        client.set_read_timeout(Some(TUNNEL_POLL_INTERVAL))?;
+
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            if !copy_available(&mut client, &mut server, &mut buf)?
+                || !copy_available(&mut server, &mut client, &mut buf)?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Copies data read from `from` within the poll interval.
+    ///
+    /// ### Returns
+    /// `false` if `from` is closed.
+    pub(super) fn copy_available(
+        from: &mut impl Read,
+        to: &mut impl Write,
+        buf: &mut [u8],
+    ) -> io::Result<bool> {
+        match from.read(buf) {
+            Ok(0) => Ok(false),
+            Ok(len) => to.write_all(&buf[..len]).map(|_| true),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod native {
+    use super::TlsConfig;
+    use anyhow::bail;
+    use postgres::{Client, Config};
+
+    const TLS_DISABLED: &str = "TLS connections require picotest built with `tls` feature";
+
+    pub(crate) fn connect_pg(_config: &Config, _tls: &TlsConfig) -> anyhow::Result<Client> {
+        bail!(TLS_DISABLED)
+    }
+
+    pub(crate) struct TlsTunnel;
+
+    impl TlsTunnel {
+        pub(crate) fn start(
+            _tls: &TlsConfig,
+            _host: &str,
+            _target: String,
+        ) -> anyhow::Result<Self> {
+            bail!(TLS_DISABLED)
+        }
+
+        pub(crate) fn target(&self) -> &str {
+            unreachable!("TLS tunnel isn't started without `tls` feature")
+        }
+
+        pub(crate) fn local_address(&self) -> String {
+            unreachable!("TLS tunnel isn't started without `tls` feature")
+        }
+    }
+}
+
+pub(crate) use native::{connect_pg, TlsTunnel};
+
+#[cfg(test)]
+mod tests {
+    use super::TlsConfig;
+    use rstest::rstest;
+    use std::path::PathBuf;
+
+    #[rstest]
+    fn test_tls_config_builder() {
+        let tls = TlsConfig::new()
+            .ca_cert("certs/ca.pem")
+            .client_identity("certs/client.pem", "certs/client.key")
+            .accept_invalid(true);
+        assert_eq!(
+            tls,
+            TlsConfig {
+                ca_cert: Some(PathBuf::from("certs/ca.pem")),
+                client_cert: Some(PathBuf::from("certs/client.pem")),
+                client_key: Some(PathBuf::from("certs/client.key")),
+                accept_invalid: true,
+            }
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[rstest]
+    fn test_copy_available() {
+        use super::native::copy_available;
+        use std::io::{self, Cursor, ErrorKind, Read};
+
+        struct Stalled;
+        impl Read for Stalled {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let mut buf = [0; 4];
+        let mut to = Vec::new();
+        let mut from = Cursor::new(b"iproto".to_vec());
+        assert!(copy_available(&mut from, &mut to, &mut buf).unwrap());
+        assert!(copy_available(&mut from, &mut to, &mut buf).unwrap());
+        assert!(!copy_available(&mut from, &mut to, &mut buf).unwrap());
+        assert_eq!(to, b"iproto");
+
+        assert!(copy_available(&mut Stalled, &mut to, &mut buf).unwrap());
+    }
+}
@@ -1,8 +1,14 @@
 use anyhow::Context;
-use pike::cluster::Tier;
+use pike::cluster::{Service, Tier};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
 use std::{fs, path::PathBuf};
 
+use crate::catalog::ServiceInfo;
 use crate::migration::MigrationContextProvider;
+use crate::Cluster;
+
+pub use crate::catalog::{ReplicasetInfo, TierInfo};
 
 pub const DEFAULT_TIER: &str = "default";
 
@@ -18,10 +24,363 @@ pub fn parse_topology(path: &PathBuf) -> anyhow::Result<PluginTopology> {
     ))
 }
 
+impl Cluster {
+    /// Returns tiers of the running cluster.
+    pub fn tiers(&self) -> anyhow::Result<Vec<TierInfo>> {
+        self.catalog().tiers()
+    }
+
+    /// Returns replicasets of the running cluster.
+    pub fn replicasets(&self) -> anyhow::Result<Vec<ReplicasetInfo>> {
+        self.catalog().replicasets()
+    }
+
+    /// Returns topology of the running cluster.
+    ///
+    /// Tiers and placement of plugin services are taken from system tables,
+    /// the rest (e.g. migration context) is taken from the declared topology.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    ///
+    /// #[picotest]
+    /// fn test_topology_is_deployed() {
+    ///     let topology = cluster.topology().unwrap();
+    ///     assert_eq!(topology.tiers["default"].replicasets, 2);
+    /// }
+    /// ```
+    pub fn topology(&self) -> anyhow::Result<PluginTopology> {
+        let services = self.catalog().services()?;
+
+        Ok(build_topology(
+            &self.topology,
+            &self.tiers()?,
+            &self.replicasets()?,
+            &services,
+        ))
+    }
+}
+
+/// Combines declared topology with the state of the running cluster.
+fn build_topology(
+    declared: &PluginTopology,
+    tiers: &[TierInfo],
+    replicasets: &[ReplicasetInfo],
+    services: &[ServiceInfo],
+) -> PluginTopology {
+    let mut topology = declared.clone();
+
+    topology.tiers = tiers
+        .iter()
+        .map(|tier| {
+            let replicasets = replicasets
+                .iter()
+                .filter(|replicaset| replicaset.tier == tier.name)
+                .count();
+            let tier_topology = Tier {
+                replicasets: replicasets as _,
+                replication_factor: tier.replication_factor as _,
+            };
+            (tier.name.clone(), tier_topology)
+        })
+        .collect();
+
+    for plugin in topology.plugins.values_mut() {
+        plugin.services.clear();
+    }
+    for service in services {
+        topology
+            .plugins
+            .entry(service.plugin_name.clone())
+            .or_default()
+            .services
+            .insert(
+                service.name.clone(),
+                Service {
+                    tiers: service.tiers.clone(),
+                },
+            );
+    }
+
+    topology
+}
+
+/// Problem of the topology, which prevents cluster from being deployed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopologyIssue {
+    /// Topology declares no tiers.
+    NoTiers,
+    /// Tier has zero replicasets.
+    ZeroReplicasets { tier: String },
+    /// Tier has zero replication factor.
+    ZeroReplicationFactor { tier: String },
+    /// Service is placed on a tier, which isn't declared.
+    UnknownTier {
+        plugin: String,
+        service: String,
+        tier: String,
+    },
+    /// Service isn't placed on any tier.
+    ServiceWithoutTiers { plugin: String, service: String },
+    /// Service with the same name is declared by several plugins.
+    /// Services are looked up by name only, e.g. by [`Cluster::get_service_config`].
+    DuplicateService {
+        service: String,
+        plugins: Vec<String>,
+    },
+}
+
+impl Display for TopologyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoTiers => write!(f, "no tiers are declared"),
+            Self::ZeroReplicasets { tier } => write!(f, "tier '{tier}' has zero replicasets"),
+            Self::ZeroReplicationFactor { tier } => {
+                write!(f, "tier '{tier}' has zero replication factor")
+            }
+            Self::UnknownTier {
+                plugin,
+                service,
+                tier,
+            } => write!(
+                f,
+                "service '{plugin}.{service}' is placed on undeclared tier '{tier}'"
+            ),
+            Self::ServiceWithoutTiers { plugin, service } => {
+                write!(f, "service '{plugin}.{service}' is not placed on any tier")
+            }
+            Self::DuplicateService { service, plugins } => {
+                write!(f, "service '{service}' is declared by plugins {plugins:?}")
+            }
+        }
+    }
+}
+
+/// Checks topology for problems, which would make pike fail
+/// deep inside cluster start or make services ambiguous.
+///
+/// ### Returns
+///
+/// Found issues, empty if topology is valid.
+pub fn validate(topology: &PluginTopology) -> Vec<TopologyIssue> {
+    let mut issues = Vec::new();
+    if topology.tiers.is_empty() {
+        issues.push(TopologyIssue::NoTiers);
+    }
+    for (tier_name, tier) in &topology.tiers {
+        if tier.replicasets == 0 {
+            issues.push(TopologyIssue::ZeroReplicasets {
+                tier: tier_name.clone(),
+            });
+        }
+        if tier.replication_factor == 0 {
+            issues.push(TopologyIssue::ZeroReplicationFactor {
+                tier: tier_name.clone(),
+            });
+        }
+    }
+
+    let mut service_plugins: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (plugin_name, plugin) in &topology.plugins {
+        for (service_name, service) in &plugin.services {
+            service_plugins
+                .entry(service_name)
+                .or_default()
+                .push(plugin_name.clone());
+            if service.tiers.is_empty() {
+                issues.push(TopologyIssue::ServiceWithoutTiers {
+                    plugin: plugin_name.clone(),
+                    service: service_name.clone(),
+                });
+            }
+            for tier in &service.tiers {
+                if !topology.tiers.contains_key(tier) {
+                    issues.push(TopologyIssue::UnknownTier {
+                        plugin: plugin_name.clone(),
+                        service: service_name.clone(),
+                        tier: tier.clone(),
+                    });
+                }
+            }
+        }
+    }
+    for (service, plugins) in service_plugins {
+        if plugins.len() > 1 {
+            issues.push(TopologyIssue::DuplicateService {
+                service: service.to_string(),
+                plugins,
+            });
+        }
+    }
+
+    issues
+}
+
+/// Compares topologies and returns readable list of changes from `old` to `new`.
+///
+/// Lines start with `+` for added entities, `-` for removed ones and `~` for changed ones.
+///
+/// ### Examples:
+///
+/// ```rust,ignore
+/// let changes = topology::diff(&declared, &cluster.topology()?);
+/// assert!(changes.is_empty(), "topology has drifted:\n{}", changes.join("\n"));
+/// ```
+pub fn diff(old: &PluginTopology, new: &PluginTopology) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    for (name, tier) in &old.tiers {
+        match new.tiers.get(name) {
+            None => changes.push(format!("- tier '{name}'")),
+            Some(new_tier) => {
+                if new_tier.replicasets != tier.replicasets {
+                    changes.push(format!(
+                        "~ tier '{name}': replicasets {} -> {}",
+                        tier.replicasets, new_tier.replicasets
+                    ));
+                }
+                if new_tier.replication_factor != tier.replication_factor {
+                    changes.push(format!(
+                        "~ tier '{name}': replication factor {} -> {}",
+                        tier.replication_factor, new_tier.replication_factor
+                    ));
+                }
+            }
+        }
+    }
+    for (name, tier) in &new.tiers {
+        if !old.tiers.contains_key(name) {
+            changes.push(format!(
+                "+ tier '{name}' (replicasets: {}, replication factor: {})",
+                tier.replicasets, tier.replication_factor
+            ));
+        }
+    }
+
+    let plugin_names: BTreeSet<&String> = old.plugins.keys().chain(new.plugins.keys()).collect();
+    for plugin_name in plugin_names {
+        let (old_plugin, new_plugin) =
+            match (old.plugins.get(plugin_name), new.plugins.get(plugin_name)) {
+                (Some(_), None) => {
+                    changes.push(format!("- plugin '{plugin_name}'"));
+                    continue;
+                }
+                (None, Some(_)) => {
+                    changes.push(format!("+ plugin '{plugin_name}'"));
+                    (None, new.plugins.get(plugin_name))
+                }
+                (old_plugin, new_plugin) => (old_plugin, new_plugin),
+            };
+        let old_services = old_plugin.map(|plugin| &plugin.services);
+        let new_services = new_plugin.map(|plugin| &plugin.services);
+        let service_names: BTreeSet<&String> = old_services
+            .into_iter()
+            .chain(new_services)
+            .flat_map(|services| services.keys())
+            .collect();
+        for service_name in service_names {
+            let old_tiers = old_services
+                .and_then(|services| services.get(service_name))
+                .map(|service| &service.tiers);
+            let new_tiers = new_services
+                .and_then(|services| services.get(service_name))
+                .map(|service| &service.tiers);
+            match (old_tiers, new_tiers) {
+                (Some(_), None) => {
+                    changes.push(format!("- service '{plugin_name}.{service_name}'"))
+                }
+                (None, Some(tiers)) => changes.push(format!(
+                    "+ service '{plugin_name}.{service_name}' on tiers {tiers:?}"
+                )),
+                (Some(old_tiers), Some(new_tiers)) if old_tiers != new_tiers => {
+                    changes.push(format!(
+                        "~ service '{plugin_name}.{service_name}': tiers {old_tiers:?} -> {new_tiers:?}"
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let env_names: BTreeSet<&String> = old.enviroment.keys().chain(new.enviroment.keys()).collect();
+    for name in env_names {
+        match (old.enviroment.get(name), new.enviroment.get(name)) {
+            (Some(_), None) => changes.push(format!("- environment variable '{name}'")),
+            (None, Some(value)) => {
+                changes.push(format!("+ environment variable '{name}' = '{value}'"))
+            }
+            (Some(old_value), Some(new_value)) if old_value != new_value => changes.push(format!(
+                "~ environment variable '{name}': '{old_value}' -> '{new_value}'"
+            )),
+            _ => {}
+        }
+    }
+
+    changes
+}
+
 pub trait TopologyTransformer {
     fn transform(&self, source_topology: &PluginTopology) -> PluginTopology;
 }
 
+/// Plain functions, e.g. passed to `topology_transform` attribute of `#[picotest]`, are transformers too.
+impl<F> TopologyTransformer for F
+where
+    F: Fn(&PluginTopology) -> PluginTopology,
+{
+    fn transform(&self, source_topology: &PluginTopology) -> PluginTopology {
+        self(source_topology)
+    }
+}
+
+/// Applies transformers one after another, each one to the result of the previous.
+///
+/// ### Examples:
+///
+/// ```rust,ignore
+/// fn drop_optional_service(topology: &PluginTopology) -> PluginTopology {
+///     let mut topology = topology.clone();
+///     topology.plugins.get_mut("plugin").unwrap().services.remove("metrics");
+///     topology
+/// }
+///
+/// let topology = TransformerChain::new()
+///     .then(drop_optional_service)
+///     .then(SingleNodeTopologyTransformer::default())
+///     .transform(&source_topology);
+/// ```
+#[derive(Default)]
+pub struct TransformerChain {
+    transformers: Vec<Box<dyn TopologyTransformer>>,
+}
+
+impl TransformerChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `transformer` to the end of the chain.
+    pub fn then<T>(mut self, transformer: T) -> Self
+    where
+        T: TopologyTransformer + 'static,
+    {
+        self.transformers.push(Box::new(transformer));
+        self
+    }
+}
+
+impl TopologyTransformer for TransformerChain {
+    fn transform(&self, source_topology: &PluginTopology) -> PluginTopology {
+        self.transformers
+            .iter()
+            .fold(source_topology.clone(), |topology, transformer| {
+                transformer.transform(&topology)
+            })
+    }
+}
+
 /// Produces single-node topology from source topology.
 ///
 /// This routine transforms input topology to a single-node
@@ -79,7 +438,10 @@ impl TopologyTransformer for SingleNodeTopologyTransformer {
 #[cfg(test)]
 mod tests {
 
-    use crate::topology::{SingleNodeTopologyTransformer, TopologyTransformer, DEFAULT_TIER};
+    use crate::topology::{
+        build_topology, diff, validate, ReplicasetInfo, ServiceInfo, SingleNodeTopologyTransformer,
+        TierInfo, TopologyIssue, TopologyTransformer, TransformerChain, DEFAULT_TIER,
+    };
     use pike::cluster::{Plugin, Service, Tier, Topology};
     use rstest::{fixture, rstest};
     use std::collections::BTreeMap;
@@ -162,4 +524,142 @@ mod tests {
             "env should've not changed"
         );
     }
+
+    #[rstest]
+    fn test_build_topology(topology: Topology) {
+        let tiers = vec![TierInfo {
+            name: "default".to_string(),
+            replication_factor: 2,
+            can_vote: true,
+        }];
+        let replicaset = |name: &str| ReplicasetInfo {
+            name: name.to_string(),
+            uuid: String::new(),
+            tier: "default".to_string(),
+            current_master_name: format!("{name}_1"),
+        };
+        let replicasets = vec![replicaset("default_1"), replicaset("default_2")];
+        let services = vec![ServiceInfo {
+            plugin_name: "test_plugin".to_string(),
+            name: "storage".to_string(),
+            version: "0.1.0".to_string(),
+            tiers: vec!["default".to_string()],
+        }];
+
+        let actual = build_topology(&topology, &tiers, &replicasets, &services);
+
+        assert_eq!(1, actual.tiers.len(), "should contain only running tiers");
+        assert_eq!(2, actual.tiers["default"].replicasets);
+        assert_eq!(2, actual.tiers["default"].replication_factor);
+
+        let plugin = &actual.plugins["test_plugin"];
+        assert_eq!(
+            1,
+            plugin.services.len(),
+            "should contain only running services"
+        );
+        assert_eq!(vec!["default"], plugin.services["storage"].tiers);
+        assert_eq!("value", actual.enviroment["key"]);
+    }
+
+    #[rstest]
+    fn test_validate_topology(topology: Topology) {
+        assert!(validate(&topology).is_empty());
+
+        let mut invalid = topology.clone();
+        invalid.tiers.get_mut("extra").unwrap().replication_factor = 0;
+        let plugin = invalid.plugins.get_mut("test_plugin").unwrap();
+        plugin.services.get_mut("storage").unwrap().tiers = vec!["missing".to_string()];
+        let mut other_plugin = plugin.clone();
+        other_plugin.services.remove("storage");
+        other_plugin
+            .services
+            .get_mut("router")
+            .unwrap()
+            .tiers
+            .clear();
+        invalid
+            .plugins
+            .insert("other_plugin".to_string(), other_plugin);
+
+        assert_eq!(
+            validate(&invalid),
+            vec![
+                TopologyIssue::ZeroReplicationFactor {
+                    tier: "extra".to_string()
+                },
+                TopologyIssue::ServiceWithoutTiers {
+                    plugin: "other_plugin".to_string(),
+                    service: "router".to_string()
+                },
+                TopologyIssue::UnknownTier {
+                    plugin: "test_plugin".to_string(),
+                    service: "storage".to_string(),
+                    tier: "missing".to_string()
+                },
+                TopologyIssue::DuplicateService {
+                    service: "router".to_string(),
+                    plugins: vec!["other_plugin".to_string(), "test_plugin".to_string()]
+                },
+            ]
+        );
+        assert_eq!(validate(&Topology::default()), vec![TopologyIssue::NoTiers]);
+    }
+
+    #[rstest]
+    fn test_diff_topology(topology: Topology) {
+        assert!(diff(&topology, &topology).is_empty());
+
+        let mut changed = topology.clone();
+        changed.tiers.remove("extra");
+        changed.tiers.get_mut("default").unwrap().replicasets = 3;
+        changed
+            .plugins
+            .get_mut("test_plugin")
+            .unwrap()
+            .services
+            .get_mut("router")
+            .unwrap()
+            .tiers = vec!["default".to_string()];
+        changed
+            .enviroment
+            .insert("key".to_string(), "other".to_string());
+
+        assert_eq!(
+            diff(&topology, &changed),
+            vec![
+                "~ tier 'default': replicasets 2 -> 3",
+                "- tier 'extra'",
+                "~ service 'test_plugin.router': tiers [\"extra\"] -> [\"default\"]",
+                "~ environment variable 'key': 'value' -> 'other'",
+            ]
+        );
+        assert_eq!(
+            diff(&changed, &topology)[1],
+            "+ tier 'extra' (replicasets: 3, replication factor: 2)"
+        );
+    }
+
+    #[rstest]
+    fn test_transformer_chain(topology: Topology) {
+        fn drop_router(topology: &Topology) -> Topology {
+            let mut topology = topology.clone();
+            for plugin in topology.plugins.values_mut() {
+                plugin.services.remove("router");
+            }
+            topology
+        }
+
+        assert!(diff(&topology, &TransformerChain::new().transform(&topology)).is_empty());
+
+        let transformed = TransformerChain::new()
+            .then(drop_router)
+            .then(SingleNodeTopologyTransformer::default())
+            .transform(&topology);
+
+        let plugin = &transformed.plugins["test_plugin"];
+        assert_eq!(vec!["storage"], plugin.services.keys().collect::<Vec<_>>());
+        assert_eq!(vec![DEFAULT_TIER], plugin.services["storage"].tiers);
+        assert_eq!(1, transformed.tiers.len());
+    }
 }
@@ -0,0 +1,195 @@
+//! Transactions over pgproto, which are always rolled back.
+//!
+//! Tests sharing the session cluster may change data inside
+//! [`Cluster::with_rollback`] without leaving it changed for the next test:
+//! transaction is rolled back when the closure returns or panics.
+//!
+//! DDL isn't undone by the rollback, so statements reverting it are
+//! registered by [`RollbackTransaction::cleanup`] and executed after the
+//! rollback in reverse order.
+//!
+//! **Note:** pgproto client is blocking, so it must not be used from the
+//! async runtime thread, e.g. inside `#[tokio::test]`.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_discount() {
+//!     cluster
+//!         .with_rollback(|tx| {
+//!             tx.execute("CREATE TABLE prices (id INT PRIMARY KEY, value INT)", &[])?;
+//!             tx.cleanup("DROP TABLE prices");
+//!             tx.execute("INSERT INTO prices VALUES (1, 100)", &[])?;
+//!             let rows = tx.query("SELECT value FROM prices", &[])?;
+//!             assert_eq!(rows[0].get::<_, i32>(0), 100);
+//!             Ok(())
+//!         })
+//!         .unwrap();
+//! }
+//! ```
+
+use crate::tls;
+use crate::users::TestUser;
+use crate::{Cluster, PICOTEST_USER, PICOTEST_USER_PASSWORD};
+use anyhow::Context;
+use log::{debug, warn};
+use postgres::config::SslMode;
+use postgres::{Client, NoTls, Transaction};
+
+pub use postgres::types::ToSql;
+pub use postgres::Row;
+
+/// Transaction handed to the closure of [`Cluster::with_rollback`].
+pub struct RollbackTransaction<'a> {
+    // Fields are dropped in declaration order, so on panic
+    // transaction is rolled back before the cleanup.
+    transaction: Transaction<'a>,
+    cleanup: Cleanup<'a>,
+}
+
+impl<'a> RollbackTransaction<'a> {
+    /// Executes statement, returning number of the modified rows.
+    pub fn execute(&mut self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> anyhow::Result<u64> {
+        self.transaction
+            .execute(sql, params)
+            .with_context(|| format!("failed to execute '{sql}'"))
+    }
+
+    /// Executes query, returning the selected rows.
+    pub fn query(&mut self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> anyhow::Result<Vec<Row>> {
+        self.transaction
+            .query(sql, params)
+            .with_context(|| format!("failed to query '{sql}'"))
+    }
+
+    /// Registers statement executed after the rollback, e.g. `DROP TABLE`
+    /// reverting `CREATE TABLE` executed in the transaction.
+    ///
+    /// Statements are executed in reverse order of registration through the
+    /// admin console, so they don't depend on the state of the transaction.
+    pub fn cleanup(&mut self, sql: impl Into<String>) {
+        self.cleanup.statements.push(sql.into());
+    }
+
+    /// Returns underlying transaction of `postgres` client.
+    pub fn transaction(&mut self) -> &mut Transaction<'a> {
+        &mut self.transaction
+    }
+}
+
+/// Statements registered by [`RollbackTransaction::cleanup`].
+struct Cleanup<'a> {
+    cluster: &'a Cluster,
+    statements: Vec<String>,
+}
+
+impl Cleanup<'_> {
+    /// Executes every statement, even if the previous one has failed.
+    ///
+    /// ### Returns
+    /// The first error of the statements.
+    fn run(&mut self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        while let Some(statement) = self.statements.pop() {
+            debug!("Cleaning up after rolled back transaction: {statement}");
+            let executed = self
+                .cluster
+                .sql_query(&statement, &[])
+                .with_context(|| format!("failed to clean up by '{statement}'"));
+            if let Err(err) = executed {
+                if result.is_ok() {
+                    result = Err(err);
+                } else {
+                    warn!("{err:#}");
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Cleanup<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.run() {
+            warn!("Failed to clean up after rolled back transaction: {err:#}");
+        }
+    }
+}
+
+impl Cluster {
+    /// Connects to the main instance over pgproto as [`PICOTEST_USER`].
+    /// Connection is encrypted if [`Cluster::pg_tls`] is set.
+    pub fn pg_client(&self) -> anyhow::Result<Client> {
+        self.pg_connect(PICOTEST_USER, PICOTEST_USER_PASSWORD)
+    }
+
+    /// Connects to the main instance over pgproto as `user`
+    /// created by [`Cluster::create_user`].
+    pub fn pg_client_as(&self, user: &TestUser) -> anyhow::Result<Client> {
+        self.pg_connect(&user.name, &user.password)
+    }
+
+    fn pg_connect(&self, user: &str, password: &str) -> anyhow::Result<Client> {
+        let main = self.main();
+        let mut config = Client::configure();
+        config
+            .host(main.host())
+            .port(main.pg_port)
+            .user(user)
+            .password(password);
+        let connected = match &self.pg_tls {
+            Some(tls) => {
+                config.ssl_mode(SslMode::Require);
+                tls::connect_pg(&config, tls)
+            }
+            None => config.connect(NoTls).map_err(Into::into),
+        };
+        connected.with_context(|| {
+            format!(
+                "failed to connect to '{}' over pgproto at {}:{} as '{user}'",
+                main.instance_name,
+                main.host(),
+                main.pg_port
+            )
+        })
+    }
+
+    /// Runs `f` in a transaction over pgproto, which is rolled back
+    /// afterwards, see [module docs](self).
+    ///
+    /// ### Returns
+    /// - On success, value returned by `f`.
+    /// - On failure, error returned by `f` or instance of [`anyhow::Error`]
+    ///   if transaction can't be started or rolled back, or cleanup
+    ///   statement has failed.
+    pub fn with_rollback<T>(
+        &self,
+        f: impl FnOnce(&mut RollbackTransaction<'_>) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut client = self.pg_client()?;
+        let transaction = client
+            .transaction()
+            .context("failed to begin transaction")?;
+        let mut tx = RollbackTransaction {
+            transaction,
+            cleanup: Cleanup {
+                cluster: self,
+                statements: Vec::new(),
+            },
+        };
+        let result = f(&mut tx);
+
+        let RollbackTransaction {
+            transaction,
+            mut cleanup,
+        } = tx;
+        let rolled_back = transaction
+            .rollback()
+            .context("failed to roll back transaction");
+        let cleaned_up = cleanup.run();
+        let value = result?;
+        rolled_back?;
+        cleaned_up?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,302 @@
+//! Context of `#[picotest_unit]` test executed by the instance.
+//!
+//! Unit test is run by the plugin library loaded into the instance, so it
+//! has no access to the cluster of the test process. Runner passes it the
+//! properties of the instance along with the call, and the test reads them
+//! by [`context`] to make assertions depending on the environment.
+//! Runtime of the tests is compiled only with `unit_runner` feature.
+//!
+//! Tests are tracked per fiber: every fiber has its own stack of running
+//! tests, so test calling another one or tests running in several fibers
+//! at once don't take context of each other.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest_unit]
+//! fn test_storage_path() {
+//!     let context = picotest::unit::context();
+//!     assert_eq!(context.tier, "default");
+//!     assert!(storage_path().starts_with(&context.data_dir));
+//! }
+//! ```
+
+use crate::lua::lua_string_literal;
+use crate::PicotestInstance;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "unit_runner")]
+use std::collections::BTreeMap;
+#[cfg(feature = "unit_runner")]
+use std::ffi::{c_char, c_void};
+use std::path::PathBuf;
+#[cfg(feature = "unit_runner")]
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Contexts of running tests by the fiber running them, innermost last.
+#[cfg(feature = "unit_runner")]
+static RUNNING: Mutex<BTreeMap<usize, Vec<UnitTestContext>>> = Mutex::new(BTreeMap::new());
+
+/// Properties of the instance and the plugin the unit test is run with.
+///
+/// In the embedded runtime there's no instance, so its name, tier
+/// and ports are empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitTestContext {
+    pub instance_name: String,
+    pub tier: String,
+    /// Port of iproto protocol.
+    pub bin_port: u16,
+    /// Port of PostgreSQL protocol.
+    pub pg_port: u16,
+    pub http_port: u16,
+    /// Working directory of the instance or the embedded runtime.
+    #[serde(default)]
+    pub data_dir: PathBuf,
+    #[serde(default)]
+    pub plugin_name: String,
+    #[serde(default)]
+    pub plugin_version: String,
+}
+
+impl UnitTestContext {
+    /// Creates context of the test executed by the `instance`.
+    pub fn for_instance(instance: &PicotestInstance) -> Self {
+        Self {
+            instance_name: instance.instance_name.clone(),
+            tier: instance.tier.clone(),
+            bin_port: instance.bin_port,
+            pg_port: instance.pg_port,
+            http_port: instance.http_port,
+            ..Default::default()
+        }
+    }
+
+    /// Serializes context into Lua string literal passed to the runner.
+    pub fn to_lua_literal(&self) -> String {
+        let json = serde_json::to_string(self).expect("context is always serializable");
+        lua_string_literal(&json)
+    }
+}
+
+/// Test entered by [`enter`], which has to be passed to [`leave`].
+#[cfg(feature = "unit_runner")]
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct UnitFrame {
+    fiber: usize,
+    depth: usize,
+}
+
+/// Returns context of the running unit test, see [module docs](self).
+///
+/// Panics if called outside of `#[picotest_unit]` test.
+#[cfg(feature = "unit_runner")]
+#[track_caller]
+pub fn context() -> UnitTestContext {
+    RUNNING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&current_fiber())
+        .and_then(|stack| stack.last())
+        .cloned()
+        .expect("unit test context is available only inside #[picotest_unit] test")
+}
+
+/// Returns `true` if some fiber is running unit test.
+#[cfg(feature = "unit_runner")]
+#[doc(hidden)]
+pub fn is_running() -> bool {
+    !RUNNING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_empty()
+}
+
+/// Sets context of the unit test from JSON passed by the runner.
+/// Called by the code generated by `#[picotest_unit]` before the test.
+#[cfg(feature = "unit_runner")]
+#[doc(hidden)]
+pub fn enter(context: &[u8], plugin_name: &str, plugin_version: &str) -> UnitFrame {
+    // Malformed context must not panic outside of the test,
+    // as panic can't unwind through FFI boundary.
+    let mut context: UnitTestContext = serde_json::from_slice(context).unwrap_or_default();
+    context.plugin_name = plugin_name.to_string();
+    context.plugin_version = plugin_version.to_string();
+
+    let fiber = current_fiber();
+    let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    let stack = running.entry(fiber).or_default();
+    stack.push(context);
+    UnitFrame {
+        fiber,
+        depth: stack.len(),
+    }
+}
+
+/// Removes context of the finished unit test.
+///
+/// ### Returns
+/// Error if tests called by the finished one haven't left, e.g. Lua error
+/// has unwound through them. Their contexts are removed as well.
+#[cfg(feature = "unit_runner")]
+#[doc(hidden)]
+pub fn leave(frame: UnitFrame) -> Result<(), String> {
+    let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(stack) = running.get_mut(&frame.fiber) else {
+        return Err("context of the unit test has been removed while it was running".into());
+    };
+    let nested = stack.len().saturating_sub(frame.depth);
+    stack.truncate(frame.depth.saturating_sub(1));
+    if stack.is_empty() {
+        running.remove(&frame.fiber);
+    }
+    if nested > 0 {
+        return Err(format!(
+            "{nested} unit test(s) called by the test haven't finished properly"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "unit_runner")]
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+#[cfg(all(feature = "unit_runner", target_os = "macos"))]
+const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
+#[cfg(all(feature = "unit_runner", not(target_os = "macos")))]
+const RTLD_DEFAULT: *mut c_void = std::ptr::null_mut();
+
+/// Returns key of the running fiber.
+///
+/// Fibers of the instance share its thread, so they're told apart by
+/// `fiber_self` of picodata. Library isn't linked against picodata, so the
+/// function is looked up at runtime. Outside of picodata every thread is
+/// treated as a fiber.
+#[cfg(feature = "unit_runner")]
+fn current_fiber() -> usize {
+    type FiberSelf = unsafe extern "C" fn() -> *mut c_void;
+    static FIBER_SELF: OnceLock<Option<FiberSelf>> = OnceLock::new();
+    thread_local! {
+        static THREAD_KEY: u8 = const { 0 };
+    }
+
+    let fiber_self = FIBER_SELF.get_or_init(|| {
+        // SAFETY: symbol name is a valid C string.
+        let symbol = unsafe { dlsym(RTLD_DEFAULT, c"fiber_self".as_ptr()) };
+        // SAFETY: `fiber_self` of picodata has `FiberSelf` signature.
+        (!symbol.is_null())
+            .then(|| unsafe { std::mem::transmute::<*mut c_void, FiberSelf>(symbol) })
+    });
+    match fiber_self {
+        // SAFETY: library is loaded by picodata, so fiber API is available.
+        Some(fiber_self) => unsafe { fiber_self() as usize },
+        None => THREAD_KEY.with(|key| key as *const u8 as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnitTestContext;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_context_lua_literal() {
+        let literal = UnitTestContext::default().to_lua_literal();
+        assert!(literal.starts_with(r#""{\"instance_name\":\"\","#));
+    }
+}
+
+#[cfg(all(test, feature = "unit_runner"))]
+mod runtime_tests {
+    use super::{context, enter, is_running, leave, UnitTestContext};
+    use rstest::rstest;
+    use std::path::PathBuf;
+    use std::sync::Barrier;
+    use std::thread;
+
+    // Outside of picodata tests are tracked per thread, which stands for a fiber.
+
+    fn context_json(instance_name: &str) -> String {
+        serde_json::to_string(&UnitTestContext {
+            instance_name: instance_name.into(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[rstest]
+    fn test_enter_context() {
+        let runner_context = UnitTestContext {
+            instance_name: "default_1_1".into(),
+            tier: "default".into(),
+            bin_port: 3301,
+            pg_port: 5433,
+            http_port: 8001,
+            ..Default::default()
+        };
+        let mut json = serde_json::to_value(&runner_context).unwrap();
+        json["data_dir"] = "/plugin/tmp/cluster/default_1_1".into();
+
+        let frame = enter(json.to_string().as_bytes(), "weather", "0.1.0");
+        let context = context();
+        assert_eq!(leave(frame), Ok(()));
+
+        assert_eq!(
+            context,
+            UnitTestContext {
+                data_dir: PathBuf::from("/plugin/tmp/cluster/default_1_1"),
+                plugin_name: "weather".into(),
+                plugin_version: "0.1.0".into(),
+                ..runner_context
+            }
+        );
+        assert!(std::panic::catch_unwind(super::context).is_err());
+    }
+
+    #[rstest]
+    fn test_nested_panicking_test() {
+        let outer = enter(context_json("outer").as_bytes(), "weather", "0.1.0");
+
+        let inner = enter(context_json("inner").as_bytes(), "weather", "0.1.0");
+        let result = std::panic::catch_unwind(|| {
+            assert_eq!(context().instance_name, "inner");
+            panic!("inner test has failed");
+        });
+        assert!(result.is_err());
+        assert_eq!(leave(inner), Ok(()));
+
+        assert_eq!(context().instance_name, "outer");
+        assert_eq!(leave(outer), Ok(()));
+        assert!(std::panic::catch_unwind(context).is_err());
+    }
+
+    #[rstest]
+    fn test_nested_test_not_left() {
+        let outer = enter(context_json("outer").as_bytes(), "weather", "0.1.0");
+        let _inner = enter(context_json("inner").as_bytes(), "weather", "0.1.0");
+
+        let err = leave(outer).unwrap_err();
+        assert!(err.contains("1 unit test(s)"), "{err}");
+        assert!(std::panic::catch_unwind(context).is_err());
+    }
+
+    #[rstest]
+    fn test_concurrent_tests() {
+        let entered = Barrier::new(2);
+        let checked = Barrier::new(2);
+        thread::scope(|scope| {
+            for name in ["first", "second"] {
+                let (entered, checked) = (&entered, &checked);
+                scope.spawn(move || {
+                    let frame = enter(context_json(name).as_bytes(), "weather", "0.1.0");
+                    entered.wait();
+                    assert_eq!(context().instance_name, name);
+                    assert!(is_running());
+                    checked.wait();
+                    assert_eq!(leave(frame), Ok(()));
+                });
+            }
+        });
+    }
+}
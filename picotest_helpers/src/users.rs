@@ -0,0 +1,242 @@
+//! Test users authenticated by various methods.
+//!
+//! Clients of picotest connect as [`PICOTEST_USER`] authenticated by `md5`
+//! over pgproto and as [`PICOTEST_USER_IPROTO`] authenticated by `chap-sha1`
+//! over iproto. Authentication features of the plugin are tested by users
+//! created by [`Cluster::create_user`] with other methods, e.g. `ldap` if
+//! LDAP server is configured for picodata. Clients connect as them through
+//! [`Cluster::pg_client_as`] and [`PicotestInstance::execute_rpc_as`].
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! #[picotest]
+//! fn test_ldap_login() {
+//!     let user = TestUser::new("alice", AuthMethod::Ldap)
+//!         .password("Alice-secret1")
+//!         .grant("READ TABLE");
+//!     cluster.create_user(&user).unwrap();
+//!
+//!     let mut client = cluster.pg_client_as(&user).unwrap();
+//!     client.query("SELECT * FROM warehouse", &[]).unwrap();
+//! }
+//! ```
+//!
+//! [`PICOTEST_USER`]: crate::PICOTEST_USER
+//! [`PICOTEST_USER_IPROTO`]: crate::PICOTEST_USER_IPROTO
+
+use crate::compat::Capability;
+use crate::sql::quote_identifier;
+use crate::{call_rpc, Cluster, PicotestInstance, PICOTEST_USER_PASSWORD};
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::iter;
+use std::str::FromStr;
+
+/// Authentication method of the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    ChapSha1,
+    Md5,
+    /// Password is verified by LDAP server configured for picodata,
+    /// so it isn't stored by the cluster.
+    Ldap,
+    /// Supported by recent picodata versions only,
+    /// user creation fails on others.
+    ScramSha256,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ChapSha1 => "chap-sha1",
+            Self::Md5 => "md5",
+            Self::Ldap => "ldap",
+            Self::ScramSha256 => "scram-sha256",
+        }
+    }
+}
+
+impl Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chap-sha1" => Ok(Self::ChapSha1),
+            "md5" => Ok(Self::Md5),
+            "ldap" => Ok(Self::Ldap),
+            "scram-sha256" => Ok(Self::ScramSha256),
+            _ => bail!(
+                "unknown auth method '{s}', expected one of: chap-sha1, md5, ldap, scram-sha256"
+            ),
+        }
+    }
+}
+
+/// User created by [`Cluster::create_user`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestUser {
+    pub name: String,
+    /// Password clients authenticate by.
+    pub password: String,
+    pub auth_method: AuthMethod,
+    /// Privileges granted on creation, e.g. `READ TABLE`.
+    pub privileges: Vec<String>,
+}
+
+impl TestUser {
+    /// Creates user with password of picotest users and without privileges.
+    pub fn new(name: impl Into<String>, auth_method: AuthMethod) -> Self {
+        Self {
+            name: name.into(),
+            password: PICOTEST_USER_PASSWORD.to_string(),
+            auth_method,
+            privileges: Vec::new(),
+        }
+    }
+
+    /// Sets password of the user, which must satisfy password policy of the cluster.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = password.into();
+        self
+    }
+
+    /// Grants privilege to the user on creation, e.g. `CREATE TABLE`
+    /// or `EXECUTE ON PROCEDURE "picotest_sum"`.
+    pub fn grant(mut self, privilege: impl Into<String>) -> Self {
+        self.privileges.push(privilege.into());
+        self
+    }
+
+    /// Returns statements creating the user and granting its privileges.
+    fn create_statements(&self) -> Vec<String> {
+        let name = quote_identifier(&self.name);
+        let create = match self.auth_method {
+            AuthMethod::Ldap => format!("CREATE USER {name} USING ldap"),
+            method => format!(
+                "CREATE USER {name} WITH PASSWORD '{}' USING {method}",
+                self.password.replace('\'', "''")
+            ),
+        };
+        iter::once(create)
+            .chain(
+                self.privileges
+                    .iter()
+                    .map(|privilege| format!("GRANT {privilege} TO {name}")),
+            )
+            .collect()
+    }
+}
+
+impl Cluster {
+    /// Creates `user` and grants its privileges, see [module docs](self).
+    ///
+    /// ### Returns
+    /// - On success, nothing.
+    /// - On failure, instance of [`anyhow::Error`], e.g. if user already exists
+    ///   or auth method isn't supported by picodata.
+    pub fn create_user(&self, user: &TestUser) -> anyhow::Result<()> {
+        for statement in user.create_statements() {
+            self.sql_query(&statement, &[])
+                .with_context(|| format!("failed to create user '{}'", user.name))?;
+        }
+        Ok(())
+    }
+
+    /// Drops user created by [`Cluster::create_user`], if it exists.
+    pub fn drop_user(&self, name: &str) -> anyhow::Result<()> {
+        self.sql_query(
+            &format!("DROP USER IF EXISTS {}", quote_identifier(name)),
+            &[],
+        )
+        .with_context(|| format!("failed to drop user '{name}'"))?;
+        Ok(())
+    }
+}
+
+impl PicotestInstance {
+    /// Calls RPC endpoint of the plugin as `user`, like [`PicotestInstance::execute_rpc`].
+    ///
+    /// Call is made over a new connection, which isn't pooled and isn't
+    /// recorded by cassette. iproto client authenticates by `chap-sha1`
+    /// only, so users with other auth methods fail.
+    pub async fn execute_rpc_as<S, G>(
+        &self,
+        user: &TestUser,
+        plugin_name: &str,
+        path: &str,
+        service_name: &str,
+        plugin_version: &str,
+        input: &S,
+    ) -> anyhow::Result<G>
+    where
+        G: DeserializeOwned,
+        S: Serialize,
+    {
+        if user.auth_method != AuthMethod::ChapSha1 {
+            bail!(
+                "iproto client supports only chap-sha1 authentication, \
+                user '{}' is authenticated by {}",
+                user.name,
+                user.auth_method
+            );
+        }
+        self.ensure_supports(Capability::RpcDispatchContext)?;
+        let client = self.rpc_client_as(&user.name, &user.password);
+        let response = call_rpc(
+            &client,
+            plugin_name,
+            path,
+            service_name,
+            plugin_version,
+            input,
+        )
+        .await?;
+        rmp_serde::from_slice(&response).context("Failed to deserialise rpc response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthMethod, TestUser};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("chap-sha1", AuthMethod::ChapSha1)]
+    #[case("MD5", AuthMethod::Md5)]
+    #[case("ldap", AuthMethod::Ldap)]
+    #[case("scram-sha256", AuthMethod::ScramSha256)]
+    fn test_parse_auth_method(#[case] value: &str, #[case] expected: AuthMethod) {
+        assert_eq!(value.parse::<AuthMethod>().unwrap(), expected);
+        assert_eq!(expected.to_string(), value.to_lowercase());
+    }
+
+    #[rstest]
+    fn test_create_statements() {
+        let user = TestUser::new("o\"brien", AuthMethod::Md5)
+            .password("It's-Pic0test")
+            .grant("READ TABLE")
+            .grant("EXECUTE ON PROCEDURE \"picotest_sum\"");
+        assert_eq!(
+            user.create_statements(),
+            [
+                r#"CREATE USER "o""brien" WITH PASSWORD 'It''s-Pic0test' USING md5"#,
+                r#"GRANT READ TABLE TO "o""brien""#,
+                r#"GRANT EXECUTE ON PROCEDURE "picotest_sum" TO "o""brien""#,
+            ]
+        );
+
+        let user = TestUser::new("alice", AuthMethod::Ldap);
+        assert_eq!(
+            user.create_statements(),
+            [r#"CREATE USER "alice" USING ldap"#]
+        );
+    }
+}
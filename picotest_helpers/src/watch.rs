@@ -0,0 +1,217 @@
+//! Watching for changes of table contents.
+//!
+//! Watcher periodically selects all rows of the table and turns
+//! difference between consecutive selections into a sequence of
+//! [`TableEvent`]s, so tests can assert on state transitions
+//! instead of sampling the table at arbitrary moments.
+
+use crate::sql::quote_identifier;
+use crate::{Cluster, PicotestInstance};
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde_norway::{Mapping, Value};
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default interval between selections of watched table.
+pub const DEFAULT_WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Change of the watched table.
+///
+/// Update of the row is represented by deletion of the old row
+/// followed by insertion of the new one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableEvent<T> {
+    Inserted(T),
+    Deleted(T),
+}
+
+impl<T> TableEvent<T> {
+    /// Returns changed row.
+    pub fn row(&self) -> &T {
+        match self {
+            TableEvent::Inserted(row) | TableEvent::Deleted(row) => row,
+        }
+    }
+}
+
+/// Watcher of the table created by [`Cluster::watch_table`].
+pub struct TableWatcher<'a, T> {
+    instance: &'a PicotestInstance,
+    query: String,
+    rows: Vec<Mapping>,
+    events: VecDeque<TableEvent<T>>,
+    poll_interval: Duration,
+}
+
+impl<'a, T: DeserializeOwned> TableWatcher<'a, T> {
+    fn new(instance: &'a PicotestInstance, table: &str) -> anyhow::Result<Self> {
+        let query = format!("SELECT * FROM {}", quote_identifier(table));
+        let rows = instance.sql_query(&query, &[])?.to_maps();
+        Ok(Self {
+            instance,
+            query,
+            rows,
+            events: VecDeque::new(),
+            poll_interval: DEFAULT_WATCH_POLL_INTERVAL,
+        })
+    }
+
+    /// Sets interval between selections of the table.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Waits for the next change of the table.
+    ///
+    /// Returns `None` if table hasn't changed within `timeout`.
+    pub fn next_event(&mut self, timeout: Duration) -> anyhow::Result<Option<TableEvent<T>>> {
+        let start_time = Instant::now();
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if start_time.elapsed() > timeout {
+                return Ok(None);
+            }
+            thread::sleep(self.poll_interval);
+            self.poll()?;
+        }
+    }
+
+    /// Skips changes of the table until one matching `predicate` occurs.
+    pub fn wait_for<P>(
+        &mut self,
+        timeout: Duration,
+        mut predicate: P,
+    ) -> anyhow::Result<TableEvent<T>>
+    where
+        P: FnMut(&TableEvent<T>) -> bool,
+    {
+        let start_time = Instant::now();
+        loop {
+            let remaining = timeout.saturating_sub(start_time.elapsed());
+            match self.next_event(remaining)? {
+                Some(event) if predicate(&event) => return Ok(event),
+                Some(_) => continue,
+                None => bail!(
+                    "expected change of the table didn't happen within {}s",
+                    timeout.as_secs_f64()
+                ),
+            }
+        }
+    }
+
+    fn poll(&mut self) -> anyhow::Result<()> {
+        let rows = self.instance.sql_query(&self.query, &[])?.to_maps();
+        let (deleted, inserted) = rows_changes(&self.rows, &rows);
+        for row in deleted {
+            self.events
+                .push_back(TableEvent::Deleted(deserialize_row(row)?));
+        }
+        for row in inserted {
+            self.events
+                .push_back(TableEvent::Inserted(deserialize_row(row)?));
+        }
+        self.rows = rows;
+        Ok(())
+    }
+}
+
+impl Cluster {
+    /// Starts watching for changes of the table on the main instance.
+    ///
+    /// Rows existing at the moment of the call don't produce events.
+    ///
+    /// ### Examples:
+    ///
+    /// ```rust,ignore
+    /// use picotest::prelude::*;
+    /// use picotest_helpers::watch::TableEvent;
+    /// use serde::Deserialize;
+    /// use std::time::Duration;
+    ///
+    /// #[derive(Debug, Deserialize)]
+    /// struct Plugin {
+    ///     name: String,
+    ///     enabled: bool,
+    /// }
+    ///
+    /// #[picotest]
+    /// fn test_plugin_is_disabled() {
+    ///     let mut watcher = cluster.watch_table::<Plugin>("_pico_plugin").unwrap();
+    ///     cluster.run_sql("ALTER PLUGIN plugin 0.1.0 DISABLE").unwrap();
+    ///
+    ///     watcher
+    ///         .wait_for(Duration::from_secs(10), |event| {
+    ///             matches!(event, TableEvent::Inserted(plugin) if !plugin.enabled)
+    ///         })
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn watch_table<T: DeserializeOwned>(
+        &self,
+        table: &str,
+    ) -> anyhow::Result<TableWatcher<'_, T>> {
+        TableWatcher::new(self.main(), table)
+    }
+}
+
+fn deserialize_row<T: DeserializeOwned>(row: &Mapping) -> anyhow::Result<T> {
+    serde_norway::from_value(Value::Mapping(row.clone()))
+        .with_context(|| format!("failed to deserialize row {row:?}"))
+}
+
+/// Returns rows, which are present only in `old` and only in `new` respectively.
+fn rows_changes<'a>(
+    old: &'a [Mapping],
+    new: &'a [Mapping],
+) -> (Vec<&'a Mapping>, Vec<&'a Mapping>) {
+    let mut inserted: Vec<&Mapping> = new.iter().collect();
+    let mut deleted = Vec::new();
+    for row in old {
+        match inserted.iter().position(|new_row| *new_row == row) {
+            Some(index) => {
+                inserted.remove(index);
+            }
+            None => deleted.push(row),
+        }
+    }
+    (deleted, inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rows_changes, TableEvent};
+    use rstest::rstest;
+    use serde_norway::Mapping;
+
+    fn maps(yaml: &str) -> Vec<Mapping> {
+        serde_norway::from_str(yaml).unwrap()
+    }
+
+    #[rstest]
+    fn test_rows_changes() {
+        let old = maps("[{id: 1, on: true}, {id: 2, on: true}]");
+        let new = maps("[{id: 2, on: false}, {id: 1, on: true}, {id: 3, on: true}]");
+
+        let (deleted, inserted) = rows_changes(&old, &new);
+        assert_eq!(deleted, vec![&old[1]]);
+        assert_eq!(inserted, vec![&new[0], &new[2]]);
+    }
+
+    #[rstest]
+    fn test_rows_changes_unchanged() {
+        let rows = maps("[{id: 1}, {id: 1}]");
+        let (deleted, inserted) = rows_changes(&rows, &rows);
+        assert!(deleted.is_empty() && inserted.is_empty());
+    }
+
+    #[rstest]
+    fn test_table_event_row() {
+        assert_eq!(TableEvent::Inserted(1).row(), &1);
+        assert_eq!(TableEvent::Deleted(2).row(), &2);
+    }
+}
@@ -0,0 +1,270 @@
+//! Watchdog of hanging tests.
+//!
+//! Test, which is still running after the soft timeout, gets fibers of every
+//! instance printed (`fiber.info()` with backtraces and `fiber.top()`), so
+//! it's visible what the cluster was doing. If the hard timeout is set, test
+//! process is terminated after it, stopping the cluster on exit.
+//!
+//! Watchdog is started by `#[picotest]` and `#[picotest_unit]` tests, timeouts
+//! are configured by `PICOTEST_SOFT_TIMEOUT` and `PICOTEST_HARD_TIMEOUT`.
+//!
+//! ### Examples:
+//! ```rust,ignore
+//! let config = WatchdogConfig::from_env()?;
+//! let _watchdog = Watchdog::start("my_test", config, || cluster.fiber_dump());
+//! ```
+
+use crate::Cluster;
+use anyhow::Context;
+use std::env;
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Environment variable, which sets soft timeout of the test in seconds.
+/// `0` disables dump of fibers.
+pub const PICOTEST_SOFT_TIMEOUT: &str = "PICOTEST_SOFT_TIMEOUT";
+
+/// Environment variable, which sets hard timeout of the test in seconds.
+pub const PICOTEST_HARD_TIMEOUT: &str = "PICOTEST_HARD_TIMEOUT";
+
+pub const DEFAULT_SOFT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Exit code of the test process terminated by the hard timeout.
+pub const HARD_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Timeout of the dump of one instance, so stalled instance
+/// doesn't block the dump of the others.
+const FIBER_DUMP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Collects `fiber.info()` with backtraces and `fiber.top()`. If `fiber.top`
+/// isn't enabled, it's enabled for a second to gather statistics.
+const FIBER_DUMP_EXPRESSION: &str = "(function() \
+    local fiber = require('fiber'); \
+    local top_enabled = pcall(fiber.top); \
+    if not top_enabled and pcall(fiber.top_enable) then fiber.sleep(1) end; \
+    local top_ok, top = pcall(fiber.top); \
+    if not top_enabled then pcall(fiber.top_disable) end; \
+    return { info = fiber.info({backtrace = true}), top = top_ok and top or nil }; \
+end)()";
+
+/// Timeouts of the test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// Time after which fibers of the instances are printed.
+    pub soft_timeout: Option<Duration>,
+    /// Time after which test process is terminated.
+    pub hard_timeout: Option<Duration>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            soft_timeout: Some(DEFAULT_SOFT_TIMEOUT),
+            hard_timeout: None,
+        }
+    }
+}
+
+impl WatchdogConfig {
+    /// Reads timeouts from `PICOTEST_SOFT_TIMEOUT` and `PICOTEST_HARD_TIMEOUT`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(value) = env::var(PICOTEST_SOFT_TIMEOUT) {
+            config.soft_timeout = parse_timeout(&value)
+                .with_context(|| format!("invalid {PICOTEST_SOFT_TIMEOUT}"))?;
+        }
+        if let Ok(value) = env::var(PICOTEST_HARD_TIMEOUT) {
+            config.hard_timeout = parse_timeout(&value)
+                .with_context(|| format!("invalid {PICOTEST_HARD_TIMEOUT}"))?;
+        }
+        Ok(config)
+    }
+
+    /// Returns time after which fibers are dumped. Fibers are always
+    /// dumped before the hard kill, even if soft timeout is disabled.
+    fn dump_timeout(&self) -> Option<Duration> {
+        match (self.soft_timeout, self.hard_timeout) {
+            (Some(soft), Some(hard)) => Some(soft.min(hard)),
+            (soft, hard) => soft.or(hard),
+        }
+    }
+}
+
+/// Parses timeout in seconds, where `0` means no timeout.
+fn parse_timeout(value: &str) -> anyhow::Result<Option<Duration>> {
+    let secs = value
+        .parse::<f64>()
+        .with_context(|| format!("expected number of seconds, got '{value}'"))?;
+    let timeout = Duration::try_from_secs_f64(secs)
+        .with_context(|| format!("expected non-negative number of seconds, got '{value}'"))?;
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
+
+/// Watchdog of the running test, which is stopped on drop.
+pub struct Watchdog {
+    // Watchdog thread is woken up by disconnect of the channel.
+    _stop: Sender<()>,
+}
+
+impl Watchdog {
+    /// Starts watching the test `test_name`.
+    ///
+    /// ### Arguments:
+    /// - `config` - timeouts of the test.
+    /// - `dump` - collects state printed on the soft timeout,
+    ///   e.g. [`Cluster::fiber_dump`].
+    ///
+    /// ### Returns
+    /// `None` if both timeouts are disabled.
+    pub fn start(
+        test_name: &str,
+        config: WatchdogConfig,
+        dump: impl FnOnce() -> String + Send + 'static,
+    ) -> Option<Self> {
+        let dump_timeout = config.dump_timeout()?;
+        let (stop, stopped) = mpsc::channel::<()>();
+        let test_name = test_name.to_string();
+        let start_time = Instant::now();
+
+        thread::Builder::new()
+            .name("picotest-watchdog".into())
+            .spawn(move || {
+                if !matches!(
+                    stopped.recv_timeout(dump_timeout),
+                    Err(RecvTimeoutError::Timeout)
+                ) {
+                    return;
+                }
+                eprintln!(
+                    "Test '{test_name}' is still running after {dump_timeout:?}, \
+                    fibers of the instances:\n{}",
+                    dump()
+                );
+
+                let Some(hard_timeout) = config.hard_timeout else {
+                    return;
+                };
+                let remaining = hard_timeout.saturating_sub(start_time.elapsed());
+                if matches!(
+                    stopped.recv_timeout(remaining),
+                    Err(RecvTimeoutError::Timeout)
+                ) {
+                    eprintln!(
+                        "Test '{test_name}' has exceeded hard timeout of {hard_timeout:?}, \
+                        terminating"
+                    );
+                    process::exit(HARD_TIMEOUT_EXIT_CODE);
+                }
+            })
+            .expect("Failed to spawn watchdog thread");
+
+        Some(Self { _stop: stop })
+    }
+}
+
+impl Cluster {
+    /// Returns fibers of every instance (`fiber.info()` with backtraces
+    /// and `fiber.top()`) as text. Failure to collect fibers of the instance
+    /// is written instead of them.
+    pub fn fiber_dump(&self) -> String {
+        let mut dump = String::new();
+        for instance in self.instances() {
+            let fibers = instance
+                .run_query_with_timeout(
+                    format!("\\lua\nreturn {FIBER_DUMP_EXPRESSION}\n"),
+                    FIBER_DUMP_TIMEOUT,
+                )
+                .unwrap_or_else(|err| format!("# failed to collect: {err}\n"));
+            dump.push_str(&format!("--- {} ---\n{fibers}\n", instance.instance_name));
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_timeout, Watchdog, WatchdogConfig, DEFAULT_SOFT_TIMEOUT};
+    use rstest::rstest;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case("30", Some(Duration::from_secs(30)))]
+    #[case("0.5", Some(Duration::from_millis(500)))]
+    #[case("0", None)]
+    fn test_parse_timeout(#[case] value: &str, #[case] expected: Option<Duration>) {
+        assert_eq!(parse_timeout(value).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("-1")]
+    #[case("forever")]
+    fn test_parse_invalid_timeout(#[case] value: &str) {
+        assert!(parse_timeout(value).is_err());
+    }
+
+    #[rstest]
+    #[case(Some(DEFAULT_SOFT_TIMEOUT), None, Some(DEFAULT_SOFT_TIMEOUT))]
+    #[case(
+        Some(Duration::from_secs(60)),
+        Some(Duration::from_secs(30)),
+        Some(Duration::from_secs(30))
+    )]
+    #[case(None, Some(Duration::from_secs(30)), Some(Duration::from_secs(30)))]
+    #[case(None, None, None)]
+    fn test_dump_timeout(
+        #[case] soft_timeout: Option<Duration>,
+        #[case] hard_timeout: Option<Duration>,
+        #[case] expected: Option<Duration>,
+    ) {
+        let config = WatchdogConfig {
+            soft_timeout,
+            hard_timeout,
+        };
+        assert_eq!(config.dump_timeout(), expected);
+    }
+
+    #[rstest]
+    fn test_watchdog_dumps_after_soft_timeout() {
+        let config = WatchdogConfig {
+            soft_timeout: Some(Duration::from_millis(50)),
+            hard_timeout: None,
+        };
+        let (dumped, dumps) = mpsc::channel();
+        let _watchdog = Watchdog::start("slow_test", config, move || {
+            dumped.send(()).unwrap();
+            String::new()
+        })
+        .unwrap();
+
+        assert!(dumps.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[rstest]
+    fn test_stopped_watchdog_doesnt_dump() {
+        let config = WatchdogConfig {
+            soft_timeout: Some(Duration::from_millis(200)),
+            hard_timeout: None,
+        };
+        let (dumped, dumps) = mpsc::channel();
+        let watchdog = Watchdog::start("fast_test", config, move || {
+            dumped.send(()).unwrap();
+            String::new()
+        })
+        .unwrap();
+        drop(watchdog);
+
+        assert!(dumps.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[rstest]
+    fn test_disabled_watchdog() {
+        let config = WatchdogConfig {
+            soft_timeout: None,
+            hard_timeout: None,
+        };
+        assert!(Watchdog::start("test", config, String::new).is_none());
+    }
+}
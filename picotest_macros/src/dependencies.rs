@@ -0,0 +1,179 @@
+use crate::utils::{self, TestOptions};
+use quote::format_ident;
+use std::collections::BTreeMap;
+use syn::punctuated::Punctuated;
+use syn::{parse_quote, Attribute, Error, Ident, Item, ItemFn, Token, Visibility};
+
+/// Attribute listing tests of `#[picotest]` module, which must pass before the test.
+const DEPENDS_ON_ATTRIBUTE: &str = "depends_on";
+
+/// Prerequisites of tests of `#[picotest]` module declared by `#[depends_on(...)]`.
+///
+/// Prerequisite runs once: either by itself or before the first of its
+/// dependents, so dependents run after their prerequisites regardless of
+/// the order tests are started by libtest. Dependents of failed
+/// prerequisite are skipped.
+#[derive(Default)]
+pub struct TestDependencies {
+    /// Prerequisites keyed by name of the dependent test.
+    prerequisites: BTreeMap<Ident, Vec<Ident>>,
+}
+
+impl TestDependencies {
+    /// Removes `#[depends_on]` attributes from functions of the module.
+    ///
+    /// Checks that prerequisites are tests of the module without arguments,
+    /// which don't depend on each other cyclically.
+    pub fn collect(items: &mut [Item], options: &TestOptions) -> Result<Self, Error> {
+        let mut dependencies = Self::default();
+        let mut errors: Vec<Error> = Vec::new();
+        let mut tests: BTreeMap<Ident, ItemFn> = BTreeMap::new();
+
+        for item in items.iter_mut() {
+            let Item::Fn(func) = item else {
+                continue;
+            };
+            let (attrs, other_attrs) = std::mem::take(&mut func.attrs)
+                .into_iter()
+                .partition(is_depends_on);
+            func.attrs = other_attrs;
+
+            let is_test = utils::is_test_function(func, options);
+            let mut prerequisites = Vec::new();
+            for attr in attrs {
+                if !is_test {
+                    errors.push(Error::new_spanned(
+                        &attr,
+                        "#[depends_on] can only be applied to a test function",
+                    ));
+                    continue;
+                }
+                match attr.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated) {
+                    Ok(names) => prerequisites.extend(names),
+                    Err(err) => errors.push(err),
+                }
+            }
+            if is_test {
+                tests.insert(func.sig.ident.clone(), func.clone());
+            }
+            if !prerequisites.is_empty() {
+                dependencies
+                    .prerequisites
+                    .insert(func.sig.ident.clone(), prerequisites);
+            }
+        }
+
+        for prerequisite in dependencies.prerequisites.values().flatten() {
+            match tests.get(prerequisite) {
+                None => errors.push(Error::new_spanned(
+                    prerequisite,
+                    format!("`{prerequisite}` is not a test of this #[picotest] module"),
+                )),
+                Some(test) if has_arguments(test, options) => errors.push(Error::new_spanned(
+                    prerequisite,
+                    format!(
+                        "test `{prerequisite}` can't be a prerequisite, because it has arguments"
+                    ),
+                )),
+                Some(_) => {}
+            }
+        }
+        errors.extend(dependencies.find_cycle());
+
+        match errors.into_iter().reduce(|mut combined, error| {
+            combined.combine(error);
+            combined
+        }) {
+            Some(errors) => Err(errors),
+            None => Ok(dependencies),
+        }
+    }
+
+    /// Returns prerequisites of the `test`.
+    pub fn of(&self, test: &Ident) -> &[Ident] {
+        self.prerequisites.get(test).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns `true` if some test depends on the `test`.
+    pub fn is_prerequisite(&self, test: &Ident) -> bool {
+        self.prerequisites
+            .values()
+            .flatten()
+            .any(|name| name == test)
+    }
+
+    /// Returns error pointing to the prerequisite closing a dependency cycle.
+    fn find_cycle(&self) -> Option<Error> {
+        fn visit<'a>(
+            dependencies: &'a TestDependencies,
+            test: &'a Ident,
+            path: &mut Vec<&'a Ident>,
+        ) -> Option<Error> {
+            if let Some(start) = path.iter().position(|name| *name == test) {
+                let cycle: Vec<String> = path[start..]
+                    .iter()
+                    .chain([&test])
+                    .map(|name| name.to_string())
+                    .collect();
+                return Some(Error::new_spanned(
+                    test,
+                    format!(
+                        "tests depend on each other cyclically: {}",
+                        cycle.join(" -> ")
+                    ),
+                ));
+            }
+            path.push(test);
+            let error = dependencies
+                .of(test)
+                .iter()
+                .find_map(|prerequisite| visit(dependencies, prerequisite, path));
+            path.pop();
+            error
+        }
+
+        self.prerequisites
+            .keys()
+            .find_map(|test| visit(self, test, &mut Vec::new()))
+    }
+}
+
+/// Returns `true` if attribute is `#[depends_on(...)]`.
+pub fn is_depends_on(attr: &Attribute) -> bool {
+    utils::attribute_name(attr).is_some_and(|name| name == DEPENDS_ON_ATTRIBUTE)
+}
+
+/// Returns `true` if test takes arguments other than the cluster,
+/// including `engine` injected by `#[picotest(engines = ...)]`.
+fn has_arguments(test: &ItemFn, options: &TestOptions) -> bool {
+    let cluster_arguments = usize::from(utils::find_argument(test, &options.cluster_var).is_some());
+    options.engines.is_some() || test.sig.inputs.len() > cluster_arguments
+}
+
+/// Returns name of the function running body of the prerequisite `test`.
+pub fn prerequisite_body(test: &Ident) -> Ident {
+    format_ident!("__picotest_prerequisite_{}", test)
+}
+
+/// Splits processed prerequisite `test` into the function running its body
+/// and the test, which runs it unless it has already run before a dependent.
+pub fn split_prerequisite(test: ItemFn, options: &TestOptions) -> (ItemFn, ItemFn) {
+    let name = test.sig.ident.clone();
+    let test_name = name.to_string();
+    let body_name = prerequisite_body(&name);
+
+    let mut body = test.clone();
+    body.attrs.clear();
+    body.vis = Visibility::Inherited;
+    body.sig.ident = body_name.clone();
+
+    let krate = &options.crate_path;
+    let mut test = test;
+    test.block = parse_quote! {{
+        #krate::internal::run_prerequisite_test(
+            concat!(module_path!(), "::", #test_name),
+            #body_name,
+        );
+    }};
+    (body, test)
+}
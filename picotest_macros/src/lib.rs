@@ -1,10 +1,17 @@
+mod dependencies;
+mod manifest;
+mod rpc_client;
+mod scheduler;
+mod unit_crate;
 mod utils;
 
 use darling::ast::NestedMeta;
+use darling::util::PathList;
 use darling::{Error, FromMeta};
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse, parse_macro_input, parse_quote, Ident, Item, ItemFn};
+use std::iter;
+use syn::{parse, parse_macro_input, parse_quote, Ident, Item, ItemFn, ItemTrait};
 
 fn parse_attrs<T: FromMeta>(attr: TokenStream) -> Result<T, TokenStream> {
     NestedMeta::parse_meta_list(attr.into())
@@ -13,9 +20,152 @@ fn parse_attrs<T: FromMeta>(attr: TokenStream) -> Result<T, TokenStream> {
         .map_err(|e| TokenStream::from(e.write_errors()))
 }
 
+/// Storage engines supported by Picodata tables.
+const SUPPORTED_ENGINES: [&str; 2] = ["memtx", "vinyl"];
+
+/// Sources the plugin can be installed from, see `install_from` attribute.
+const PLUGIN_SOURCES: [&str; 2] = ["build", "package"];
+
+/// Storage engines listed in `engines` attribute either as array of strings
+/// (`engines = ["memtx", "vinyl"]`) or as list of identifiers (`engines(memtx, vinyl)`).
+#[derive(Debug)]
+struct EngineList(Vec<darling::export::syn::LitStr>);
+
+impl FromMeta for EngineList {
+    fn from_list(items: &[NestedMeta]) -> Result<Self, Error> {
+        use darling::export::syn::spanned::Spanned;
+
+        let paths = PathList::from_list(items)?;
+        Ok(Self(
+            paths
+                .iter()
+                .map(|path| {
+                    let name = path
+                        .get_ident()
+                        .map(ToString::to_string)
+                        .unwrap_or_default();
+                    darling::export::syn::LitStr::new(&name, path.span())
+                })
+                .collect(),
+        ))
+    }
+
+    fn from_expr(expr: &darling::export::syn::Expr) -> Result<Self, Error> {
+        use darling::export::syn::{Expr, ExprLit, Lit};
+
+        let Expr::Array(array) = expr else {
+            return Err(Error::unexpected_expr_type(expr));
+        };
+        let mut errors = Error::accumulator();
+        let names = array
+            .elems
+            .iter()
+            .filter_map(|elem| match elem {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(name),
+                    ..
+                }) => Some(name.clone()),
+                elem => {
+                    errors.push(Error::unexpected_expr_type(elem).with_span(elem));
+                    None
+                }
+            })
+            .collect();
+        errors.finish_with(Self(names))
+    }
+}
+
 #[derive(Debug, FromMeta)]
 struct PluginCfg {
     path: Option<String>,
+    engines: Option<EngineList>,
+    #[darling(default)]
+    keepalive: bool,
+    #[darling(default)]
+    serial: bool,
+    #[darling(default)]
+    shared_lock: bool,
+    cluster_var: Option<String>,
+    prefix: Option<String>,
+    install_from: Option<String>,
+    topology_transform: Option<darling::export::syn::Path>,
+    tag: Option<String>,
+    /// Path to picotest crate, if it's imported under another name.
+    #[darling(rename = "crate")]
+    krate: Option<darling::export::syn::Path>,
+}
+
+impl PluginCfg {
+    /// Returns names of storage engines the test should be run against.
+    fn engines(&self) -> Result<Option<Vec<String>>, Error> {
+        let Some(engines) = &self.engines else {
+            return Ok(None);
+        };
+
+        let mut errors = Error::accumulator();
+        let mut names = Vec::with_capacity(engines.0.len());
+        for engine in &engines.0 {
+            let name = engine.value();
+            if SUPPORTED_ENGINES.contains(&name.as_str()) {
+                names.push(name);
+            } else {
+                errors.push(
+                    Error::custom(format!(
+                        "unsupported storage engine, expected one of {SUPPORTED_ENGINES:?}"
+                    ))
+                    .with_span(engine),
+                );
+            }
+        }
+        errors.finish_with(Some(names))
+    }
+
+    fn lock(&self) -> Result<utils::TestLock, Error> {
+        match (self.serial, self.shared_lock) {
+            (true, true) => Err(Error::custom(
+                "`serial` and `shared_lock` attributes are mutually exclusive",
+            )),
+            (true, false) => Ok(utils::TestLock::Exclusive),
+            (false, true) => Ok(utils::TestLock::Shared),
+            (false, false) => Ok(utils::TestLock::None),
+        }
+    }
+
+    /// Returns name of the test argument the cluster is injected into.
+    fn cluster_var(&self) -> Result<Ident, Error> {
+        let name = self
+            .cluster_var
+            .as_deref()
+            .unwrap_or(utils::CLUSTER_FIXTURE);
+        syn::parse_str(name)
+            .map_err(|_| Error::custom(format!("`{name}` is not a valid identifier")))
+    }
+
+    fn install_from(&self) -> Result<Option<String>, Error> {
+        match &self.install_from {
+            Some(source) if !PLUGIN_SOURCES.contains(&source.as_str()) => Err(Error::custom(
+                format!("unknown plugin source `{source}`, expected one of {PLUGIN_SOURCES:?}"),
+            )),
+            source => Ok(source.clone()),
+        }
+    }
+
+    fn into_test_options(self) -> Result<utils::TestOptions, Error> {
+        Ok(utils::TestOptions {
+            install_from: self.install_from()?,
+            engines: self.engines()?,
+            lock: self.lock()?,
+            cluster_var: self.cluster_var()?,
+            prefix: self
+                .prefix
+                .unwrap_or_else(|| utils::DEFAULT_TEST_PREFIX.to_string()),
+            path: self.path,
+            keepalive: self.keepalive,
+            topology_transform: self.topology_transform.map(|path| quote!(#path)),
+            tag: self.tag,
+            crate_path: manifest::picotest_crate_path(self.krate.as_ref()),
+        })
+    }
 }
 
 #[proc_macro_attribute]
@@ -25,58 +175,164 @@ pub fn picotest(attr: TokenStream, item: TokenStream) -> TokenStream {
         Ok(cfg) => cfg,
         Err(err) => return err,
     };
+    let options = match cfg.into_test_options() {
+        Ok(options) => options,
+        Err(err) => return TokenStream::from(err.write_errors()),
+    };
+
+    let input = match process_item(input, &options) {
+        Ok(input) => input,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+    TokenStream::from(quote! (#input))
+}
 
-    let path = cfg.path;
-    let input = match input {
-        Item::Fn(func) => Item::Fn(utils::process_test_function(func, &path)),
+fn process_item(input: Item, options: &utils::TestOptions) -> syn::Result<Item> {
+    match input {
+        Item::Fn(func) => {
+            utils::validate_standalone_function(&func, options)?;
+            let test = utils::process_test_function(func, options, &[])?;
+            let items = scheduler::split_scheduled(test, options);
+            Ok(Item::Verbatim(quote! { #(#items)* }))
+        }
+        Item::Impl(suite) => Ok(Item::Verbatim(utils::process_test_suite(suite, options)?)),
         Item::Mod(mut m) => {
-            let (brace, items) = m.content.unwrap();
-            let mut items: Vec<Item> = items
+            let Some((brace, mut items)) = m.content.take() else {
+                return Err(syn::Error::new_spanned(
+                    &m,
+                    "#[picotest] can't be applied to a module declared in a separate file",
+                ));
+            };
+
+            let dependencies = dependencies::TestDependencies::collect(&mut items, options)?;
+            let mut errors: Option<syn::Error> = None;
+            let items: Vec<Item> = items
                 .into_iter()
-                .map(|item| {
-                    if let Item::Fn(func) = item {
-                        Item::Fn(utils::process_test_function(func, &path))
-                    } else {
-                        item
+                .flat_map(|item| match item {
+                    Item::Fn(func) => {
+                        let name = func.sig.ident.clone();
+                        match utils::process_test_function(
+                            func.clone(),
+                            options,
+                            dependencies.of(&name),
+                        ) {
+                            Ok(func) if dependencies.is_prerequisite(&name) => {
+                                let (body, test) = dependencies::split_prerequisite(func, options);
+                                iter::once(body)
+                                    .chain(scheduler::split_scheduled(test, options))
+                                    .map(Item::Fn)
+                                    .collect()
+                            }
+                            Ok(func) => scheduler::split_scheduled(func, options)
+                                .into_iter()
+                                .map(Item::Fn)
+                                .collect(),
+                            Err(err) => {
+                                match &mut errors {
+                                    Some(errors) => errors.combine(err),
+                                    None => errors = Some(err),
+                                }
+                                vec![Item::Fn(func)]
+                            }
+                        }
                     }
+                    item => vec![item],
                 })
                 .collect();
+            if let Some(errors) = errors {
+                return Err(errors);
+            }
 
-            let mut content = vec![parse_quote!(
-                use picotest::*;
-            )];
-            content.push(parse_quote!(
-                use std::panic;
-            ));
-            content.append(&mut items);
-
-            m.content = Some((brace, content));
-            Item::Mod(m)
-        }
-        _ => {
-            panic!("The #[picotest] macro is only valid when called on a function or module.");
+            m.content = Some((brace, items));
+            Ok(Item::Mod(m))
         }
+        item => Err(syn::Error::new_spanned(
+            item,
+            "The #[picotest] macro is only valid when called on a function, module or impl block.",
+        )),
+    }
+}
+
+/// Generates typed client of plugin service RPC endpoints from the trait.
+///
+/// Every method of the trait declares an endpoint: its input and output
+/// types and path, which is `/<method name>` unless set by `#[rpc(path = "...")]`.
+/// Client struct named after the trait has async method for every endpoint.
+///
+/// ### Examples:
+/// ```rust,ignore
+/// #[rpc_client(plugin = "weather", service = "weather_service", version = "0.1.0")]
+/// trait WeatherClient {
+///     #[rpc(path = "/forecast_rpc")]
+///     fn forecast(city: &City) -> Forecast;
+/// }
+///
+/// let forecast = WeatherClient::from(cluster).forecast(&city).await?;
+/// ```
+#[proc_macro_attribute]
+pub fn rpc_client(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let client = parse_macro_input!(item as ItemTrait);
+    let cfg: rpc_client::RpcClientCfg = match parse_attrs(attr) {
+        Ok(cfg) => cfg,
+        Err(err) => return err,
     };
-    TokenStream::from(quote! (#input))
+    match rpc_client::generate_rpc_client(cfg, client) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.write_errors().into(),
+    }
+}
+
+/// Runtimes unit test can be executed in, see `runtime` attribute.
+const UNIT_RUNTIMES: [&str; 2] = ["cluster", "embedded"];
+
+#[derive(Debug, FromMeta)]
+struct UnitTestCfg {
+    runtime: Option<String>,
+    /// Path to picotest crate, if it's imported under another name.
+    #[darling(rename = "crate")]
+    krate: Option<darling::export::syn::Path>,
+}
+
+impl UnitTestCfg {
+    /// Returns `true` if test is executed by the embedded runtime
+    /// instead of the session cluster.
+    fn is_embedded(&self) -> Result<bool, Error> {
+        match self.runtime.as_deref() {
+            None | Some("cluster") => Ok(false),
+            Some("embedded") => Ok(true),
+            Some(runtime) => Err(Error::custom(format!(
+                "unknown runtime `{runtime}`, expected one of {UNIT_RUNTIMES:?}"
+            ))),
+        }
+    }
 }
 
 static UNIT_COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
 
 #[proc_macro_attribute]
-pub fn picotest_unit(_: TokenStream, tokens: TokenStream) -> TokenStream {
+pub fn picotest_unit(attr: TokenStream, tokens: TokenStream) -> TokenStream {
+    let cfg: UnitTestCfg = match parse_attrs(attr) {
+        Ok(cfg) => cfg,
+        Err(err) => return err,
+    };
+    let is_embedded = match cfg.is_embedded() {
+        Ok(is_embedded) => is_embedded,
+        Err(err) => return TokenStream::from(err.write_errors()),
+    };
+    let krate = manifest::picotest_crate_path(cfg.krate.as_ref());
+
     match parse_macro_input!(tokens as Item) {
         Item::Fn(mut test_fn) => {
-            let test_fn_attrs = test_fn.attrs.clone();
+            // Test of the crate, which isn't loaded by picodata, would fail
+            // at runtime with the confusing error of the dynamic loader.
+            if let Err(message) = unit_crate::validate_unit_test_crate() {
+                return syn::Error::new(test_fn.sig.ident.span(), message)
+                    .to_compile_error()
+                    .into();
+            }
+
+            let test_fn_attrs = std::mem::take(&mut test_fn.attrs);
             let test_fn_name = test_fn.sig.ident.to_string();
-            // We want test routine to be called through FFI.
-            // So mark it as 'pub extern "C"'.
-            test_fn.vis = parse_quote! { pub };
-            test_fn.sig.abi = parse_quote! { extern "C" };
-            // Set no mangle attribute to avoid spoiling of function signature.
-            test_fn.attrs = vec![
-                parse_quote! { #[allow(dead_code)]  },
-                parse_quote! { #[unsafe(no_mangle)] },
-            ];
 
             // Create test runner - it's a wrapper around main test function.
             // This wrapper will call main test routine in a Lua runtime running
@@ -86,35 +342,172 @@ pub fn picotest_unit(_: TokenStream, tokens: TokenStream) -> TokenStream {
             // Name of the function to be invoked on instance-side as test payload
             let test_idx = UNIT_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Acquire);
             let ffi_test_callable = format!("test_impl_{test_idx}_{test_fn_name}");
-            test_fn.sig.ident = Ident::new(&ffi_test_callable, test_fn.sig.ident.span());
+            let ffi_test_ident = Ident::new(&ffi_test_callable, test_fn.sig.ident.span());
 
-            let tokens = quote! {
-                #[test]
-                fn #test_runner_ident() {
-                    use picotest::internal;
+            // We want test routine to be called through FFI, so it's wrapped
+            // into 'pub extern "C"' function with unmangled name. Panic message
+            // is copied into the buffer provided by the caller, so no memory
+            // allocated by the plugin crosses the library boundary. Context of
+            // the test is passed by the caller as JSON, see `picotest::unit`.
+            // It's compiled only with `unit_runner` feature of picotest.
+            test_fn.sig.ident = parse_quote! { test_body };
+            test_fn.vis = syn::Visibility::Inherited;
+            let test_callable = quote! {
+                #[allow(dead_code, clippy::not_unsafe_ptr_arg_deref)]
+                #[unsafe(no_mangle)]
+                pub extern "C" fn #ffi_test_ident(
+                    buffer: *mut u8,
+                    capacity: usize,
+                    context: *const u8,
+                    context_length: usize,
+                ) -> usize {
+                    #test_fn
 
-                    let plugin_path = internal::plugin_root_dir();
-                    let plugin_dylib_path =
-                        internal::plugin_dylib_path(&plugin_path, env!("CARGO_PKG_NAME"));
-                    let plugin_topology = internal::get_or_create_unit_test_topology();
+                    let context = if context.is_null() {
+                        &[][..]
+                    } else {
+                        // SAFETY: caller guarantees that context has `context_length` bytes.
+                        unsafe { std::slice::from_raw_parts(context, context_length) }
+                    };
+                    // Test may be called by another one or run concurrently
+                    // in other fiber, so its context is tracked per fiber.
+                    let frame = #krate::unit::enter(
+                        context,
+                        env!("CARGO_PKG_NAME"),
+                        env!("CARGO_PKG_VERSION"),
+                    );
+                    let result = std::panic::catch_unwind(test_body);
+                    let left = #krate::unit::leave(frame);
+                    // Mocks installed by the tests must not leak into the next ones,
+                    // so they're cleared once no test is running.
+                    if !#krate::unit::is_running() {
+                        #krate::mock::clear();
+                    }
+                    let message = match result {
+                        Ok(()) => match left {
+                            Ok(()) => return 0,
+                            Err(message) => message,
+                        },
+                        Err(payload) => {
+                            let message = payload
+                                .downcast_ref::<&str>()
+                                .map(|message| message.to_string())
+                                .or_else(|| payload.downcast_ref::<String>().cloned())
+                                .unwrap_or_else(|| "test panicked".to_string());
+                            // Payload is dropped under guard, so panic in its destructor
+                            // doesn't unwind through FFI boundary. Payload of such panic
+                            // is leaked on purpose.
+                            let dropped = std::panic::catch_unwind(
+                                std::panic::AssertUnwindSafe(move || drop(payload))
+                            );
+                            if let Err(payload) = dropped {
+                                std::mem::forget(payload);
+                            }
+                            message
+                        }
+                    };
 
-                    let call_test_fn_query =
-                        internal::lua_ffi_call_unit_test(
-                            #ffi_test_callable, plugin_dylib_path.to_str().unwrap());
+                    // Message is truncated on char boundary to fit into the buffer.
+                    // Zero length is reserved for successful result.
+                    let mut length = message.len().min(capacity);
+                    while !message.is_char_boundary(length) {
+                        length -= 1;
+                    }
+                    // SAFETY: caller guarantees that buffer has `capacity` bytes.
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(message.as_ptr(), buffer, length);
+                    }
+                    length.max(1)
+                }
+            };
 
-                    let cluster = picotest::get_or_create_session_cluster(
-                        plugin_path.to_str().unwrap().into(),
-                        plugin_topology.into(),
-                    );
+            let tokens = if is_embedded {
+                // Test is run in its own runtime process, so there's
+                // no cluster to pass to hooks.
+                quote! {
+                    #[test]
+                    fn #test_runner_ident() {
+                        use #krate::internal;
+
+                        if internal::skip_requested() {
+                            internal::report_test_skipped(#test_fn_name);
+                            return;
+                        }
 
-                    let output = cluster.run_lua(call_test_fn_query)
-                        .expect("Failed to execute query");
+                        let plugin_path = internal::plugin_root_dir();
+                        let plugin_dylib_path = internal::package_dylib_path(
+                            &plugin_path,
+                            std::path::Path::new(env!("CARGO_MANIFEST_DIR")),
+                            env!("CARGO_PKG_NAME"),
+                        );
 
-                    if let Err(err) = internal::verify_unit_test_output(&output) {
-                        for l in output.split("----") {
-                            println!("[Lua] {l}")
+                        let output = internal::run_embedded_unit_test(
+                            #ffi_test_callable,
+                            &plugin_path,
+                            &plugin_dylib_path,
+                        );
+                        if let Err(err) = internal::verify_unit_test_output(&output) {
+                            panic!("Test '{}' exited with failure: {}", #test_fn_name, err);
+                        }
+                    }
+                }
+            } else {
+                quote! {
+                    #[test]
+                    fn #test_runner_ident() {
+                        use #krate::internal;
+
+                        if internal::skip_requested() {
+                            internal::report_test_skipped(#test_fn_name);
+                            return;
+                        }
+
+                        let plugin_path = internal::plugin_root_dir();
+                        let plugin_dylib_path = internal::package_dylib_path(
+                            &plugin_path,
+                            std::path::Path::new(env!("CARGO_MANIFEST_DIR")),
+                            env!("CARGO_PKG_NAME"),
+                        );
+                        let plugin_topology = internal::get_or_create_unit_test_topology();
+
+                        let cluster = #krate::get_or_create_session_cluster(
+                            plugin_path.to_str().unwrap().into(),
+                            plugin_topology.into(),
+                        );
+                        internal::check_unit_test_library(cluster, &plugin_dylib_path);
+
+                        let call_test_fn_query = internal::lua_ffi_call_unit_test(
+                            #ffi_test_callable,
+                            plugin_dylib_path.to_str().unwrap(),
+                            cluster,
+                        );
+
+                        let _log_stream = internal::stream_logs(cluster);
+                        internal::run_before_each_hooks(#test_fn_name, cluster);
+                        let test_tracker = internal::track_test(
+                            concat!(module_path!(), "::", #test_fn_name),
+                            cluster,
+                        );
+                        let watchdog = internal::watch_test(
+                            concat!(module_path!(), "::", #test_fn_name),
+                            cluster,
+                        );
+                        let output = cluster.run_lua(call_test_fn_query);
+                        drop(watchdog);
+                        let result = internal::verify_unit_test_output(&output);
+                        if result.is_err() {
+                            internal::dump_test_state(
+                                concat!(module_path!(), "::", #test_fn_name),
+                                cluster,
+                            );
+                            internal::inspect_failed_test(cluster);
+                        }
+                        internal::run_after_each_hooks(#test_fn_name, cluster, result.is_err());
+                        internal::record_test_outcome(test_tracker, result.is_err());
+
+                        if let Err(err) = result {
+                            panic!("Test '{}' exited with failure: {}", #test_fn_name, err);
                         }
-                        panic!("Test '{}' exited with failure: {}", #test_fn_name, err);
                     }
                 }
             };
@@ -126,11 +519,18 @@ pub fn picotest_unit(_: TokenStream, tokens: TokenStream) -> TokenStream {
             test_runner.attrs.extend(test_fn_attrs);
 
             quote! {
-                #test_fn
+                #krate::__unit_test_entry! {
+                    #test_callable
+                }
                 #test_runner
             }
             .into()
         }
-        _ => panic!("The #[picotest_unit] macro is only valid when called on a function."),
+        item => syn::Error::new_spanned(
+            item,
+            "The #[picotest_unit] macro is only valid when called on a function.",
+        )
+        .to_compile_error()
+        .into(),
     }
 }
@@ -0,0 +1,104 @@
+use proc_macro2::Span;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use syn::{parse_quote, Ident, Path as SynPath};
+
+/// Name of the package providing runtime of the generated code.
+const PICOTEST_PACKAGE: &str = "picotest";
+
+/// Tables of the manifest declaring dependencies.
+const DEPENDENCY_TABLES: [&str; 2] = ["dependencies", "dev-dependencies"];
+
+/// Reads `Cargo.toml` located in `manifest_dir`.
+pub fn read_manifest(manifest_dir: &Path) -> Result<toml::Table, String> {
+    let manifest_path = manifest_dir.join("Cargo.toml");
+    std::fs::read_to_string(&manifest_path)
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .ok_or_else(|| format!("failed to read '{}'", manifest_path.display()))
+}
+
+/// Returns path to picotest crate in the code generated for the crate
+/// under compilation, e.g. `::picotest`.
+///
+/// Path set by `crate` attribute of the macro takes precedence, e.g. if
+/// picotest is re-exported by another crate. Otherwise dependency on
+/// picotest is looked up in the manifest of the package, so renamed
+/// dependency (`my_picotest = { package = "picotest" }`) is supported.
+pub fn picotest_crate_path(explicit: Option<&darling::export::syn::Path>) -> SynPath {
+    if let Some(path) = explicit {
+        return parse_quote!(#path);
+    }
+    let (Ok(manifest_dir), Ok(crate_name)) =
+        (env::var("CARGO_MANIFEST_DIR"), env::var("CARGO_CRATE_NAME"))
+    else {
+        return parse_quote!(::picotest);
+    };
+
+    // Macros of the crate are expanded many times, but
+    // the manifest of every package is read only once.
+    static DEPENDENCIES: Mutex<BTreeMap<PathBuf, Option<PicotestDependency>>> =
+        Mutex::new(BTreeMap::new());
+    let dependency = DEPENDENCIES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .entry(PathBuf::from(&manifest_dir))
+        .or_insert_with(|| {
+            read_manifest(Path::new(&manifest_dir))
+                .ok()
+                .and_then(|manifest| picotest_dependency(&manifest))
+        })
+        .clone();
+
+    match dependency {
+        // Integration tests of picotest import it as usual.
+        Some(PicotestDependency::Itself) if crate_name == PICOTEST_PACKAGE => parse_quote!(crate),
+        Some(PicotestDependency::Renamed(name)) => {
+            let name = Ident::new(&name, Span::call_site());
+            parse_quote!(::#name)
+        }
+        _ => parse_quote!(::picotest),
+    }
+}
+
+/// How the package depends on picotest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PicotestDependency {
+    /// Package is picotest itself.
+    Itself,
+    /// Dependency is imported under another name.
+    Renamed(String),
+}
+
+/// Looks dependency on picotest up in the manifest.
+fn picotest_dependency(manifest: &toml::Table) -> Option<PicotestDependency> {
+    let package_name = manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(toml::Value::as_str);
+    if package_name == Some(PICOTEST_PACKAGE) {
+        return Some(PicotestDependency::Itself);
+    }
+
+    // Dependencies may be declared per target, e.g. `[target.'cfg(unix)'.dependencies]`.
+    let targets = manifest
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values().filter_map(toml::Value::as_table));
+    std::iter::once(manifest)
+        .chain(targets)
+        .flat_map(|table| {
+            DEPENDENCY_TABLES
+                .iter()
+                .filter_map(|name| table.get(*name).and_then(toml::Value::as_table))
+        })
+        .flatten()
+        .find_map(|(name, dependency)| {
+            let package = dependency.get("package").and_then(toml::Value::as_str)?;
+            (package == PICOTEST_PACKAGE && name != PICOTEST_PACKAGE)
+                .then(|| PicotestDependency::Renamed(name.replace('-', "_")))
+        })
+}
@@ -0,0 +1,159 @@
+use darling::ast::NestedMeta;
+use darling::{Error, FromMeta};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{FnArg, ItemTrait, Meta, Pat, ReturnType, TraitItem, TraitItemFn, Type, Visibility};
+
+/// Service of the plugin the client calls RPC endpoints of.
+#[derive(Debug, FromMeta)]
+pub struct RpcClientCfg {
+    plugin: String,
+    service: String,
+    version: String,
+    /// Path to picotest crate, see `crate` attribute of `#[picotest]`.
+    #[darling(rename = "crate")]
+    krate: Option<darling::export::syn::Path>,
+}
+
+/// Endpoint of the client method, `/<method name>` by default.
+#[derive(Debug, Default, FromMeta)]
+struct RpcMethodCfg {
+    path: Option<String>,
+}
+
+/// Generates client struct named after the `client` trait with
+/// async method calling RPC endpoint for every method of the trait.
+pub fn generate_rpc_client(cfg: RpcClientCfg, client: ItemTrait) -> Result<TokenStream, Error> {
+    if !client.generics.params.is_empty() {
+        return Err(
+            Error::custom("#[rpc_client] trait can't be generic").with_span(&client.generics)
+        );
+    }
+
+    let mut errors = Error::accumulator();
+    let methods: Vec<TokenStream> = client
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Fn(method) => errors.handle(client_method(&cfg, &client.vis, method)),
+            item => {
+                errors.push(
+                    Error::custom("#[rpc_client] trait may contain only methods").with_span(item),
+                );
+                None
+            }
+        })
+        .collect();
+    errors.finish()?;
+
+    let attrs = &client.attrs;
+    let vis = &client.vis;
+    let name = &client.ident;
+    let krate = crate::manifest::picotest_crate_path(cfg.krate.as_ref());
+    Ok(quote! {
+        #(#attrs)*
+        #vis struct #name<'a> {
+            instance: &'a #krate::PicotestInstance,
+        }
+
+        impl<'a> #name<'a> {
+            /// Creates client calling RPC endpoints of the `instance`.
+            #vis fn new(instance: &'a #krate::PicotestInstance) -> Self {
+                Self { instance }
+            }
+
+            #(#methods)*
+        }
+
+        impl<'a> From<&'a #krate::Cluster> for #name<'a> {
+            /// Creates client calling RPC endpoints of the main instance.
+            fn from(cluster: &'a #krate::Cluster) -> Self {
+                Self::new(cluster.main())
+            }
+        }
+    })
+}
+
+/// Generates client method calling endpoint declared by trait `method`.
+fn client_method(
+    cfg: &RpcClientCfg,
+    vis: &Visibility,
+    method: &TraitItemFn,
+) -> Result<TokenStream, Error> {
+    let mut method_cfg = RpcMethodCfg::default();
+    let mut attrs = Vec::new();
+    for attr in &method.attrs {
+        if attr.path().is_ident("rpc") {
+            let Meta::List(list) = &attr.meta else {
+                return Err(Error::custom("expected `#[rpc(path = \"...\")]`").with_span(attr));
+            };
+            method_cfg = NestedMeta::parse_meta_list(list.tokens.clone())
+                .map_err(Error::from)
+                .and_then(|meta| RpcMethodCfg::from_list(&meta))?;
+        } else {
+            attrs.push(attr);
+        }
+    }
+
+    let sig = &method.sig;
+    if sig.receiver().is_some() || sig.asyncness.is_some() || method.default.is_some() {
+        return Err(Error::custom(
+            "#[rpc_client] method must be declared as `fn name(input: &Input) -> Output;`",
+        )
+        .with_span(sig));
+    }
+
+    // Endpoint without input is called with unit.
+    let (input_arg, input_value, input_ty) = match sig.inputs.iter().collect::<Vec<_>>()[..] {
+        [] => (quote! {}, quote! { &() }, quote! { () }),
+        [FnArg::Typed(arg)] => {
+            let Pat::Ident(pat) = &*arg.pat else {
+                return Err(Error::custom("expected argument name").with_span(&arg.pat));
+            };
+            let Type::Reference(input_ty) = &*arg.ty else {
+                return Err(
+                    Error::custom("input of RPC method must be a reference").with_span(&arg.ty)
+                );
+            };
+            let input = &pat.ident;
+            let input_ty = &input_ty.elem;
+            (quote! { #arg }, quote! { #input }, quote! { #input_ty })
+        }
+        _ => {
+            return Err(
+                Error::custom("#[rpc_client] method takes at most one input argument")
+                    .with_span(&sig.inputs),
+            )
+        }
+    };
+
+    let output = match &sig.output {
+        ReturnType::Default => quote! { () },
+        ReturnType::Type(_, ty) => quote! { #ty },
+    };
+
+    let name = &sig.ident;
+    let path = method_cfg.path.unwrap_or_else(|| format!("/{name}"));
+    let RpcClientCfg {
+        plugin,
+        service,
+        version,
+        krate,
+    } = cfg;
+    let krate = crate::manifest::picotest_crate_path(krate.as_ref());
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis async fn #name(&self, #input_arg) -> #krate::internal::RpcResult<#output> {
+            self.instance
+                .execute_rpc::<#input_ty, #output>(
+                    #plugin,
+                    #path,
+                    #service,
+                    #version,
+                    #input_value,
+                )
+                .await
+        }
+    })
+}
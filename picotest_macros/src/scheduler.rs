@@ -0,0 +1,100 @@
+use crate::utils::{attribute_name, TestOptions};
+use quote::{format_ident, quote};
+use syn::{parse_quote, ItemFn, Visibility};
+
+/// Attributes, which make rstest generate the test from a function,
+/// so it can't be called as is by the custom harness.
+const UNSCHEDULED_ATTRIBUTES: [&str; 5] = ["test", "case", "timeout", "trace", "awt"];
+
+/// Returns `true` if processed test is registered in the custom harness
+/// of picotest, i.e. it's a plain function without arguments.
+fn is_scheduled(test: &ItemFn) -> bool {
+    test.sig.inputs.is_empty()
+        && !test.attrs.iter().any(|attr| {
+            attribute_name(attr).is_some_and(|name| UNSCHEDULED_ATTRIBUTES.contains(&name.as_str()))
+        })
+}
+
+/// Splits processed `test` into the function running its body and the test
+/// calling it, so the body is available to the custom harness of picotest.
+///
+/// Test binary built with `harness = false` drops `#[test]` functions, so
+/// the body function registers itself in the harness on startup. Test, which
+/// the harness can't run, registers its name to be reported as skipped.
+/// Registration is compiled only with `harness` feature of picotest.
+pub fn split_scheduled(mut test: ItemFn, options: &TestOptions) -> Vec<ItemFn> {
+    let krate = &options.crate_path;
+    let name = test.sig.ident.clone();
+    let test_name = name.to_string();
+    if !is_scheduled(&test) {
+        test.block.stmts.insert(
+            0,
+            parse_quote! {
+                #krate::__harness_registration! {
+                    #[#krate::__private::ctor::ctor(crate_path = #krate::__private::ctor)]
+                    unsafe fn __picotest_schedule() {
+                        #krate::scheduler::register_unscheduled(
+                            concat!(module_path!(), "::", #test_name),
+                        );
+                    }
+                }
+            },
+        );
+        return vec![test];
+    }
+    let body_name = format_ident!("__picotest_scheduled_{}", name);
+    let has_attribute = |name: &str| test.attrs.iter().any(|attr| attr.path().is_ident(name));
+    let ignored = has_attribute("ignore");
+    let should_panic = has_attribute("should_panic");
+
+    let quote_str = |value: &Option<String>| match value {
+        Some(value) => quote! { Some(#value) },
+        None => quote! { None },
+    };
+    let plugin_path = quote_str(&options.path);
+    let install_from = quote_str(&options.install_from);
+    let tag = quote_str(&options.tag);
+    let keepalive = options.keepalive;
+    let topology_transform = match &options.topology_transform {
+        Some(transform) => quote! {
+            Some((
+                stringify!(#transform),
+                #transform as #krate::scheduler::TopologyTransformFn,
+            ))
+        },
+        None => quote! { None },
+    };
+
+    let mut body = test.clone();
+    body.attrs.clear();
+    body.vis = Visibility::Inherited;
+    body.sig.ident = body_name.clone();
+    body.block.stmts.insert(
+        0,
+        parse_quote! {
+            #krate::__harness_registration! {
+                #[#krate::__private::ctor::ctor(crate_path = #krate::__private::ctor)]
+                unsafe fn __picotest_schedule() {
+                    #krate::scheduler::register(#krate::scheduler::ScheduledTest {
+                        name: concat!(module_path!(), "::", #test_name),
+                        profile: #krate::scheduler::ClusterProfile {
+                            plugin_path: #plugin_path,
+                            install_from: #install_from,
+                            keepalive: #keepalive,
+                            topology_transform: #topology_transform,
+                            tag: #tag,
+                        },
+                        ignored: #ignored,
+                        should_panic: #should_panic,
+                        run: #body_name,
+                    });
+                }
+            }
+        },
+    );
+
+    test.block = parse_quote! {{
+        #body_name();
+    }};
+    vec![body, test]
+}
@@ -0,0 +1,72 @@
+use crate::manifest::read_manifest;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Name of the plugin topology file picotest starts the cluster from.
+const PLUGIN_TOPOLOGY_FILENAME: &str = "topology.toml";
+
+/// Checks that crate declaring `#[picotest_unit]` is the `cdylib` library
+/// of the plugin, so the test can be loaded by the instance.
+///
+/// Crate is inspected through environment variables cargo sets for rustc,
+/// check is skipped if the crate isn't built by cargo.
+///
+/// ### Returns
+/// Description of the violated requirement.
+pub fn validate_unit_test_crate() -> Result<(), String> {
+    let (Ok(manifest_dir), Ok(crate_name)) =
+        (env::var("CARGO_MANIFEST_DIR"), env::var("CARGO_CRATE_NAME"))
+    else {
+        return Ok(());
+    };
+    let manifest_dir = PathBuf::from(manifest_dir);
+    let manifest_path = manifest_dir.join("Cargo.toml");
+    let manifest = read_manifest(&manifest_dir)?;
+
+    let package_name = manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or_default();
+    let lib = manifest.get("lib");
+    let lib_name = lib
+        .and_then(|lib| lib.get("name"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or(package_name)
+        .replace('-', "_");
+    let is_cdylib = lib
+        .and_then(|lib| lib.get("crate-type"))
+        .and_then(toml::Value::as_array)
+        .is_some_and(|types| types.iter().any(|kind| kind.as_str() == Some("cdylib")));
+
+    if !is_cdylib {
+        return Err(format!(
+            "#[picotest_unit] requires package '{package_name}' to build dynamic library \
+             loaded by picodata, add to '{}':\n\n[lib]\ncrate-type = [\"cdylib\"]",
+            manifest_path.display()
+        ));
+    }
+    if crate_name != lib_name {
+        return Err(format!(
+            "#[picotest_unit] must be declared in the library '{lib_name}' of package \
+             '{package_name}', but it's used in crate '{crate_name}' (integration test, \
+             example or binary), which isn't loaded by picodata. Use #[picotest] there instead"
+        ));
+    }
+    if find_topology(&manifest_dir).is_none() {
+        return Err(format!(
+            "#[picotest_unit] requires plugin topology file {PLUGIN_TOPOLOGY_FILENAME} \
+             in '{}' or in the root of its workspace",
+            manifest_dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Looks the topology file up in `package_dir` and its parents.
+fn find_topology(package_dir: &Path) -> Option<PathBuf> {
+    package_dir
+        .ancestors()
+        .map(|dir| dir.join(PLUGIN_TOPOLOGY_FILENAME))
+        .find(|path| path.is_file())
+}
@@ -1,39 +1,502 @@
+use crate::dependencies::{is_depends_on, prerequisite_body};
+use crate::scheduler::split_scheduled;
+use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{parse_quote, Attribute, FnArg, ItemFn, Stmt};
-const TEST_PREFIX: &str = "test_";
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_quote, Attribute, Error, FnArg, Ident, ImplItem, ImplItemFn, ItemFn, ItemImpl, Pat,
+    ReceiverKind, Stmt, Type, Visibility,
+};
+/// Default prefix of names of test functions.
+pub const DEFAULT_TEST_PREFIX: &str = "test_";
 
-pub fn process_test_function(mut func: ItemFn, path: &Option<String>) -> ItemFn {
-    let func_name = func.sig.ident.to_string();
-    if !func_name.starts_with(TEST_PREFIX) {
-        return func;
+/// Attributes, which are applied by `#[picotest]` itself
+/// and must not be added to the test function manually.
+const CONFLICTING_ATTRIBUTES: [&str; 2] = ["test", "rstest"];
+
+/// Attributes of rstest, which make sense for test functions only.
+const RSTEST_CASE_ATTRIBUTES: [&str; 3] = ["case", "values", "files"];
+
+/// Name of the cluster fixture provided by picotest.
+pub const CLUSTER_FIXTURE: &str = "cluster";
+
+/// Lock on global test state acquired for the duration of the test.
+pub enum TestLock {
+    None,
+    /// Test runs alone, e.g. because it mutates cluster state.
+    Exclusive,
+    /// Test runs in parallel with other shared tests, but not with exclusive ones.
+    Shared,
+}
+
+/// Options of `#[picotest]` applied to every test function.
+pub struct TestOptions {
+    pub path: Option<String>,
+    pub engines: Option<Vec<String>>,
+    pub keepalive: bool,
+    pub lock: TestLock,
+    /// Name of the test argument the cluster is injected into.
+    pub cluster_var: Ident,
+    /// Prefix of names of functions treated as tests.
+    pub prefix: String,
+    /// Source the plugin is installed from, `None` for the default one.
+    pub install_from: Option<String>,
+    /// Path to the function transforming plugin topology.
+    pub topology_transform: Option<proc_macro2::TokenStream>,
+    /// Tag of the test, see `PICOTEST_TAGS`.
+    pub tag: Option<String>,
+    /// Path to picotest crate in the generated code.
+    pub crate_path: syn::Path,
+}
+
+/// Returns `true` if function is treated as a test by `#[picotest]`, i.e.
+/// its name starts with the test prefix or it's marked with `#[picotest::test]`.
+pub fn is_test_function(func: &ItemFn, options: &TestOptions) -> bool {
+    func.sig.ident.to_string().starts_with(&options.prefix) || func.attrs.iter().any(is_test_marker)
+}
+
+/// Checks that function annotated with `#[picotest]` directly
+/// can be turned into a test.
+pub fn validate_standalone_function(func: &ItemFn, options: &TestOptions) -> Result<(), Error> {
+    if is_test_function(func, options) {
+        return Ok(());
+    }
+    Err(Error::new_spanned(
+        &func.sig.ident,
+        format!(
+            "#[picotest] test function name must start with `{}`",
+            options.prefix
+        ),
+    ))
+}
+
+/// Checks that function inside `#[picotest]` module, which isn't treated
+/// as a test, doesn't look like a test, so it won't be silently skipped.
+pub fn validate_skipped_function(func: &ItemFn, options: &TestOptions) -> Result<(), Error> {
+    let has_test_attributes = func.attrs.iter().any(|attr| {
+        attribute_name(attr).is_some_and(|name| {
+            CONFLICTING_ATTRIBUTES.contains(&name.as_str())
+                || RSTEST_CASE_ATTRIBUTES.contains(&name.as_str())
+        })
+    });
+    if !has_test_attributes {
+        return Ok(());
     }
+    Err(Error::new_spanned(
+        &func.sig.ident,
+        format!(
+            "function is not treated as a test by #[picotest], because its name \
+            doesn't start with `{}`, mark it with #[picotest::test]",
+            options.prefix
+        ),
+    ))
+}
+
+/// Turns function into a test running after its `prerequisites`,
+/// see [`crate::dependencies::TestDependencies`].
+pub fn process_test_function(
+    mut func: ItemFn,
+    options: &TestOptions,
+    prerequisites: &[Ident],
+) -> Result<ItemFn, Error> {
+    if !is_test_function(&func, options) {
+        validate_skipped_function(&func, options)?;
+        return Ok(func);
+    }
+    func.attrs.retain(|attr| !is_test_marker(attr));
+    validate_test_function(&func)?;
 
-    let rstest_macro: Attribute = parse_quote! { #[rstest] };
+    let krate = &options.crate_path;
+    let rstest_macro: Attribute = parse_quote! { #[#krate::__private::rstest] };
     func.attrs.insert(0, rstest_macro);
 
-    let path = match path {
+    let path = match &options.path {
         Some(cfg_path) => quote! { Some(#cfg_path) },
         None => quote! { None },
     };
+    let keepalive = options.keepalive;
+    let install_from = match &options.install_from {
+        Some(source) => quote! { Some(#source) },
+        None => quote! { None },
+    };
+    let topology_transform = match &options.topology_transform {
+        Some(transform) => quote! {
+            Some(#transform as fn(&#krate::PluginTopology) -> #krate::PluginTopology)
+        },
+        None => quote! { None },
+    };
+
+    // Cluster is obtained in the test body instead of being injected as
+    // a fixture, so it's started only when a test using it actually runs.
+    // Explicitly declared cluster argument is replaced with it.
+    let cluster_var = &options.cluster_var;
+    if let Some(index) = find_argument(&func, cluster_var) {
+        func.sig.inputs = func
+            .sig
+            .inputs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, arg)| (i != index).then_some(arg))
+            .collect();
+    }
 
-    let cluster: FnArg = parse_quote! {
-        #[with(#path)] cluster: &Cluster
+    // Every listed engine produces a separate rstest case
+    // with the engine name injected as `engine` argument.
+    if let Some(engines) = &options.engines {
+        let engine: FnArg = parse_quote! {
+            #[values(#(#engines),*)] engine: &str
+        };
+        func.sig.inputs.insert(0, engine);
+    }
+
+    let test_name = func.sig.ident.to_string();
+    let skip: Stmt = parse_quote! {
+        if #krate::internal::skip_requested() {
+            #krate::internal::report_test_skipped(#test_name);
+            return;
+        }
+    };
+    let tag = match &options.tag {
+        Some(tag) => quote! { Some(#tag) },
+        None => quote! { None },
+    };
+    let tag_filter: Stmt = parse_quote! {
+        if !#krate::internal::tag_selected(#tag) {
+            #krate::internal::report_test_filtered(#test_name);
+            return;
+        }
+    };
+    // Filtered before the cluster is started, so unaffected tests are skipped quickly.
+    let impact_filter: Stmt = parse_quote! {
+        if !#krate::internal::impact_selected(
+            #path,
+            concat!(module_path!(), "::", #test_name),
+            file!(),
+        ) {
+            #krate::internal::report_test_not_impacted(#test_name);
+            return;
+        }
+    };
+    let cluster: Stmt = parse_quote! {
+        let #cluster_var: &'static #krate::Cluster =
+            #krate::internal::session_cluster(
+                #path,
+                #keepalive,
+                #install_from,
+                #topology_transform,
+            );
+    };
+
+    let log_stream: Stmt = parse_quote! {
+        let _log_stream = #krate::internal::stream_logs(#cluster_var);
+    };
+
+    let before_each: Stmt = parse_quote! {
+        #krate::internal::run_before_each_hooks(#test_name, #cluster_var);
+    };
+
+    let test_tracker: Stmt = parse_quote! {
+        let test_tracker = #krate::internal::track_test(
+            concat!(module_path!(), "::", #test_name),
+            #cluster_var,
+        );
+    };
+
+    let impact_tracker: Stmt = parse_quote! {
+        let impact_tracker = #krate::internal::track_impact(
+            concat!(module_path!(), "::", #test_name),
+            file!(),
+            #cluster_var,
+        );
+    };
+
+    let watchdog: Stmt = parse_quote! {
+        let watchdog = #krate::internal::watch_test(
+            concat!(module_path!(), "::", #test_name),
+            #cluster_var,
+        );
     };
-    func.sig.inputs.insert(0, cluster);
 
     let block = func.block.clone();
     let new_body: Stmt = parse_quote! {
-        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            #block
-        }));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| #block));
+    };
+
+    // Test paused for inspection mustn't be terminated by the watchdog.
+    let stop_watchdog: Stmt = parse_quote! {
+        drop(watchdog);
+    };
+
+    // State is dumped and inspected before `after_each` hooks, which may clean it up.
+    let dump_state: Stmt = parse_quote! {
+        if result.is_err() {
+            #krate::internal::dump_test_state(
+                concat!(module_path!(), "::", #test_name),
+                #cluster_var,
+            );
+            #krate::internal::inspect_failed_test(#cluster_var);
+        }
+    };
+
+    let after_each: Stmt = parse_quote! {
+        #krate::internal::run_after_each_hooks(#test_name, #cluster_var, result.is_err());
+    };
+
+    let record_outcome: Stmt = parse_quote! {
+        #krate::internal::record_test_outcome(test_tracker, result.is_err());
+    };
+
+    let record_impact: Stmt = parse_quote! {
+        #krate::internal::record_test_impact(impact_tracker, result.is_err());
     };
 
     let resume: Stmt = parse_quote! {
         if let Err(err) = result {
-            panic::resume_unwind(err);
+            #krate::internal::report_test_failure();
+            std::panic::resume_unwind(err);
         }
     };
-    func.block.stmts = vec![new_body, resume];
+    func.block.stmts = vec![
+        cluster,
+        log_stream,
+        before_each,
+        test_tracker,
+        impact_tracker,
+        watchdog,
+        new_body,
+        stop_watchdog,
+        dump_state,
+        after_each,
+        record_outcome,
+        record_impact,
+        resume,
+    ];
+
+    let lock_guard: Option<Stmt> = match options.lock {
+        TestLock::None => None,
+        TestLock::Exclusive => Some(parse_quote! {
+            let _test_lock_guard = #krate::internal::lock_test_exclusive();
+        }),
+        TestLock::Shared => Some(parse_quote! {
+            let _test_lock_guard = #krate::internal::lock_test_shared();
+        }),
+    };
+    if let Some(lock_guard) = lock_guard {
+        func.block.stmts.insert(0, lock_guard);
+    }
+    // Prerequisites are run before the lock is acquired,
+    // as they may acquire it themselves.
+    if !prerequisites.is_empty() {
+        let names = prerequisites.iter().map(Ident::to_string);
+        let bodies = prerequisites.iter().map(prerequisite_body);
+        func.block.stmts.insert(
+            0,
+            parse_quote! {
+                if !#krate::internal::prerequisites_passed(
+                    #test_name,
+                    &[#((concat!(module_path!(), "::", #names), #bodies as fn())),*],
+                ) {
+                    return;
+                }
+            },
+        );
+    }
+    func.block.stmts.insert(0, impact_filter);
+    func.block.stmts.insert(0, tag_filter);
+    func.block.stmts.insert(0, skip);
+
+    Ok(func)
+}
+
+/// Turns test methods of the suite `impl` block into tests sharing
+/// the suite instance, which is created once by `new(cluster)`.
+pub fn process_test_suite(
+    mut suite: ItemImpl,
+    options: &TestOptions,
+) -> Result<TokenStream, Error> {
+    if suite.trait_.is_some() || !suite.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &suite.self_ty,
+            "#[picotest] can only be applied to inherent impl block of a non-generic type",
+        ));
+    }
+    let self_ty = suite.self_ty.clone();
+    let krate = &options.crate_path;
+
+    let mut tests = Vec::new();
+    let mut errors: Vec<Error> = Vec::new();
+    for item in &mut suite.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        let func = ItemFn {
+            attrs: method.attrs.clone(),
+            vis: Visibility::Inherited,
+            modifiers: method.modifiers.clone(),
+            sig: method.sig.clone(),
+            block: Box::new(method.block.clone()),
+        };
+        if !is_test_function(&func, options) {
+            errors.extend(validate_skipped_function(&func, options).err());
+            continue;
+        }
+        match suite_test(&self_ty, method, options)
+            .and_then(|test| process_test_function(test, options, &[]))
+        {
+            Ok(test) => tests.extend(split_scheduled(test, options)),
+            Err(err) => errors.push(err),
+        }
+    }
+    if let Some(errors) = errors.into_iter().reduce(|mut combined, error| {
+        combined.combine(error);
+        combined
+    }) {
+        return Err(errors);
+    }
+
+    // Suite is shared by tests of the binary, so it's
+    // created by the first test and never dropped.
+    Ok(quote! {
+        #suite
+
+        impl #self_ty {
+            #[doc(hidden)]
+            fn __picotest_suite(cluster: &'static #krate::Cluster) -> &'static Self {
+                static SUITE: std::sync::OnceLock<#self_ty> = std::sync::OnceLock::new();
+                SUITE.get_or_init(|| <#self_ty>::new(cluster))
+            }
+        }
+
+        #(#tests)*
+    })
+}
+
+/// Creates test function calling test `method` of the suite.
+///
+/// Attributes of the method and its arguments (e.g. rstest cases) are moved
+/// to the test function, so the method itself remains a plain one.
+fn suite_test(
+    self_ty: &Type,
+    method: &mut ImplItemFn,
+    options: &TestOptions,
+) -> Result<ItemFn, Error> {
+    let takes_shared_self = method
+        .sig
+        .receiver()
+        .is_some_and(|receiver| matches!(receiver.kind, ReceiverKind::Reference(_, _, None)));
+    if !takes_shared_self {
+        return Err(Error::new_spanned(
+            &method.sig,
+            "test method of #[picotest] suite must take `&self`",
+        ));
+    }
+
+    let mut inputs = Punctuated::new();
+    let mut args = Vec::new();
+    for arg in &mut method.sig.inputs {
+        let FnArg::Typed(arg) = arg else {
+            continue;
+        };
+        let Pat::Ident(pat) = &*arg.pat else {
+            return Err(Error::new_spanned(
+                &arg.pat,
+                "arguments of #[picotest] suite test method must be plain identifiers",
+            ));
+        };
+        args.push(pat.ident.clone());
+        inputs.push(FnArg::Typed(arg.clone()));
+        arg.attrs.clear();
+    }
+
+    let name = &method.sig.ident;
+    let attrs = std::mem::take(&mut method.attrs);
+    let cluster_var = &options.cluster_var;
+    let mut test: ItemFn = parse_quote! {
+        #(#attrs)*
+        fn #name() {
+            <#self_ty>::__picotest_suite(#cluster_var).#name(#(#args),*);
+        }
+    };
+    test.sig.inputs = inputs;
+
+    // Engine argument is injected by `process_test_function`.
+    if options.engines.is_some() {
+        let engine: Ident = parse_quote!(engine);
+        if let Some(index) = find_argument(&test, &engine) {
+            test.sig.inputs = test
+                .sig
+                .inputs
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, arg)| (i != index).then_some(arg))
+                .collect();
+        }
+    }
+    Ok(test)
+}
+
+/// Checks test function for constructs, which aren't compatible with `#[picotest]`.
+fn validate_test_function(func: &ItemFn) -> Result<(), Error> {
+    let mut errors: Vec<Error> = Vec::new();
+
+    if let Some(asyncness) = &func.sig.asyncness {
+        errors.push(Error::new_spanned(
+            asyncness,
+            "async test functions are not supported by #[picotest] directly, \
+            place #[tokio::test] above #[picotest]",
+        ));
+    }
+
+    // `#[tokio::test]` placed above `#[picotest]` is expanded first and
+    // leaves fully qualified `#[::core::prelude::v1::test]` on the function.
+    // It's fine, as rstest doesn't add its own `#[test]` then.
+    for attr in &func.attrs {
+        if is_depends_on(attr) {
+            errors.push(Error::new_spanned(
+                attr,
+                "#[depends_on] is only supported by tests of #[picotest] module",
+            ));
+        }
+        if attr.path().is_ident("test") || attribute_name(attr).is_some_and(|name| name == "rstest")
+        {
+            errors.push(Error::new_spanned(
+                attr,
+                "#[picotest] already declares the function as a test, remove this attribute",
+            ));
+        }
+    }
+
+    errors
+        .into_iter()
+        .reduce(|mut combined, error| {
+            combined.combine(error);
+            combined
+        })
+        .map_or(Ok(()), Err)
+}
+
+/// Returns `true` if attribute is `#[picotest::test]` marker.
+///
+/// Marker is consumed by enclosing `#[picotest]` module, so it doesn't
+/// correspond to any real macro. Otherwise `use picotest::*` would shadow
+/// built-in `#[test]` attribute.
+fn is_test_marker(attr: &Attribute) -> bool {
+    let segments = &attr.path().segments;
+    segments.len() == 2 && segments[0].ident == "picotest" && segments[1].ident == "test"
+}
+
+/// Returns last segment of attribute path, e.g. `test` for `#[tokio::test]`.
+pub fn attribute_name(attr: &Attribute) -> Option<String> {
+    attr.path()
+        .segments
+        .last()
+        .map(|segment| segment.ident.to_string())
+}
 
-    func
+/// Returns index of the function argument named `name`.
+pub fn find_argument(func: &ItemFn, name: &Ident) -> Option<usize> {
+    func.sig.inputs.iter().position(|arg| match arg {
+        FnArg::Typed(arg) => matches!(&*arg.pat, Pat::Ident(pat) if &pat.ident == name),
+        FnArg::Receiver(_) => false,
+    })
 }